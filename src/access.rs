@@ -0,0 +1,230 @@
+//! IP access control lists: ordered allow/deny rules on the client address, applied to the whole
+//! server, to a virtual host or to a path prefix.
+
+use crate::utils::{self, IpNetwork};
+
+use std::{net, str::FromStr};
+
+/// Requests an access control list applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AclScope {
+    /// All the requests
+    Global,
+    /// Requests made to a virtual host (Host header)
+    Host(String),
+    /// Requests whose path starts with a prefix
+    PathPrefix(String),
+}
+
+/// Clients an access control rule applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AclClients {
+    All,
+    Network(IpNetwork),
+}
+
+/// An access control rule, allowing or denying some clients.
+///
+/// Its string format is `allow:CLIENTS` or `deny:CLIENTS`, where `CLIENTS` is `all` or an IPv4 or
+/// IPv6 network in CIDR notation (e.g. `allow:10.0.0.0/8`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AclRule {
+    pub allow: bool,
+    pub clients: AclClients,
+}
+
+impl FromStr for AclRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (allow, clients) = match s.trim().split_once(':') {
+            Some(("allow", clients)) => (true, clients),
+            Some(("deny", clients)) => (false, clients),
+            _ => return Err(format!("Invalid access control rule: {}", s)),
+        };
+        let clients = match clients {
+            "all" => AclClients::All,
+            network => AclClients::Network(network.parse()?),
+        };
+        Ok(Self { allow, clients })
+    }
+}
+
+impl AclRule {
+    /// Check whether the rule applies to a client (clients without an IP address, connected to a
+    /// Unix domain socket, only match `all`).
+    fn matches(&self, client_ip: Option<net::IpAddr>) -> bool {
+        match &self.clients {
+            AclClients::All => true,
+            AclClients::Network(network) => client_ip.is_some_and(|ip| network.contains(ip)),
+        }
+    }
+}
+
+/// An access control list, whose first rule matching the client decides whether it is allowed.
+/// Clients matching none of the rules are allowed.
+///
+/// Its string format is `SCOPE=RULE[,RULE...]`, where `SCOPE` is `*` (all requests),
+/// `host:NAME` or a path prefix (e.g. `/admin=allow:10.0.0.0/8,allow:fd00::/8,deny:all`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Acl {
+    pub scope: AclScope,
+    pub rules: Vec<AclRule>,
+}
+
+impl FromStr for Acl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scope, rules) = s
+            .split_once('=')
+            .ok_or(format!("Invalid access control list: {}", s))?;
+        let scope = if scope == "*" {
+            AclScope::Global
+        } else if let Some(host) = scope.strip_prefix("host:") {
+            AclScope::Host(host.to_ascii_lowercase())
+        } else if scope.starts_with('/') {
+            AclScope::PathPrefix(String::from(scope))
+        } else {
+            return Err(format!("Invalid access control scope: {}", scope));
+        };
+        Ok(Self {
+            scope,
+            rules: rules
+                .split(',')
+                .map(|rule| rule.parse())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Acl {
+    fn applies_to(&self, host: Option<&str>, path: &str) -> bool {
+        match &self.scope {
+            AclScope::Global => true,
+            AclScope::Host(name) => host.is_some_and(|host| utils::matches_host(host, name)),
+            AclScope::PathPrefix(prefix) => utils::matches_path_prefix(path, prefix),
+        }
+    }
+
+    fn allows(&self, client_ip: Option<net::IpAddr>) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(client_ip))
+            .is_none_or(|rule| rule.allow)
+    }
+}
+
+/// Check whether a client is allowed to make a request, i.e. by all the access control lists
+/// applying to it.
+///
+/// The path must be normalized (as done when parsing requests), so that it cannot escape a path
+/// prefix with dot segments or repeated slashes.
+pub(crate) fn is_allowed(
+    acls: &[Acl],
+    client_ip: Option<net::IpAddr>,
+    host: Option<&str>,
+    path: &str,
+) -> bool {
+    acls.iter()
+        .filter(|acl| acl.applies_to(host, path))
+        .all(|acl| acl.allows(client_ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acl_from_str_test() {
+        assert_eq!(
+            "/admin=allow:10.0.0.0/8,deny:all".parse::<Acl>(),
+            Ok(Acl {
+                scope: AclScope::PathPrefix(String::from("/admin")),
+                rules: vec![
+                    AclRule {
+                        allow: true,
+                        clients: AclClients::Network("10.0.0.0/8".parse().unwrap()),
+                    },
+                    AclRule {
+                        allow: false,
+                        clients: AclClients::All,
+                    },
+                ],
+            })
+        );
+        assert_eq!(
+            "host:Intranet.example.org=allow:all"
+                .parse::<Acl>()
+                .unwrap()
+                .scope,
+            AclScope::Host(String::from("intranet.example.org"))
+        );
+        assert_eq!(
+            "*=deny:2001:db8::/32".parse::<Acl>().unwrap().scope,
+            AclScope::Global
+        );
+        assert!("/admin".parse::<Acl>().is_err());
+        assert!("admin=deny:all".parse::<Acl>().is_err());
+        assert!("/admin=".parse::<Acl>().is_err());
+        assert!("/admin=block:all".parse::<Acl>().is_err());
+        assert!("/admin=deny:10.0.0.0/33".parse::<Acl>().is_err());
+    }
+
+    #[test]
+    fn is_allowed_test() {
+        let acls = [
+            "*=deny:192.0.2.0/24".parse().unwrap(),
+            "/admin=allow:10.0.0.0/8,allow:fd00::/8,deny:all"
+                .parse()
+                .unwrap(),
+            "host:intranet.example.org=deny:10.0.0.66,allow:10.0.0.0/8,deny:all"
+                .parse()
+                .unwrap(),
+        ];
+        let ip = |ip: &str| Some(ip.parse().unwrap());
+
+        assert!(is_allowed(&acls, ip("198.51.100.1"), None, "/"));
+        assert!(!is_allowed(&acls, ip("192.0.2.1"), None, "/"));
+        assert!(is_allowed(&acls, None, None, "/"));
+
+        // first matching rule
+        assert!(is_allowed(&acls, ip("10.1.2.3"), None, "/admin/users"));
+        assert!(is_allowed(&acls, ip("fd00::1"), None, "/admin"));
+        assert!(is_allowed(&acls, ip("::ffff:10.1.2.3"), None, "/admin"));
+        assert!(!is_allowed(&acls, ip("198.51.100.1"), None, "/admin"));
+        assert!(!is_allowed(&acls, None, None, "/admin"));
+        assert!(is_allowed(
+            &acls,
+            ip("198.51.100.1"),
+            None,
+            "/administration"
+        ));
+
+        // all the lists applying to the request must allow it
+        assert!(is_allowed(
+            &acls,
+            ip("10.0.0.1"),
+            Some("Intranet.example.org:8080"),
+            "/admin"
+        ));
+        assert!(!is_allowed(
+            &acls,
+            ip("10.0.0.66"),
+            Some("intranet.example.org"),
+            "/admin"
+        ));
+        assert!(!is_allowed(
+            &acls,
+            ip("198.51.100.1"),
+            Some("intranet.example.org"),
+            "/"
+        ));
+        assert!(is_allowed(
+            &acls,
+            ip("198.51.100.1"),
+            Some("example.org"),
+            "/"
+        ));
+    }
+}
@@ -0,0 +1,893 @@
+//! Access log of the requests served, in the Common, Combined or JSON-lines formats (or in a
+//! custom one, defined by a template), written to the standard output, to a file (rotated by size
+//! or time) or to syslog, possibly per virtual host.
+//!
+//! The entries are formatted by the connections, and written by a thread of each log, so that
+//! serving requests never waits for the disk (entries are dropped, and counted, if a log cannot
+//! keep up).
+
+use crate::http_req::{HttpReq, ReqPath, ReqTarget};
+use crate::tls::TlsSession;
+use crate::utils;
+
+use log::{info, warn};
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic;
+use std::{ffi, fs, io, path, process, str::FromStr, sync, thread, time};
+
+/// Template of the Common Log Format.
+const COMMON: &str = r#"%h %l %u %t "%r" %>s %b"#;
+/// Template of the Combined Log Format, i.e. the Common one with the referer and the user agent.
+const COMBINED: &str = r#"%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-Agent}i""#;
+/// Socket of the local syslog daemon, used by default.
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+/// Syslog priority of the entries: local0 facility, informational severity.
+const SYSLOG_PRIORITY: u8 = 16 * 8 + 6;
+/// Maximum number of entries waiting to be written to an access log, the next ones being dropped.
+const MAX_PENDING_ENTRIES: usize = 10_000;
+
+static COMMON_TEMPLATE: sync::LazyLock<Template> = sync::LazyLock::new(|| COMMON.parse().unwrap());
+static COMBINED_TEMPLATE: sync::LazyLock<Template> =
+    sync::LazyLock::new(|| COMBINED.parse().unwrap());
+
+/// A part of a log template, the fields being named like the ones of Apache `mod_log_config`.
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    Literal(String),
+    /// `%h`: client IP address (or the one reported by a trusted proxy)
+    RemoteAddr,
+    /// `%l`: remote logname, never known
+    Logname,
+    /// `%u`: authenticated user
+    User,
+    /// `%t`: date the request was received
+    Time,
+    /// `%r`: first line of the request
+    RequestLine,
+    /// `%m`
+    Method,
+    /// `%U`: decoded path, without the query string
+    Path,
+    /// `%q`: query string (with its `?`), if any
+    Query,
+    /// `%H`
+    Protocol,
+    /// `%v`: Host header
+    Host,
+    /// `%s` (or `%>s`)
+    Status,
+    /// `%b`: bytes of the response body sent, `-` for none
+    Bytes,
+    /// `%B`: bytes of the response body sent
+    BytesZero,
+    /// `%D`: time taken to serve the request, in microseconds
+    Micros,
+    /// `%T`: time taken to serve the request, in seconds
+    Seconds,
+    /// `%{NAME}i`: request header
+    Header(String),
+    /// `%{SSL_PROTOCOL}x`
+    TlsProtocol,
+    /// `%{SSL_CIPHER}x`
+    TlsCipher,
+}
+
+/// A custom log format, e.g. `%h %u "%r" %>s %D`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template(Vec<Field>);
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid log template: {}", s);
+        let mut fields = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let field = match chars.next().ok_or_else(invalid)? {
+                '%' => {
+                    literal.push('%');
+                    continue;
+                }
+                'h' => Field::RemoteAddr,
+                'l' => Field::Logname,
+                'u' => Field::User,
+                't' => Field::Time,
+                'r' => Field::RequestLine,
+                'm' => Field::Method,
+                'U' => Field::Path,
+                'q' => Field::Query,
+                'H' => Field::Protocol,
+                'v' => Field::Host,
+                's' => Field::Status,
+                '>' if chars.next() == Some('s') => Field::Status,
+                'b' => Field::Bytes,
+                'B' => Field::BytesZero,
+                'D' => Field::Micros,
+                'T' => Field::Seconds,
+                '{' => {
+                    let name = chars.by_ref().take_while(|c| *c != '}').collect::<String>();
+                    match (name.as_str(), chars.next()) {
+                        ("", _) => return Err(invalid()),
+                        (_, Some('i')) => Field::Header(name),
+                        ("SSL_PROTOCOL", Some('x')) => Field::TlsProtocol,
+                        ("SSL_CIPHER", Some('x')) => Field::TlsCipher,
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => return Err(invalid()),
+            };
+            if !literal.is_empty() {
+                fields.push(Field::Literal(literal.clone()));
+                literal.clear();
+            }
+            fields.push(field);
+        }
+        if !literal.is_empty() {
+            fields.push(Field::Literal(literal));
+        }
+        Ok(Self(fields))
+    }
+}
+
+impl Template {
+    fn format(&self, entry: &LogEntry) -> String {
+        let req = entry.req;
+        let or_dash = |value: Option<&str>| utils::escape(value.unwrap_or("-"));
+        let mut line = String::new();
+        for field in &self.0 {
+            match field {
+                Field::Literal(literal) => line.push_str(literal),
+                Field::RemoteAddr => line.push_str(&utils::escape(entry.remote_addr)),
+                Field::Logname => line.push('-'),
+                Field::User => line.push_str(&or_dash(req.and_then(HttpReq::user))),
+                Field::Time => {
+                    line.push_str(&entry.date.format("[%d/%b/%Y:%H:%M:%S %z]").to_string())
+                }
+                Field::RequestLine => {
+                    line.push_str(&or_dash(req.map(HttpReq::first_line).as_deref()))
+                }
+                Field::Method => {
+                    line.push_str(&or_dash(req.map(|req| req.verb().to_string()).as_deref()))
+                }
+                Field::Path => line.push_str(&or_dash(req.map(|req| match req.target() {
+                    ReqTarget::Path(ReqPath { decoded, .. })
+                    | ReqTarget::Absolute(_, ReqPath { decoded, .. }) => decoded.as_str(),
+                    ReqTarget::All => "*",
+                    ReqTarget::Authority(authority) => authority.as_str(),
+                }))),
+                Field::Query => {
+                    if let Some(query) = req.and_then(query).filter(|query| !query.is_empty()) {
+                        line.push('?');
+                        line.push_str(&utils::escape(query));
+                    }
+                }
+                Field::Protocol => line.push_str(&or_dash(req.map(HttpReq::version))),
+                Field::Host => line.push_str(&or_dash(req.and_then(HttpReq::host))),
+                Field::Status => line.push_str(&entry.status.to_string()),
+                Field::Bytes if entry.bytes == 0 => line.push('-'),
+                Field::Bytes | Field::BytesZero => line.push_str(&entry.bytes.to_string()),
+                Field::Micros => line.push_str(&entry.latency.as_micros().to_string()),
+                Field::Seconds => line.push_str(&entry.latency.as_secs().to_string()),
+                Field::Header(name) => line.push_str(&or_dash(
+                    req.and_then(|req| req.header_by_name(name)).as_deref(),
+                )),
+                Field::TlsProtocol => line.push_str(entry.tls.map_or("-", |tls| tls.protocol)),
+                Field::TlsCipher => {
+                    line.push_str(entry.tls.and_then(|tls| tls.cipher).unwrap_or("-"))
+                }
+            }
+        }
+        line
+    }
+}
+
+/// Format of the entries of an access log.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogFormat {
+    /// Common Log Format
+    Common,
+    /// Combined Log Format
+    Combined,
+    /// One JSON object per line
+    Json,
+    Template(Template),
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "common" => Ok(Self::Common),
+            "combined" => Ok(Self::Combined),
+            "json" => Ok(Self::Json),
+            _ if s.contains('%') => Ok(Self::Template(s.parse()?)),
+            _ => Err(format!(
+                "Invalid log format (expected common, combined, json or a template): {}",
+                s
+            )),
+        }
+    }
+}
+
+impl LogFormat {
+    /// Format the entry of a request, as a line.
+    pub(crate) fn format(&self, entry: &LogEntry) -> String {
+        match self {
+            Self::Common => COMMON_TEMPLATE.format(entry),
+            Self::Combined => COMBINED_TEMPLATE.format(entry),
+            Self::Template(template) => template.format(entry),
+            Self::Json => {
+                let req = entry.req;
+                serde_json::json!({
+                    "time": entry.date.to_rfc3339(),
+                    "remote_addr": entry.remote_addr,
+                    "user": req.and_then(HttpReq::user),
+                    "method": req.map(|req| req.verb().to_string()),
+                    "target": req.map(|req| req.target().to_string()),
+                    "protocol": req.map(HttpReq::version),
+                    "host": req.and_then(HttpReq::host),
+                    "status": entry.status,
+                    "bytes": entry.bytes,
+                    "duration_us": entry.latency.as_micros() as u64,
+                    "referer": req.and_then(|req| req.header_by_name("Referer")),
+                    "user_agent": req.and_then(|req| req.header_by_name("User-Agent")),
+                    "tls_protocol": entry.tls.map(|tls| tls.protocol),
+                    "tls_cipher": entry.tls.and_then(|tls| tls.cipher),
+                })
+                .to_string()
+            }
+        }
+    }
+}
+
+/// Query string of the target of a request.
+fn query(req: &HttpReq) -> Option<&str> {
+    match req.target() {
+        ReqTarget::Path(ReqPath { query, .. }) | ReqTarget::Absolute(_, ReqPath { query, .. }) => {
+            Some(query.as_str())
+        }
+        _ => None,
+    }
+}
+
+/// Period after which a log file is rotated, whatever its size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RotationPeriod {
+    Hourly,
+    Daily,
+}
+
+impl FromStr for RotationPeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => Ok(Self::Hourly),
+            "daily" => Ok(Self::Daily),
+            _ => Err(format!(
+                "Invalid rotation period (expected hourly or daily): {}",
+                s
+            )),
+        }
+    }
+}
+
+impl RotationPeriod {
+    /// Number of the period (hour or day, in UTC) of a date, since the UNIX epoch.
+    fn of(&self, date: time::SystemTime) -> u64 {
+        let seconds = date
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match self {
+            Self::Hourly => seconds / 3600,
+            Self::Daily => seconds / 86400,
+        }
+    }
+}
+
+/// Where the entries of an access log are written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogOutput {
+    Stdout,
+    File(path::PathBuf),
+    /// Unix domain socket of a syslog daemon
+    Syslog(path::PathBuf),
+}
+
+/// An access log.
+///
+/// Its string format is
+/// `OUTPUT[|format=FORMAT][|max-size=SIZE][|rotate=PERIOD][|keep=N][|host=NAME]`, where `OUTPUT`
+/// is `stdout`, `syslog[:SOCKET]` or a file path, `FORMAT` is `common`, `combined` (the default),
+/// `json` or a template, `SIZE` is a number of bytes (with a `K`, `M` or `G` suffix) and `PERIOD`
+/// is `hourly` or `daily` (e.g. `/var/log/access.log|format=json|max-size=100M|keep=10`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLog {
+    pub output: LogOutput,
+    pub format: LogFormat,
+    /// Size over which a log file is rotated
+    pub max_size: Option<u64>,
+    /// Period after which a log file is rotated
+    pub rotate: Option<RotationPeriod>,
+    /// Number of rotated files kept (e.g. `access.log.1` to `access.log.5`)
+    pub keep: usize,
+    /// Virtual host (Host header) whose requests are logged, if any: otherwise, the log gets the
+    /// requests of the hosts without a log of their own
+    pub host: Option<String>,
+}
+
+impl FromStr for AccessLog {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('|');
+        let output = match parts.next().unwrap_or_default() {
+            "" => return Err(format!("Invalid access log: {}", s)),
+            "stdout" => LogOutput::Stdout,
+            "syslog" => LogOutput::Syslog(path::PathBuf::from(DEFAULT_SYSLOG_SOCKET)),
+            output => match output.strip_prefix("syslog:") {
+                Some(socket) => LogOutput::Syslog(path::PathBuf::from(socket)),
+                None => LogOutput::File(path::PathBuf::from(output)),
+            },
+        };
+        let mut log = Self {
+            output,
+            format: LogFormat::Combined,
+            max_size: None,
+            rotate: None,
+            keep: 5,
+            host: None,
+        };
+        for option in parts {
+            match option.split_once('=') {
+                Some(("format", format)) => log.format = format.parse()?,
+                Some(("max-size", size)) => log.max_size = Some(parse_size(size)?),
+                Some(("rotate", period)) => log.rotate = Some(period.parse()?),
+                Some(("keep", keep)) => {
+                    log.keep = match keep.parse() {
+                        Ok(keep) if keep > 0 => keep,
+                        _ => return Err(format!("Invalid number of rotated logs: {}", keep)),
+                    }
+                }
+                Some(("host", host)) if !host.is_empty() => log.host = Some(String::from(host)),
+                _ => return Err(format!("Invalid access log option: {}", option)),
+            }
+        }
+        if (log.max_size.is_some() || log.rotate.is_some())
+            && !matches!(log.output, LogOutput::File(_))
+        {
+            return Err(format!("Only log files can be rotated: {}", s));
+        }
+        Ok(log)
+    }
+}
+
+/// Parse a size in bytes, possibly with a `K`, `M` or `G` suffix (powers of 1024).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (number, unit) = match s.char_indices().last() {
+        Some((i, 'K')) => (&s[..i], 1 << 10),
+        Some((i, 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(number) if number > 0 => number
+            .checked_mul(unit)
+            .ok_or_else(|| format!("Invalid size: {}", s)),
+        _ => Err(format!("Invalid size: {}", s)),
+    }
+}
+
+/// What is logged about a request, once its response is sent.
+pub(crate) struct LogEntry<'a> {
+    /// Address of the client (or the one reported by a trusted proxy)
+    pub(crate) remote_addr: &'a str,
+    /// Request, if it could be parsed
+    pub(crate) req: Option<&'a HttpReq>,
+    /// Date the request was received
+    pub(crate) date: chrono::DateTime<chrono::Utc>,
+    pub(crate) status: u16,
+    /// Bytes of the response body sent
+    pub(crate) bytes: u64,
+    /// Time taken to serve the request
+    pub(crate) latency: time::Duration,
+    /// TLS session of the connection, if any
+    pub(crate) tls: Option<&'a TlsSession>,
+}
+
+/// A log file, rotated when it gets too big or too old.
+struct LogFile {
+    path: path::PathBuf,
+    file: fs::File,
+    size: u64,
+    max_size: Option<u64>,
+    rotate: Option<RotationPeriod>,
+    keep: usize,
+    /// Period of the entries of the file, if it is rotated periodically
+    period: Option<u64>,
+}
+
+impl LogFile {
+    fn open(
+        path: &path::Path,
+        max_size: Option<u64>,
+        rotate: Option<RotationPeriod>,
+        keep: usize,
+    ) -> io::Result<Self> {
+        let file = Self::open_file(path)?;
+        let metadata = file.metadata()?;
+        // an existing file is rotated once its period is over, even if the server was stopped
+        let modified = match metadata.len() {
+            0 => time::SystemTime::now(),
+            _ => metadata.modified()?,
+        };
+        Ok(Self {
+            path: path::PathBuf::from(path),
+            file,
+            size: metadata.len(),
+            max_size,
+            rotate,
+            keep,
+            period: rotate.map(|period| period.of(modified)),
+        })
+    }
+
+    fn open_file(path: &path::Path) -> io::Result<fs::File> {
+        fs::OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        let line = format!("{}\n", line);
+        let period = self.rotate.map(|period| period.of(time::SystemTime::now()));
+        let is_full = self
+            .max_size
+            .is_some_and(|max_size| self.size > 0 && self.size + line.len() as u64 > max_size);
+        if period != self.period || is_full {
+            self.rotate()?;
+            self.period = period;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Rotate the file: `access.log` is renamed to `access.log.1`, `access.log.1` to
+    /// `access.log.2`, and so on, the oldest file being removed.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut path = ffi::OsString::from(self.path.as_os_str());
+            path.push(format!(".{}", n));
+            path::PathBuf::from(path)
+        };
+        for n in (1..self.keep).rev() {
+            if rotated(n).exists() {
+                fs::rename(rotated(n), rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(1))?;
+        self.file = Self::open_file(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Writer of the entries of an access log.
+enum Writer {
+    Stdout,
+    File(LogFile),
+    /// Socket connected to a syslog daemon, and its path
+    Syslog(UnixDatagram, path::PathBuf),
+}
+
+impl Writer {
+    fn open(log: &AccessLog) -> io::Result<Self> {
+        let with_path = |path: &path::Path, err: io::Error| {
+            io::Error::new(
+                err.kind(),
+                format!("cannot open access log {}: {}", path.display(), err),
+            )
+        };
+        match &log.output {
+            LogOutput::Stdout => Ok(Self::Stdout),
+            LogOutput::File(path) => LogFile::open(path, log.max_size, log.rotate, log.keep)
+                .map(Self::File)
+                .map_err(|err| with_path(path, err)),
+            LogOutput::Syslog(path) => Self::connect_syslog(path)
+                .map(|socket| Self::Syslog(socket, path.clone()))
+                .map_err(|err| with_path(path, err)),
+        }
+    }
+
+    fn connect_syslog(path: &path::Path) -> io::Result<UnixDatagram> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(socket)
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Self::Stdout => writeln!(io::stdout().lock(), "{}", line),
+            Self::File(file) => file.write(line),
+            Self::Syslog(socket, path) => {
+                let message = format!(
+                    "<{}>rust-http-server[{}]: {}",
+                    SYSLOG_PRIORITY,
+                    process::id(),
+                    line
+                );
+                if socket.send(message.as_bytes()).is_err() {
+                    // the daemon may have been restarted
+                    *socket = Self::connect_syslog(path)?;
+                    socket.send(message.as_bytes())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An access log, whose entries are sent to its writing thread.
+struct Target {
+    host: Option<String>,
+    format: LogFormat,
+    sender: sync::mpsc::SyncSender<String>,
+    /// Entries dropped since the last one written, as the writing thread could not keep up
+    dropped: sync::Arc<atomic::AtomicU64>,
+}
+
+/// Logger of the requests served, to the access logs. Without any, the requests are logged in the
+/// Common Log Format with the other messages of the server.
+pub(crate) struct AccessLogger {
+    targets: Vec<Target>,
+}
+
+impl AccessLogger {
+    /// Open the access logs, and start their writing threads (which stop with the logger).
+    pub(crate) fn new(logs: &[AccessLog]) -> io::Result<Self> {
+        let mut targets = Vec::new();
+        for log in logs {
+            let mut writer = Writer::open(log)?;
+            let (sender, receiver) = sync::mpsc::sync_channel::<String>(MAX_PENDING_ENTRIES);
+            let dropped = sync::Arc::new(atomic::AtomicU64::new(0));
+            let dropped_entries = dropped.clone();
+            thread::Builder::new()
+                .name(String::from("access-log"))
+                .spawn(move || {
+                    for line in receiver {
+                        if let Err(err) = writer.write(&line) {
+                            warn!("Cannot write access log: {}", err);
+                        }
+                        let dropped = dropped_entries.swap(0, atomic::Ordering::Relaxed);
+                        if dropped > 0 {
+                            warn!(
+                                "{} access log entries dropped, the log is too slow",
+                                dropped
+                            );
+                        }
+                    }
+                })?;
+            targets.push(Target {
+                host: log.host.clone(),
+                format: log.format.clone(),
+                sender,
+                dropped,
+            });
+        }
+        Ok(Self { targets })
+    }
+
+    /// Log a request in the access logs of its virtual host, or in the other ones.
+    pub(crate) fn log(&self, entry: &LogEntry) {
+        if self.targets.is_empty() {
+            info!("{}", LogFormat::Common.format(entry));
+            return;
+        }
+        for target in self.targets(entry.req.and_then(HttpReq::host)) {
+            // the writing thread only stops with the logger, so sending fails only when its queue
+            // is full: serving requests does not wait for it
+            if target.sender.try_send(target.format.format(entry)).is_err() {
+                target.dropped.fetch_add(1, atomic::Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Access logs of a virtual host, if it has some: the ones of no host otherwise.
+    fn targets(&self, host: Option<&str>) -> impl Iterator<Item = &Target> {
+        let matches = move |target: &Target| match (target.host.as_deref(), host) {
+            (Some(name), Some(host)) => utils::matches_host(host, name),
+            _ => false,
+        };
+        let has_own_logs = self.targets.iter().any(matches);
+        self.targets
+            .iter()
+            .filter(move |target| match has_own_logs {
+                true => matches(target),
+                false => target.host.is_none(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_req::{
+        HeaderValue, ReqHead, ReqHeader, ReqOnlyHeader, ReqVerb, SimpleHeaderValue,
+    };
+    use std::collections;
+
+    fn build_req() -> HttpReq {
+        let req_head = ReqHead::new(
+            ReqVerb::Get,
+            ReqTarget::Path(ReqPath {
+                original: String::from("/private/my%20file.html?a=b"),
+                decoded: String::from("/private/my file.html"),
+                query: String::from("a=b"),
+            }),
+            String::from("HTTP/1.1"),
+            collections::HashMap::from([
+                (
+                    ReqHeader::ReqOnly(ReqOnlyHeader::Host),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from("example.org"))),
+                ),
+                (
+                    ReqHeader::ReqOnly(ReqOnlyHeader::Referer),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from(
+                        "https://example.org/",
+                    ))),
+                ),
+                (
+                    ReqHeader::ReqOnly(ReqOnlyHeader::UserAgent),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from(
+                        "curl/8.0 \"quoted\"",
+                    ))),
+                ),
+            ]),
+            None,
+            None,
+        );
+        let date = chrono::DateTime::parse_from_rfc3339("2025-10-10T13:55:36Z")
+            .unwrap()
+            .to_utc();
+        let mut req = HttpReq::new(date, req_head, None);
+        req.set_user(Some(String::from("alice")));
+        req
+    }
+
+    fn build_entry(req: Option<&HttpReq>) -> LogEntry<'_> {
+        LogEntry {
+            remote_addr: "127.0.0.1",
+            req,
+            date: req.map_or_else(chrono::Utc::now, |req| *req.date()),
+            status: 200,
+            bytes: 2326,
+            latency: time::Duration::from_micros(1500),
+            tls: Some(&TlsSession {
+                protocol: "TLSv1.3",
+                cipher: Some("TLS13_AES_256_GCM_SHA384"),
+            }),
+        }
+    }
+
+    #[test]
+    fn template_from_str_test() {
+        assert_eq!(
+            r#"%h "%r" %>s %{X-Request-Id}i 100%%"#.parse(),
+            Ok(Template(vec![
+                Field::RemoteAddr,
+                Field::Literal(String::from(" \"")),
+                Field::RequestLine,
+                Field::Literal(String::from("\" ")),
+                Field::Status,
+                Field::Literal(String::from(" ")),
+                Field::Header(String::from("X-Request-Id")),
+                Field::Literal(String::from(" 100%")),
+            ]))
+        );
+        assert_eq!(
+            "%{SSL_PROTOCOL}x %{SSL_CIPHER}x".parse(),
+            Ok(Template(vec![
+                Field::TlsProtocol,
+                Field::Literal(String::from(" ")),
+                Field::TlsCipher,
+            ]))
+        );
+        assert!("%".parse::<Template>().is_err());
+        assert!("%z".parse::<Template>().is_err());
+        assert!("%>b".parse::<Template>().is_err());
+        assert!("%{Referer".parse::<Template>().is_err());
+        assert!("%{}i".parse::<Template>().is_err());
+        assert!("%{SSL_VERSION}x".parse::<Template>().is_err());
+    }
+
+    #[test]
+    fn log_format_test() {
+        let req = build_req();
+        let entry = build_entry(Some(&req));
+        assert_eq!(
+            LogFormat::Common.format(&entry),
+            r#"127.0.0.1 - alice [10/Oct/2025:13:55:36 +0000] "GET /private/my%20file.html?a=b HTTP/1.1" 200 2326"#
+        );
+        assert_eq!(
+            LogFormat::Combined.format(&entry),
+            r#"127.0.0.1 - alice [10/Oct/2025:13:55:36 +0000] "GET /private/my%20file.html?a=b HTTP/1.1" 200 2326 "https://example.org/" "curl/8.0 \"quoted\"""#
+        );
+        let template: LogFormat =
+            "%v %m %U%q %H %D %T %B %{SSL_PROTOCOL}x %{SSL_CIPHER}x %{Cookie}i"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            template.format(&entry),
+            "example.org GET /private/my file.html?a=b HTTP/1.1 1500 0 2326 TLSv1.3 TLS13_AES_256_GCM_SHA384 -"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&LogFormat::Json.format(&entry)).unwrap();
+        assert_eq!(json["time"], "2025-10-10T13:55:36+00:00");
+        assert_eq!(json["user"], "alice");
+        assert_eq!(json["target"], "/private/my%20file.html?a=b");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["duration_us"], 1500);
+        assert_eq!(json["user_agent"], "curl/8.0 \"quoted\"");
+        assert_eq!(json["tls_protocol"], "TLSv1.3");
+
+        // requests which could not be parsed
+        let mut entry = build_entry(None);
+        entry.bytes = 0;
+        entry.tls = None;
+        assert!(
+            LogFormat::Combined
+                .format(&entry)
+                .ends_with(r#"] "-" 200 - "-" "-""#)
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&LogFormat::Json.format(&entry)).unwrap();
+        assert_eq!(json["user"], serde_json::Value::Null);
+        assert_eq!(json["tls_cipher"], serde_json::Value::Null);
+
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn access_log_from_str_test() {
+        assert_eq!(
+            "stdout".parse(),
+            Ok(AccessLog {
+                output: LogOutput::Stdout,
+                format: LogFormat::Combined,
+                max_size: None,
+                rotate: None,
+                keep: 5,
+                host: None,
+            })
+        );
+        assert_eq!(
+            "/var/log/access.log|format=json|max-size=100M|rotate=daily|keep=10|host=example.org"
+                .parse(),
+            Ok(AccessLog {
+                output: LogOutput::File(path::PathBuf::from("/var/log/access.log")),
+                format: LogFormat::Json,
+                max_size: Some(100 * 1024 * 1024),
+                rotate: Some(RotationPeriod::Daily),
+                keep: 10,
+                host: Some(String::from("example.org")),
+            })
+        );
+        assert_eq!(
+            "syslog|format=common"
+                .parse::<AccessLog>()
+                .map(|log| log.output),
+            Ok(LogOutput::Syslog(path::PathBuf::from("/dev/log")))
+        );
+        assert_eq!(
+            "syslog:/run/syslog.sock|format=%h %r"
+                .parse::<AccessLog>()
+                .map(|log| log.output),
+            Ok(LogOutput::Syslog(path::PathBuf::from("/run/syslog.sock")))
+        );
+        assert!("".parse::<AccessLog>().is_err());
+        assert!("stdout|max-size=1M".parse::<AccessLog>().is_err());
+        assert!("access.log|max-size=0".parse::<AccessLog>().is_err());
+        assert!("access.log|max-size=1T".parse::<AccessLog>().is_err());
+        assert!("access.log|rotate=weekly".parse::<AccessLog>().is_err());
+        assert!("access.log|keep=0".parse::<AccessLog>().is_err());
+        assert!("access.log|host=".parse::<AccessLog>().is_err());
+        assert!("access.log|level=info".parse::<AccessLog>().is_err());
+    }
+
+    #[test]
+    fn log_file_test() {
+        let dir = std::env::temp_dir().join(format!("access-log-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let rotated = |n: usize| dir.join(format!("access.log.{}", n));
+
+        // rotated by size
+        let mut file = LogFile::open(&path, Some(10), None, 2).unwrap();
+        for line in ["first", "second", "third", "fourth"] {
+            file.write(line).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(rotated(2)).unwrap(), "second\n");
+        assert!(!rotated(3).exists());
+
+        // and by time, an old file being rotated right away
+        let mut file = LogFile::open(&path, None, Some(RotationPeriod::Hourly), 2).unwrap();
+        file.period = file.period.map(|period| period - 1);
+        file.write("fifth").unwrap();
+        file.write("sixth").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fifth\nsixth\n");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "fourth\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn syslog_test() {
+        let path = std::env::temp_dir().join(format!("access-log-syslog-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let log: AccessLog = format!("syslog:{}|format=%h %s", path.display())
+            .parse()
+            .unwrap();
+        assert!(Writer::open(&log).is_err());
+
+        let daemon = UnixDatagram::bind(&path).unwrap();
+        let mut writer = Writer::open(&log).unwrap();
+        writer.write("127.0.0.1 200").unwrap();
+        let mut buf = [0; 128];
+        let len = daemon.recv(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..len]),
+            format!("<134>rust-http-server[{}]: 127.0.0.1 200", process::id())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn access_logger_test() {
+        let logs: Vec<AccessLog> = [
+            "stdout",
+            "stdout|host=example.org",
+            "stdout|host=example.com",
+        ]
+        .iter()
+        .map(|log| log.parse().unwrap())
+        .collect();
+        let logger = AccessLogger::new(&logs).unwrap();
+        let hosts = |host| {
+            logger
+                .targets(host)
+                .map(|target| target.host.as_deref())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hosts(Some("example.org:8080")), [Some("example.org")]);
+        assert_eq!(hosts(Some("EXAMPLE.COM")), [Some("example.com")]);
+        assert_eq!(hosts(Some("example.net")), [None]);
+        assert_eq!(hosts(None), [None]);
+
+        // entries are dropped (and counted) while the queue of a log is full
+        let (sender, receiver) = sync::mpsc::sync_channel(MAX_PENDING_ENTRIES);
+        let logger = AccessLogger {
+            targets: vec![Target {
+                host: None,
+                format: LogFormat::Common,
+                sender,
+                dropped: sync::Arc::new(atomic::AtomicU64::new(0)),
+            }],
+        };
+        let entry = build_entry(None);
+        for _ in 0..MAX_PENDING_ENTRIES + 2 {
+            logger.log(&entry);
+        }
+        assert_eq!(logger.targets[0].dropped.load(atomic::Ordering::Relaxed), 2);
+        assert_eq!(receiver.try_iter().count(), MAX_PENDING_ENTRIES);
+    }
+}
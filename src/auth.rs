@@ -0,0 +1,1067 @@
+//! Authentication realms: locations (path prefixes) protected with their own realm name, users and
+//! authorization rules, or left public.
+
+use crate::bearer::{BearerVerifier, Claims};
+use crate::credentials::{self, CredentialFile, CredentialStore, Htgroup};
+use crate::digest::{self, DigestCredentials, NonceUse, Nonces};
+use crate::http_header::ReqHeader;
+use crate::http_req::{ReqHead, ReqVerb};
+use crate::session::{self, LoginSettings, Sessions};
+use crate::utils;
+
+use log::{info, warn};
+use std::{cmp, fmt, path, str::FromStr};
+
+/// Name of the realm protecting the paths outside the locations, when credentials are configured.
+pub const DEFAULT_REALM: &str = "simple";
+
+/// HTTP authentication scheme.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthScheme {
+    /// The password is sent with each request (base64-encoded)
+    Basic,
+    /// Only a digest of the password (and of a nonce given by the server) is sent, with SHA-256 or
+    /// MD5 (RFC 7616)
+    Digest,
+    /// A static token or a JSON Web Token is sent with each request (RFC 6750)
+    Bearer,
+    /// The session cookie of the login form is sent with each request (it is not offered as a
+    /// challenge, but accepted instead of the password schemes)
+    Form,
+}
+
+impl FromStr for AuthScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "basic" => Ok(Self::Basic),
+            "digest" => Ok(Self::Digest),
+            "bearer" => Ok(Self::Bearer),
+            _ => Err(format!("Invalid authentication scheme: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AuthScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Basic => f.write_str("Basic"),
+            Self::Digest => f.write_str("Digest"),
+            Self::Bearer => f.write_str("Bearer"),
+            Self::Form => f.write_str("Form"),
+        }
+    }
+}
+
+/// Credentials given by a client to authenticate.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Credentials<'a> {
+    /// Username and password
+    Basic(&'a str, &'a str),
+    /// Digest credentials, with the method and the target of the request they were computed for
+    /// (as sent by the client)
+    Digest(&'a DigestCredentials, &'a ReqVerb, &'a str),
+    /// Static token or JSON Web Token
+    Bearer(&'a str),
+    /// Session cookie
+    Session(&'a str),
+}
+
+impl<'a> Credentials<'a> {
+    /// Credentials of the Authorization header of a request, if any.
+    pub(crate) fn of(head: &'a ReqHead) -> Option<Self> {
+        if let Some((username, password)) = head.auth_creds() {
+            Some(Self::Basic(username, password))
+        } else if let Some(digest) = head.digest_creds() {
+            Some(Self::Digest(digest, head.verb(), head.raw_target()))
+        } else if let Some(token) = head.bearer_token() {
+            Some(Self::Bearer(token))
+        } else {
+            head.header_str(&ReqHeader::Other(String::from("cookie")))
+                .and_then(session::cookie_value)
+                .map(Self::Session)
+        }
+    }
+
+    pub(crate) fn scheme(&self) -> AuthScheme {
+        match self {
+            Self::Basic(..) => AuthScheme::Basic,
+            Self::Digest(..) => AuthScheme::Digest,
+            Self::Bearer(_) => AuthScheme::Bearer,
+            Self::Session(_) => AuthScheme::Form,
+        }
+    }
+}
+
+/// A user authenticated in a realm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub scheme: AuthScheme,
+    /// Name of the user, or of the client a static token identifies, or subject (`sub` claim) of a
+    /// JSON Web Token
+    pub username: String,
+    /// Verified claims of the JSON Web Token the user authenticated with, if any
+    pub claims: Option<Claims>,
+}
+
+/// Rule granting access to some of the authenticated users.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Requirement {
+    /// `user:NAME`
+    User(String),
+    /// `group:NAME`, the groups being read from the group file
+    Group(String),
+    /// `claim:NAME=VALUE`, the claim of a JSON Web Token having the value (or one of its values)
+    Claim(String, String),
+}
+
+impl FromStr for Requirement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().split_once(':') {
+            Some(("user", name)) if !name.is_empty() => Ok(Self::User(String::from(name))),
+            Some(("group", name)) if !name.is_empty() => Ok(Self::Group(String::from(name))),
+            Some(("claim", claim)) => match claim.split_once('=') {
+                Some((name, value)) if !name.is_empty() && !value.is_empty() => {
+                    Ok(Self::Claim(String::from(name), String::from(value)))
+                }
+                _ => Err(format!("Invalid claim requirement: {}", s)),
+            },
+            _ => Err(format!("Invalid authorization requirement: {}", s)),
+        }
+    }
+}
+
+/// Protection of a location.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Protection {
+    /// No authentication is required
+    Public,
+    /// Users must authenticate in a realm
+    Realm {
+        name: String,
+        /// htpasswd file of the users of the realm, instead of the credentials of the server
+        file: Option<path::PathBuf>,
+        /// Rules of which (at least) one must grant access to the user, if any (otherwise all the
+        /// authenticated users are granted access)
+        requirements: Vec<Requirement>,
+        /// Schemes offered to authenticate, instead of the ones of the server (if not empty)
+        schemes: Vec<AuthScheme>,
+    },
+}
+
+/// Protection of the paths starting with a prefix. The location with the longest matching prefix
+/// applies to a request.
+///
+/// Its string format is `PREFIX=public` or
+/// `PREFIX=NAME[|file=PATH][|require=RULE[,RULE...]][|schemes=SCHEME[,SCHEME...]]`, where `RULE`
+/// is `user:NAME`, `group:NAME` or `claim:NAME=VALUE` (e.g.
+/// `/admin=Administration|require=group:admins` or `/api=API|schemes=bearer|require=claim:scope=write`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path_prefix: String,
+    pub protection: Protection,
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('|');
+        let (path_prefix, name) = match parts.next().and_then(|p| p.split_once('=')) {
+            Some((path_prefix, name)) if path_prefix.starts_with('/') => (path_prefix, name),
+            _ => return Err(format!("Invalid authentication location: {}", s)),
+        };
+        let path_prefix = String::from(path_prefix);
+        if name == "public" {
+            return match parts.next() {
+                Some(option) => Err(format!("Invalid option for a public location: {}", option)),
+                None => Ok(Self {
+                    path_prefix,
+                    protection: Protection::Public,
+                }),
+            };
+        }
+        // the name is quoted in the WWW-Authenticate header
+        if name.is_empty() || name.contains(['"', '\\']) {
+            return Err(format!("Invalid realm name: {}", name));
+        }
+
+        let mut file = None;
+        let mut requirements = Vec::new();
+        let mut schemes = Vec::new();
+        for option in parts {
+            match option.split_once('=') {
+                Some(("file", path)) if !path.is_empty() => file = Some(path::PathBuf::from(path)),
+                Some(("require", rules)) => {
+                    for rule in rules.split(',') {
+                        requirements.push(rule.parse()?);
+                    }
+                }
+                Some(("schemes", names)) => {
+                    for name in names.split(',') {
+                        schemes.push(name.parse()?);
+                    }
+                }
+                _ => return Err(format!("Invalid realm option: {}", option)),
+            }
+        }
+        Ok(Self {
+            path_prefix,
+            protection: Protection::Realm {
+                name: String::from(name),
+                file,
+                requirements,
+                schemes,
+            },
+        })
+    }
+}
+
+/// A realm, whose users authenticate with its own credentials or with the ones of the server.
+pub(crate) struct Realm {
+    name: String,
+    credentials: Option<CredentialStore>,
+    requirements: Vec<Requirement>,
+    /// Schemes offered to authenticate, in order of preference
+    schemes: Vec<AuthScheme>,
+}
+
+impl Realm {
+    /// Check whether the sessions of the login form are accepted, i.e. whether the realm
+    /// authenticates the users of the server with their password.
+    pub(crate) fn accepts_sessions(&self) -> bool {
+        self.credentials.is_none()
+            && self
+                .schemes
+                .iter()
+                .any(|scheme| matches!(scheme, AuthScheme::Basic | AuthScheme::Digest))
+    }
+
+    fn accepts(&self, scheme: AuthScheme) -> bool {
+        match scheme {
+            AuthScheme::Form => self.accepts_sessions(),
+            _ => self.schemes.contains(&scheme),
+        }
+    }
+}
+
+/// Outcome of the authentication of a request.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Access {
+    /// No authentication is required
+    Public,
+    /// The credentials are valid, and the user is granted access
+    Granted(Identity),
+    /// The credentials are missing or invalid
+    Unauthenticated,
+    /// The digest credentials are valid, but their nonce expired: the client may retry with a new
+    /// one
+    Stale,
+    /// The credentials are valid, but the user is not granted access
+    Forbidden,
+}
+
+impl Access {
+    pub(crate) fn is_granted(&self) -> bool {
+        matches!(self, Self::Public | Self::Granted(_))
+    }
+}
+
+/// Authentication and authorization of the requests, depending on their path.
+pub(crate) struct Authenticator {
+    /// Credentials of the server, if any
+    credentials: Option<CredentialStore>,
+    /// Realm protecting the paths outside the locations, if the server has credentials
+    default_realm: Option<Realm>,
+    /// Same realm, protecting the forward proxy (with the Basic scheme only)
+    proxy_realm: Option<Realm>,
+    /// Locations, by decreasing length of their prefix (public ones have no realm)
+    locations: Vec<(String, Option<Realm>)>,
+    groups: Option<CredentialFile<Htgroup>>,
+    /// Nonces issued for the Digest scheme
+    nonces: Nonces,
+    /// Static tokens and keys of the JSON Web Tokens, for the Bearer scheme
+    bearer: Option<BearerVerifier>,
+    /// Sessions of the users logged in with the login form, if enabled
+    sessions: Option<Sessions>,
+}
+
+impl Authenticator {
+    pub(crate) fn new(
+        inline: Option<&[(String, String)]>,
+        file: Option<&path::Path>,
+        locations: &[Location],
+        groups_file: Option<&path::Path>,
+        schemes: &[AuthScheme],
+        bearer: Option<BearerVerifier>,
+        login: Option<&LoginSettings>,
+    ) -> Result<Self, credentials::Error> {
+        let credentials = CredentialStore::new(inline, file)?;
+        if login.is_some() && credentials.is_none() {
+            return Err(credentials::Error::Login(
+                "no credentials to authenticate its users",
+            ));
+        }
+        let has_bearer = bearer.is_some();
+        let default_realm = |schemes: &[AuthScheme]| Realm {
+            name: String::from(DEFAULT_REALM),
+            credentials: None,
+            requirements: Vec::new(),
+            schemes: Vec::from(schemes),
+        };
+        let mut realms = Vec::new();
+        for location in locations {
+            let realm = match &location.protection {
+                Protection::Public => None,
+                Protection::Realm {
+                    name,
+                    file,
+                    requirements,
+                    schemes: realm_schemes,
+                } => {
+                    let realm_credentials = CredentialStore::new(None, file.as_deref())?;
+                    let realm_schemes = match realm_schemes.is_empty() {
+                        true => schemes,
+                        false => realm_schemes.as_slice(),
+                    };
+                    // the credentials of the server are used unless the realm has its own
+                    check_schemes(
+                        name,
+                        realm_schemes,
+                        realm_credentials.as_ref().or(credentials.as_ref()),
+                        has_bearer,
+                    )?;
+                    let has_groups = requirements
+                        .iter()
+                        .any(|requirement| matches!(requirement, Requirement::Group(_)));
+                    if has_groups && groups_file.is_none() {
+                        return Err(credentials::Error::Realm(
+                            name.clone(),
+                            "group requirements without a group file",
+                        ));
+                    }
+                    Some(Realm {
+                        name: name.clone(),
+                        credentials: realm_credentials,
+                        requirements: requirements.clone(),
+                        schemes: Vec::from(realm_schemes),
+                    })
+                }
+            };
+            realms.push((location.path_prefix.clone(), realm));
+        }
+        realms.sort_by_key(|(path_prefix, _)| cmp::Reverse(path_prefix.len()));
+        // the whole server is protected by the credentials, or by bearer tokens if offered
+        let has_default_realm =
+            credentials.is_some() || (has_bearer && schemes.contains(&AuthScheme::Bearer));
+        if has_default_realm {
+            check_schemes(DEFAULT_REALM, schemes, credentials.as_ref(), has_bearer)?;
+        }
+        Ok(Self {
+            default_realm: has_default_realm.then(|| default_realm(schemes)),
+            proxy_realm: credentials
+                .as_ref()
+                .map(|_| default_realm(&[AuthScheme::Basic])),
+            credentials,
+            locations: realms,
+            groups: groups_file.map(CredentialFile::load).transpose()?,
+            nonces: Nonces::new(digest::NONCE_LIFETIME),
+            bearer,
+            sessions: login.cloned().map(Sessions::new),
+        })
+    }
+
+    /// Settings of the login form, if enabled.
+    pub(crate) fn login_settings(&self) -> Option<&LoginSettings> {
+        self.sessions.as_ref().map(Sessions::settings)
+    }
+
+    /// Log a user in with the login form: if the password is valid, a session is opened and the
+    /// Set-Cookie header value of its cookie returned.
+    pub(crate) fn login(&self, username: &str, password: &str) -> Option<String> {
+        let sessions = self.sessions.as_ref()?;
+        match self
+            .credentials
+            .as_ref()
+            .is_some_and(|c| c.verify(username, password))
+        {
+            true => Some(sessions.set_cookie(Some(&sessions.open(username)))),
+            false => None,
+        }
+    }
+
+    /// Close the session of a cookie (if any), and return the Set-Cookie header value removing it.
+    pub(crate) fn logout(&self, cookie: Option<&str>) -> Option<String> {
+        let sessions = self.sessions.as_ref()?;
+        if let Some(cookie) = cookie {
+            sessions.close(cookie);
+        }
+        Some(sessions.set_cookie(None))
+    }
+
+    /// Realm protecting a path, if any.
+    pub(crate) fn realm(&self, path: &str) -> Option<&Realm> {
+        match self
+            .locations
+            .iter()
+            .find(|(path_prefix, _)| utils::matches_path_prefix(path, path_prefix))
+        {
+            Some((_, realm)) => realm.as_ref(),
+            None => self.default_realm.as_ref(),
+        }
+    }
+
+    /// Realm protecting the forward proxy, if any.
+    pub(crate) fn proxy_realm(&self) -> Option<&Realm> {
+        self.proxy_realm.as_ref()
+    }
+
+    /// Challenges asking a client to authenticate in a realm, one per scheme (and digest
+    /// algorithm) in order of preference.
+    pub(crate) fn challenges(&self, realm: &Realm, stale: bool) -> Vec<String> {
+        let mut challenges = Vec::new();
+        for scheme in &realm.schemes {
+            match scheme {
+                AuthScheme::Basic => challenges.push(format!("Basic realm=\"{}\"", realm.name)),
+                AuthScheme::Digest => {
+                    let nonce = self.nonces.issue();
+                    for algorithm in [digest::Algorithm::Sha256, digest::Algorithm::Md5] {
+                        challenges.push(digest::challenge(&realm.name, algorithm, &nonce, stale));
+                    }
+                }
+                AuthScheme::Bearer => challenges.push(format!("Bearer realm=\"{}\"", realm.name)),
+                AuthScheme::Form => (),
+            }
+        }
+        challenges
+    }
+
+    /// Authenticate a user in a realm (if any), and check whether it is granted access.
+    pub(crate) fn check(&self, realm: Option<&Realm>, creds: Option<Credentials>) -> Access {
+        let Some(realm) = realm else {
+            return Access::Public;
+        };
+        let credentials = realm.credentials.as_ref().or(self.credentials.as_ref());
+        let identity = |scheme, username: &str, claims| Identity {
+            scheme,
+            username: String::from(username),
+            claims,
+        };
+        let identity = match creds {
+            Some(creds) if !realm.accepts(creds.scheme()) => {
+                return Access::Unauthenticated;
+            }
+            Some(Credentials::Basic(username, password))
+                if credentials.is_some_and(|c| c.verify(username, password)) =>
+            {
+                identity(AuthScheme::Basic, username, None)
+            }
+            // the digest must be computed for this realm and request
+            Some(Credentials::Digest(digest, verb, target))
+                if digest.realm == realm.name
+                    && digest.uri == target
+                    && credentials.is_some_and(|c| c.verify_digest(digest, &verb.to_string())) =>
+            {
+                match self.nonces.use_nonce(&digest.nonce, digest.nc) {
+                    NonceUse::Valid => identity(AuthScheme::Digest, &digest.username, None),
+                    NonceUse::Stale => return Access::Stale,
+                    NonceUse::Replayed => {
+                        warn!("Replayed digest credentials of {}", digest.username);
+                        return Access::Unauthenticated;
+                    }
+                }
+            }
+            Some(Credentials::Bearer(token)) => {
+                match self.bearer.as_ref().map(|bearer| bearer.verify(token)) {
+                    Some(Ok((username, claims))) => identity(AuthScheme::Bearer, &username, claims),
+                    Some(Err(err)) => {
+                        info!("Rejected bearer token: {}", err);
+                        return Access::Unauthenticated;
+                    }
+                    None => return Access::Unauthenticated,
+                }
+            }
+            Some(Credentials::Session(cookie)) => {
+                match self.sessions.as_ref().and_then(|s| s.user(cookie)) {
+                    Some(username) => identity(AuthScheme::Form, &username, None),
+                    None => return Access::Unauthenticated,
+                }
+            }
+            _ => return Access::Unauthenticated,
+        };
+        let is_granted = realm.requirements.is_empty()
+            || realm
+                .requirements
+                .iter()
+                .any(|requirement| match requirement {
+                    Requirement::User(name) => *name == identity.username,
+                    Requirement::Group(group) => self
+                        .groups
+                        .as_ref()
+                        .is_some_and(|groups| groups.get().is_member(&identity.username, group)),
+                    Requirement::Claim(name, value) => identity
+                        .claims
+                        .as_ref()
+                        .is_some_and(|claims| claims.contains(name, value)),
+                });
+        match is_granted {
+            true => Access::Granted(identity),
+            false => Access::Forbidden,
+        }
+    }
+}
+
+/// Check that a realm can authenticate its users with each of its schemes, given its credentials.
+fn check_schemes(
+    name: &str,
+    schemes: &[AuthScheme],
+    credentials: Option<&CredentialStore>,
+    has_bearer: bool,
+) -> Result<(), credentials::Error> {
+    for scheme in schemes {
+        let error = match scheme {
+            AuthScheme::Basic | AuthScheme::Digest if credentials.is_none() => {
+                "no credentials to authenticate its users"
+            }
+            // digests are computed from the plain text password, which htpasswd files do not have
+            AuthScheme::Digest if !credentials.is_some_and(CredentialStore::has_inline) => {
+                "no inline credentials for the Digest scheme (htpasswd passwords are hashed)"
+            }
+            AuthScheme::Bearer if !has_bearer => "no bearer tokens or JWT keys",
+            _ => continue,
+        };
+        return Err(credentials::Error::Realm(String::from(name), error));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::credentials::HashScheme;
+
+    use base64::Engine;
+
+    use std::fs;
+
+    /// Access granted to a user, authenticated with a scheme.
+    fn granted(scheme: AuthScheme, username: &str) -> Access {
+        Access::Granted(Identity {
+            scheme,
+            username: String::from(username),
+            claims: None,
+        })
+    }
+
+    #[test]
+    fn location_from_str_test() {
+        assert_eq!(
+            "/health=public".parse(),
+            Ok(Location {
+                path_prefix: String::from("/health"),
+                protection: Protection::Public,
+            })
+        );
+        assert_eq!(
+            "/admin=Administration|file=admin.htpasswd|require=user:root,group:admins".parse(),
+            Ok(Location {
+                path_prefix: String::from("/admin"),
+                protection: Protection::Realm {
+                    name: String::from("Administration"),
+                    file: Some(path::PathBuf::from("admin.htpasswd")),
+                    requirements: vec![
+                        Requirement::User(String::from("root")),
+                        Requirement::Group(String::from("admins")),
+                    ],
+                    schemes: Vec::new(),
+                },
+            })
+        );
+        assert_eq!(
+            "/private=Private area".parse(),
+            Ok(Location {
+                path_prefix: String::from("/private"),
+                protection: Protection::Realm {
+                    name: String::from("Private area"),
+                    file: None,
+                    requirements: Vec::new(),
+                    schemes: Vec::new(),
+                },
+            })
+        );
+        assert!("/admin".parse::<Location>().is_err());
+        assert!("admin=Administration".parse::<Location>().is_err());
+        assert!("/admin=".parse::<Location>().is_err());
+        assert!("/admin=\"Admin\"".parse::<Location>().is_err());
+        assert!(
+            "/health=public|require=user:root"
+                .parse::<Location>()
+                .is_err()
+        );
+        assert!("/admin=Admin|file=".parse::<Location>().is_err());
+        assert!("/admin=Admin|require=root".parse::<Location>().is_err());
+        assert!("/admin=Admin|require=user:".parse::<Location>().is_err());
+        assert!("/admin=Admin|timeout=10".parse::<Location>().is_err());
+        assert_eq!(
+            "/api=API|schemes=bearer,basic|require=claim:scope=write,claim:roles=admin".parse(),
+            Ok(Location {
+                path_prefix: String::from("/api"),
+                protection: Protection::Realm {
+                    name: String::from("API"),
+                    file: None,
+                    requirements: vec![
+                        Requirement::Claim(String::from("scope"), String::from("write")),
+                        Requirement::Claim(String::from("roles"), String::from("admin")),
+                    ],
+                    schemes: vec![AuthScheme::Bearer, AuthScheme::Basic],
+                },
+            })
+        );
+        assert!("/api=API|schemes=ntlm".parse::<Location>().is_err());
+        assert!("/api=API|require=claim:scope".parse::<Location>().is_err());
+        assert!("/api=API|require=claim:=write".parse::<Location>().is_err());
+    }
+
+    #[test]
+    fn authenticator_test() {
+        let dir = std::env::temp_dir().join(format!("auth-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let users_file = dir.join("admin.htpasswd");
+        let hash = HashScheme::Sha256Crypt.hash("root-pass").unwrap();
+        fs::write(&users_file, format!("root:{}\n", hash)).unwrap();
+        let groups_file = dir.join("groups");
+        fs::write(&groups_file, "staff: bob\n").unwrap();
+
+        let inline = [
+            (String::from("alice"), String::from("alice-pass")),
+            (String::from("bob"), String::from("bob-pass")),
+        ];
+        let locations = [
+            "/admin=Administration|file=PATH".replace("PATH", users_file.to_str().unwrap()),
+            String::from("/admin/status=public"),
+            String::from("/staff=Staff|require=group:staff,user:alice"),
+            String::from("/bob=Bob|require=user:bob"),
+        ]
+        .map(|location| location.parse().unwrap());
+        let basic = [AuthScheme::Basic];
+        let auth = Authenticator::new(
+            Some(&inline),
+            None,
+            &locations,
+            Some(&groups_file),
+            &basic,
+            None,
+            None,
+        )
+        .unwrap();
+        let check = |path, creds: Option<(&str, &str)>| {
+            let creds = creds.map(|(username, password)| Credentials::Basic(username, password));
+            auth.check(auth.realm(path), creds)
+        };
+
+        // paths outside the locations are protected by the default realm
+        assert_eq!(auth.realm("/").unwrap().name, DEFAULT_REALM);
+        assert_eq!(check("/", None), Access::Unauthenticated);
+        assert_eq!(
+            check("/", Some(("alice", "alice-pass"))),
+            granted(AuthScheme::Basic, "alice")
+        );
+        assert_eq!(
+            check("/", Some(("alice", "bob-pass"))),
+            Access::Unauthenticated
+        );
+        assert_eq!(auth.proxy_realm().unwrap().name, DEFAULT_REALM);
+
+        // locations with their own credentials
+        assert_eq!(auth.realm("/admin/").unwrap().name, "Administration");
+        assert_eq!(
+            check("/admin", Some(("root", "root-pass"))),
+            granted(AuthScheme::Basic, "root")
+        );
+        assert_eq!(
+            check("/admin", Some(("alice", "alice-pass"))),
+            Access::Unauthenticated
+        );
+        assert_eq!(check("/administration", None), Access::Unauthenticated);
+        assert_eq!(auth.realm("/administration").unwrap().name, DEFAULT_REALM);
+
+        // the longest prefix wins
+        assert!(auth.realm("/admin/status").is_none());
+        assert_eq!(check("/admin/status/", None), Access::Public);
+
+        // authorization rules
+        assert_eq!(
+            check("/staff", Some(("bob", "bob-pass"))),
+            granted(AuthScheme::Basic, "bob")
+        );
+        assert_eq!(
+            check("/staff", Some(("alice", "alice-pass"))),
+            granted(AuthScheme::Basic, "alice")
+        );
+        assert_eq!(
+            check("/staff", Some(("root", "root-pass"))),
+            Access::Unauthenticated
+        );
+        assert_eq!(
+            check("/bob", Some(("bob", "bob-pass"))),
+            granted(AuthScheme::Basic, "bob")
+        );
+        assert_eq!(
+            check("/bob", Some(("alice", "alice-pass"))),
+            Access::Forbidden
+        );
+        assert_eq!(
+            check("/bob", Some(("alice", "bob-pass"))),
+            Access::Unauthenticated
+        );
+
+        // without credentials, only the locations are protected
+        let auth =
+            Authenticator::new(None, None, &locations[..2], None, &basic, None, None).unwrap();
+        assert!(auth.realm("/").is_none());
+        assert!(auth.proxy_realm().is_none());
+        assert_eq!(auth.check(auth.realm("/"), None), Access::Public);
+        assert_eq!(auth.realm("/admin").unwrap().name, "Administration");
+
+        // realms need credentials, and a group file for group rules
+        assert!(matches!(
+            Authenticator::new(
+                None,
+                None,
+                &locations,
+                Some(&groups_file),
+                &basic,
+                None,
+                None
+            ),
+            Err(credentials::Error::Realm(..))
+        ));
+        assert!(matches!(
+            Authenticator::new(Some(&inline), None, &locations, None, &basic, None, None),
+            Err(credentials::Error::Realm(..))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn digest_test() {
+        let inline = [(String::from("alice"), String::from("alice-pass"))];
+        let locations = ["/basic=Basic".parse().unwrap()];
+        let auth = Authenticator::new(
+            Some(&inline),
+            None,
+            &locations,
+            None,
+            &[AuthScheme::Digest, AuthScheme::Basic],
+            None,
+            None,
+        )
+        .unwrap();
+        let realm = auth.realm("/").unwrap();
+
+        let challenges = auth.challenges(realm, false);
+        assert_eq!(challenges.len(), 3);
+        assert!(
+            challenges[0].starts_with("Digest realm=\"simple\", qop=\"auth\", algorithm=SHA-256")
+        );
+        assert!(challenges[1].contains("algorithm=MD5"));
+        assert_eq!(challenges[2], "Basic realm=\"simple\"");
+        assert!(auth.challenges(realm, true)[0].ends_with("stale=true"));
+
+        let verb = ReqVerb::Get;
+        let target = "*";
+        let mut digest = DigestCredentials {
+            username: String::from("alice"),
+            realm: String::from(DEFAULT_REALM),
+            nonce: auth.nonces.issue(),
+            uri: String::from("*"),
+            algorithm: digest::Algorithm::Sha256,
+            nc: 1,
+            cnonce: String::from("0a4f113b"),
+            response: String::new(),
+        };
+        let check = |digest: &mut DigestCredentials, password| {
+            digest.response = digest.expected_response(password, "GET");
+            auth.check(
+                Some(realm),
+                Some(Credentials::Digest(digest, &verb, target)),
+            )
+        };
+        assert_eq!(
+            check(&mut digest, "alice-pass"),
+            granted(AuthScheme::Digest, "alice")
+        );
+        // nonce counts cannot be replayed
+        assert_eq!(check(&mut digest, "alice-pass"), Access::Unauthenticated);
+        digest.nc = 2;
+        digest.algorithm = digest::Algorithm::Md5;
+        assert_eq!(
+            check(&mut digest, "alice-pass"),
+            granted(AuthScheme::Digest, "alice")
+        );
+        digest.nc = 3;
+        assert_eq!(check(&mut digest, "bob-pass"), Access::Unauthenticated);
+        // unknown (or expired) nonces are stale
+        digest.nonce = String::from("0123");
+        assert_eq!(check(&mut digest, "alice-pass"), Access::Stale);
+        // the digest is computed for a realm and a request target
+        digest.nonce = auth.nonces.issue();
+        digest.uri = String::from("/");
+        assert_eq!(check(&mut digest, "alice-pass"), Access::Unauthenticated);
+        digest.uri = String::from("*");
+        digest.realm = String::from("Basic");
+        assert_eq!(check(&mut digest, "alice-pass"), Access::Unauthenticated);
+        digest.realm = String::from(DEFAULT_REALM);
+        assert_eq!(
+            check(&mut digest, "alice-pass"),
+            granted(AuthScheme::Digest, "alice")
+        );
+
+        // the proxy only offers the Basic scheme
+        let proxy_realm = auth.proxy_realm().unwrap();
+        assert_eq!(
+            auth.challenges(proxy_realm, false),
+            ["Basic realm=\"simple\""]
+        );
+        assert_eq!(
+            auth.check(
+                Some(proxy_realm),
+                Some(Credentials::Basic("alice", "alice-pass"))
+            ),
+            granted(AuthScheme::Basic, "alice")
+        );
+        assert_eq!(
+            auth.check(
+                Some(proxy_realm),
+                Some(Credentials::Digest(&digest, &verb, target))
+            ),
+            Access::Unauthenticated
+        );
+
+        // schemes which are not offered are refused
+        let auth = Authenticator::new(
+            Some(&inline),
+            None,
+            &[],
+            None,
+            &[AuthScheme::Digest],
+            None,
+            None,
+        )
+        .unwrap();
+        let realm = auth.realm("/");
+        assert_eq!(
+            auth.check(realm, Some(Credentials::Basic("alice", "alice-pass"))),
+            Access::Unauthenticated
+        );
+
+        // htpasswd users cannot authenticate with digests, their passwords being hashed
+        let dir = std::env::temp_dir().join(format!("auth-digest-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("users.htpasswd");
+        let hash = HashScheme::Sha256Crypt.hash("bob-pass").unwrap();
+        fs::write(&file, format!("bob:{}\n", hash)).unwrap();
+        let digest = [AuthScheme::Digest];
+        assert!(matches!(
+            Authenticator::new(None, Some(&file), &[], None, &digest, None, None),
+            Err(credentials::Error::Realm(..))
+        ));
+        let locations = [format!("/bob=Bob|file={}", file.display()).parse().unwrap()];
+        assert!(matches!(
+            Authenticator::new(Some(&inline), None, &locations, None, &digest, None, None),
+            Err(credentials::Error::Realm(..))
+        ));
+        assert!(
+            Authenticator::new(Some(&inline), Some(&file), &[], None, &digest, None, None).is_ok()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bearer_test() {
+        let dir = std::env::temp_dir().join(format!("auth-bearer-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tokens_file = dir.join("tokens");
+        fs::write(&tokens_file, "ci:0123456789abcdef\n").unwrap();
+        let secret_file = dir.join("secret");
+        fs::write(&secret_file, "a secret shared with the issuer!").unwrap();
+        let keys = [format!("HS256={}", secret_file.display()).parse().unwrap()];
+        let bearer = || BearerVerifier::new(Some(&tokens_file), &keys, None, None).unwrap();
+
+        let inline = [(String::from("alice"), String::from("alice-pass"))];
+        let locations = [
+            "/api=API|schemes=bearer|require=claim:scope=write,user:ci"
+                .parse()
+                .unwrap(),
+            "/api/read=API|schemes=bearer".parse().unwrap(),
+        ];
+        let basic = [AuthScheme::Basic];
+        let auth = Authenticator::new(
+            Some(&inline),
+            None,
+            &locations,
+            None,
+            &basic,
+            bearer(),
+            None,
+        )
+        .unwrap();
+        let api_realm = auth.realm("/api");
+        assert_eq!(
+            auth.challenges(api_realm.unwrap(), false),
+            ["Bearer realm=\"API\""]
+        );
+
+        // JSON Web Tokens, whose claims are given with the identity of the user
+        let sign = |payload: &str| {
+            let encode = |part: &str| base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(part);
+            let signed = format!("{}.{}", encode(r#"{"alg":"HS256"}"#), encode(payload));
+            let key = aws_lc_rs::hmac::Key::new(
+                aws_lc_rs::hmac::HMAC_SHA256,
+                b"a secret shared with the issuer!",
+            );
+            let sig = aws_lc_rs::hmac::sign(&key, signed.as_bytes());
+            format!(
+                "{}.{}",
+                signed,
+                base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(sig.as_ref())
+            )
+        };
+        let writer = sign(r#"{"sub": "bob", "scope": "read write", "exp": 4102444800}"#);
+        let Access::Granted(identity) = auth.check(api_realm, Some(Credentials::Bearer(&writer)))
+        else {
+            panic!("bearer token refused");
+        };
+        assert_eq!(identity.scheme, AuthScheme::Bearer);
+        assert_eq!(identity.username, "bob");
+        assert_eq!(
+            identity.claims.unwrap().get_str("scope"),
+            Some("read write")
+        );
+
+        // authorization rules on the claims
+        let reader = sign(r#"{"sub": "carol", "scope": "read", "exp": 4102444800}"#);
+        assert_eq!(
+            auth.check(api_realm, Some(Credentials::Bearer(&reader))),
+            Access::Forbidden
+        );
+        assert!(matches!(
+            auth.check(auth.realm("/api/read"), Some(Credentials::Bearer(&reader))),
+            Access::Granted(_)
+        ));
+        let expired = sign(r#"{"sub": "bob", "scope": "write", "exp": 1700000000}"#);
+        assert_eq!(
+            auth.check(api_realm, Some(Credentials::Bearer(&expired))),
+            Access::Unauthenticated
+        );
+
+        // static tokens, without claims
+        assert_eq!(
+            auth.check(api_realm, Some(Credentials::Bearer("0123456789abcdef"))),
+            granted(AuthScheme::Bearer, "ci")
+        );
+        assert_eq!(
+            auth.check(api_realm, Some(Credentials::Bearer("0123"))),
+            Access::Unauthenticated
+        );
+
+        // each realm only accepts its schemes
+        assert_eq!(
+            auth.check(api_realm, Some(Credentials::Basic("alice", "alice-pass"))),
+            Access::Unauthenticated
+        );
+        assert_eq!(
+            auth.check(
+                auth.realm("/"),
+                Some(Credentials::Bearer("0123456789abcdef"))
+            ),
+            Access::Unauthenticated
+        );
+
+        // the whole server can be protected with bearer tokens only
+        let bearer_only = [AuthScheme::Bearer];
+        let auth = Authenticator::new(None, None, &[], None, &bearer_only, bearer(), None).unwrap();
+        assert!(matches!(
+            auth.check(auth.realm("/"), Some(Credentials::Bearer(&writer))),
+            Access::Granted(_)
+        ));
+        assert!(auth.proxy_realm().is_none());
+        let auth =
+            Authenticator::new(None, None, &locations, None, &basic, bearer(), None).unwrap();
+        assert!(auth.realm("/").is_none());
+
+        // realms need tokens or keys for the Bearer scheme
+        assert!(matches!(
+            Authenticator::new(Some(&inline), None, &locations, None, &basic, None, None),
+            Err(credentials::Error::Realm(..))
+        ));
+        assert!(matches!(
+            Authenticator::new(Some(&inline), None, &[], None, &bearer_only, None, None),
+            Err(credentials::Error::Realm(..))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn session_test() {
+        let inline = [(String::from("alice"), String::from("alice-pass"))];
+        let locations = ["/admin=Administration|require=user:root".parse().unwrap()];
+        let basic = [AuthScheme::Basic];
+        let login = "/login".parse().unwrap();
+        let auth = Authenticator::new(
+            Some(&inline),
+            None,
+            &locations,
+            None,
+            &basic,
+            None,
+            Some(&login),
+        )
+        .unwrap();
+        assert_eq!(auth.login_settings(), Some(&login));
+        assert_eq!(auth.login("alice", "wrong-pass"), None);
+        assert_eq!(auth.login("bob", "alice-pass"), None);
+
+        // the session cookie identifies the user, with the Form scheme
+        let set_cookie = auth.login("alice", "alice-pass").unwrap();
+        let cookie = session::cookie_value(set_cookie.split_once(';').unwrap().0).unwrap();
+        assert_eq!(
+            auth.check(auth.realm("/"), Some(Credentials::Session(cookie))),
+            granted(AuthScheme::Form, "alice")
+        );
+        assert_eq!(
+            auth.check(auth.realm("/admin"), Some(Credentials::Session(cookie))),
+            Access::Forbidden
+        );
+        assert!(auth.realm("/").unwrap().accepts_sessions());
+
+        // until the user logs out
+        assert_eq!(
+            auth.logout(Some(cookie)).as_deref(),
+            Some("rhs_session=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Lax")
+        );
+        assert_eq!(
+            auth.check(auth.realm("/"), Some(Credentials::Session(cookie))),
+            Access::Unauthenticated
+        );
+
+        // without the login form, session cookies are ignored
+        let auth =
+            Authenticator::new(Some(&inline), None, &locations, None, &basic, None, None).unwrap();
+        assert_eq!(auth.login("alice", "alice-pass"), None);
+        assert_eq!(
+            auth.check(auth.realm("/"), Some(Credentials::Session(cookie))),
+            Access::Unauthenticated
+        );
+
+        // the login form needs the server credentials
+        assert!(matches!(
+            Authenticator::new(None, None, &locations, None, &basic, None, Some(&login)),
+            Err(credentials::Error::Login(_))
+        ));
+    }
+}
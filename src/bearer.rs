@@ -0,0 +1,657 @@
+//! Bearer tokens (RFC 6750): static tokens read from a file, or JSON Web Tokens (RFC 7519) signed
+//! with HS256, RS256 or ES256 and verified with locally configured keys.
+
+use crate::credentials::{self, CredentialFile};
+
+use aws_lc_rs::{hmac, signature};
+use base64::Engine;
+use rustls::pki_types::{SubjectPublicKeyInfoDer, pem::PemObject};
+use std::{collections, fmt, fs, path, str::FromStr, time};
+
+/// Tolerated difference between the clock of the server and the one of the token issuer, when
+/// checking the expiry (`exp`) and start (`nbf`) of a token.
+pub const CLOCK_SKEW: time::Duration = time::Duration::from_secs(60);
+
+/// Algorithm a JSON Web Token is signed with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JwtAlgorithm {
+    /// HMAC with SHA-256, with a shared secret
+    Hs256,
+    /// RSASSA-PKCS1-v1_5 with SHA-256, with an RSA public key
+    Rs256,
+    /// ECDSA with the P-256 curve and SHA-256, with an EC public key
+    Es256,
+}
+
+impl FromStr for JwtAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HS256" => Ok(Self::Hs256),
+            "RS256" => Ok(Self::Rs256),
+            "ES256" => Ok(Self::Es256),
+            _ => Err(format!("Unsupported JWT algorithm: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for JwtAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hs256 => f.write_str("HS256"),
+            Self::Rs256 => f.write_str("RS256"),
+            Self::Es256 => f.write_str("ES256"),
+        }
+    }
+}
+
+/// A key verifying the signature of JSON Web Tokens: a file holding the secret (HS256) or the PEM
+/// public key (RS256 and ES256), optionally with a key ID matched against the `kid` of the tokens.
+///
+/// Its string format is `ALGORITHM=PATH[|kid=ID]` (e.g. `RS256=issuer.pem|kid=2024-01`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct JwtKey {
+    pub algorithm: JwtAlgorithm,
+    pub path: path::PathBuf,
+    pub kid: Option<String>,
+}
+
+impl FromStr for JwtKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('|');
+        let (algorithm, path) = match parts.next().and_then(|p| p.split_once('=')) {
+            Some((algorithm, path)) if !path.is_empty() => (algorithm.trim().parse()?, path),
+            _ => return Err(format!("Invalid JWT key: {}", s)),
+        };
+        let mut kid = None;
+        for option in parts {
+            match option.split_once('=') {
+                Some(("kid", id)) if !id.is_empty() => kid = Some(String::from(id)),
+                _ => return Err(format!("Invalid JWT key option: {}", option)),
+            }
+        }
+        Ok(Self {
+            algorithm,
+            path: path::PathBuf::from(path),
+            kid,
+        })
+    }
+}
+
+/// The static tokens of a file, with the name of the client they identify.
+///
+/// Its format is one `NAME:TOKEN` entry per line, empty lines and lines starting with `#` being
+/// ignored.
+#[derive(Debug, Default, PartialEq)]
+pub struct StaticTokens {
+    /// Names, by SHA-256 digest of their token: looking a token up does not leak it through the
+    /// response time
+    names: collections::HashMap<Vec<u8>, String>,
+}
+
+impl FromStr for StaticTokens {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut names = collections::HashMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, token) = match line.split_once(':') {
+                Some((name, token)) if !name.is_empty() && !token.is_empty() => (name, token),
+                _ => return Err(format!("line {}: invalid entry", i + 1)),
+            };
+            // the token itself is not reported, as it is a secret
+            if names
+                .insert(token_digest(token), String::from(name))
+                .is_some()
+            {
+                return Err(format!("line {}: duplicate token", i + 1));
+            }
+        }
+        Ok(Self { names })
+    }
+}
+
+impl StaticTokens {
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Name of the client a token identifies, if any.
+    pub fn name(&self, token: &str) -> Option<&str> {
+        self.names.get(&token_digest(token)).map(String::as_str)
+    }
+}
+
+fn token_digest(token: &str) -> Vec<u8> {
+    <sha2::Sha256 as sha2::Digest>::digest(token.as_bytes()).to_vec()
+}
+
+/// Verified claims of a JSON Web Token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claims(serde_json::Map<String, serde_json::Value>);
+
+impl Claims {
+    /// Value of a string claim, if any.
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.0.get(name).and_then(serde_json::Value::as_str)
+    }
+
+    /// Check whether a claim has a value: it is the value itself, one of the values of an array,
+    /// or one of the space-separated scopes of the `scope` claim (RFC 8693).
+    pub fn contains(&self, name: &str, value: &str) -> bool {
+        let matches = |claim: &serde_json::Value| match claim {
+            serde_json::Value::String(s) => s == value,
+            serde_json::Value::Number(n) => {
+                value.parse().is_ok_and(|v: serde_json::Number| v == *n)
+            }
+            serde_json::Value::Bool(b) => value.parse() == Ok(*b),
+            _ => false,
+        };
+        match self.0.get(name) {
+            Some(serde_json::Value::String(scopes)) if name == "scope" => {
+                scopes.split(' ').any(|scope| scope == value)
+            }
+            Some(serde_json::Value::Array(values)) => values.iter().any(matches),
+            Some(claim) => matches(claim),
+            None => false,
+        }
+    }
+
+    /// CGI variables of the claims, named `JWT_` followed by the claim name in upper case (other
+    /// characters than letters and digits being replaced with `_`): string values are given as is,
+    /// other ones as JSON.
+    pub fn cgi_variables(&self) -> Vec<(String, String)> {
+        self.0
+            .iter()
+            .map(|(name, value)| {
+                let name = name
+                    .chars()
+                    .map(|c| match c.is_ascii_alphanumeric() {
+                        true => c.to_ascii_uppercase(),
+                        false => '_',
+                    })
+                    .collect::<String>();
+                let value = match value {
+                    serde_json::Value::String(s) => s.clone(),
+                    _ => value.to_string(),
+                };
+                (format!("JWT_{}", name), value)
+            })
+            .collect()
+    }
+}
+
+/// Reason why a bearer token is rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenError {
+    /// Neither a static token nor a well-formed JSON Web Token
+    Unknown,
+    /// No key of the algorithm (and ID) of the token
+    NoKey,
+    InvalidSignature,
+    Expired,
+    NotYetValid,
+    InvalidIssuer,
+    InvalidAudience,
+    /// Missing `exp` or `sub` claim
+    MissingClaim,
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown => f.write_str("unknown token"),
+            Self::NoKey => f.write_str("no key to verify the token"),
+            Self::InvalidSignature => f.write_str("invalid signature"),
+            Self::Expired => f.write_str("expired token"),
+            Self::NotYetValid => f.write_str("token not yet valid"),
+            Self::InvalidIssuer => f.write_str("invalid issuer"),
+            Self::InvalidAudience => f.write_str("invalid audience"),
+            Self::MissingClaim => f.write_str("missing exp or sub claim"),
+        }
+    }
+}
+
+enum VerificationKey {
+    Hmac(Box<hmac::Key>),
+    Public(signature::ParsedPublicKey),
+}
+
+/// Verifies the bearer tokens given by the clients: static tokens, then JSON Web Tokens.
+pub(crate) struct BearerVerifier {
+    tokens: Option<CredentialFile<StaticTokens>>,
+    keys: Vec<(JwtAlgorithm, Option<String>, VerificationKey)>,
+    /// Required `iss` claim, if any
+    issuer: Option<String>,
+    /// Required `aud` claim (or one of its values), if any
+    audience: Option<String>,
+}
+
+impl BearerVerifier {
+    /// Load the static tokens and the keys, if any are configured.
+    pub(crate) fn new(
+        tokens_file: Option<&path::Path>,
+        keys: &[JwtKey],
+        issuer: Option<&str>,
+        audience: Option<&str>,
+    ) -> Result<Option<Self>, credentials::Error> {
+        if tokens_file.is_none() && keys.is_empty() {
+            return Ok(None);
+        }
+        let mut verification_keys = Vec::new();
+        for key in keys {
+            let invalid_key = |e: String| credentials::Error::Parse(key.path.clone(), e);
+            let verification_key = match key.algorithm {
+                JwtAlgorithm::Hs256 => {
+                    let secret = fs::read(&key.path)
+                        .map_err(|e| credentials::Error::Io(key.path.clone(), e))?;
+                    let secret = secret.trim_ascii_end();
+                    if secret.len() < 32 {
+                        return Err(invalid_key(String::from(
+                            "HS256 secrets must be at least 32 bytes long",
+                        )));
+                    }
+                    VerificationKey::Hmac(Box::new(hmac::Key::new(hmac::HMAC_SHA256, secret)))
+                }
+                JwtAlgorithm::Rs256 | JwtAlgorithm::Es256 => {
+                    let der = SubjectPublicKeyInfoDer::from_pem_file(&key.path)
+                        .map_err(|e| invalid_key(e.to_string()))?;
+                    let algorithm: &'static dyn signature::VerificationAlgorithm =
+                        match key.algorithm {
+                            JwtAlgorithm::Rs256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+                            _ => &signature::ECDSA_P256_SHA256_FIXED,
+                        };
+                    signature::ParsedPublicKey::new(algorithm, der.as_ref())
+                        .map(VerificationKey::Public)
+                        .map_err(|e| invalid_key(format!("invalid {} key: {}", key.algorithm, e)))?
+                }
+            };
+            verification_keys.push((key.algorithm, key.kid.clone(), verification_key));
+        }
+        Ok(Some(Self {
+            tokens: tokens_file.map(CredentialFile::load).transpose()?,
+            keys: verification_keys,
+            issuer: issuer.map(String::from),
+            audience: audience.map(String::from),
+        }))
+    }
+
+    /// Verify a token: the name of the client it identifies is returned, with the claims of the
+    /// JSON Web Tokens.
+    pub(crate) fn verify(&self, token: &str) -> Result<(String, Option<Claims>), TokenError> {
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.verify_at(token, now)
+    }
+
+    fn verify_at(
+        &self,
+        token: &str,
+        now: time::Duration,
+    ) -> Result<(String, Option<Claims>), TokenError> {
+        if let Some(tokens) = &self.tokens
+            && let Some(name) = tokens.get().name(token)
+        {
+            return Ok((String::from(name), None));
+        }
+        let claims = self.verify_jwt(token, now)?;
+        match claims.get_str("sub") {
+            Some(subject) => Ok((String::from(subject), Some(claims))),
+            None => Err(TokenError::MissingClaim),
+        }
+    }
+
+    fn verify_jwt(&self, token: &str, now: time::Duration) -> Result<Claims, TokenError> {
+        let decode = |part: &str| {
+            base64::prelude::BASE64_URL_SAFE_NO_PAD
+                .decode(part)
+                .map_err(|_| TokenError::Unknown)
+        };
+        let decode_json = |part: &str| match serde_json::from_slice(&decode(part)?) {
+            Ok(serde_json::Value::Object(object)) => Ok(object),
+            _ => Err(TokenError::Unknown),
+        };
+        let [header, payload, sig] = *token.split('.').collect::<Vec<_>>() else {
+            return Err(TokenError::Unknown);
+        };
+        let header = decode_json(header)?;
+        // critical extensions are not supported (RFC 7515, section 4.1.11)
+        if header.contains_key("crit") {
+            return Err(TokenError::Unknown);
+        }
+        // the algorithm is the one of the key, and never "none"
+        let algorithm = header
+            .get("alg")
+            .and_then(serde_json::Value::as_str)
+            .and_then(|alg| alg.parse::<JwtAlgorithm>().ok())
+            .ok_or(TokenError::NoKey)?;
+        let kid = header.get("kid").and_then(serde_json::Value::as_str);
+        let mut keys = self
+            .keys
+            .iter()
+            .filter(|(key_algorithm, key_id, _)| {
+                *key_algorithm == algorithm
+                    && (kid.is_none() || key_id.is_none() || key_id.as_deref() == kid)
+            })
+            .peekable();
+        if keys.peek().is_none() {
+            return Err(TokenError::NoKey);
+        }
+        let signed = &token[..token.len() - sig.len() - 1];
+        let sig = decode(sig)?;
+        let is_valid = keys.any(|(_, _, key)| match key {
+            VerificationKey::Hmac(key) => hmac::verify(key, signed.as_bytes(), &sig).is_ok(),
+            VerificationKey::Public(key) => key.verify_sig(signed.as_bytes(), &sig).is_ok(),
+        });
+        if !is_valid {
+            return Err(TokenError::InvalidSignature);
+        }
+
+        let claims = Claims(decode_json(payload)?);
+        let time_claim = |name| claims.0.get(name).and_then(serde_json::Value::as_f64);
+        let now = now.as_secs_f64();
+        let skew = CLOCK_SKEW.as_secs_f64();
+        match time_claim("exp") {
+            Some(exp) if now >= exp + skew => return Err(TokenError::Expired),
+            Some(_) => (),
+            None => return Err(TokenError::MissingClaim),
+        }
+        if time_claim("nbf").is_some_and(|nbf| now + skew < nbf) {
+            return Err(TokenError::NotYetValid);
+        }
+        if let Some(issuer) = &self.issuer
+            && claims.get_str("iss") != Some(issuer)
+        {
+            return Err(TokenError::InvalidIssuer);
+        }
+        if let Some(audience) = &self.audience
+            && !claims.contains("aud", audience)
+        {
+            return Err(TokenError::InvalidAudience);
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use aws_lc_rs::rand;
+
+    /// Sign a token with a key, whose algorithm is given in the header.
+    fn sign(header: &str, payload: &str, sign: impl Fn(&[u8]) -> Vec<u8>) -> String {
+        let encode = |part: &[u8]| base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(part);
+        let signed = format!(
+            "{}.{}",
+            encode(header.as_bytes()),
+            encode(payload.as_bytes())
+        );
+        let sig = sign(signed.as_bytes());
+        format!("{}.{}", signed, encode(&sig))
+    }
+
+    #[test]
+    fn jwt_key_from_str_test() {
+        assert_eq!(
+            "RS256=issuer.pem|kid=2024-01".parse(),
+            Ok(JwtKey {
+                algorithm: JwtAlgorithm::Rs256,
+                path: path::PathBuf::from("issuer.pem"),
+                kid: Some(String::from("2024-01")),
+            })
+        );
+        assert_eq!(
+            "HS256=secret".parse::<JwtKey>().map(|key| key.kid),
+            Ok(None)
+        );
+        assert!("none=secret".parse::<JwtKey>().is_err());
+        assert!("HS512=secret".parse::<JwtKey>().is_err());
+        assert!("HS256=".parse::<JwtKey>().is_err());
+        assert!("HS256".parse::<JwtKey>().is_err());
+        assert!("HS256=secret|kid=".parse::<JwtKey>().is_err());
+        assert!("HS256=secret|alg=RS256".parse::<JwtKey>().is_err());
+    }
+
+    #[test]
+    fn static_tokens_test() {
+        let tokens: StaticTokens =
+            "# CI jobs\nci:0123456789abcdef\n\nmonitoring:fedcba:9876543210\n"
+                .parse()
+                .unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens.name("0123456789abcdef"), Some("ci"));
+        assert_eq!(tokens.name("fedcba:9876543210"), Some("monitoring"));
+        assert_eq!(tokens.name("0123456789abcde"), None);
+        assert_eq!(tokens.name("ci"), None);
+
+        assert!("ci:".parse::<StaticTokens>().is_err());
+        assert!(":token".parse::<StaticTokens>().is_err());
+        assert!("token".parse::<StaticTokens>().is_err());
+        assert_eq!(
+            "a:token\nb:token".parse::<StaticTokens>(),
+            Err(String::from("line 2: duplicate token"))
+        );
+        assert!("".parse::<StaticTokens>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn claims_test() {
+        let claims = Claims(
+            serde_json::from_str(
+                r#"{"sub": "alice", "scope": "read write", "roles": ["admin", 7], "admin": true, "x-tenant": {"id": 1}}"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(claims.get_str("sub"), Some("alice"));
+        assert_eq!(claims.get_str("admin"), None);
+        assert!(claims.contains("sub", "alice"));
+        assert!(claims.contains("scope", "write"));
+        assert!(!claims.contains("scope", "read write admin"));
+        assert!(claims.contains("roles", "admin"));
+        assert!(claims.contains("roles", "7"));
+        assert!(claims.contains("admin", "true"));
+        assert!(!claims.contains("x-tenant", "1"));
+        assert!(!claims.contains("email", "alice"));
+
+        let variables = claims.cgi_variables();
+        assert!(variables.contains(&(String::from("JWT_SUB"), String::from("alice"))));
+        assert!(variables.contains(&(String::from("JWT_SCOPE"), String::from("read write"))));
+        assert!(variables.contains(&(String::from("JWT_ROLES"), String::from("[\"admin\",7]"))));
+        assert!(variables.contains(&(String::from("JWT_X_TENANT"), String::from("{\"id\":1}"))));
+    }
+
+    #[test]
+    fn bearer_verifier_test() {
+        let dir = std::env::temp_dir().join(format!("bearer-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tokens_file = dir.join("tokens");
+        fs::write(&tokens_file, "ci:0123456789abcdef\n").unwrap();
+        let secret_file = dir.join("secret");
+        fs::write(&secret_file, "a secret shared with the issuer!\n").unwrap();
+        let keys = [
+            format!("HS256={}", secret_file.display()),
+            String::from("RS256=ssl/jwt-rsa.pub|kid=rsa"),
+            String::from("ES256=ssl/jwt-ec.pub"),
+        ]
+        .map(|key| key.parse().unwrap());
+        let verifier = BearerVerifier::new(
+            Some(&tokens_file),
+            &keys,
+            Some("https://issuer.localhost"),
+            Some("api"),
+        )
+        .unwrap()
+        .unwrap();
+        let now = time::Duration::from_secs(1_700_000_000);
+        let verify = |token: &str| verifier.verify_at(token, now);
+
+        // static tokens
+        assert_eq!(verify("0123456789abcdef"), Ok((String::from("ci"), None)));
+        assert_eq!(verify("0123456789abcde"), Err(TokenError::Unknown));
+
+        // tokens signed with each algorithm
+        let payload = r#"{"sub": "alice", "iss": "https://issuer.localhost", "aud": ["api", "web"], "exp": 1700000100, "nbf": 1699999900, "scope": "read"}"#;
+        let hmac_key = hmac::Key::new(hmac::HMAC_SHA256, b"a secret shared with the issuer!");
+        let hs256 = |header: &str, payload: &str| {
+            sign(header, payload, |signed| {
+                hmac::sign(&hmac_key, signed).as_ref().to_vec()
+            })
+        };
+        let token = hs256(r#"{"alg": "HS256", "typ": "JWT"}"#, payload);
+        let (subject, claims) = verify(&token).unwrap();
+        assert_eq!(subject, "alice");
+        assert!(claims.unwrap().contains("scope", "read"));
+
+        let rsa_key = signature::RsaKeyPair::from_pkcs8(
+            rustls::pki_types::PrivatePkcs8KeyDer::from_pem_file("ssl/jwt-rsa.key")
+                .unwrap()
+                .secret_pkcs8_der(),
+        )
+        .unwrap();
+        let rs256 = |header: &str, payload: &str| {
+            sign(header, payload, |signed| {
+                let mut sig = vec![0; rsa_key.public_modulus_len()];
+                let rng = rand::SystemRandom::new();
+                rsa_key
+                    .sign(&signature::RSA_PKCS1_SHA256, &rng, signed, &mut sig)
+                    .unwrap();
+                sig
+            })
+        };
+        assert!(verify(&rs256(r#"{"alg": "RS256", "kid": "rsa"}"#, payload)).is_ok());
+        assert!(verify(&rs256(r#"{"alg": "RS256"}"#, payload)).is_ok());
+        assert_eq!(
+            verify(&rs256(r#"{"alg": "RS256", "kid": "other"}"#, payload)),
+            Err(TokenError::NoKey)
+        );
+
+        let ec_key = signature::EcdsaKeyPair::from_pkcs8(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            rustls::pki_types::PrivatePkcs8KeyDer::from_pem_file("ssl/jwt-ec.key")
+                .unwrap()
+                .secret_pkcs8_der(),
+        )
+        .unwrap();
+        let es256 = |header: &str, payload: &str| {
+            sign(header, payload, |signed| {
+                let rng = rand::SystemRandom::new();
+                ec_key.sign(&rng, signed).unwrap().as_ref().to_vec()
+            })
+        };
+        assert!(verify(&es256(r#"{"alg": "ES256"}"#, payload)).is_ok());
+
+        // the algorithm of the header must be the one of the key
+        assert_eq!(
+            verify(&es256(r#"{"alg": "RS256"}"#, payload)),
+            Err(TokenError::InvalidSignature)
+        );
+        assert_eq!(
+            verify(&hs256(r#"{"alg": "none"}"#, payload)),
+            Err(TokenError::NoKey)
+        );
+        let unsigned = hs256(r#"{"alg": "none"}"#, payload);
+        assert_eq!(
+            verify(&format!("{}.", unsigned.rsplit_once('.').unwrap().0)),
+            Err(TokenError::NoKey)
+        );
+        // tampered tokens
+        let token = hs256(r#"{"alg": "HS256"}"#, payload);
+        let tampered = hs256(r#"{"alg": "HS256"}"#, &payload.replace("alice", "admin"));
+        let (signed, _) = tampered.rsplit_once('.').unwrap();
+        let (_, sig) = token.rsplit_once('.').unwrap();
+        assert_eq!(
+            verify(&format!("{}.{}", signed, sig)),
+            Err(TokenError::InvalidSignature)
+        );
+        assert_eq!(verify("a.b.c"), Err(TokenError::Unknown));
+        assert_eq!(
+            verify(&hs256(r#"{"alg": "HS256", "crit": ["exp"]}"#, payload)),
+            Err(TokenError::Unknown)
+        );
+
+        // checks of the claims
+        assert_eq!(
+            verify(&hs256(
+                r#"{"alg": "HS256"}"#,
+                &payload.replace("1700000100", "1699999900")
+            )),
+            Err(TokenError::Expired)
+        );
+        // within the clock skew
+        assert!(
+            verify(&hs256(
+                r#"{"alg": "HS256"}"#,
+                &payload.replace("1700000100", "1699999950")
+            ))
+            .is_ok()
+        );
+        assert_eq!(
+            verify(&hs256(
+                r#"{"alg": "HS256"}"#,
+                &payload.replace("1699999900", "1700000100")
+            )),
+            Err(TokenError::NotYetValid)
+        );
+        assert_eq!(
+            verify(&hs256(
+                r#"{"alg": "HS256"}"#,
+                &payload.replace("issuer.localhost", "localhost")
+            )),
+            Err(TokenError::InvalidIssuer)
+        );
+        assert_eq!(
+            verify(&hs256(
+                r#"{"alg": "HS256"}"#,
+                &payload.replace("\"api\"", "\"API\"")
+            )),
+            Err(TokenError::InvalidAudience)
+        );
+        assert_eq!(
+            verify(&hs256(
+                r#"{"alg": "HS256"}"#,
+                &payload.replace("\"sub\"", "\"user\"")
+            )),
+            Err(TokenError::MissingClaim)
+        );
+        assert_eq!(
+            verify(&hs256(
+                r#"{"alg": "HS256"}"#,
+                &payload.replace("\"exp\"", "\"expiry\"")
+            )),
+            Err(TokenError::MissingClaim)
+        );
+
+        // invalid keys
+        assert!(
+            BearerVerifier::new(None, &[], None, None)
+                .unwrap()
+                .is_none()
+        );
+        let invalid_keys = [
+            "HS256=ssl/none",
+            "RS256=ssl/jwt-ec.pub",
+            "ES256=ssl/jwt-rsa.pub",
+            "RS256=ssl/jwt-rsa.key",
+        ];
+        for key in invalid_keys {
+            assert!(BearerVerifier::new(None, &[key.parse().unwrap()], None, None).is_err());
+        }
+        fs::write(&secret_file, "too short").unwrap();
+        assert!(BearerVerifier::new(None, &keys[..1], None, None).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+//! Minimal asynchronous HTTP/1.1 client.
+//!
+//! The client sends an HTTP request over a TCP connection (optionally encrypted with TLS), and
+//! reads back the HTTP response using the response parser.
+
+use crate::http_header::{EntityHeader, HeaderValue, ReqHeader, ReqOnlyHeader, SimpleHeaderValue};
+use crate::http_req::{HttpReq, ReqVerb};
+use crate::http_res::{HttpRes, ResBody};
+use crate::res_parser::{self, ResBodyLength, ResHeadParser, ResHeadParsingError};
+
+use log::debug;
+use rustls::pki_types::pem::PemObject;
+use std::{fmt, io, path, sync};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+/// Define a wrapper trait for the stream used by the client
+pub trait ClientStream: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send {}
+impl<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send> ClientStream for S {}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Tls(rustls::Error),
+    TlsPem(rustls::pki_types::pem::Error),
+    InvalidServerName(String),
+    Parsing(ResHeadParsingError),
+    ConnectionClosed,
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Tls(e) => write!(f, "TLS error: {}", e),
+            Self::TlsPem(e) => write!(f, "TLS Pem error: {}", e),
+            Self::InvalidServerName(name) => write!(f, "invalid server name: {}", name),
+            Self::Parsing(e) => write!(f, "cannot parse response: {:?}", e),
+            Self::ConnectionClosed => write!(f, "connection closed by server"),
+        }
+    }
+}
+
+/// An HTTP client, bound to a single connection with a server.
+pub struct Client {
+    host: String,
+    stream: tokio::io::BufReader<Box<dyn ClientStream>>,
+}
+
+impl Client {
+    /// Open a plain TCP connection to a server (e.g. "localhost:8080").
+    pub async fn connect(addr: &str) -> Result<Self, Error> {
+        let stream = tokio::net::TcpStream::connect(addr)
+            .await
+            .map_err(Error::Io)?;
+        Ok(Self::from_stream(addr, Box::new(stream)))
+    }
+
+    /// Open a TLS connection to a server (e.g. "localhost:8443").
+    ///
+    /// The server certificate is checked against the host part of the address.
+    pub async fn connect_tls(
+        addr: &str,
+        config: sync::Arc<rustls::ClientConfig>,
+    ) -> Result<Self, Error> {
+        let server_name = rustls::pki_types::ServerName::try_from(String::from(host_name(addr)))
+            .map_err(|_| Error::InvalidServerName(String::from(addr)))?;
+        let stream = tokio::net::TcpStream::connect(addr)
+            .await
+            .map_err(Error::Io)?;
+        let stream = tokio_rustls::TlsConnector::from(config)
+            .connect(server_name, stream)
+            .await
+            .map_err(Error::Io)?;
+        Ok(Self::from_stream(addr, Box::new(stream)))
+    }
+
+    /// Create a client on top of an already established stream.
+    ///
+    /// The host is used to fill in the Host header of requests that do not have one.
+    pub fn from_stream(host: &str, stream: Box<dyn ClientStream>) -> Self {
+        Self {
+            host: String::from(host),
+            stream: tokio::io::BufReader::new(stream),
+        }
+    }
+
+    /// Build a TLS configuration trusting the certificate authorities of a PEM file.
+    pub fn tls_config(ca_cert_path: &path::Path) -> Result<sync::Arc<rustls::ClientConfig>, Error> {
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in
+            rustls::pki_types::CertificateDer::pem_file_iter(ca_cert_path).map_err(Error::TlsPem)?
+        {
+            roots
+                .add(cert.map_err(Error::TlsPem)?)
+                .map_err(Error::Tls)?;
+        }
+        Ok(sync::Arc::new(
+            rustls::ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        ))
+    }
+
+    /// Send a request and wait for the complete response.
+    pub async fn send(&mut self, req: &mut HttpReq) -> Result<HttpRes, Error> {
+        self.write_req(req).await?;
+        let head_only = *req.verb() == ReqVerb::Head;
+        let mut res = self.read_res_head().await?;
+        self.read_res_body(&mut res, head_only).await?;
+        Ok(res)
+    }
+
+    /// Write a request (head and body) to the server.
+    ///
+    /// The Host and Content-Length headers are added to the request if missing.
+    pub async fn write_req(&mut self, req: &mut HttpReq) -> Result<(), Error> {
+        let body_len = req.body().map_or(0, |b| b.bytes().len());
+        let headers = req.headers();
+        headers
+            .entry(ReqHeader::ReqOnly(ReqOnlyHeader::Host))
+            .or_insert_with(|| HeaderValue::Simple(SimpleHeaderValue::String(self.host.clone())));
+        if body_len != 0 {
+            headers
+                .entry(ReqHeader::Entity(EntityHeader::ContentLength))
+                .or_insert(HeaderValue::Simple(SimpleHeaderValue::Number(
+                    body_len as u64,
+                )));
+        }
+
+        debug!("sending request: {}", req.first_line());
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(&req.head_bytes())
+            .await
+            .map_err(Error::Io)?;
+        if let Some(body) = req.body() {
+            stream.write_all(body.bytes()).await.map_err(Error::Io)?;
+        }
+        stream.flush().await.map_err(Error::Io)
+    }
+
+    /// Read a response head (status line and headers) from the server.
+    ///
+    /// Interim responses (1xx, except 101 Switching Protocols) are skipped.
+    pub async fn read_res_head(&mut self) -> Result<HttpRes, Error> {
+        let mut res_head_parser = ResHeadParser::new();
+        loop {
+            res_head_parser.reset();
+            while !res_head_parser.is_complete() {
+                // read one line from the stream
+                // with a maximum limit on bytes read (8000)
+                let mut line: Vec<u8> = Vec::new();
+                let mut handle = (&mut self.stream).take(8000);
+                handle
+                    .read_until(b'\n', &mut line)
+                    .await
+                    .map_err(Error::Io)?;
+                if line.is_empty() {
+                    return Err(Error::ConnectionClosed);
+                }
+                res_head_parser
+                    .process_bytes(line)
+                    .map_err(Error::Parsing)?;
+            }
+            let res = res_head_parser.do_parse().map_err(Error::Parsing)?;
+            if !(100..200).contains(&res.status_code()) || res.status_code() == 101 {
+                return Ok(res);
+            }
+            debug!("skipping interim response {}", res.status_code());
+        }
+    }
+
+    /// Read the body of a response whose head was just read.
+    pub async fn read_res_body(&mut self, res: &mut HttpRes, head_only: bool) -> Result<(), Error> {
+        let body_length = ResBodyLength::of(res, head_only);
+        let body = res_parser::read_res_body(&mut self.stream, &body_length)
+            .await
+            .map_err(Error::Parsing)?;
+        if body_length != ResBodyLength::None {
+            res.set_body(Some(ResBody::Bytes(body)));
+        }
+        Ok(())
+    }
+
+    /// Get the underlying (buffered) stream, e.g. to read a response body in a streaming fashion.
+    pub fn stream(&mut self) -> &mut tokio::io::BufReader<Box<dyn ClientStream>> {
+        &mut self.stream
+    }
+}
+
+/// Extract the host part of a socket address (e.g. "localhost" from "localhost:8080").
+fn host_name(addr: &str) -> &str {
+    match addr.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => {
+            host.trim_start_matches('[').trim_end_matches(']')
+        }
+        _ => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_name_test() {
+        assert_eq!(host_name("localhost:8080"), "localhost");
+        assert_eq!(host_name("localhost"), "localhost");
+        assert_eq!(host_name("127.0.0.1:443"), "127.0.0.1");
+        assert_eq!(host_name("[::1]:8080"), "::1");
+    }
+}
@@ -0,0 +1,581 @@
+//! Credentials of the users allowed to access the server: given inline, or loaded from
+//! htpasswd-compatible files whose passwords are hashed with bcrypt, SHA-crypt or argon2.
+
+use crate::digest::DigestCredentials;
+use crate::utils::FileWatcher;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{self, PasswordHasher, PasswordVerifier, SaltString};
+use aws_lc_rs::hmac;
+use log::{error, info};
+use std::{collections, fmt, fs, io, path, str::FromStr, sync, time};
+use subtle::ConstantTimeEq;
+
+/// Scheme a password is hashed with.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HashScheme {
+    /// bcrypt (`$2y$`, `$2b$` or `$2a$`), the default of htpasswd
+    #[default]
+    Bcrypt,
+    /// SHA-256 crypt (`$5$`)
+    Sha256Crypt,
+    /// SHA-512 crypt (`$6$`)
+    Sha512Crypt,
+    /// argon2 (`$argon2id$`, `$argon2i$` or `$argon2d$`)
+    Argon2,
+}
+
+impl FromStr for HashScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bcrypt" => Ok(Self::Bcrypt),
+            "sha256-crypt" => Ok(Self::Sha256Crypt),
+            "sha512-crypt" => Ok(Self::Sha512Crypt),
+            "argon2" => Ok(Self::Argon2),
+            _ => Err(format!("Invalid password hash scheme: {}", s)),
+        }
+    }
+}
+
+impl HashScheme {
+    /// Hash a password with a random salt (and the default cost of the scheme).
+    pub fn hash(&self, password: &str) -> Result<String, String> {
+        match self {
+            // htpasswd writes 2y bcrypt hashes
+            Self::Bcrypt => pwhash::bcrypt::hash_with(
+                pwhash::bcrypt::BcryptSetup {
+                    variant: Some(pwhash::bcrypt::BcryptVariant::V2y),
+                    ..Default::default()
+                },
+                password,
+            )
+            .map_err(|e| e.to_string()),
+            // less recommended than SHA-512, but still written by htpasswd -2
+            #[allow(deprecated)]
+            Self::Sha256Crypt => pwhash::sha256_crypt::hash(password).map_err(|e| e.to_string()),
+            Self::Sha512Crypt => pwhash::sha512_crypt::hash(password).map_err(|e| e.to_string()),
+            Self::Argon2 => {
+                let salt = SaltString::generate(&mut password_hash::rand_core::OsRng);
+                argon2::Argon2::default()
+                    .hash_password(password.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+                    .map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+/// A hashed password, in the modular crypt format used by htpasswd files (e.g. `$2y$10$...`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordHash {
+    scheme: HashScheme,
+    hash: String,
+}
+
+impl FromStr for PasswordHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let scheme = match s.split('$').nth(1) {
+            Some("2y" | "2b" | "2a") if s.starts_with('$') => HashScheme::Bcrypt,
+            Some("5") if s.starts_with('$') => HashScheme::Sha256Crypt,
+            Some("6") if s.starts_with('$') => HashScheme::Sha512Crypt,
+            Some("argon2id" | "argon2i" | "argon2d")
+                if password_hash::PasswordHash::new(s).is_ok_and(|hash| hash.hash.is_some()) =>
+            {
+                HashScheme::Argon2
+            }
+            // the hash itself is not reported, as it is a secret
+            _ => {
+                return Err(String::from(
+                    "Unsupported password hash (expected bcrypt, SHA-crypt or argon2)",
+                ));
+            }
+        };
+        Ok(Self {
+            scheme,
+            hash: String::from(s),
+        })
+    }
+}
+
+impl PasswordHash {
+    pub fn scheme(&self) -> HashScheme {
+        self.scheme
+    }
+
+    /// Check whether a password matches the hash. The computed hash is compared in constant time.
+    pub fn verify(&self, password: &str) -> bool {
+        match self.scheme {
+            HashScheme::Argon2 => password_hash::PasswordHash::new(&self.hash).is_ok_and(|hash| {
+                argon2::Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            }),
+            _ => pwhash::unix::verify(password, &self.hash),
+        }
+    }
+}
+
+/// The users of an htpasswd file, with their password hash.
+///
+/// Its format is one `USERNAME:HASH` entry per line, empty lines and lines starting with `#`
+/// being ignored.
+#[derive(Debug, Default, PartialEq)]
+pub struct Htpasswd {
+    users: collections::HashMap<String, PasswordHash>,
+}
+
+impl FromStr for Htpasswd {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut users = collections::HashMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (username, hash) = match line.split_once(':') {
+                Some((username, hash)) if !username.is_empty() => (username, hash),
+                _ => return Err(format!("line {}: invalid entry", i + 1)),
+            };
+            let hash = hash.parse().map_err(|e| format!("line {}: {}", i + 1, e))?;
+            if users.insert(String::from(username), hash).is_some() {
+                return Err(format!("line {}: duplicate user {}", i + 1, username));
+            }
+        }
+        Ok(Self { users })
+    }
+}
+
+impl Htpasswd {
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// Check whether a password is the one of a user.
+    ///
+    /// For an unknown user, the password is still checked against the hash of another user (to be
+    /// rejected anyway), so that valid usernames cannot be told from the response time.
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        match self.users.get(username) {
+            Some(hash) => hash.verify(password),
+            None => {
+                if let Some(hash) = self.users.values().next() {
+                    hash.verify(password);
+                }
+                false
+            }
+        }
+    }
+}
+
+/// The groups of an htgroup file, with their members.
+///
+/// Its format is one `GROUP: USERNAME [USERNAME...]` entry per line, empty lines and lines starting
+/// with `#` being ignored.
+#[derive(Debug, Default, PartialEq)]
+pub struct Htgroup {
+    groups: collections::HashMap<String, collections::HashSet<String>>,
+}
+
+impl FromStr for Htgroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut groups = collections::HashMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (group, members) = match line.split_once(':') {
+                Some((group, members)) if !group.trim().is_empty() => (group.trim(), members),
+                _ => return Err(format!("line {}: invalid entry", i + 1)),
+            };
+            let members = members.split_whitespace().map(String::from).collect();
+            if groups.insert(String::from(group), members).is_some() {
+                return Err(format!("line {}: duplicate group {}", i + 1, group));
+            }
+        }
+        Ok(Self { groups })
+    }
+}
+
+impl Htgroup {
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Check whether a user is a member of a group.
+    pub fn is_member(&self, username: &str, group: &str) -> bool {
+        self.groups
+            .get(group)
+            .is_some_and(|members| members.contains(username))
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(path::PathBuf, io::Error),
+    Parse(path::PathBuf, String),
+    /// A realm cannot be set up (e.g. no credentials to authenticate its users with)
+    Realm(String, &'static str),
+    /// The login form cannot be set up
+    Login(&'static str),
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            Self::Parse(path, e) => write!(f, "invalid credential file {}, {}", path.display(), e),
+            Self::Realm(name, e) => write!(f, "invalid realm {}, {}", name, e),
+            Self::Login(e) => write!(f, "invalid login form, {}", e),
+        }
+    }
+}
+
+/// An htpasswd (or htgroup) file, reloaded when it changes. An invalid file is logged and
+/// rejected, the previous entries being kept.
+pub(crate) struct CredentialFile<T = Htpasswd> {
+    path: path::PathBuf,
+    watcher: sync::Mutex<FileWatcher>,
+    entries: sync::RwLock<sync::Arc<T>>,
+}
+
+impl<T: FromStr<Err = String>> CredentialFile<T> {
+    pub(crate) fn load(path: &path::Path) -> Result<Self, Error> {
+        // watch the file before reading it, so that changes made meanwhile are not missed
+        let watcher = FileWatcher::new(vec![path.to_path_buf()]);
+        Ok(Self {
+            path: path.to_path_buf(),
+            watcher: sync::Mutex::new(watcher),
+            entries: sync::RwLock::new(sync::Arc::new(Self::read(path)?)),
+        })
+    }
+
+    fn read(path: &path::Path) -> Result<T, Error> {
+        fs::read_to_string(path)
+            .map_err(|e| Error::Io(path.to_path_buf(), e))?
+            .parse()
+            .map_err(|e| Error::Parse(path.to_path_buf(), e))
+    }
+
+    /// Reload the file if it changed since the previous check.
+    fn reload_if_changed(&self) {
+        // another request is already checking the file
+        let Ok(mut watcher) = self.watcher.try_lock() else {
+            return;
+        };
+        if !watcher.has_changed() {
+            return;
+        }
+        match Self::read(&self.path) {
+            Ok(entries) => {
+                info!("Credential file {} reloaded", self.path.display());
+                *self.entries.write().unwrap() = sync::Arc::new(entries);
+            }
+            Err(err) => error!(
+                "Cannot reload credentials, keeping the current ones: {}",
+                err
+            ),
+        }
+    }
+
+    /// Current entries of the file, reloaded first if it changed.
+    pub(crate) fn get(&self) -> sync::Arc<T> {
+        self.reload_if_changed();
+        self.entries.read().unwrap().clone()
+    }
+}
+
+/// Credentials granting access to the server: inline ones (with plain text passwords) and the
+/// entries of an htpasswd file.
+pub(crate) struct CredentialStore {
+    inline: Vec<(String, String)>,
+    file: Option<CredentialFile>,
+    /// Successful verifications of htpasswd passwords, as hashing them is slow (and blocks the
+    /// server meanwhile)
+    verified: VerifiedCache,
+}
+
+impl CredentialStore {
+    /// Load the credentials, if any are configured.
+    pub(crate) fn new(
+        inline: Option<&[(String, String)]>,
+        file: Option<&path::Path>,
+    ) -> Result<Option<Self>, Error> {
+        if inline.is_none() && file.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            inline: inline.map(Vec::from).unwrap_or_default(),
+            file: file.map(CredentialFile::load).transpose()?,
+            verified: VerifiedCache::new(),
+        }))
+    }
+
+    /// Check whether there are inline credentials, the only ones digest credentials can be checked
+    /// against.
+    pub(crate) fn has_inline(&self) -> bool {
+        !self.inline.is_empty()
+    }
+
+    /// Check whether digest credentials were computed with the password of a user. Only the inline
+    /// credentials can be checked, the htpasswd files having hashed passwords.
+    pub(crate) fn verify_digest(&self, creds: &DigestCredentials, method: &str) -> bool {
+        self.inline.iter().any(|(username, password)| {
+            *username == creds.username && creds.verify(password, method)
+        })
+    }
+
+    /// Check whether a username and password match some credentials.
+    pub(crate) fn verify(&self, username: &str, password: &str) -> bool {
+        // compared in constant time, so that the passwords cannot be guessed from the response time
+        let inline = self.inline.iter().any(|(u, p)| {
+            (u.as_bytes().ct_eq(username.as_bytes()) & p.as_bytes().ct_eq(password.as_bytes()))
+                .into()
+        });
+        if inline {
+            return true;
+        }
+        let Some(file) = self.file.as_ref() else {
+            return false;
+        };
+        // hashing is slow: the entries are not locked meanwhile
+        let entries = file.get();
+        let hash = entries.users.get(username).map(|hash| hash.hash.as_str());
+        let key = hash.map(|hash| self.verified.key(username, password, hash));
+        if key.as_ref().is_some_and(|key| self.verified.contains(key)) {
+            return true;
+        }
+        let is_valid = entries.verify(username, password);
+        if is_valid && let Some(key) = key {
+            self.verified.insert(key);
+        }
+        is_valid
+    }
+}
+
+/// Maximum number of successful password verifications cached, the oldest ones being dropped first.
+const MAX_VERIFIED: usize = 1000;
+
+/// Successful password verifications, identified by a keyed digest of the username, the password
+/// and the hash it matched: a changed hash (e.g. once the htpasswd file is reloaded) is thus
+/// verified again.
+struct VerifiedCache {
+    /// Key of the digests, generated when the server starts (so that the passwords cannot be
+    /// guessed from the cache)
+    key: hmac::Key,
+    verified: sync::Mutex<collections::HashMap<Vec<u8>, time::Instant>>,
+}
+
+impl VerifiedCache {
+    fn new() -> Self {
+        let mut key = [0; 32];
+        OsRng.fill_bytes(&mut key);
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+            verified: sync::Mutex::new(collections::HashMap::new()),
+        }
+    }
+
+    fn key(&self, username: &str, password: &str, hash: &str) -> Vec<u8> {
+        let mut context = hmac::Context::with_key(&self.key);
+        // each field is prefixed with its length, so that they cannot be shifted
+        for field in [username, password, hash] {
+            context.update(&(field.len() as u64).to_be_bytes());
+            context.update(field.as_bytes());
+        }
+        context.sign().as_ref().to_vec()
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.verified.lock().unwrap().contains_key(key)
+    }
+
+    fn insert(&self, key: Vec<u8>) {
+        let mut verified = self.verified.lock().unwrap();
+        if verified.len() >= MAX_VERIFIED
+            && let Some(oldest) = verified
+                .iter()
+                .min_by_key(|(_, verified)| **verified)
+                .map(|(key, _)| key.clone())
+        {
+            verified.remove(&oldest);
+        }
+        verified.insert(key, time::Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time;
+
+    #[test]
+    fn password_hash_test() {
+        // test vectors of the bcrypt and SHA-crypt specifications
+        for (hash, password) in [
+            (
+                "$2y$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe",
+                "password",
+            ),
+            (
+                "$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5",
+                "Hello world!",
+            ),
+            (
+                "$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1",
+                "Hello world!",
+            ),
+        ] {
+            let hash: PasswordHash = hash.parse().unwrap();
+            assert!(hash.verify(password));
+            assert!(!hash.verify("hello world!"));
+        }
+
+        assert!("password".parse::<PasswordHash>().is_err());
+        assert!(
+            "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g="
+                .parse::<PasswordHash>()
+                .is_err()
+        );
+        assert!(
+            "$1$5pZSV9va$azfrPr6af3Fc7dLblQXVa0"
+                .parse::<PasswordHash>()
+                .is_err()
+        );
+        assert!("$argon2id$invalid".parse::<PasswordHash>().is_err());
+
+        for scheme in [
+            HashScheme::Bcrypt,
+            HashScheme::Sha256Crypt,
+            HashScheme::Sha512Crypt,
+            HashScheme::Argon2,
+        ] {
+            let hash: PasswordHash = scheme.hash("secret").unwrap().parse().unwrap();
+            assert_eq!(hash.scheme(), scheme);
+            assert!(hash.verify("secret"));
+            assert!(!hash.verify("Secret"));
+            assert!(!hash.verify(""));
+        }
+        assert!(
+            HashScheme::Bcrypt
+                .hash("secret")
+                .unwrap()
+                .starts_with("$2y$")
+        );
+        assert_eq!("argon2".parse(), Ok(HashScheme::Argon2));
+        assert!("md5".parse::<HashScheme>().is_err());
+    }
+
+    #[test]
+    fn htpasswd_test() {
+        let htpasswd: Htpasswd =
+            "# users\n\nfoo:$2y$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe\n"
+                .parse()
+                .unwrap();
+        assert_eq!(htpasswd.len(), 1);
+        assert!(htpasswd.verify("foo", "password"));
+        assert!(!htpasswd.verify("foo", "wrong"));
+        assert!(!htpasswd.verify("bar", "password"));
+
+        assert_eq!("".parse(), Ok(Htpasswd::default()));
+        assert_eq!(
+            "foo:bar".parse::<Htpasswd>(),
+            Err(String::from(
+                "line 1: Unsupported password hash (expected bcrypt, SHA-crypt or argon2)"
+            ))
+        );
+        assert!(":$5$salt$hash".parse::<Htpasswd>().is_err());
+        assert!("foo".parse::<Htpasswd>().is_err());
+        assert!("foo:$5$a$b\nfoo:$6$a$b".parse::<Htpasswd>().is_err());
+    }
+
+    #[test]
+    fn htgroup_test() {
+        let htgroup: Htgroup = "# groups\n\nadmins: alice bob\nstaff:carol\nnobody:\n"
+            .parse()
+            .unwrap();
+        assert_eq!(htgroup.len(), 3);
+        assert!(htgroup.is_member("alice", "admins"));
+        assert!(htgroup.is_member("bob", "admins"));
+        assert!(htgroup.is_member("carol", "staff"));
+        assert!(!htgroup.is_member("carol", "admins"));
+        assert!(!htgroup.is_member("alice", "nobody"));
+        assert!(!htgroup.is_member("alice", "others"));
+
+        assert_eq!("".parse(), Ok(Htgroup::default()));
+        assert!("admins alice".parse::<Htgroup>().is_err());
+        assert!(": alice".parse::<Htgroup>().is_err());
+        assert!("admins: alice\nadmins: bob".parse::<Htgroup>().is_err());
+    }
+
+    #[test]
+    fn credential_store_test() {
+        assert!(CredentialStore::new(None, None).unwrap().is_none());
+        assert!(matches!(
+            CredentialStore::new(None, Some(path::Path::new("ssl/none.htpasswd"))),
+            Err(Error::Io(..))
+        ));
+
+        let dir = std::env::temp_dir().join(format!("credentials-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("users.htpasswd");
+        fs::write(&file, "invalid").unwrap();
+        assert!(matches!(
+            CredentialStore::new(None, Some(&file)),
+            Err(Error::Parse(..))
+        ));
+
+        let hash = HashScheme::Sha256Crypt.hash("file-pass").unwrap();
+        fs::write(&file, format!("file-user:{}\n", hash)).unwrap();
+        let inline = [(String::from("inline-user"), String::from("inline-pass"))];
+        let store = CredentialStore::new(Some(&inline), Some(&file))
+            .unwrap()
+            .unwrap();
+        assert!(store.verify("inline-user", "inline-pass"));
+        assert!(!store.verify("inline-user", "inline-pas"));
+        assert!(store.verify("file-user", "file-pass"));
+        assert!(!store.verify("file-user", "inline-pass"));
+        assert!(!store.verify("other-user", "file-pass"));
+        // successful verifications are cached
+        assert_eq!(store.verified.verified.lock().unwrap().len(), 1);
+        assert!(store.verify("file-user", "file-pass"));
+        assert_eq!(store.verified.verified.lock().unwrap().len(), 1);
+
+        // the file is reloaded when it changes, unless it becomes invalid
+        let touch = |secs| {
+            fs::File::options()
+                .write(true)
+                .open(&file)
+                .unwrap()
+                .set_modified(time::SystemTime::now() + time::Duration::from_secs(secs))
+                .unwrap()
+        };
+        let hash = HashScheme::Sha256Crypt.hash("new-pass").unwrap();
+        fs::write(&file, format!("file-user:{}\n", hash)).unwrap();
+        touch(1);
+        assert!(store.verify("file-user", "new-pass"));
+        assert!(!store.verify("file-user", "file-pass"));
+        fs::write(&file, "invalid").unwrap();
+        touch(2);
+        assert!(store.verify("file-user", "new-pass"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+//! Running the server as a daemon: privilege dropping and PID file.
+
+use std::{fs, io, path};
+
+/// User ID of a user, given by name or ID.
+pub(crate) fn user_id(user: &str) -> io::Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    nix::unistd::User::from_name(user)?
+        .map(|user| user.uid.as_raw())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("unknown user: {}", user)))
+}
+
+/// Group ID of a group, given by name or ID.
+pub(crate) fn group_id(group: &str) -> io::Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    nix::unistd::Group::from_name(group)?
+        .map(|group| group.gid.as_raw())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("unknown group: {}", group)))
+}
+
+/// Run the process as another user and group (typically once privileged ports are bound).
+///
+/// Without a group, the primary group of the user is used. The supplementary groups are dropped.
+pub(crate) fn drop_privileges(user: Option<&str>, group: Option<&str>) -> io::Result<()> {
+    let uid = user.map(user_id).transpose()?;
+    let gid = match (group, uid) {
+        (Some(group), _) => Some(group_id(group)?),
+        (None, Some(uid)) => nix::unistd::User::from_uid(nix::unistd::Uid::from_raw(uid))?
+            .map(|user| user.gid.as_raw()),
+        (None, None) => None,
+    };
+    if let Some(gid) = gid.map(nix::unistd::Gid::from_raw) {
+        nix::unistd::setgroups(&[gid])?;
+        nix::unistd::setgid(gid)?;
+    }
+    if let Some(uid) = uid {
+        nix::unistd::setuid(nix::unistd::Uid::from_raw(uid))?;
+    }
+    Ok(())
+}
+
+/// File containing the ID of the server process, removed when dropped.
+pub(crate) struct PidFile {
+    path: path::PathBuf,
+}
+
+impl PidFile {
+    pub(crate) fn create(path: &path::Path) -> io::Result<Self> {
+        fs::write(path, format!("{}\n", std::process::id()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // the process may not be allowed to remove it anymore, once privileges are dropped
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_test() {
+        assert_eq!(user_id("root").unwrap(), 0);
+        assert_eq!(user_id("1000").unwrap(), 1000);
+        assert!(user_id("unknown-user").is_err());
+        assert_eq!(group_id("root").unwrap(), 0);
+        assert_eq!(group_id("1000").unwrap(), 1000);
+        assert!(group_id("unknown-group").is_err());
+    }
+
+    #[test]
+    fn pid_file_test() {
+        let path = std::env::temp_dir().join(format!("pid-file-test-{}", std::process::id()));
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+        assert!(PidFile::create(&path.join("none")).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+//! Full-screen terminal dashboard of the server: live request and error rates, busiest paths,
+//! active connections and latest log messages.
+//!
+//! The messages of the server are then kept for the dashboard instead of being written to the
+//! terminal, and the debug ones can be toggled at runtime.
+
+use crate::status::{self, ServerStatus, Snapshot};
+use crate::utils;
+
+use log::info;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{cmp, sync, thread, time};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::IntoAlternateScreen;
+
+/// Interval between two refreshes of the dashboard (and of its rates).
+const REFRESH_INTERVAL: time::Duration = time::Duration::from_secs(1);
+/// Number of log messages kept for the dashboard.
+const MAX_LOG_LINES: usize = 500;
+/// Module whose debug messages are toggled.
+const DEBUG_MODULE: &str = "rust_http_server";
+const KEYS: &str = "<d> toggle debug logging   <x> drain the server   <q> quit";
+
+/// Latest log messages of the server, shown by the dashboard.
+pub struct Logs {
+    lines: sync::Mutex<VecDeque<String>>,
+    debug: AtomicBool,
+}
+
+/// Logger writing the messages to the dashboard, with or without the debug ones of the server.
+struct Logger {
+    logs: sync::Arc<Logs>,
+    logger: env_logger::Logger,
+    debug_logger: env_logger::Logger,
+}
+
+/// Output of a logger, split in lines.
+struct LogWriter(sync::Arc<Logs>);
+
+/// Rates of the requests (per second) over the last refresh interval.
+#[derive(Debug, Default, Clone, Copy)]
+struct Rates {
+    requests: f64,
+    client_errors: f64,
+    server_errors: f64,
+}
+
+impl Logs {
+    fn push(&self, line: &str) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == MAX_LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(String::from(line));
+    }
+
+    /// Latest log messages, at most `count` of them.
+    fn latest(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+
+    fn is_debug(&self) -> bool {
+        self.debug.load(Ordering::Relaxed)
+    }
+
+    fn toggle_debug(&self) {
+        let debug = !self.debug.fetch_xor(true, Ordering::Relaxed);
+        info!(
+            "Debug logging {}",
+            if debug { "enabled" } else { "disabled" }
+        );
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.current().enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.current().log(record);
+    }
+
+    fn flush(&self) {}
+}
+
+impl Logger {
+    fn current(&self) -> &env_logger::Logger {
+        match self.logs.is_debug() {
+            true => &self.debug_logger,
+            false => &self.logger,
+        }
+    }
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in String::from_utf8_lossy(buf).lines() {
+            self.0.push(line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Set up the logger of the dashboard (configured by the `RUST_LOG` environment variable, like the
+/// default one), instead of writing the messages to the terminal.
+pub fn init_logger() -> Result<sync::Arc<Logs>, log::SetLoggerError> {
+    let logs = sync::Arc::new(Logs {
+        lines: sync::Mutex::new(VecDeque::new()),
+        debug: AtomicBool::new(false),
+    });
+    let build = |debug: bool| {
+        let mut builder = env_logger::Builder::from_default_env();
+        if debug {
+            builder.filter_module(DEBUG_MODULE, log::LevelFilter::Debug);
+        }
+        builder
+            .target(env_logger::Target::Pipe(Box::new(LogWriter(logs.clone()))))
+            .write_style(env_logger::WriteStyle::Never)
+            .build()
+    };
+    let logger = Logger {
+        logs: logs.clone(),
+        logger: build(false),
+        debug_logger: build(true),
+    };
+    let max_level = cmp::max(logger.logger.filter(), logger.debug_logger.filter());
+    log::set_boxed_logger(Box::new(logger))?;
+    log::set_max_level(max_level);
+    Ok(logs)
+}
+
+/// Show the dashboard until <q> is pressed, the terminal being restored then.
+///
+/// The server can be drained from the dashboard, which is then still shown while the open
+/// connections finish.
+pub async fn run(status: sync::Arc<ServerStatus>, logs: sync::Arc<Logs>) -> io::Result<()> {
+    let mut screen = io::stdout().into_raw_mode()?.into_alternate_screen()?;
+    write!(screen, "{}", termion::cursor::Hide)?;
+
+    // keys are read from a blocking thread
+    let (keys_tx, mut keys) = tokio::sync::mpsc::unbounded_channel();
+    thread::spawn(move || {
+        for key in io::stdin().keys() {
+            match key {
+                Ok(key) if keys_tx.send(key).is_ok() => (),
+                _ => break,
+            }
+        }
+    });
+
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    let mut previous: Option<(time::Instant, Snapshot)> = None;
+    let mut rates = Rates::default();
+    let result = loop {
+        let mut refresh_rates = false;
+        tokio::select! {
+            _ = interval.tick() => refresh_rates = true,
+            key = keys.recv() => match key {
+                Some(Key::Char('q') | Key::Ctrl('c')) | None => break Ok(()),
+                Some(Key::Char('d')) => logs.toggle_debug(),
+                Some(Key::Char('x')) if !status.is_draining() => {
+                    info!("Draining the server, press <q> to stop it right away");
+                    status.drain();
+                }
+                _ => continue,
+            },
+        }
+
+        let snapshot = status.snapshot();
+        let now = time::Instant::now();
+        if refresh_rates && let Some((instant, previous)) = previous.as_ref() {
+            rates = Rates::between(previous, &snapshot, now - *instant);
+        }
+        let (width, height) = termion::terminal_size().unwrap_or((80, 24));
+        let lines = render(&snapshot, rates, &logs, width.into(), height.into());
+        let drawn = write!(screen, "{}", termion::clear::All).and_then(|()| {
+            for (row, line) in lines.iter().enumerate() {
+                write!(
+                    screen,
+                    "{}{}",
+                    termion::cursor::Goto(1, row as u16 + 1),
+                    line
+                )?;
+            }
+            screen.flush()
+        });
+        if let Err(err) = drawn {
+            break Err(err);
+        }
+        if refresh_rates {
+            previous = Some((now, snapshot));
+        }
+    };
+    write!(screen, "{}", termion::cursor::Show)?;
+    screen.flush()?;
+    result
+}
+
+impl Rates {
+    fn between(previous: &Snapshot, current: &Snapshot, elapsed: time::Duration) -> Self {
+        let secs = elapsed.as_secs_f64().max(0.001);
+        let rate = |previous: u64, current: u64| current.saturating_sub(previous) as f64 / secs;
+        Self {
+            requests: rate(previous.requests, current.requests),
+            client_errors: rate(previous.client_errors, current.client_errors),
+            server_errors: rate(previous.server_errors, current.server_errors),
+        }
+    }
+}
+
+/// Lines of the dashboard, fitting in a terminal of the given size.
+fn render(
+    snapshot: &Snapshot,
+    rates: Rates,
+    logs: &Logs,
+    width: usize,
+    height: usize,
+) -> Vec<String> {
+    let bold = termion::style::Bold;
+    let reset = termion::style::Reset;
+    let mut lines = vec![
+        format!(
+            "{}rust-http-server{}   up {}   {} connection(s){}",
+            bold,
+            reset,
+            status::format_duration(snapshot.uptime),
+            snapshot.connections.len(),
+            if snapshot.draining { "   DRAINING" } else { "" }
+        ),
+        format!(
+            "Requests: {:.1}/s ({} total)   Errors: {:.1}/s client, {:.1}/s server ({:.1}% overall)",
+            rates.requests,
+            snapshot.requests,
+            rates.client_errors,
+            rates.server_errors,
+            status::error_ratio(
+                snapshot.requests,
+                snapshot.client_errors + snapshot.server_errors
+            ),
+        ),
+        String::new(),
+        format!("{}Top paths{}", bold, reset),
+    ];
+    lines.extend(
+        snapshot
+            .top_paths
+            .iter()
+            .map(|(path, count)| format!("{:>8}  {}", count, utils::escape(path))),
+    );
+
+    // the connections and the log messages share the rest of the screen (but for the keys)
+    let remaining = height.saturating_sub(lines.len() + 5);
+    let connections = cmp::min(snapshot.connections.len(), remaining / 2);
+    lines.push(String::new());
+    lines.push(format!(
+        "{}Connections{}   client / connected for / requests / bytes sent / state / duration / request",
+        bold, reset
+    ));
+    // the longest running requests first
+    let mut by_duration = snapshot.connections.iter().collect::<Vec<_>>();
+    by_duration.sort_by_key(|connection| cmp::Reverse((connection.busy, connection.duration)));
+    lines.extend(by_duration.iter().take(connections).map(|connection| {
+        format!(
+            "{}  {}  {}  {}  {}  {:.3}s  {}",
+            connection.client,
+            status::format_duration(connection.age),
+            connection.requests,
+            connection.bytes_sent,
+            if connection.busy { "serving" } else { "idle" },
+            connection.duration.as_secs_f64(),
+            match connection.request.as_ref() {
+                Some((line, Some(host))) =>
+                    format!("{} {}", utils::escape(host), utils::escape(line)),
+                Some((line, None)) => utils::escape(line),
+                None => String::new(),
+            },
+        )
+    }));
+    lines.push(String::new());
+    lines.push(format!(
+        "{}Log{} (debug {})",
+        bold,
+        reset,
+        if logs.is_debug() { "on" } else { "off" }
+    ));
+    lines.extend(
+        logs.latest(remaining - connections)
+            .iter()
+            .map(|line| utils::escape(line)),
+    );
+    lines.truncate(height.saturating_sub(1));
+    lines.push(String::from(KEYS));
+
+    // the lines are cut to the width of the terminal, formatting aside
+    lines
+        .into_iter()
+        .map(|line| {
+            let visible = line
+                .replace(&bold.to_string(), "")
+                .replace(&reset.to_string(), "");
+            match visible.chars().count() > width {
+                true => visible.chars().take(width).collect(),
+                false => line,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_test() {
+        let logs = Logs {
+            lines: sync::Mutex::new(VecDeque::new()),
+            debug: AtomicBool::new(false),
+        };
+        for i in 0..MAX_LOG_LINES + 2 {
+            logs.push(&i.to_string());
+        }
+        assert_eq!(
+            logs.latest(2),
+            vec![(MAX_LOG_LINES).to_string(), (MAX_LOG_LINES + 1).to_string()]
+        );
+        assert_eq!(logs.latest(MAX_LOG_LINES + 10).len(), MAX_LOG_LINES);
+        assert!(!logs.is_debug());
+        logs.toggle_debug();
+        assert!(logs.is_debug());
+        logs.toggle_debug();
+        assert!(!logs.is_debug());
+
+        let mut writer = LogWriter(sync::Arc::new(logs));
+        writer.write_all(b"first\nsecond\n").unwrap();
+        assert_eq!(writer.0.latest(2), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn render_test() {
+        let status = sync::Arc::new(ServerStatus::new());
+        let _slot = status.open("127.0.0.1:4000");
+        let previous = status.snapshot();
+        let logs = Logs {
+            lines: sync::Mutex::new(VecDeque::from([String::from("[INFO] Server listening")])),
+            debug: AtomicBool::new(false),
+        };
+        let mut snapshot = status.snapshot();
+        snapshot.requests = 20;
+        snapshot.server_errors = 5;
+        snapshot.top_paths = vec![
+            (String::from("/index.html"), 15),
+            (String::from("/\x1b[2J"), 1),
+        ];
+        let rates = Rates::between(&previous, &snapshot, time::Duration::from_secs(2));
+        assert_eq!(rates.requests, 10.0);
+        assert_eq!(rates.server_errors, 2.5);
+
+        let lines = render(&snapshot, rates, &logs, 200, 24);
+        assert_eq!(lines.len(), 13);
+        assert!(lines[0].contains("1 connection(s)"));
+        assert!(!lines[0].contains("DRAINING"));
+        assert!(lines[1].starts_with("Requests: 10.0/s (20 total)"));
+        assert!(lines[1].contains("2.5/s server (25.0% overall)"));
+        assert_eq!(lines[4], "      15  /index.html");
+        // the escape sequences sent by clients are not written to the terminal
+        assert_eq!(lines[5], "       1  /\\x1b[2J");
+        assert!(lines[8].starts_with("127.0.0.1:4000  00:00:00  0  0  idle"));
+        assert!(lines[10].contains("(debug off)"));
+        assert_eq!(lines[11], "[INFO] Server listening");
+        assert_eq!(lines[12], KEYS);
+
+        // small terminals only get the first lines, and the keys
+        let lines = render(&snapshot, rates, &logs, 20, 4);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1], "Requests: 10.0/s (20");
+        assert_eq!(lines[3], "<d> toggle debug log");
+    }
+}
@@ -0,0 +1,420 @@
+//! HTTP Digest authentication (RFC 7616), with the `auth` quality of protection: credentials sent
+//! by clients, and nonces issued by the server.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use aws_lc_rs::hmac;
+use std::{collections, fmt, str::FromStr, sync, time};
+use subtle::ConstantTimeEq;
+
+/// Lifetime of the nonces issued by the server, after which clients must use a new one.
+pub const NONCE_LIFETIME: time::Duration = time::Duration::from_secs(300);
+
+/// Maximum number of used nonces tracked at once (the ones expiring first are forgotten beyond).
+const MAX_NONCES: usize = 10000;
+/// Length of the signed part of a nonce (issue time and random bytes).
+const NONCE_DATA_LEN: usize = 16;
+/// Length of the (truncated) signature of a nonce.
+const NONCE_SIGNATURE_LEN: usize = 16;
+
+/// Hash algorithm of the digests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    /// SHA-256, preferred
+    Sha256,
+    /// MD5, for legacy clients
+    Md5,
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "SHA-256" => Ok(Self::Sha256),
+            "MD5" => Ok(Self::Md5),
+            _ => Err(format!("Unsupported digest algorithm: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256 => f.write_str("SHA-256"),
+            Self::Md5 => f.write_str("MD5"),
+        }
+    }
+}
+
+impl Algorithm {
+    /// Hash some data, in lowercase hexadecimal.
+    fn hash(&self, data: &str) -> String {
+        let digest = match self {
+            Self::Sha256 => <sha2::Sha256 as sha2::Digest>::digest(data.as_bytes()).to_vec(),
+            Self::Md5 => <md5::Md5 as md5::Digest>::digest(data.as_bytes()).to_vec(),
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Credentials of an Authorization header with the Digest scheme.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DigestCredentials {
+    pub username: String,
+    pub realm: String,
+    pub nonce: String,
+    /// Target of the request the response was computed for
+    pub uri: String,
+    pub algorithm: Algorithm,
+    /// Nonce count: number of requests made by the client with the nonce
+    pub nc: u32,
+    pub cnonce: String,
+    pub response: String,
+}
+
+/// Split the parameters of an authentication scheme (e.g. `realm="simple", qop=auth`) into
+/// names (lowercase) and values (unquoted).
+fn parse_auth_params(s: &str) -> Option<collections::HashMap<String, String>> {
+    let mut params = collections::HashMap::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ',' || c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Some(params);
+        }
+        let name: String = chars
+            .by_ref()
+            .take_while(|c| *c != '=')
+            .collect::<String>()
+            .trim()
+            .to_ascii_lowercase();
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => value.push(chars.next()?),
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                value.push(c);
+            }
+            value = String::from(value.trim());
+        }
+        if name.is_empty() || params.insert(name, value).is_some() {
+            return None;
+        }
+    }
+}
+
+impl FromStr for DigestCredentials {
+    type Err = String;
+
+    /// Parse the parameters of the Digest scheme (i.e. what follows `Digest `).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid digest credentials: {}", s);
+        let mut params = parse_auth_params(s).ok_or_else(invalid)?;
+        let mut take = |name: &str| params.remove(name).ok_or_else(invalid);
+        // only the auth quality of protection is offered
+        if take("qop")? != "auth" {
+            return Err(invalid());
+        }
+        Ok(Self {
+            username: take("username")?,
+            realm: take("realm")?,
+            nonce: take("nonce")?,
+            uri: take("uri")?,
+            algorithm: match take("algorithm") {
+                Ok(algorithm) => algorithm.parse()?,
+                Err(_) => Algorithm::Md5,
+            },
+            nc: take("nc").and_then(|nc| u32::from_str_radix(&nc, 16).map_err(|_| invalid()))?,
+            cnonce: take("cnonce")?,
+            response: take("response")?,
+        })
+    }
+}
+
+/// Quote a value of an authentication parameter.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl fmt::Display for DigestCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Digest username={}, realm={}, nonce={}, uri={}, algorithm={}, qop=auth, nc={:08x}, \
+             cnonce={}, response={}",
+            quote(&self.username),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(&self.uri),
+            self.algorithm,
+            self.nc,
+            quote(&self.cnonce),
+            quote(&self.response)
+        )
+    }
+}
+
+impl DigestCredentials {
+    /// Compute the response matching a password, for a request method.
+    pub fn expected_response(&self, password: &str, method: &str) -> String {
+        let ha1 = self
+            .algorithm
+            .hash(&format!("{}:{}:{}", self.username, self.realm, password));
+        let ha2 = self.algorithm.hash(&format!("{}:{}", method, self.uri));
+        self.algorithm.hash(&format!(
+            "{}:{}:{:08x}:{}:auth:{}",
+            ha1, self.nonce, self.nc, self.cnonce, ha2
+        ))
+    }
+
+    /// Check whether the response was computed with a password, for a request method. The
+    /// responses are compared in constant time.
+    pub fn verify(&self, password: &str, method: &str) -> bool {
+        self.expected_response(password, method)
+            .as_bytes()
+            .ct_eq(self.response.to_ascii_lowercase().as_bytes())
+            .into()
+    }
+}
+
+/// Challenge of a WWW-Authenticate header, asking for digest credentials in a realm.
+pub fn challenge(realm: &str, algorithm: Algorithm, nonce: &str, stale: bool) -> String {
+    format!(
+        "Digest realm={}, qop=\"auth\", algorithm={}, nonce={}{}",
+        quote(realm),
+        algorithm,
+        quote(nonce),
+        if stale { ", stale=true" } else { "" }
+    )
+}
+
+/// Outcome of the use of a nonce by a client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum NonceUse {
+    Valid,
+    /// The nonce expired (or was not issued by the server)
+    Stale,
+    /// The nonce count was already used (or a greater one), i.e. the request is replayed
+    Replayed,
+}
+
+/// Nonces issued by the server, and the greatest nonce count used by clients with each one.
+///
+/// Nonces are stateless: each one carries its issue time and a random part, signed with a key
+/// generated when the server starts. Only the nonces used with valid credentials are tracked, to
+/// reject replayed requests, so that issuing nonces (to any client) costs no memory.
+pub(crate) struct Nonces {
+    lifetime: time::Duration,
+    key: hmac::Key,
+    /// Time the issue times of the nonces are relative to
+    started: time::Instant,
+    used: sync::Mutex<collections::HashMap<String, (time::Instant, u32)>>,
+}
+
+impl Nonces {
+    pub(crate) fn new(lifetime: time::Duration) -> Self {
+        let mut key = [0; 32];
+        OsRng.fill_bytes(&mut key);
+        Self {
+            lifetime,
+            key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+            started: time::Instant::now(),
+            used: sync::Mutex::new(collections::HashMap::new()),
+        }
+    }
+
+    /// Issue a new nonce: its issue time (in milliseconds) and a random part, followed by their
+    /// signature, in hexadecimal.
+    pub(crate) fn issue(&self) -> String {
+        let mut bytes = [0; NONCE_DATA_LEN];
+        let issued = self.started.elapsed().as_millis() as u64;
+        bytes[..8].copy_from_slice(&issued.to_be_bytes());
+        OsRng.fill_bytes(&mut bytes[8..]);
+        let signature = hmac::sign(&self.key, &bytes);
+        bytes
+            .iter()
+            .chain(&signature.as_ref()[..NONCE_SIGNATURE_LEN])
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Expiry of a nonce issued by the server, if it is one.
+    fn expiry(&self, nonce: &str) -> Option<time::Instant> {
+        if nonce.len() != 2 * (NONCE_DATA_LEN + NONCE_SIGNATURE_LEN) || !nonce.is_ascii() {
+            return None;
+        }
+        let bytes = (0..nonce.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&nonce[i..i + 2], 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        let (data, signature) = bytes.split_at(NONCE_DATA_LEN);
+        let expected = hmac::sign(&self.key, data);
+        if !bool::from(expected.as_ref()[..NONCE_SIGNATURE_LEN].ct_eq(signature)) {
+            return None;
+        }
+        let issued = u64::from_be_bytes(data[..8].try_into().ok()?);
+        Some(self.started + time::Duration::from_millis(issued) + self.lifetime)
+    }
+
+    /// Use a nonce with a nonce count, which must be greater than the previous ones.
+    pub(crate) fn use_nonce(&self, nonce: &str, nc: u32) -> NonceUse {
+        let now = time::Instant::now();
+        let Some(expiry) = self.expiry(nonce).filter(|expiry| *expiry > now) else {
+            self.used.lock().unwrap().remove(nonce);
+            return NonceUse::Stale;
+        };
+        let mut used = self.used.lock().unwrap();
+        if let Some((_, last_nc)) = used.get_mut(nonce) {
+            if nc <= *last_nc {
+                return NonceUse::Replayed;
+            }
+            *last_nc = nc;
+            return NonceUse::Valid;
+        }
+        if used.len() >= MAX_NONCES {
+            used.retain(|_, (expiry, _)| *expiry > now);
+        }
+        if used.len() >= MAX_NONCES
+            && let Some(oldest) = used
+                .iter()
+                .min_by_key(|(_, (expiry, _))| *expiry)
+                .map(|(nonce, _)| nonce.clone())
+        {
+            used.remove(&oldest);
+        }
+        used.insert(String::from(nonce), (expiry, nc));
+        NonceUse::Valid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest_credentials_test() {
+        // examples of RFC 7616 (section 3.9.1)
+        let header = r#"username="Mufasa",
+            realm="http-auth@example.org",
+            uri="/dir/index.html",
+            algorithm=SHA-256,
+            nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v",
+            nc=00000001,
+            cnonce="f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+            qop=auth,
+            response="753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+            opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#;
+        let creds: DigestCredentials = header.parse().unwrap();
+        assert_eq!(creds.username, "Mufasa");
+        assert_eq!(creds.algorithm, Algorithm::Sha256);
+        assert_eq!(creds.nc, 1);
+        assert!(creds.verify("Circle of Life", "GET"));
+        assert!(!creds.verify("Circle of life", "GET"));
+        assert!(!creds.verify("Circle of Life", "POST"));
+
+        let creds: DigestCredentials = header
+            .replace("SHA-256", "MD5")
+            .replace(
+                "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+                "8ca523f5e9506fed4657c9700eebdbec",
+            )
+            .parse()
+            .unwrap();
+        assert_eq!(creds.algorithm, Algorithm::Md5);
+        assert!(creds.verify("Circle of Life", "GET"));
+
+        // written back with the same parameters
+        assert_eq!(
+            creds.to_string()["Digest ".len()..].parse(),
+            Ok(creds.clone())
+        );
+        let creds: DigestCredentials = r#"username="a \"b\"", realm=r, nonce=n, uri="/", qop=auth, nc=0000000a, cnonce=c, response=x"#
+            .parse()
+            .unwrap();
+        assert_eq!(creds.username, "a \"b\"");
+        assert_eq!(creds.algorithm, Algorithm::Md5);
+        assert_eq!(creds.nc, 10);
+        assert_eq!(
+            creds.to_string()["Digest ".len()..].parse(),
+            Ok(creds.clone())
+        );
+
+        assert!(
+            header
+                .replace("qop=auth", "qop=auth-int")
+                .parse::<DigestCredentials>()
+                .is_err()
+        );
+        assert!(
+            header
+                .replace("SHA-256", "SHA-512-256")
+                .parse::<DigestCredentials>()
+                .is_err()
+        );
+        assert!(
+            header
+                .replace("00000001", "g")
+                .parse::<DigestCredentials>()
+                .is_err()
+        );
+        assert!(
+            header
+                .replace("cnonce", "c")
+                .parse::<DigestCredentials>()
+                .is_err()
+        );
+        assert!(r#"username="Mufasa"#.parse::<DigestCredentials>().is_err());
+        assert!(
+            "username=a, username=b"
+                .parse::<DigestCredentials>()
+                .is_err()
+        );
+        assert!("".parse::<DigestCredentials>().is_err());
+    }
+
+    #[test]
+    fn challenge_test() {
+        assert_eq!(
+            challenge("simple", Algorithm::Sha256, "abc", false),
+            r#"Digest realm="simple", qop="auth", algorithm=SHA-256, nonce="abc""#
+        );
+        assert_eq!(
+            challenge("a \"b\"", Algorithm::Md5, "abc", true),
+            r#"Digest realm="a \"b\"", qop="auth", algorithm=MD5, nonce="abc", stale=true"#
+        );
+    }
+
+    #[test]
+    fn nonces_test() {
+        let nonces = Nonces::new(time::Duration::from_millis(100));
+        let nonce = nonces.issue();
+        assert_eq!(nonce.len(), 64);
+        assert_ne!(nonces.issue(), nonce);
+        // issuing nonces costs no memory
+        assert!(nonces.used.lock().unwrap().is_empty());
+
+        assert_eq!(nonces.use_nonce(&nonce, 1), NonceUse::Valid);
+        assert_eq!(nonces.use_nonce(&nonce, 1), NonceUse::Replayed);
+        assert_eq!(nonces.use_nonce(&nonce, 3), NonceUse::Valid);
+        assert_eq!(nonces.use_nonce(&nonce, 2), NonceUse::Replayed);
+        assert_eq!(nonces.use_nonce("unknown", 1), NonceUse::Stale);
+        // nonces must be signed by the server
+        let mut forged = nonce.clone();
+        forged.replace_range(..1, if nonce.starts_with('0') { "1" } else { "0" });
+        assert_eq!(nonces.use_nonce(&forged, 1), NonceUse::Stale);
+        let other = Nonces::new(time::Duration::from_millis(100));
+        assert_eq!(nonces.use_nonce(&other.issue(), 1), NonceUse::Stale);
+
+        std::thread::sleep(time::Duration::from_millis(150));
+        assert_eq!(nonces.use_nonce(&nonce, 4), NonceUse::Stale);
+        // expired nonces are forgotten
+        assert!(nonces.used.lock().unwrap().is_empty());
+    }
+}
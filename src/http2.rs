@@ -0,0 +1,512 @@
+//! HTTP/2 support (RFC 9113).
+//!
+//! HTTP/2 is negotiated with ALPN over TLS (`h2`), or used with prior knowledge over plain TCP
+//! (`h2c`, detected with the connection preface). Each HTTP/2 stream is turned into an HTTP/1.1
+//! request and served by a regular client handler over an in-memory connection; its response is
+//! then turned back into an HTTP/2 response. This way, every feature of the server (static files,
+//! PHP, authentication, proxying...) behaves the same with both protocol versions, while streams
+//! are served concurrently.
+
+use crate::client;
+use crate::http_res::HttpRes;
+use crate::res_parser::{self, ResBodyLength};
+
+use bytes::Bytes;
+use log::{debug, warn};
+use std::{fmt, future, io, pin, task};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Connection preface sent by HTTP/2 clients.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+/// ALPN protocol identifier of HTTP/2 over TLS.
+pub const ALPN_H2: &[u8] = b"h2";
+/// ALPN protocol identifier of HTTP/1.1.
+pub const ALPN_HTTP1: &[u8] = b"http/1.1";
+/// Size of the in-memory connection buffer between a stream and its handler.
+pub(crate) const LOCAL_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Connection-specific headers, which are forbidden in HTTP/2 (see RFC 9113, section 8.2.2).
+const CONNECTION_HEADERS: [&str; 6] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+    "te",
+];
+
+#[derive(Debug)]
+pub enum Error {
+    H2(h2::Error),
+    Io(io::Error),
+    /// The response of the handler cannot be read
+    Client(client::Error),
+    Body(res_parser::ResHeadParsingError),
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::H2(e) => write!(f, "HTTP/2 error: {}", e),
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Client(e) => write!(f, "cannot read response: {}", e),
+            Self::Body(e) => write!(f, "cannot read response body: {:?}", e),
+        }
+    }
+}
+
+/// A stream whose first bytes were already read, and are replayed before reading the rest.
+pub(crate) struct Prefixed<S> {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: S,
+}
+
+impl<S> Prefixed<S> {
+    pub(crate) fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for Prefixed<S> {
+    fn poll_read(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> task::Poll<io::Result<()>> {
+        if self.position < self.prefix.len() {
+            let len = buf.remaining().min(self.prefix.len() - self.position);
+            buf.put_slice(&self.prefix[self.position..self.position + len]);
+            self.position += len;
+            return task::Poll::Ready(Ok(()));
+        }
+        pin::Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for Prefixed<S> {
+    fn poll_write(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> task::Poll<io::Result<usize>> {
+        pin::Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> task::Poll<io::Result<()>> {
+        pin::Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> task::Poll<io::Result<()>> {
+        pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// In-memory connection between an HTTP/2 stream and the client handler serving it.
+pub(crate) struct LocalStream {
+    inner: tokio::io::DuplexStream,
+    /// Whether the HTTP/2 connection is encrypted
+    pub(crate) secure: bool,
+}
+
+impl tokio::io::AsyncRead for LocalStream {
+    fn poll_read(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> task::Poll<io::Result<()>> {
+        pin::Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl tokio::io::AsyncWrite for LocalStream {
+    fn poll_write(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> task::Poll<io::Result<usize>> {
+        pin::Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> task::Poll<io::Result<()>> {
+        pin::Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> task::Poll<io::Result<()>> {
+        pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Writer sending data on an HTTP/2 stream, waiting for flow control capacity as needed.
+struct BodyWriter {
+    stream: h2::SendStream<Bytes>,
+}
+
+impl tokio::io::AsyncWrite for BodyWriter {
+    fn poll_write(
+        mut self: pin::Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> task::Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return task::Poll::Ready(Ok(0));
+        }
+        self.stream.reserve_capacity(buf.len());
+        match self.stream.poll_capacity(cx) {
+            task::Poll::Ready(Some(Ok(len))) => {
+                let len = len.min(buf.len());
+                match self
+                    .stream
+                    .send_data(Bytes::copy_from_slice(&buf[..len]), false)
+                {
+                    Ok(()) => task::Poll::Ready(Ok(len)),
+                    Err(err) => task::Poll::Ready(Err(io::Error::other(err))),
+                }
+            }
+            task::Poll::Ready(Some(Err(err))) => task::Poll::Ready(Err(io::Error::other(err))),
+            task::Poll::Ready(None) => {
+                task::Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe)))
+            }
+            task::Poll::Pending => task::Poll::Pending,
+        }
+    }
+
+    fn poll_flush(
+        self: pin::Pin<&mut Self>,
+        _cx: &mut task::Context<'_>,
+    ) -> task::Poll<io::Result<()>> {
+        task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        mut self: pin::Pin<&mut Self>,
+        _cx: &mut task::Context<'_>,
+    ) -> task::Poll<io::Result<()>> {
+        task::Poll::Ready(
+            self.stream
+                .send_data(Bytes::new(), true)
+                .map_err(io::Error::other),
+        )
+    }
+}
+
+/// Read the first bytes of a plain connection, to detect whether it starts with the HTTP/2
+/// connection preface (h2c with prior knowledge).
+///
+/// Reading stops as soon as the bytes differ from the preface, so that HTTP/1.1 requests shorter
+/// than the preface are not waited for. The bytes read are replayed by the returned stream.
+pub(crate) async fn detect_preface<S: tokio::io::AsyncRead + Unpin>(
+    mut stream: S,
+) -> (bool, Prefixed<S>) {
+    let mut prefix = vec![0; PREFACE.len()];
+    let mut len = 0;
+    let is_h2 = loop {
+        match stream.read(&mut prefix[len..]).await {
+            Ok(0) | Err(_) => break false,
+            Ok(n) => len += n,
+        }
+        if !PREFACE.starts_with(&prefix[..len]) {
+            break false;
+        }
+        if len == PREFACE.len() {
+            break true;
+        }
+    };
+    prefix.truncate(len);
+    (
+        is_h2,
+        Prefixed {
+            prefix,
+            position: 0,
+            inner: stream,
+        },
+    )
+}
+
+/// Create an in-memory connection: one end is meant to be served by a client handler, the other
+/// one to send it a request.
+pub(crate) fn local_connection(secure: bool) -> (LocalStream, tokio::io::DuplexStream) {
+    let (local, remote) = tokio::io::duplex(LOCAL_BUFFER_SIZE);
+    (
+        LocalStream {
+            inner: local,
+            secure,
+        },
+        remote,
+    )
+}
+
+/// A response read from an in-memory connection.
+pub(crate) struct LocalResponse {
+    /// Response head, converted for HTTP/2 or HTTP/3
+    pub(crate) head: http::Response<()>,
+    pub(crate) body_length: ResBodyLength,
+    /// Client the response body can be read from
+    pub(crate) client: client::Client,
+}
+
+/// Send a request (HTTP/2 or HTTP/3) over an in-memory connection, and read the response head.
+pub(crate) async fn exchange(
+    parts: &http::request::Parts,
+    body: &[u8],
+    mut remote: tokio::io::DuplexStream,
+) -> Result<LocalResponse, Error> {
+    remote
+        .write_all(&build_req_head(parts, body.len()))
+        .await
+        .map_err(Error::Io)?;
+    remote.write_all(body).await.map_err(Error::Io)?;
+    remote.flush().await.map_err(Error::Io)?;
+
+    // the client is only used to read the response (its host is not needed)
+    let mut client = client::Client::from_stream("", Box::new(remote));
+    let mut res = client.read_res_head().await.map_err(Error::Client)?;
+    Ok(LocalResponse {
+        body_length: ResBodyLength::of(&mut res, parts.method == http::Method::HEAD),
+        head: build_res(&mut res),
+        client,
+    })
+}
+
+/// Serve an HTTP/2 connection, until the client closes it.
+///
+/// Every stream is served concurrently, by running `serve` on an in-memory connection (which is
+/// expected to handle HTTP/1.1 requests).
+pub(crate) async fn serve_connection<S, F, Fut>(
+    stream: S,
+    secure: bool,
+    serve: F,
+) -> Result<(), Error>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    F: Fn(LocalStream) -> Fut,
+    Fut: future::Future<Output = ()> + Send + 'static,
+{
+    let mut connection = h2::server::handshake(stream).await.map_err(Error::H2)?;
+    while let Some(result) = connection.accept().await {
+        let (req, respond) = result.map_err(Error::H2)?;
+        let (local, remote) = local_connection(secure);
+        tokio::spawn(serve(local));
+        tokio::spawn(async move {
+            if let Err(err) = serve_stream(req, respond, remote).await {
+                warn!("Cannot serve HTTP/2 stream: {}", err);
+            }
+        });
+    }
+    debug!("HTTP/2 connection closed");
+    Ok(())
+}
+
+/// Serve an HTTP/2 stream: send the request over the in-memory connection, and send the response
+/// read from it back to the client.
+async fn serve_stream(
+    req: http::Request<h2::RecvStream>,
+    mut respond: h2::server::SendResponse<Bytes>,
+    remote: tokio::io::DuplexStream,
+) -> Result<(), Error> {
+    let (parts, mut body) = req.into_parts();
+    // tunnels (e.g. extended CONNECT) are not supported over HTTP/2
+    if parts.method == http::Method::CONNECT {
+        let res = http::Response::builder()
+            .status(501)
+            .body(())
+            .unwrap_or_default();
+        respond.send_response(res, true).map_err(Error::H2)?;
+        return Ok(());
+    }
+
+    // the request body is read entirely, to provide its length
+    let mut req_body = Vec::new();
+    while let Some(data) = body.data().await {
+        let data = data.map_err(Error::H2)?;
+        let _ = body.flow_control().release_capacity(data.len());
+        req_body.extend_from_slice(&data);
+    }
+    let LocalResponse {
+        head,
+        body_length,
+        mut client,
+    } = exchange(&parts, &req_body, remote).await?;
+    let send_stream = respond
+        .send_response(head, body_length == ResBodyLength::None)
+        .map_err(Error::H2)?;
+    if body_length != ResBodyLength::None {
+        let mut writer = BodyWriter {
+            stream: send_stream,
+        };
+        res_parser::decode_res_body(client.stream(), &mut writer, &body_length)
+            .await
+            .map_err(Error::Body)?;
+        writer.shutdown().await.map_err(Error::Io)?;
+    }
+    Ok(())
+}
+
+/// Build the HTTP/1.1 request head matching an HTTP/2 (or HTTP/3) request.
+///
+/// The connection is closed once the request is served, and header fields sent several times are
+/// combined (e.g. cookies, which are split into several fields in HTTP/2).
+fn build_req_head(parts: &http::request::Parts, body_len: usize) -> Vec<u8> {
+    let target = parts
+        .uri
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+    let mut head = format!("{} {} HTTP/1.1\r\n", parts.method, target);
+    if !parts.headers.contains_key(http::header::HOST)
+        && let Some(authority) = parts.uri.authority()
+    {
+        head.push_str(&format!("host: {}\r\n", authority));
+    }
+    for name in parts.headers.keys() {
+        if CONNECTION_HEADERS.contains(&name.as_str()) || name == http::header::CONTENT_LENGTH {
+            continue;
+        }
+        let values = parts
+            .headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect::<Vec<_>>();
+        let separator = if name == http::header::COOKIE {
+            "; "
+        } else {
+            ", "
+        };
+        head.push_str(&format!("{}: {}\r\n", name, values.join(separator)));
+    }
+    if body_len != 0 {
+        head.push_str(&format!("content-length: {}\r\n", body_len));
+    }
+    head.push_str("connection: close\r\n\r\n");
+    head.into_bytes()
+}
+
+/// Build the HTTP/2 (or HTTP/3) response head matching an HTTP/1.1 response.
+fn build_res(res: &mut HttpRes) -> http::Response<()> {
+    let mut builder = http::Response::builder().status(res.status_code());
+    let raw_headers = res.raw_headers().map(String::from).unwrap_or_default();
+    let headers = res
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .chain(raw_headers.lines().filter_map(|line| {
+            line.split_once(':')
+                .map(|(name, value)| (String::from(name), String::from(value.trim())))
+        }));
+    for (name, value) in headers {
+        let name = name.to_ascii_lowercase();
+        if CONNECTION_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        builder = builder.header(name, value);
+    }
+    builder.body(()).unwrap_or_else(|err| {
+        warn!("Invalid response header: {}", err);
+        let mut res = http::Response::new(());
+        *res.status_mut() = http::StatusCode::INTERNAL_SERVER_ERROR;
+        res
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_header::{
+        EntityHeader, GeneralHeader, HeaderValue, ResHeader, SimpleHeaderValue,
+    };
+
+    #[tokio::test]
+    async fn detect_preface_test() {
+        // HTTP/1.1 requests are replayed, even when shorter than the preface
+        let (mut client, server) = tokio::io::duplex(1024);
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        let (is_h2, mut stream) = detect_preface(server).await;
+        assert!(!is_h2);
+        let mut buf = vec![0; 18];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, b"GET / HTTP/1.1\r\n\r\n");
+
+        // the preface may be received in several parts
+        let (mut client, server) = tokio::io::duplex(1024);
+        let task = tokio::spawn(detect_preface(server));
+        client.write_all(&PREFACE[..10]).await.unwrap();
+        tokio::task::yield_now().await;
+        client.write_all(&PREFACE[10..]).await.unwrap();
+        client.write_all(b"frames").await.unwrap();
+        let (is_h2, mut stream) = task.await.unwrap();
+        assert!(is_h2);
+        let mut buf = vec![0; PREFACE.len() + 6];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf[..PREFACE.len()], PREFACE);
+        assert_eq!(&buf[PREFACE.len()..], b"frames");
+    }
+
+    #[test]
+    fn build_req_head_test() {
+        let req = http::Request::builder()
+            .method("POST")
+            .uri("https://localhost:8080/index.php?foo=bar")
+            .header("cookie", "a=b")
+            .header("cookie", "c=d")
+            .header("te", "trailers")
+            .body(())
+            .unwrap();
+        let (parts, _) = req.into_parts();
+        let head = String::from_utf8(build_req_head(&parts, 3)).unwrap();
+        let mut lines = head.split("\r\n").collect::<Vec<_>>();
+        assert_eq!(lines[0], "POST /index.php?foo=bar HTTP/1.1");
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                "",
+                "",
+                "POST /index.php?foo=bar HTTP/1.1",
+                "connection: close",
+                "content-length: 3",
+                "cookie: a=b; c=d",
+                "host: localhost:8080",
+            ]
+        );
+    }
+
+    #[test]
+    fn build_res_test() {
+        let mut res = HttpRes::new("HTTP/1.1");
+        res.set_status(404);
+        res.set_header(
+            ResHeader::Entity(EntityHeader::ContentLength),
+            HeaderValue::Simple(SimpleHeaderValue::Number(12)),
+        );
+        res.set_header(
+            ResHeader::General(GeneralHeader::Connection),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from("close"))),
+        );
+        res.set_raw_headers(String::from("Set-Cookie: a=b\r\nSet-Cookie: c=d\r\n"));
+
+        let res = build_res(&mut res);
+        assert_eq!(res.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(res.headers()["content-length"], "12");
+        assert!(!res.headers().contains_key("connection"));
+        assert_eq!(
+            res.headers()
+                .get_all("set-cookie")
+                .iter()
+                .collect::<Vec<_>>(),
+            vec!["a=b", "c=d"]
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! HTTP/3 support (RFC 9114).
+//!
+//! The HTTP/3 listener runs over QUIC on the UDP port matching the TCP one, with the same
+//! certificate, and is advertised to HTTP/1.1 and HTTP/2 clients with the Alt-Svc header. Like
+//! HTTP/2 streams, each request is served by a regular client handler over an in-memory connection
+//! (see [`crate::http2`]).
+
+use crate::http2::{self, LocalResponse, LocalStream};
+use crate::res_parser::{self, ResBodyLength};
+use crate::tls::ClientCert;
+
+use bytes::{Buf, Bytes};
+use log::{debug, warn};
+use std::{fmt, future, io, net, sync};
+use tokio::io::AsyncReadExt;
+
+/// ALPN protocol identifier of HTTP/3.
+pub const ALPN_H3: &[u8] = b"h3";
+/// How long clients may remember that HTTP/3 is available (in seconds).
+const ALT_SVC_MAX_AGE: u32 = 86400;
+
+#[derive(Debug)]
+pub enum Error {
+    Quic(quinn::ConnectionError),
+    Connection(h3::error::ConnectionError),
+    Stream(h3::error::StreamError),
+    Local(http2::Error),
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Quic(e) => write!(f, "QUIC error: {}", e),
+            Self::Connection(e) => write!(f, "HTTP/3 connection error: {}", e),
+            Self::Stream(e) => write!(f, "HTTP/3 stream error: {}", e),
+            Self::Local(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Value of the Alt-Svc header advertising an HTTP/3 listener on a port.
+pub fn alt_svc(port: u16) -> String {
+    format!("h3=\":{}\"; ma={}", port, ALT_SVC_MAX_AGE)
+}
+
+/// Create the QUIC configuration from the TLS configuration of the server.
+pub(crate) fn server_config(
+    mut tls_config: rustls::ServerConfig,
+) -> io::Result<quinn::ServerConfig> {
+    tls_config.alpn_protocols = vec![Vec::from(ALPN_H3)];
+    let crypto =
+        quinn::crypto::rustls::QuicServerConfig::try_from(tls_config).map_err(io::Error::other)?;
+    Ok(quinn::ServerConfig::with_crypto(sync::Arc::new(crypto)))
+}
+
+/// Create a QUIC endpoint listening on a UDP socket, with the TLS configuration of the server.
+pub(crate) fn bind(
+    address: net::SocketAddr,
+    tls_config: rustls::ServerConfig,
+) -> io::Result<quinn::Endpoint> {
+    quinn::Endpoint::server(server_config(tls_config)?, address)
+}
+
+/// Serve an HTTP/3 connection, until the client closes it.
+///
+/// Every request is served concurrently, by running `serve` on an in-memory connection (which is
+/// expected to handle HTTP/1.1 requests), along with the verified certificate of the client.
+pub(crate) async fn serve_connection<F, Fut>(
+    incoming: quinn::Incoming,
+    serve: F,
+) -> Result<(), Error>
+where
+    F: Fn(LocalStream, Option<sync::Arc<ClientCert>>) -> Fut,
+    Fut: future::Future<Output = ()> + Send + 'static,
+{
+    let connection = incoming.await.map_err(Error::Quic)?;
+    let client_cert = connection
+        .peer_identity()
+        .and_then(|identity| {
+            identity
+                .downcast::<Vec<rustls::pki_types::CertificateDer<'static>>>()
+                .ok()
+        })
+        .and_then(|chain| ClientCert::from_chain(Some(&chain)));
+    let mut connection = h3::server::Connection::new(h3_quinn::Connection::new(connection))
+        .await
+        .map_err(Error::Connection)?;
+    loop {
+        match connection.accept().await {
+            Ok(Some(resolver)) => {
+                let (local, remote) = http2::local_connection(true);
+                tokio::spawn(serve(local, client_cert.clone()));
+                tokio::spawn(async move {
+                    let result = match resolver.resolve_request().await {
+                        Ok((req, stream)) => serve_stream(req, stream, remote).await,
+                        Err(err) => Err(Error::Stream(err)),
+                    };
+                    if let Err(err) = result {
+                        warn!("Cannot serve HTTP/3 request: {}", err);
+                    }
+                });
+            }
+            Ok(None) => break,
+            Err(err) if err.is_h3_no_error() => break,
+            Err(err) => return Err(Error::Connection(err)),
+        }
+    }
+    debug!("HTTP/3 connection closed");
+    Ok(())
+}
+
+/// Serve an HTTP/3 request: send it over the in-memory connection, and send the response read from
+/// it back to the client.
+async fn serve_stream(
+    req: http::Request<()>,
+    mut stream: h3::server::RequestStream<h3_quinn::BidiStream<Bytes>, Bytes>,
+    remote: tokio::io::DuplexStream,
+) -> Result<(), Error> {
+    let (parts, ()) = req.into_parts();
+    // tunnels (e.g. extended CONNECT) are not supported over HTTP/3
+    if parts.method == http::Method::CONNECT {
+        let res = http::Response::builder()
+            .status(501)
+            .body(())
+            .unwrap_or_default();
+        stream.send_response(res).await.map_err(Error::Stream)?;
+        return stream.finish().await.map_err(Error::Stream);
+    }
+
+    // the request body is read entirely, to provide its length
+    let mut req_body = Vec::new();
+    while let Some(mut data) = stream.recv_data().await.map_err(Error::Stream)? {
+        while data.has_remaining() {
+            let chunk = data.chunk();
+            req_body.extend_from_slice(chunk);
+            let len = chunk.len();
+            data.advance(len);
+        }
+    }
+    let LocalResponse {
+        head,
+        body_length,
+        mut client,
+    } = http2::exchange(&parts, &req_body, remote)
+        .await
+        .map_err(Error::Local)?;
+    stream.send_response(head).await.map_err(Error::Stream)?;
+
+    if body_length != ResBodyLength::None {
+        // the body is read while being sent, its chunked framing (if any) being removed as HTTP/3
+        // frames delimit it
+        let (mut writer, mut reader) = tokio::io::duplex(http2::LOCAL_BUFFER_SIZE);
+        let copy = async move {
+            let result =
+                res_parser::decode_res_body(client.stream(), &mut writer, &body_length).await;
+            drop(writer);
+            result.map_err(|err| Error::Local(http2::Error::Body(err)))
+        };
+        let send = async {
+            let mut buf = vec![0; http2::LOCAL_BUFFER_SIZE];
+            loop {
+                let len = reader
+                    .read(&mut buf)
+                    .await
+                    .map_err(|err| Error::Local(http2::Error::Io(err)))?;
+                if len == 0 {
+                    return Ok(());
+                }
+                stream
+                    .send_data(Bytes::copy_from_slice(&buf[..len]))
+                    .await
+                    .map_err(Error::Stream)?;
+            }
+        };
+        tokio::try_join!(copy, send)?;
+    }
+    stream.finish().await.map_err(Error::Stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alt_svc_test() {
+        assert_eq!(alt_svc(443), "h3=\":443\"; ma=86400");
+    }
+}
@@ -3,6 +3,9 @@
 //! The header value itself is made of a main value, optionally followed by list of comma separated members.
 //! A member itself can have some attributes, separated by a semicolon (;) and written with the syntax name=value.
 
+use crate::digest::DigestCredentials;
+
+use base64::Engine;
 use std::{collections, fmt};
 
 /// Known names of header value members.
@@ -120,12 +123,15 @@ impl fmt::Display for ParsedHeaderValue {
     }
 }
 
-/// A header value is either simple or parsed, or a pair of credentials.
+/// A header value is either simple or parsed, or a pair of credentials (written with the Basic scheme),
+/// digest credentials or a bearer token.
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub enum HeaderValue {
     Simple(SimpleHeaderValue),
     Parsed(ParsedHeaderValue),
     Credentials(String, String),
+    Digest(DigestCredentials),
+    Bearer(String),
 }
 
 impl fmt::Display for HeaderValue {
@@ -134,7 +140,13 @@ impl fmt::Display for HeaderValue {
         match self {
             Self::Simple(s) => write!(f, "{}", s),
             Self::Parsed(s) => write!(f, "{}", s),
-            Self::Credentials(a, b) => write!(f, "{}:{}", a, b),
+            Self::Credentials(a, b) => write!(
+                f,
+                "Basic {}",
+                base64::prelude::BASE64_STANDARD.encode(format!("{}:{}", a, b))
+            ),
+            Self::Digest(c) => write!(f, "{}", c),
+            Self::Bearer(token) => write!(f, "Bearer {}", token),
         }
     }
 }
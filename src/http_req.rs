@@ -2,12 +2,14 @@
 //!
 //! An HTTP request is represented as two parts: a head (first line and headers) and an optional body.
 
+use crate::digest::DigestCredentials;
 pub(crate) use crate::http_header::{
-    EntityHeader, GeneralHeader, HeaderValue, ReqHeader, SimpleHeaderValue,
+    EntityHeader, GeneralHeader, HeaderValue, ReqHeader, ReqOnlyHeader, SimpleHeaderValue,
 };
 use crate::req_parser::SupportedEncoding;
+use crate::tls::ClientCert;
 
-use std::{collections, fmt};
+use std::{collections, fmt, net, sync};
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
@@ -15,10 +17,14 @@ use std::{collections, fmt};
 #[derive(Debug, PartialEq)]
 pub enum ReqVerb {
     Get,
+    Head,
     Post,
     Put,
     Patch,
     Delete,
+    Options,
+    Trace,
+    Connect,
 }
 
 impl fmt::Display for ReqVerb {
@@ -26,16 +32,20 @@ impl fmt::Display for ReqVerb {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Get => f.write_str("GET"),
+            Self::Head => f.write_str("HEAD"),
             Self::Post => f.write_str("POST"),
             Self::Put => f.write_str("PUT"),
             Self::Patch => f.write_str("PATCH"),
             Self::Delete => f.write_str("DELETE"),
+            Self::Options => f.write_str("OPTIONS"),
+            Self::Trace => f.write_str("TRACE"),
+            Self::Connect => f.write_str("CONNECT"),
         }
     }
 }
 
 /// The path of an HTTP request. This path is URL-encoded, and can contain query params.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct ReqPath {
     // url-encoded path
     pub original: String,
@@ -46,10 +56,16 @@ pub struct ReqPath {
 }
 
 /// The target can be either a path or a star '*' for OPTIONS requests.
+///
+/// Requests made to a forward proxy use an absolute URI (e.g. http://example.org/index.html), or an
+/// authority (e.g. example.org:443) for CONNECT requests.
 #[derive(Debug, PartialEq)]
 pub enum ReqTarget {
     All,
     Path(ReqPath),
+    /// Absolute URI (http scheme only), made of an authority (host and optional port) and a path
+    Absolute(String, ReqPath),
+    Authority(String),
 }
 
 impl fmt::Display for ReqTarget {
@@ -57,6 +73,10 @@ impl fmt::Display for ReqTarget {
         match self {
             Self::All => write!(f, "*"),
             Self::Path(ReqPath { original, .. }) => write!(f, "{}", original),
+            Self::Absolute(authority, ReqPath { original, .. }) => {
+                write!(f, "http://{}{}", authority, original)
+            }
+            Self::Authority(authority) => write!(f, "{}", authority),
         }
     }
 }
@@ -66,6 +86,8 @@ impl fmt::Display for ReqTarget {
 pub struct ReqHead {
     verb: ReqVerb,
     target: ReqTarget,
+    /// Target as sent by the client, before its path is normalized
+    raw_target: String,
     version: String,
     headers: collections::HashMap<ReqHeader, HeaderValue>,
     authentication_credentials: Option<(String, String)>,
@@ -83,6 +105,7 @@ impl ReqHead {
     ) -> Self {
         Self {
             verb,
+            raw_target: target.to_string(),
             target,
             version,
             headers,
@@ -91,10 +114,40 @@ impl ReqHead {
         }
     }
 
+    pub fn verb(&self) -> &ReqVerb {
+        &self.verb
+    }
+
+    pub fn target(&self) -> &ReqTarget {
+        &self.target
+    }
+
+    /// Target of the request line as sent by the client (e.g. /a/../b), unlike `target` whose path
+    /// is normalized.
+    pub fn raw_target(&self) -> &str {
+        &self.raw_target
+    }
+
+    pub(crate) fn set_raw_target(&mut self, raw_target: String) {
+        self.raw_target = raw_target;
+    }
+
+    pub fn version(&self) -> &str {
+        self.version.as_str()
+    }
+
     pub fn first_line(&self) -> String {
         format!("{} {} {}", self.verb, self.target, self.version)
     }
 
+    /// Value of the Host header (may include a port), if any.
+    pub fn host(&self) -> Option<&str> {
+        match self.headers.get(&ReqHeader::ReqOnly(ReqOnlyHeader::Host)) {
+            Some(HeaderValue::Simple(SimpleHeaderValue::String(host))) => Some(host.as_str()),
+            _ => None,
+        }
+    }
+
     pub fn should_close(&self) -> bool {
         self.headers
             .get(&ReqHeader::General(GeneralHeader::Connection))
@@ -112,6 +165,76 @@ impl ReqHead {
         self.authentication_credentials.as_ref()
     }
 
+    /// Credentials of an Authorization header with the Digest scheme, if any.
+    pub fn digest_creds(&self) -> Option<&DigestCredentials> {
+        match self
+            .headers
+            .get(&ReqHeader::ReqOnly(ReqOnlyHeader::Authorization))
+        {
+            Some(HeaderValue::Digest(creds)) => Some(creds),
+            _ => None,
+        }
+    }
+
+    /// Token of an Authorization header with the Bearer scheme, if any.
+    pub fn bearer_token(&self) -> Option<&str> {
+        match self
+            .headers
+            .get(&ReqHeader::ReqOnly(ReqOnlyHeader::Authorization))
+        {
+            Some(HeaderValue::Bearer(token)) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// Credentials provided to a proxy (Proxy-Authorization header), if any.
+    pub fn proxy_auth_creds(&self) -> Option<(&str, &str)> {
+        match self
+            .headers
+            .get(&ReqHeader::ReqOnly(ReqOnlyHeader::ProxyAuthorization))
+        {
+            Some(HeaderValue::Credentials(username, password)) => Some((username, password)),
+            _ => None,
+        }
+    }
+
+    /// Whether the Accept header explicitly lists HTML, as the ones of web browsers do.
+    pub fn accepts_html(&self) -> bool {
+        let is_html = |v: &SimpleHeaderValue| matches!(v, SimpleHeaderValue::Mime(m) if m.essence_str() == "text/html");
+        match self.headers.get(&ReqHeader::ReqOnly(ReqOnlyHeader::Accept)) {
+            Some(HeaderValue::Simple(v)) => is_html(v),
+            Some(HeaderValue::Parsed(v)) => v.0.iter().any(|(v, _)| is_html(v)),
+            _ => false,
+        }
+    }
+
+    /// Value of the Max-Forwards header, if any (and valid).
+    pub fn max_forwards(&self) -> Option<u32> {
+        match self
+            .headers
+            .get(&ReqHeader::ReqOnly(ReqOnlyHeader::MaxForwards))
+        {
+            Some(HeaderValue::Simple(SimpleHeaderValue::String(v))) => v.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Value of a header, if it is a plain string.
+    pub fn header_str(&self, name: &ReqHeader) -> Option<&str> {
+        match self.headers.get(name) {
+            Some(HeaderValue::Simple(SimpleHeaderValue::String(v))) => Some(v.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Value of a header given by its name (case-insensitive), whatever its type.
+    pub fn header_by_name(&self, name: &str) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.to_string().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.to_string())
+    }
+
     pub fn body_len(&self) -> usize {
         self.headers
             .get(&ReqHeader::Entity(EntityHeader::ContentLength))
@@ -178,11 +301,24 @@ pub struct HttpReq {
     date: chrono::DateTime<chrono::Utc>,
     head: ReqHead,
     body: Option<ReqBody>,
+    /// Verified certificate of the client (mutual TLS)
+    client_cert: Option<sync::Arc<ClientCert>>,
+    /// IP address of the client, as reported by a trusted proxy (instead of the one of the proxy)
+    client_ip: Option<net::IpAddr>,
+    /// User the client authenticated as, if any
+    user: Option<String>,
 }
 
 impl HttpReq {
     pub fn new(date: chrono::DateTime<chrono::Utc>, head: ReqHead, body: Option<ReqBody>) -> Self {
-        Self { date, head, body }
+        Self {
+            date,
+            head,
+            body,
+            client_cert: None,
+            client_ip: None,
+            user: None,
+        }
     }
 
     pub fn date(&self) -> &chrono::DateTime<chrono::Utc> {
@@ -201,6 +337,10 @@ impl HttpReq {
         &self.head.target
     }
 
+    pub fn set_target(&mut self, target: ReqTarget) {
+        self.head.target = target;
+    }
+
     pub fn first_line(&self) -> String {
         self.head.first_line()
     }
@@ -209,6 +349,16 @@ impl HttpReq {
         self.head.should_close()
     }
 
+    pub fn host(&self) -> Option<&str> {
+        self.head.host()
+    }
+
+    /// Generate the bytes corresponding to the request head (first line and headers), e.g. to
+    /// send the request to another server.
+    pub fn head_bytes(&self) -> Vec<u8> {
+        format!("{}\r\n", self.head).into_bytes()
+    }
+
     pub fn headers(&mut self) -> &mut collections::HashMap<ReqHeader, HeaderValue> {
         &mut self.head.headers
     }
@@ -221,6 +371,57 @@ impl HttpReq {
         self.head.auth_creds()
     }
 
+    /// Certificate the client authenticated with, if any.
+    pub fn client_cert(&self) -> Option<&ClientCert> {
+        self.client_cert.as_deref()
+    }
+
+    pub fn set_client_cert(&mut self, client_cert: Option<sync::Arc<ClientCert>>) {
+        self.client_cert = client_cert;
+    }
+
+    /// IP address of the client reported by a trusted proxy, if any.
+    pub fn client_ip(&self) -> Option<net::IpAddr> {
+        self.client_ip
+    }
+
+    pub fn set_client_ip(&mut self, client_ip: Option<net::IpAddr>) {
+        self.client_ip = client_ip;
+    }
+
+    /// User the client authenticated as, if any.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    pub fn set_user(&mut self, user: Option<String>) {
+        self.user = user;
+    }
+
+    pub fn proxy_auth_creds(&self) -> Option<(&str, &str)> {
+        self.head.proxy_auth_creds()
+    }
+
+    pub fn max_forwards(&self) -> Option<u32> {
+        self.head.max_forwards()
+    }
+
+    pub fn accepts_html(&self) -> bool {
+        self.head.accepts_html()
+    }
+
+    pub fn header_str(&self, name: &ReqHeader) -> Option<&str> {
+        self.head.header_str(name)
+    }
+
+    pub fn header_by_name(&self, name: &str) -> Option<String> {
+        self.head.header_by_name(name)
+    }
+
+    pub fn body_len(&self) -> usize {
+        self.head.body_len()
+    }
+
     pub fn body(&self) -> Option<&ReqBody> {
         self.body.as_ref()
     }
@@ -229,7 +430,7 @@ impl HttpReq {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::http_header::ReqOnlyHeader;
+    use crate::http_header::ParsedHeaderValue;
 
     #[test]
     fn http_req_head_path_test() {
@@ -276,7 +477,13 @@ mod tests {
         assert_eq!(*req.target(), ReqTarget::All);
 
         assert_eq!(req.first_line(), "GET * HTTP/1.1");
+        assert_eq!(req.host(), Some("foo"));
+        assert_eq!(req.header_by_name("HOST"), Some(String::from("foo")));
+        assert_eq!(req.header_by_name("Referer"), None);
         assert!(!req.should_close());
+        assert_eq!(req.user(), None);
+        req.set_user(Some(String::from("alice")));
+        assert_eq!(req.user(), Some("alice"));
         assert_eq!(
             req.headers(),
             &mut collections::HashMap::from([(
@@ -312,6 +519,155 @@ mod tests {
         assert!(!req.should_close());
     }
 
+    #[test]
+    fn http_req_head_bytes_test() {
+        let req_head = ReqHead::new(
+            ReqVerb::Head,
+            ReqTarget::Path(ReqPath {
+                original: String::from("/"),
+                decoded: String::from("/"),
+                query: String::new(),
+            }),
+            String::from("HTTP/1.1"),
+            collections::HashMap::from([(
+                ReqHeader::ReqOnly(ReqOnlyHeader::Authorization),
+                HeaderValue::Credentials(String::from("foo"), String::from("bar")),
+            )]),
+            None,
+            None,
+        );
+        let req = HttpReq::new(chrono::Utc::now(), req_head, None);
+        assert_eq!(
+            req.head_bytes(),
+            b"HEAD / HTTP/1.1\r\nAuthorization: Basic Zm9vOmJhcg==\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn http_req_digest_test() {
+        let creds: DigestCredentials =
+            r#"username="foo", realm="simple", nonce="abc", uri="/", qop=auth, nc=00000001, cnonce="def", response="0123""#
+                .parse()
+                .unwrap();
+        let mut req_head = ReqHead::new(
+            ReqVerb::Get,
+            ReqTarget::All,
+            String::from("HTTP/1.1"),
+            collections::HashMap::from([(
+                ReqHeader::ReqOnly(ReqOnlyHeader::Authorization),
+                HeaderValue::Digest(creds.clone()),
+            )]),
+            None,
+            None,
+        );
+        assert_eq!(req_head.digest_creds(), Some(&creds));
+        assert_eq!(req_head.auth_creds(), None);
+        assert!(
+            req_head
+                .to_string()
+                .contains("Authorization: Digest username=\"foo\", realm=\"simple\"")
+        );
+        req_head.headers.clear();
+        assert_eq!(req_head.digest_creds(), None);
+    }
+
+    #[test]
+    fn http_req_bearer_test() {
+        let mut req_head = ReqHead::new(
+            ReqVerb::Get,
+            ReqTarget::All,
+            String::from("HTTP/1.1"),
+            collections::HashMap::from([(
+                ReqHeader::ReqOnly(ReqOnlyHeader::Authorization),
+                HeaderValue::Bearer(String::from("mF_9.B5f-4.1JqM")),
+            )]),
+            None,
+            None,
+        );
+        assert_eq!(req_head.bearer_token(), Some("mF_9.B5f-4.1JqM"));
+        assert_eq!(req_head.digest_creds(), None);
+        assert!(
+            req_head
+                .to_string()
+                .contains("Authorization: Bearer mF_9.B5f-4.1JqM\r\n")
+        );
+        req_head.headers.clear();
+        assert_eq!(req_head.bearer_token(), None);
+    }
+
+    #[test]
+    fn http_req_accepts_html_test() {
+        let mut req_head = ReqHead::new(
+            ReqVerb::Get,
+            ReqTarget::All,
+            String::from("HTTP/1.1"),
+            collections::HashMap::from([(
+                ReqHeader::ReqOnly(ReqOnlyHeader::Accept),
+                HeaderValue::Parsed(ParsedHeaderValue(vec![
+                    (
+                        SimpleHeaderValue::Mime(mime_guess::mime::TEXT_HTML),
+                        collections::BTreeMap::new(),
+                    ),
+                    (
+                        SimpleHeaderValue::Mime(mime_guess::mime::STAR_STAR),
+                        collections::BTreeMap::new(),
+                    ),
+                ])),
+            )]),
+            None,
+            None,
+        );
+        assert!(req_head.accepts_html());
+        req_head.headers.insert(
+            ReqHeader::ReqOnly(ReqOnlyHeader::Accept),
+            HeaderValue::Simple(SimpleHeaderValue::Mime(mime_guess::mime::STAR_STAR)),
+        );
+        assert!(!req_head.accepts_html());
+        req_head.headers.clear();
+        assert!(!req_head.accepts_html());
+    }
+
+    #[test]
+    fn http_req_proxy_test() {
+        let req_head = ReqHead::new(
+            ReqVerb::Options,
+            ReqTarget::Absolute(
+                String::from("example.org:8080"),
+                ReqPath {
+                    original: String::from("/index.html"),
+                    decoded: String::from("/index.html"),
+                    query: String::new(),
+                },
+            ),
+            String::from("HTTP/1.1"),
+            collections::HashMap::from([
+                (
+                    ReqHeader::ReqOnly(ReqOnlyHeader::ProxyAuthorization),
+                    HeaderValue::Credentials(String::from("foo"), String::from("bar")),
+                ),
+                (
+                    ReqHeader::ReqOnly(ReqOnlyHeader::MaxForwards),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from("3"))),
+                ),
+            ]),
+            None,
+            None,
+        );
+        let mut req = HttpReq::new(chrono::Utc::now(), req_head, None);
+        assert_eq!(
+            req.first_line(),
+            "OPTIONS http://example.org:8080/index.html HTTP/1.1"
+        );
+        assert_eq!(req.proxy_auth_creds(), Some(("foo", "bar")));
+        assert_eq!(req.max_forwards(), Some(3));
+
+        req.set_target(ReqTarget::Authority(String::from("example.org:443")));
+        assert_eq!(req.first_line(), "OPTIONS example.org:443 HTTP/1.1");
+        req.headers().clear();
+        assert_eq!(req.proxy_auth_creds(), None);
+        assert_eq!(req.max_forwards(), None);
+    }
+
     #[test]
     fn http_req_headers_test() {
         let mut headers = collections::HashMap::new();
@@ -3,6 +3,7 @@
 //! An HTTP response is made of a first line, some headers, and a body.
 
 use crate::http_header::{HeaderValue, ResHeader};
+use crate::proxy::UpstreamBody;
 
 use std::collections;
 
@@ -26,6 +27,7 @@ pub fn get_reason_phrase(status_code: u16) -> String {
         304 => String::from("Not Modified"),
         305 => String::from("Use Proxy"),
         307 => String::from("Temporary Redirect"),
+        308 => String::from("Permanent Redirect"),
         400 => String::from("Bad Request"),
         401 => String::from("Unauthorized"),
         402 => String::from("Payment Required"),
@@ -45,6 +47,7 @@ pub fn get_reason_phrase(status_code: u16) -> String {
         416 => String::from("Range Not Satisfiable"),
         417 => String::from("Expectation Failed"),
         426 => String::from("Upgrade Required"),
+        429 => String::from("Too Many Requests"),
         500 => String::from("Internal Server Error"),
         501 => String::from("Not Implemented"),
         502 => String::from("Bad Gateway"),
@@ -59,6 +62,8 @@ pub fn get_reason_phrase(status_code: u16) -> String {
 pub enum ResBody {
     Bytes(Vec<u8>),
     Stream(tokio::fs::File, u64),
+    /// Body still to be read from an upstream server (reverse proxy)
+    Upstream(Box<UpstreamBody>),
 }
 
 impl ResBody {
@@ -66,6 +71,7 @@ impl ResBody {
         match self {
             Self::Bytes(bytes) => bytes.len(),
             Self::Stream(_, len) => *len as usize,
+            Self::Upstream(body) => body.len(),
         }
     }
 
@@ -73,6 +79,7 @@ impl ResBody {
         match self {
             Self::Bytes(bytes) => bytes.is_empty(),
             Self::Stream(_, len) => *len == 0,
+            Self::Upstream(body) => body.is_empty(),
         }
     }
 }
@@ -96,6 +103,10 @@ impl HttpRes {
         }
     }
 
+    pub fn version(&self) -> &str {
+        self.version.as_str()
+    }
+
     pub fn status_code(&self) -> u16 {
         self.status_code
     }
@@ -116,6 +127,11 @@ impl HttpRes {
         self.raw_headers = Some(headers);
     }
 
+    /// Headers kept as raw lines (e.g. several Set-Cookie headers), if any.
+    pub fn raw_headers(&self) -> Option<&str> {
+        self.raw_headers.as_deref()
+    }
+
     /// Generate the bytes corresponding to the response head (first line and headers)
     /// These bytes must be dynamically generated, contrary to the response body that can be read
     /// from a stream (typically, a static file on the filesystem).
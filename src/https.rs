@@ -0,0 +1,219 @@
+//! HTTPS enforcement: redirection of plain HTTP requests to HTTPS, and HTTP Strict Transport
+//! Security (HSTS, RFC 6797).
+
+use crate::http_req::{HttpReq, ReqPath, ReqTarget, ReqVerb};
+
+use std::{fmt, net, str::FromStr};
+
+/// Plain HTTP listener redirecting every request to HTTPS.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpsRedirect {
+    /// Socket address of the plain HTTP listener
+    pub address: net::SocketAddr,
+    /// Host (and optional port) to redirect to, instead of the one requested by the client (with
+    /// the port of the HTTPS listener)
+    pub host: Option<String>,
+}
+
+/// Value of the Strict-Transport-Security header, sent with HTTPS responses.
+///
+/// Its string format is the one of the header, e.g. `max-age=31536000; includeSubDomains`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hsts {
+    /// How long clients must only use HTTPS to reach the server (in seconds)
+    pub max_age: u64,
+    pub include_subdomains: bool,
+    /// Allow the inclusion of the domain in the HSTS preload lists of browsers
+    pub preload: bool,
+}
+
+impl FromStr for Hsts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut max_age = None;
+        let mut include_subdomains = false;
+        let mut preload = false;
+        for directive in s.split(';').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((name, value)) if name.trim().eq_ignore_ascii_case("max-age") => {
+                    max_age = Some(
+                        value
+                            .trim()
+                            .trim_matches('"')
+                            .parse()
+                            .map_err(|_| format!("Invalid HSTS max-age: {}", value))?,
+                    );
+                }
+                None if directive.eq_ignore_ascii_case("includeSubDomains") => {
+                    include_subdomains = true
+                }
+                None if directive.eq_ignore_ascii_case("preload") => preload = true,
+                _ => return Err(format!("Invalid HSTS directive: {}", directive)),
+            }
+        }
+        Ok(Self {
+            max_age: max_age.ok_or_else(|| format!("Missing HSTS max-age: {}", s))?,
+            include_subdomains,
+            preload,
+        })
+    }
+}
+
+impl fmt::Display for Hsts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "max-age={}", self.max_age)?;
+        if self.include_subdomains {
+            write!(f, "; includeSubDomains")?;
+        }
+        if self.preload {
+            write!(f, "; preload")?;
+        }
+        Ok(())
+    }
+}
+
+/// Status code of the redirect to HTTPS: methods other than GET and HEAD must be kept, with their
+/// body.
+pub(crate) fn redirect_status(verb: &ReqVerb) -> u16 {
+    match verb {
+        ReqVerb::Get | ReqVerb::Head => 301,
+        _ => 308,
+    }
+}
+
+/// HTTPS URL a plain HTTP request is redirected to, keeping its path and query.
+///
+/// The host is the configured one if any, otherwise the one requested by the client (with the port
+/// of the HTTPS listener). `None` is returned when the request has no host or no path.
+pub(crate) fn redirect_location(
+    req: &HttpReq,
+    configured_host: Option<&str>,
+    https_port: u16,
+) -> Option<String> {
+    let (authority, path) = match req.target() {
+        ReqTarget::Path(ReqPath { original, .. }) => (req.host(), original),
+        ReqTarget::Absolute(authority, ReqPath { original, .. }) => {
+            (Some(authority.as_str()), original)
+        }
+        ReqTarget::All | ReqTarget::Authority(_) => return None,
+    };
+    let host = match configured_host {
+        Some(host) => String::from(host),
+        None => {
+            let authority = authority.filter(|authority| !authority.is_empty())?;
+            let host = match authority.rsplit_once(':') {
+                // the colon may belong to an IPv6 address (e.g. [::1])
+                Some((host, port)) if !port.ends_with(']') => host,
+                _ => authority,
+            };
+            match https_port {
+                443 => String::from(host),
+                port => format!("{}:{}", host, port),
+            }
+        }
+    };
+    Some(format!("https://{}{}", host, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_header::{HeaderValue, ReqHeader, ReqOnlyHeader, SimpleHeaderValue};
+    use crate::http_req::ReqHead;
+    use std::collections;
+
+    fn build_req(verb: ReqVerb, target: ReqTarget, host: Option<&str>) -> HttpReq {
+        let mut headers = collections::HashMap::new();
+        if let Some(host) = host {
+            headers.insert(
+                ReqHeader::ReqOnly(ReqOnlyHeader::Host),
+                HeaderValue::Simple(SimpleHeaderValue::String(String::from(host))),
+            );
+        }
+        let head = ReqHead::new(verb, target, String::from("HTTP/1.1"), headers, None, None);
+        HttpReq::new(chrono::Utc::now(), head, None)
+    }
+
+    fn path(original: &str) -> ReqPath {
+        ReqPath {
+            original: String::from(original),
+            decoded: String::new(),
+            query: String::new(),
+        }
+    }
+
+    #[test]
+    fn hsts_test() {
+        let hsts: Hsts = "max-age=31536000; includeSubDomains".parse().unwrap();
+        assert_eq!(
+            hsts,
+            Hsts {
+                max_age: 31536000,
+                include_subdomains: true,
+                preload: false
+            }
+        );
+        assert_eq!(hsts.to_string(), "max-age=31536000; includeSubDomains");
+        assert_eq!(
+            "Max-Age=\"60\";preload"
+                .parse::<Hsts>()
+                .unwrap()
+                .to_string(),
+            "max-age=60; preload"
+        );
+        assert!("includeSubDomains".parse::<Hsts>().is_err());
+        assert!("max-age=-1".parse::<Hsts>().is_err());
+        assert!("max-age=60; unknown".parse::<Hsts>().is_err());
+    }
+
+    #[test]
+    fn redirect_test() {
+        assert_eq!(redirect_status(&ReqVerb::Get), 301);
+        assert_eq!(redirect_status(&ReqVerb::Head), 301);
+        assert_eq!(redirect_status(&ReqVerb::Post), 308);
+
+        let req = build_req(
+            ReqVerb::Get,
+            ReqTarget::Path(path("/path/to%20file?foo=bar")),
+            Some("example.com:8080"),
+        );
+        assert_eq!(
+            redirect_location(&req, None, 443).as_deref(),
+            Some("https://example.com/path/to%20file?foo=bar")
+        );
+        assert_eq!(
+            redirect_location(&req, None, 8443).as_deref(),
+            Some("https://example.com:8443/path/to%20file?foo=bar")
+        );
+        assert_eq!(
+            redirect_location(&req, Some("secure.example.com"), 8443).as_deref(),
+            Some("https://secure.example.com/path/to%20file?foo=bar")
+        );
+
+        let req = build_req(ReqVerb::Get, ReqTarget::Path(path("/")), Some("[::1]"));
+        assert_eq!(
+            redirect_location(&req, None, 8443).as_deref(),
+            Some("https://[::1]:8443/")
+        );
+        let req = build_req(
+            ReqVerb::Get,
+            ReqTarget::Absolute(String::from("example.com"), path("/index.html")),
+            None,
+        );
+        assert_eq!(
+            redirect_location(&req, None, 443).as_deref(),
+            Some("https://example.com/index.html")
+        );
+
+        // the host is required, unless configured
+        let req = build_req(ReqVerb::Get, ReqTarget::Path(path("/")), None);
+        assert_eq!(redirect_location(&req, None, 443), None);
+        assert_eq!(
+            redirect_location(&req, Some("example.com"), 443).as_deref(),
+            Some("https://example.com/")
+        );
+        let req = build_req(ReqVerb::Options, ReqTarget::All, Some("example.com"));
+        assert_eq!(redirect_location(&req, None, 443), None);
+    }
+}
@@ -2,10 +2,33 @@
 
 #![cfg_attr(coverage, feature(coverage_attribute))]
 
-mod http_header;
+pub mod access;
+pub mod access_log;
+pub mod auth;
+pub mod bearer;
+pub mod client;
+pub mod credentials;
+pub mod daemon;
+pub mod dashboard;
+pub mod digest;
+pub mod http2;
+pub mod http3;
+pub mod http_header;
 pub mod http_req;
 pub mod http_res;
+pub mod https;
+pub mod listener;
+pub mod metrics;
+pub mod proxy;
+pub mod proxy_protocol;
+pub mod rate_limit;
 pub mod req_parser;
 pub mod res_builder;
+pub mod res_parser;
 pub mod server;
+pub mod session;
+pub mod signed_url;
+pub mod status;
+pub mod tls;
 pub mod utils;
+pub mod websocket;
@@ -0,0 +1,582 @@
+//! Listeners of the server: TCP sockets (IPv4, IPv6 or dual-stack) and Unix domain sockets.
+
+use crate::daemon;
+
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::{env, fmt, fs, io, mem, net, path, str::FromStr, sync, task};
+
+/// Prefix of the addresses of Unix domain sockets (e.g. `unix:/run/server.sock`), also used for
+/// the peers connected to them.
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// Prefix of the listeners using sockets passed by systemd (socket activation), followed by the
+/// name of the sockets (e.g. `systemd:https`).
+pub const SYSTEMD_PREFIX: &str = "systemd:";
+
+/// First file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Address a listener is bound to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    /// TCP socket address. IPv6 listeners only accept IPv6 clients, unless they are dual-stack
+    /// (accepting IPv4 clients as well, with IPv4-mapped addresses).
+    Tcp {
+        address: net::SocketAddr,
+        dual_stack: bool,
+    },
+    /// Unix domain socket, whose file is given a mode and an owner (if any) once created
+    Unix {
+        path: path::PathBuf,
+        mode: Option<u32>,
+        owner: Option<SocketOwner>,
+    },
+    /// Sockets already bound, passed by systemd with a name (see `FileDescriptorName=`), or all of
+    /// them without one
+    Systemd { name: Option<String> },
+}
+
+/// Owner of a Unix domain socket file, as user and group names or IDs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SocketOwner {
+    pub user: Option<String>,
+    pub group: Option<String>,
+}
+
+/// A listener of the server, accepting plain HTTP or HTTPS connections.
+///
+/// Its string format is `ADDRESS[|OPTION...]`, where `ADDRESS` is a socket address (e.g.
+/// `0.0.0.0:80` or `[::]:443`), a Unix domain socket path prefixed with `unix:`, or the name of
+/// sockets passed by systemd prefixed with `systemd:`, and `OPTION` is one of `tls`,
+/// `proxy-protocol`, `dual-stack` (IPv6 only), `mode=OCTAL_MODE` or `owner=USER[:GROUP]` (Unix
+/// domain sockets only).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listener {
+    pub address: ListenAddr,
+    /// Whether connections are made over TLS (HTTPS)
+    pub tls: bool,
+    /// Whether connections start with a PROXY protocol header (v1 or v2), giving the address of the
+    /// client (e.g. behind a TCP load balancer)
+    pub proxy_protocol: bool,
+}
+
+impl FromStr for SocketOwner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+        let owner = Self {
+            user: Some(user).filter(|u| !u.is_empty()).map(String::from),
+            group: group.filter(|g| !g.is_empty()).map(String::from),
+        };
+        match owner {
+            Self {
+                user: None,
+                group: None,
+            } => Err(format!("Invalid socket owner: {}", s)),
+            owner => Ok(owner),
+        }
+    }
+}
+
+impl FromStr for Listener {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('|');
+        let address = parts.next().unwrap_or_default().trim();
+        let mut address = match (
+            address.strip_prefix(UNIX_PREFIX),
+            address.strip_prefix(SYSTEMD_PREFIX),
+        ) {
+            (Some(""), _) => return Err(format!("Invalid Unix domain socket path: {}", s)),
+            (Some(path), _) => ListenAddr::Unix {
+                path: path::PathBuf::from(path),
+                mode: None,
+                owner: None,
+            },
+            (_, Some(name)) => ListenAddr::Systemd {
+                name: Some(name).filter(|name| !name.is_empty()).map(String::from),
+            },
+            (None, None) => ListenAddr::Tcp {
+                address: address
+                    .parse()
+                    .map_err(|_| format!("Invalid listener address: {}", address))?,
+                dual_stack: false,
+            },
+        };
+        let mut tls = false;
+        let mut proxy_protocol = false;
+        for option in parts.map(str::trim) {
+            match (option.split_once('='), &mut address) {
+                (None, _) if option == "tls" => tls = true,
+                (None, _) if option == "proxy-protocol" => proxy_protocol = true,
+                (
+                    None,
+                    ListenAddr::Tcp {
+                        address,
+                        dual_stack,
+                    },
+                ) if option == "dual-stack" => {
+                    if !address.is_ipv6() {
+                        return Err(format!("Dual-stack listener is not IPv6: {}", address));
+                    }
+                    *dual_stack = true;
+                }
+                (Some(("mode", value)), ListenAddr::Unix { mode, .. }) => {
+                    *mode = Some(
+                        u32::from_str_radix(value, 8)
+                            .ok()
+                            .filter(|mode| *mode <= 0o7777)
+                            .ok_or_else(|| format!("Invalid socket mode: {}", value))?,
+                    );
+                }
+                (Some(("owner", value)), ListenAddr::Unix { owner, .. }) => {
+                    *owner = Some(value.parse()?);
+                }
+                _ => return Err(format!("Invalid listener option: {}", option)),
+            }
+        }
+        Ok(Self {
+            address,
+            tls,
+            proxy_protocol,
+        })
+    }
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp { address, .. } => write!(f, "{}", address),
+            Self::Unix { path, .. } => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+            Self::Systemd { name } => {
+                write!(
+                    f,
+                    "{}{}",
+                    SYSTEMD_PREFIX,
+                    name.as_deref().unwrap_or_default()
+                )
+            }
+        }
+    }
+}
+
+impl Listener {
+    /// Create a TCP listener, bound to a socket address.
+    pub fn tcp(address: net::SocketAddr, tls: bool) -> Self {
+        Self {
+            address: ListenAddr::Tcp {
+                address,
+                dual_stack: false,
+            },
+            tls,
+            proxy_protocol: false,
+        }
+    }
+
+    /// Socket address of the listener, if it is a TCP one.
+    pub fn socket_address(&self) -> Option<net::SocketAddr> {
+        match self.address {
+            ListenAddr::Tcp { address, .. } => Some(address),
+            ListenAddr::Unix { .. } | ListenAddr::Systemd { .. } => None,
+        }
+    }
+
+    /// Bind the listener socket, so that it is ready to accept connections.
+    ///
+    /// Listeners using sockets passed by systemd take them from the inherited ones instead, and
+    /// may therefore be made of several sockets.
+    pub(crate) fn bind(
+        &self,
+        inherited: &mut Vec<InheritedSocket>,
+    ) -> io::Result<Vec<BoundListener>> {
+        let sockets = match &self.address {
+            ListenAddr::Tcp {
+                address,
+                dual_stack,
+            } => vec![Socket::Tcp(bind_tcp(*address, *dual_stack)?)],
+            ListenAddr::Unix { path, mode, owner } => vec![Socket::Unix(
+                bind_unix(path, *mode, owner.as_ref())?,
+                Some(path.clone()),
+            )],
+            ListenAddr::Systemd { name } => {
+                let (matching, others) =
+                    mem::take(inherited)
+                        .into_iter()
+                        .partition::<Vec<_>, _>(|socket| {
+                            name.as_ref().is_none_or(|name| *name == socket.name)
+                        });
+                *inherited = others;
+                if matching.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no socket passed by systemd for {}", self.address),
+                    ));
+                }
+                matching
+                    .into_iter()
+                    .map(|socket| inherited_socket(socket.fd))
+                    .collect::<io::Result<_>>()?
+            }
+        };
+        Ok(sockets
+            .into_iter()
+            .map(|socket| BoundListener {
+                socket,
+                tls: self.tls,
+                proxy_protocol: self.proxy_protocol,
+            })
+            .collect())
+    }
+}
+
+fn bind_tcp(address: net::SocketAddr, dual_stack: bool) -> io::Result<tokio::net::TcpListener> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(address),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+    // like tokio::net::TcpListener::bind, so that the server can be restarted right away
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
+
+fn bind_unix(
+    path: &path::Path,
+    mode: Option<u32>,
+    owner: Option<&SocketOwner>,
+) -> io::Result<tokio::net::UnixListener> {
+    // remove the socket file left by a previous instance (binding fails on any other file)
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    if let Some(owner) = owner {
+        std::os::unix::fs::chown(path, owner.uid()?, owner.gid()?)?;
+    }
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+impl SocketOwner {
+    fn uid(&self) -> io::Result<Option<u32>> {
+        self.user.as_deref().map(daemon::user_id).transpose()
+    }
+
+    fn gid(&self) -> io::Result<Option<u32>> {
+        self.group.as_deref().map(daemon::group_id).transpose()
+    }
+}
+
+/// A socket passed by systemd (socket activation), with its name.
+pub(crate) struct InheritedSocket {
+    name: String,
+    fd: OwnedFd,
+}
+
+/// Whether the sockets passed by systemd were already taken, as they are owned only once.
+static SOCKETS_TAKEN: sync::atomic::AtomicBool = sync::atomic::AtomicBool::new(false);
+
+/// Number of sockets passed by systemd, if they are meant for this process.
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>) -> usize {
+    let listen_pid = listen_pid.and_then(|pid| pid.parse::<u32>().ok());
+    match (listen_pid, listen_fds.and_then(|fds| fds.parse().ok())) {
+        (Some(pid), Some(fds)) if pid == std::process::id() => fds,
+        _ => 0,
+    }
+}
+
+/// Take the sockets passed by systemd (see `sd_listen_fds`), from the `LISTEN_PID`, `LISTEN_FDS`
+/// and `LISTEN_FDNAMES` environment variables.
+pub(crate) fn inherited_sockets() -> io::Result<Vec<InheritedSocket>> {
+    let count = listen_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+    );
+    if count == 0 || SOCKETS_TAKEN.swap(true, sync::atomic::Ordering::SeqCst) {
+        return Ok(Vec::new());
+    }
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+    (0..count)
+        .map(|i| {
+            // SAFETY: the file descriptors are passed to the process, and are only taken once
+            let fd = unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START + i as RawFd) };
+            // the child processes (PHP scripts) must not inherit them
+            nix::fcntl::fcntl(
+                &fd,
+                nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC),
+            )?;
+            let name = names.next().filter(|name| !name.is_empty());
+            Ok(InheritedSocket {
+                name: String::from(name.unwrap_or("unknown")),
+                fd,
+            })
+        })
+        .collect()
+}
+
+/// Listen on a socket passed by systemd, which must be a TCP or Unix domain socket.
+fn inherited_socket(fd: OwnedFd) -> io::Result<Socket> {
+    let socket = socket2::Socket::from(fd);
+    if socket.r#type()? != socket2::Type::STREAM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "socket passed by systemd is not a stream socket",
+        ));
+    }
+    socket.set_nonblocking(true)?;
+    if socket.local_addr()?.is_unix() {
+        let listener = std::os::unix::net::UnixListener::from(OwnedFd::from(socket));
+        // the socket file belongs to systemd
+        Ok(Socket::Unix(
+            tokio::net::UnixListener::from_std(listener)?,
+            None,
+        ))
+    } else {
+        Ok(Socket::Tcp(tokio::net::TcpListener::from_std(
+            socket.into(),
+        )?))
+    }
+}
+
+enum Socket {
+    Tcp(tokio::net::TcpListener),
+    /// The path is kept to remove the socket file once the listener is closed (unless the socket
+    /// was passed by systemd)
+    Unix(tokio::net::UnixListener, Option<path::PathBuf>),
+}
+
+/// A bound listener, accepting connections.
+pub(crate) struct BoundListener {
+    socket: Socket,
+    pub(crate) tls: bool,
+    pub(crate) proxy_protocol: bool,
+}
+
+/// A connection accepted by a listener.
+pub(crate) enum Connection {
+    /// TCP stream, with the socket addresses of the peer and of the server
+    Tcp(tokio::net::TcpStream, net::SocketAddr, net::SocketAddr),
+    /// Unix domain socket stream, with the address of the peer (usually unnamed)
+    Unix(tokio::net::UnixStream, String),
+}
+
+impl BoundListener {
+    fn poll_accept(&self, cx: &mut task::Context<'_>) -> task::Poll<io::Result<Connection>> {
+        match &self.socket {
+            Socket::Tcp(listener) => listener.poll_accept(cx).map_ok(|(stream, peer_addr)| {
+                let local_addr = stream.local_addr().unwrap_or(peer_addr);
+                Connection::Tcp(stream, peer_addr, local_addr)
+            }),
+            Socket::Unix(listener, _) => listener.poll_accept(cx).map_ok(|(stream, peer_addr)| {
+                let peer_path = peer_addr.as_pathname().map(path::Path::display);
+                let peer_addr = match peer_path {
+                    Some(path) => format!("{}{}", UNIX_PREFIX, path),
+                    None => String::from(UNIX_PREFIX),
+                };
+                Connection::Unix(stream, peer_addr)
+            }),
+        }
+    }
+}
+
+impl Drop for BoundListener {
+    fn drop(&mut self) {
+        if let Socket::Unix(_, Some(path)) = &self.socket {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Accept the next connection made to any of the listeners.
+pub(crate) async fn accept(
+    listeners: &[BoundListener],
+) -> (&BoundListener, io::Result<Connection>) {
+    std::future::poll_fn(|cx| {
+        listeners
+            .iter()
+            .find_map(|listener| match listener.poll_accept(cx) {
+                task::Poll::Ready(result) => Some(task::Poll::Ready((listener, result))),
+                task::Poll::Pending => None,
+            })
+            .unwrap_or(task::Poll::Pending)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listener_test() {
+        assert_eq!(
+            "0.0.0.0:8080".parse::<Listener>(),
+            Ok(Listener::tcp("0.0.0.0:8080".parse().unwrap(), false))
+        );
+        assert_eq!(
+            "[::]:8443|tls|dual-stack".parse::<Listener>(),
+            Ok(Listener {
+                address: ListenAddr::Tcp {
+                    address: "[::]:8443".parse().unwrap(),
+                    dual_stack: true,
+                },
+                tls: true,
+                proxy_protocol: false,
+            })
+        );
+        assert_eq!(
+            "unix:/run/server.sock|mode=660|owner=www-data:1000".parse::<Listener>(),
+            Ok(Listener {
+                address: ListenAddr::Unix {
+                    path: path::PathBuf::from("/run/server.sock"),
+                    mode: Some(0o660),
+                    owner: Some(SocketOwner {
+                        user: Some(String::from("www-data")),
+                        group: Some(String::from("1000")),
+                    }),
+                },
+                tls: false,
+                proxy_protocol: false,
+            })
+        );
+        assert_eq!(
+            "unix:server.sock|owner=:www-data|tls"
+                .parse::<Listener>()
+                .map(|listener| listener.socket_address()),
+            Ok(None)
+        );
+
+        assert_eq!(
+            "systemd:https|tls".parse::<Listener>(),
+            Ok(Listener {
+                address: ListenAddr::Systemd {
+                    name: Some(String::from("https")),
+                },
+                tls: true,
+                proxy_protocol: false,
+            })
+        );
+        assert_eq!(
+            "systemd:"
+                .parse::<Listener>()
+                .map(|listener| listener.address),
+            Ok(ListenAddr::Systemd { name: None })
+        );
+
+        assert!("localhost:8080".parse::<Listener>().is_err());
+        assert!("systemd:https|mode=600".parse::<Listener>().is_err());
+        assert!("unix:".parse::<Listener>().is_err());
+        assert!("0.0.0.0:8080|dual-stack".parse::<Listener>().is_err());
+        assert!("0.0.0.0:8080|mode=660".parse::<Listener>().is_err());
+        assert!("unix:server.sock|mode=999".parse::<Listener>().is_err());
+        assert!("unix:server.sock|owner=:".parse::<Listener>().is_err());
+        assert!("unix:server.sock|unknown".parse::<Listener>().is_err());
+    }
+
+    #[test]
+    fn listen_fds_test() {
+        let pid = std::process::id().to_string();
+        assert_eq!(listen_fds(Some(&pid), Some("2")), 2);
+        assert_eq!(listen_fds(Some("1"), Some("2")), 0);
+        assert_eq!(listen_fds(Some(&pid), Some("none")), 0);
+        assert_eq!(listen_fds(None, None), 0);
+    }
+
+    #[tokio::test]
+    async fn inherited_sockets_test() {
+        let tcp_listener = std::net::TcpListener::bind("127.0.0.1:8091").unwrap();
+        let udp_socket = std::net::UdpSocket::bind("127.0.0.1:8091").unwrap();
+        let mut inherited = vec![
+            InheritedSocket {
+                name: String::from("http"),
+                fd: OwnedFd::from(tcp_listener),
+            },
+            InheritedSocket {
+                name: String::from("quic"),
+                fd: OwnedFd::from(udp_socket),
+            },
+        ];
+
+        // sockets are matched by name
+        let listener = "systemd:https".parse::<Listener>().unwrap();
+        assert!(listener.bind(&mut inherited).is_err());
+        assert_eq!(inherited.len(), 2);
+        let listener = "systemd:http|tls|proxy-protocol"
+            .parse::<Listener>()
+            .unwrap();
+        let listeners = listener.bind(&mut inherited).unwrap();
+        assert_eq!(listeners.len(), 1);
+        assert!(listeners[0].tls && listeners[0].proxy_protocol);
+        let _client = tokio::net::TcpStream::connect("127.0.0.1:8091")
+            .await
+            .unwrap();
+        assert!(matches!(
+            accept(&listeners).await,
+            (_, Ok(Connection::Tcp(..)))
+        ));
+
+        // only stream sockets can be used
+        let listener = "systemd:".parse::<Listener>().unwrap();
+        assert!(listener.bind(&mut inherited).is_err());
+        assert!(inherited.is_empty());
+    }
+
+    #[tokio::test]
+    async fn accept_test() {
+        let dir = std::env::temp_dir().join(format!("listener-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let socket_path = dir.join("server.sock");
+        let listeners = [
+            "127.0.0.1:8090".parse::<Listener>().unwrap(),
+            format!("unix:{}|mode=600|tls", socket_path.display())
+                .parse::<Listener>()
+                .unwrap(),
+        ]
+        .iter()
+        .flat_map(|listener| listener.bind(&mut Vec::new()).unwrap())
+        .collect::<Vec<_>>();
+        let metadata = fs::metadata(&socket_path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o600);
+
+        let tcp_addr = "127.0.0.1:8090".parse().unwrap();
+        let _client = tokio::net::TcpStream::connect(tcp_addr).await.unwrap();
+        match accept(&listeners).await {
+            (listener, Ok(Connection::Tcp(_, peer_addr, local_addr))) => {
+                assert!(!listener.tls);
+                assert_eq!(local_addr, tcp_addr);
+                assert!(peer_addr.ip().is_loopback());
+            }
+            _ => panic!("expected a TCP connection"),
+        }
+        let _client = tokio::net::UnixStream::connect(&socket_path).await.unwrap();
+        match accept(&listeners).await {
+            (listener, Ok(Connection::Unix(_, peer_addr))) => {
+                assert!(listener.tls);
+                assert_eq!(peer_addr, UNIX_PREFIX);
+            }
+            _ => panic!("expected a Unix domain socket connection"),
+        }
+
+        // the socket file is removed with the listener
+        drop(listeners);
+        assert!(!socket_path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
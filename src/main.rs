@@ -4,10 +4,26 @@
 
 #![cfg_attr(coverage, feature(coverage_attribute))]
 
+use rust_http_server::access::Acl;
+use rust_http_server::access_log::AccessLog;
+use rust_http_server::auth::{AuthScheme, Location};
+use rust_http_server::bearer::JwtKey;
+use rust_http_server::credentials::HashScheme;
+use rust_http_server::dashboard;
+use rust_http_server::https::{Hsts, HttpsRedirect};
+use rust_http_server::listener::Listener;
+use rust_http_server::metrics::MetricsEndpoint;
+use rust_http_server::proxy::ProxyRoute;
+use rust_http_server::rate_limit::{Rate, RateLimit};
 use rust_http_server::server::{Server, Settings};
+use rust_http_server::session::LoginSettings;
+use rust_http_server::signed_url::UrlSigner;
+use rust_http_server::status;
+use rust_http_server::tls::{ClientAuth, KeyPassword, TlsHost, TlsPolicy};
+use rust_http_server::utils::IpNetwork;
 
-use log::{debug, info};
-use std::{env, io, net, path};
+use log::{debug, info, warn};
+use std::{env, fs, io, path, pin, time};
 use termion::input::TermRead;
 
 fn parse_authentication_credentials(
@@ -32,15 +48,360 @@ fn parse_authentication_credentials(
     }
 }
 
+/// Parse the arguments of the `hash-password` subcommand: `[--scheme SCHEME] USERNAME`.
+fn parse_hash_password_args(args: &[String]) -> Result<(HashScheme, String), String> {
+    let (scheme, username) = match args {
+        [username] => (HashScheme::default(), username),
+        [option, scheme, username] if option == "--scheme" => (scheme.parse()?, username),
+        _ => {
+            return Err(String::from(
+                "Usage: rust-http-server hash-password [--scheme bcrypt|sha256-crypt|sha512-crypt|argon2] USERNAME",
+            ));
+        }
+    };
+    match username.is_empty() || username.contains(':') {
+        true => Err(format!("Invalid username: {}", username)),
+        false => Ok((scheme, username.clone())),
+    }
+}
+
+/// Print an htpasswd entry for a user. The password is read from the terminal (without echo) or
+/// from the standard input, so that it shows up neither in the process list nor in the shell
+/// history.
 #[cfg_attr(coverage, coverage(off))]
-fn parse_args() -> Result<Settings, String> {
+fn hash_password(args: &[String]) -> Result<(), String> {
+    let (scheme, username) = parse_hash_password_args(args)?;
+    let stdin = io::stdin();
+    let password = if termion::is_tty(&stdin) {
+        eprint!("Password for {}: ", username);
+        let password = stdin.lock().read_passwd(&mut io::stderr());
+        eprintln!();
+        password
+    } else {
+        stdin.lock().read_line()
+    };
+    let password = password
+        .map_err(|e| format!("Cannot read password: {}", e))?
+        .filter(|password| !password.is_empty())
+        .ok_or("No password given")?;
+    println!("{}:{}", username, scheme.hash(&password)?);
+    Ok(())
+}
+
+/// Parse the arguments of the `sign-url` subcommand:
+/// `--url-secret FILE [--expires-in SECONDS] PATH`.
+fn parse_sign_url_args(args: &[String]) -> Result<(path::PathBuf, u64, String), String> {
+    let (secret, expires_in, path) = match args {
+        [option, secret, path] if option == "--url-secret" => (secret, "3600", path),
+        [option, secret, expires_option, expires_in, path]
+            if option == "--url-secret" && expires_option == "--expires-in" =>
+        {
+            (secret, expires_in.as_str(), path)
+        }
+        _ => {
+            return Err(String::from(
+                "Usage: rust-http-server sign-url --url-secret FILE [--expires-in SECONDS] PATH",
+            ));
+        }
+    };
+    let expires_in = match expires_in.parse() {
+        Ok(seconds) if seconds > 0 => seconds,
+        _ => return Err(format!("Invalid expiry delay: {}", expires_in)),
+    };
+    match path.starts_with('/') {
+        true => Ok((path::PathBuf::from(secret), expires_in, path.clone())),
+        false => Err(format!("Invalid path: {}", path)),
+    }
+}
+
+/// Print the signed URL of a path (without the scheme and host, to be prepended), valid for some
+/// time.
+#[cfg_attr(coverage, coverage(off))]
+fn sign_url(args: &[String]) -> Result<(), String> {
+    let (secret, expires_in, path) = parse_sign_url_args(args)?;
+    let signer = UrlSigner::load(&secret).map_err(|e| e.to_string())?;
+    let now = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?;
+    println!("{}", signer.sign(&path, now.as_secs() + expires_in));
+    Ok(())
+}
+
+fn parse_proxy_routes(argument: Option<String>) -> Result<Vec<ProxyRoute>, String> {
+    match argument {
+        Some(routes) => routes.split(';').map(|route| route.parse()).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn parse_tls_hosts(argument: Option<String>) -> Result<Vec<TlsHost>, String> {
+    match argument {
+        Some(hosts) => hosts.split(';').map(|host| host.parse()).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn parse_listeners(
+    address: Option<String>,
+    tls: bool,
+    listen: Option<String>,
+) -> Result<Vec<Listener>, String> {
+    let mut listeners = Vec::new();
+    if let Some(address) = address {
+        let address = address
+            .parse()
+            .map_err(|_| format!("Invalid socket address: {}", address))?;
+        listeners.push(Listener::tcp(address, tls));
+    }
+    if let Some(listen) = listen {
+        for listener in listen.split(';') {
+            listeners.push(listener.parse()?);
+        }
+    }
+    match listeners.is_empty() {
+        true => Err(String::from(
+            "No address to listen on (see --address and --listen)",
+        )),
+        false => Ok(listeners),
+    }
+}
+
+fn parse_client_auth(
+    ca_path: Option<path::PathBuf>,
+    crl_path: Option<path::PathBuf>,
+    mode: Option<String>,
+    allowed_names: Option<String>,
+) -> Result<Option<ClientAuth>, String> {
+    let Some(ca_path) = ca_path else {
+        return match crl_path.is_some() || allowed_names.is_some() {
+            true => Err(String::from(
+                "A client CA bundle is required to verify client certificates",
+            )),
+            false => Ok(None),
+        };
+    };
+    let required = match mode.as_deref() {
+        None | Some("required") => true,
+        Some("optional") => false,
+        Some(mode) => return Err(format!("Invalid client authentication mode: {}", mode)),
+    };
+    let allowed_names = match allowed_names {
+        Some(names) => names
+            .split(',')
+            .map(|name| match name.trim() {
+                "" => Err(format!("Invalid client name list: {}", names)),
+                name => Ok(String::from(name)),
+            })
+            .collect::<Result<_, _>>()?,
+        None => Vec::new(),
+    };
+    Ok(Some(ClientAuth {
+        ca_path,
+        crl_path,
+        required,
+        allowed_names,
+    }))
+}
+
+fn read_key_password(password_file: Option<path::PathBuf>) -> Result<Option<KeyPassword>, String> {
+    match password_file {
+        Some(password_file) => {
+            let password = fs::read_to_string(&password_file).map_err(|e| {
+                format!(
+                    "Cannot read password file {}: {}",
+                    password_file.display(),
+                    e
+                )
+            })?;
+            match password.lines().next() {
+                Some(password) if !password.is_empty() => {
+                    Ok(Some(KeyPassword(String::from(password))))
+                }
+                _ => Err(format!("Empty password file: {}", password_file.display())),
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_tls_policy(
+    min_version: Option<String>,
+    cipher_suites: Option<String>,
+    kx_groups: Option<String>,
+) -> Result<TlsPolicy, String> {
+    let parse_list = |list: Option<String>| match list {
+        Some(list) => list
+            .split(',')
+            .map(|name| match name.trim() {
+                "" => Err(format!("Invalid TLS policy list: {}", list)),
+                name => Ok(String::from(name)),
+            })
+            .collect::<Result<Vec<_>, _>>(),
+        None => Ok(Vec::new()),
+    };
+    Ok(TlsPolicy {
+        min_version: match min_version {
+            Some(min_version) => min_version.parse()?,
+            None => Default::default(),
+        },
+        cipher_suites: parse_list(cipher_suites)?,
+        kx_groups: parse_list(kx_groups)?,
+    })
+}
+
+fn parse_reload_interval(argument: Option<u64>) -> Result<Option<time::Duration>, String> {
+    match argument {
+        Some(0) => Ok(None),
+        Some(seconds) => Ok(Some(time::Duration::from_secs(seconds))),
+        None => Err(String::from("invalid TLS reload interval")),
+    }
+}
+
+fn parse_https_redirect(
+    address: Option<String>,
+    host: Option<String>,
+) -> Result<Option<HttpsRedirect>, String> {
+    match (address, host) {
+        (Some(address), host) => Ok(Some(HttpsRedirect {
+            address: address
+                .parse()
+                .map_err(|_| format!("Invalid HTTPS redirect address: {}", address))?,
+            host: host.filter(|host| !host.is_empty()),
+        })),
+        (None, Some(_)) => Err(String::from(
+            "HTTPS redirect host requires an HTTPS redirect address",
+        )),
+        (None, None) => Ok(None),
+    }
+}
+
+fn parse_hsts(argument: Option<String>) -> Result<Option<Hsts>, String> {
+    argument.map(|hsts| hsts.parse()).transpose()
+}
+
+fn parse_rate_limits(argument: Option<String>) -> Result<Vec<RateLimit>, String> {
+    match argument {
+        Some(limits) => limits.split(';').map(|limit| limit.parse()).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn parse_auth_failure_rate(argument: Option<String>) -> Result<Option<Rate>, String> {
+    argument.map(|rate| rate.parse()).transpose()
+}
+
+fn parse_acls(argument: Option<String>) -> Result<Vec<Acl>, String> {
+    match argument {
+        Some(acls) => acls.split(';').map(|acl| acl.parse()).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn parse_access_logs(argument: Option<String>) -> Result<Vec<AccessLog>, String> {
+    match argument {
+        Some(logs) => logs.split(';').map(|log| log.parse()).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn parse_metrics(argument: Option<String>) -> Result<Option<MetricsEndpoint>, String> {
+    argument.map(|endpoint| endpoint.parse()).transpose()
+}
+
+fn parse_server_status(argument: Option<String>) -> Result<Option<String>, String> {
+    match argument {
+        Some(path) if !path.starts_with('/') => {
+            Err(format!("Invalid server status path: {}", path))
+        }
+        argument => Ok(argument),
+    }
+}
+
+fn parse_auth_locations(argument: Option<String>) -> Result<Vec<Location>, String> {
+    match argument {
+        Some(locations) => locations
+            .split(';')
+            .map(|location| location.parse())
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn parse_jwt_keys(argument: Option<String>) -> Result<Vec<JwtKey>, String> {
+    match argument {
+        Some(keys) => keys.split(';').map(|key| key.parse()).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn parse_login(argument: Option<String>) -> Result<Option<LoginSettings>, String> {
+    argument.map(|login| login.parse()).transpose()
+}
+
+fn parse_auth_schemes(argument: Option<String>) -> Result<Vec<AuthScheme>, String> {
+    let mut schemes = Vec::new();
+    for scheme in argument.as_deref().unwrap_or("basic").split(',') {
+        let scheme = scheme.parse()?;
+        if schemes.contains(&scheme) {
+            return Err(format!("Duplicate authentication scheme: {}", scheme));
+        }
+        schemes.push(scheme);
+    }
+    Ok(schemes)
+}
+
+fn parse_trusted_proxies(argument: Option<String>) -> Result<Vec<IpNetwork>, String> {
+    match argument {
+        Some(networks) => networks
+            .split(',')
+            .map(|network| network.trim().parse())
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Parse the CLI arguments: the settings of the server, and whether to show the dashboard.
+#[cfg_attr(coverage, coverage(off))]
+fn parse_args() -> Result<(Settings, bool), String> {
     let mut arg_parser = argparse_rs::ArgParser::new(String::from("rust-http-server"));
     arg_parser.add_opt(
         "address",
         None,
         'a',
-        true,
-        "Socket address to bind",
+        false,
+        "Socket address to bind, serving HTTPS if an SSL certificate and key are given",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "listen",
+        None,
+        'l',
+        false,
+        "Semicolon-separated list of other listeners (format: ADDRESS[|tls][|dual-stack], unix:PATH[|tls][|mode=OCTAL_MODE][|owner=USER[:GROUP]] or systemd:[NAME][|tls] for sockets passed by systemd, followed by |proxy-protocol to require a PROXY protocol header)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "user",
+        None,
+        'u',
+        false,
+        "User to run as once the listeners are bound (name or ID)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "group",
+        None,
+        'g',
+        false,
+        "Group to run as once the listeners are bound (name or ID, default: the primary group of the user)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "pid-file",
+        None,
+        'i',
+        false,
+        "File to write the process ID to",
         argparse_rs::ArgType::Option,
     );
     arg_parser.add_opt(
@@ -75,10 +436,178 @@ fn parse_args() -> Result<Settings, String> {
         "SSL key for HTTPS",
         argparse_rs::ArgType::Option,
     );
+    arg_parser.add_opt(
+        "ssl-key-password-file",
+        None,
+        'K',
+        false,
+        "File containing the password of the SSL key, when it is encrypted (PKCS#8)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "allow-plain-http",
+        Some("false"),
+        'H',
+        false,
+        "Fall back to plain HTTP when the SSL certificate or key is missing or invalid, instead of failing (default: false)",
+        argparse_rs::ArgType::Flag,
+    );
+    arg_parser.add_opt(
+        "tls-min-version",
+        Some("1.2"),
+        'm',
+        false,
+        "Oldest TLS version accepted: 1.2 or 1.3 (default: 1.2)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "tls-ciphers",
+        None,
+        'S',
+        false,
+        "Comma-separated list of TLS cipher suites (e.g. TLS13_AES_256_GCM_SHA384), all the supported ones being used by default",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "tls-groups",
+        None,
+        'G',
+        false,
+        "Comma-separated list of TLS key exchange groups (e.g. X25519), all the supported ones being used by default",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "tls-hosts",
+        None,
+        's',
+        false,
+        "Semicolon-separated list of certificates to present for some host names with SNI, instead of the default one (format: NAME[,NAME...]=CERT_PATH,KEY_PATH)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "tls-reload-interval",
+        Some("10"),
+        'R',
+        false,
+        "Interval (in seconds) at which certificate files are checked for changes, to reload them (0 to only reload them on SIGHUP, default: 10)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "client-ca",
+        None,
+        'C',
+        false,
+        "CA certificates (PEM bundle) to verify client certificates with (mutual TLS)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "client-crl",
+        None,
+        'L',
+        false,
+        "Certificate revocation lists (PEM) checked for client certificates",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "client-auth",
+        Some("required"),
+        'A',
+        false,
+        "Whether clients must present a certificate, or may connect without one: required or optional (default: required)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "client-names",
+        None,
+        'N',
+        false,
+        "Comma-separated list of client names (certificate common name or alternative name) granted access",
+        argparse_rs::ArgType::Option,
+    );
     arg_parser.add_opt(
         "auth-creds", None, 'p', false, "Comma-separated list of credentials (format: username:password). If provided, the server will only serve content to authenticated clients.",
         argparse_rs::ArgType::Option,
     );
+    arg_parser.add_opt(
+        "auth-file",
+        None,
+        'W',
+        false,
+        "htpasswd file of the users granted access, with bcrypt, SHA-crypt or argon2 password hashes (reloaded when it changes, see the hash-password subcommand)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "auth-schemes",
+        Some("basic"),
+        'D',
+        false,
+        "Comma-separated list of authentication schemes offered to clients, in order of preference: basic, digest (SHA-256 and MD5, only for the auth-creds users) or bearer (static tokens or JSON Web Tokens), default: basic",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "auth-realms",
+        None,
+        'B',
+        false,
+        "Semicolon-separated list of locations protected by their own realm, or left public (format: /prefix=public or /prefix=NAME[|file=HTPASSWD][|require=RULE[,RULE...]][|schemes=SCHEME[,SCHEME...]], where RULE is user:NAME, group:NAME or claim:NAME=VALUE)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "auth-groups",
+        None,
+        'U',
+        false,
+        "htgroup file of the groups required by the realms (format: GROUP: USER [USER...], reloaded when it changes)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "bearer-tokens",
+        None,
+        'b',
+        false,
+        "File of the static tokens accepted with the bearer scheme (format: NAME:TOKEN per line, reloaded when it changes)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "jwt-keys",
+        None,
+        'j',
+        false,
+        "Semicolon-separated list of keys verifying the JSON Web Tokens accepted with the bearer scheme (format: ALGORITHM=PATH[|kid=ID], where ALGORITHM is HS256 with a secret file, or RS256 or ES256 with a PEM public key)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "jwt-issuer",
+        None,
+        'I',
+        false,
+        "Issuer (iss claim) required from the JSON Web Tokens",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "jwt-audience",
+        None,
+        'J',
+        false,
+        "Audience (aud claim) required from the JSON Web Tokens",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "url-secret",
+        None,
+        'e',
+        false,
+        "File of the secret (at least 32 bytes) signing the URLs granting access to a path without credentials until they expire (see the sign-url subcommand)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "login",
+        None,
+        'o',
+        false,
+        "Serve a login form on a path, opening sessions kept in a cookie for the users of the server credentials, in realms offering the Basic or Digest scheme (format: LOGIN-PATH[|logout=PATH][|idle=SECONDS][|max-age=SECONDS], defaults: /logout, 1800 and 43200)",
+        argparse_rs::ArgType::Option,
+    );
     arg_parser.add_opt(
         "php-binary",
         Some("php-cgi"),
@@ -87,6 +616,119 @@ fn parse_args() -> Result<Settings, String> {
         "Alternate path for php binary, used to process PHP scripts with CGI (default: php-cgi)",
         argparse_rs::ArgType::Option,
     );
+    arg_parser.add_opt(
+        "proxy",
+        None,
+        'x',
+        false,
+        "Semicolon-separated list of reverse proxy routes (format: /prefix=host:port[,host:port...][|round-robin|least-conn|host=NAME|timeout=SECONDS], or host:NAME=... to match a virtual host)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "forward-proxy",
+        Some("false"),
+        'F',
+        false,
+        "Act as a forward proxy, accepting absolute URIs and CONNECT requests (default: false)",
+        argparse_rs::ArgType::Flag,
+    );
+
+    arg_parser.add_opt(
+        "http3",
+        Some("false"),
+        '3',
+        false,
+        "Also listen for HTTP/3 requests on the matching UDP port, requires HTTPS (default: false)",
+        argparse_rs::ArgType::Flag,
+    );
+    arg_parser.add_opt(
+        "https-redirect",
+        None,
+        'T',
+        false,
+        "Also listen for plain HTTP requests on this socket address, redirecting them to HTTPS",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "https-redirect-host",
+        None,
+        'O',
+        false,
+        "Host (and optional port) to redirect plain HTTP requests to, instead of the requested one",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "hsts",
+        None,
+        'E',
+        false,
+        "Strict-Transport-Security header sent with HTTPS responses (e.g. \"max-age=31536000; includeSubDomains\")",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "trusted-proxies",
+        None,
+        'X',
+        false,
+        "Comma-separated list of proxy networks (e.g. 10.0.0.0/8) whose Forwarded / X-Forwarded-For headers give the client address",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "rate-limit",
+        None,
+        'q',
+        false,
+        "Semicolon-separated list of rate limits (format: KEY=REQUESTS/UNIT[|burst=N], where KEY is ip, user or a path prefix and UNIT is s, min or h)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "auth-failure-rate",
+        None,
+        'f',
+        false,
+        "Rate of failed authentications of a client IP address above which its attempts are refused (format: REQUESTS/UNIT[|burst=N])",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "acl",
+        None,
+        'w',
+        false,
+        "Semicolon-separated list of IP access control lists, whose first matching rule allows or denies clients (format: SCOPE=allow:CIDR|all[,deny:CIDR|all...], where SCOPE is *, host:NAME or a path prefix)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "access-log",
+        None,
+        'y',
+        false,
+        "Semicolon-separated list of access logs, replacing the Common Log Format lines of the server log (format: OUTPUT[|format=FORMAT][|max-size=SIZE][|rotate=hourly|daily][|keep=N][|host=NAME], where OUTPUT is stdout, syslog[:SOCKET] or a file, FORMAT is common, combined, json or a template like %h %u \"%r\" %>s %D, SIZE is in bytes with an optional K, M or G suffix and keep defaults to 5)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "metrics",
+        None,
+        'M',
+        false,
+        "Expose the metrics of the server in the Prometheus text format, on a path (e.g. /metrics, subject to the access control and authentication of the server) or at /metrics on their own plain HTTP listener (e.g. 127.0.0.1:9100)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "server-status",
+        None,
+        'Z',
+        false,
+        "Path of the status page of the server, listing the active connections with their current request, in HTML or in JSON (with the json query parameter), subject to the access control and authentication of the server (e.g. /server-status)",
+        argparse_rs::ArgType::Option,
+    );
+    arg_parser.add_opt(
+        "dashboard",
+        Some("false"),
+        'Y',
+        false,
+        "Show a full-screen dashboard of the server in the terminal, with the log messages, instead of writing them to the terminal (default: false)",
+        argparse_rs::ArgType::Flag,
+    );
 
     let args = match arg_parser.parse(env::args().collect::<Vec<String>>().iter()) {
         Ok(args) => args,
@@ -96,10 +738,15 @@ fn parse_args() -> Result<Settings, String> {
         }
     };
 
-    Ok(Settings {
-        address: args
-            .get::<net::SocketAddr>("address")
-            .ok_or("invalid socket address")?,
+    let settings = Settings {
+        listeners: parse_listeners(
+            args.get::<String>("address"),
+            args.get::<String>("ssl-cert").is_some() || args.get::<String>("ssl-key").is_some(),
+            args.get::<String>("listen"),
+        )?,
+        user: args.get::<String>("user"),
+        group: args.get::<String>("group"),
+        pid_file: args.get::<path::PathBuf>("pid-file"),
         document_root: args
             .get::<path::PathBuf>("doc-root")
             .ok_or("invalid doc-root")?
@@ -110,13 +757,63 @@ fn parse_args() -> Result<Settings, String> {
             .ok_or("invalid value for directory listing")?,
         ssl_cert_path: args.get::<path::PathBuf>("ssl-cert"),
         ssl_key_path: args.get::<path::PathBuf>("ssl-key"),
+        ssl_key_password: read_key_password(args.get::<path::PathBuf>("ssl-key-password-file"))?,
+        allow_plain_http_fallback: args
+            .get::<bool>("allow-plain-http")
+            .ok_or("invalid value for plain HTTP fallback")?,
+        tls_policy: parse_tls_policy(
+            args.get::<String>("tls-min-version"),
+            args.get::<String>("tls-ciphers"),
+            args.get::<String>("tls-groups"),
+        )?,
+        tls_hosts: parse_tls_hosts(args.get::<String>("tls-hosts"))?,
+        client_auth: parse_client_auth(
+            args.get::<path::PathBuf>("client-ca"),
+            args.get::<path::PathBuf>("client-crl"),
+            args.get::<String>("client-auth"),
+            args.get::<String>("client-names"),
+        )?,
         authentication_credentials: parse_authentication_credentials(
             args.get::<String>("auth-creds"),
         )?,
+        authentication_file: args.get::<path::PathBuf>("auth-file"),
+        authentication_locations: parse_auth_locations(args.get::<String>("auth-realms"))?,
+        authentication_groups_file: args.get::<path::PathBuf>("auth-groups"),
+        authentication_schemes: parse_auth_schemes(args.get::<String>("auth-schemes"))?,
+        bearer_tokens_file: args.get::<path::PathBuf>("bearer-tokens"),
+        jwt_keys: parse_jwt_keys(args.get::<String>("jwt-keys"))?,
+        jwt_issuer: args.get::<String>("jwt-issuer"),
+        jwt_audience: args.get::<String>("jwt-audience"),
+        login: parse_login(args.get::<String>("login"))?,
+        url_secret: args.get::<path::PathBuf>("url-secret"),
         php_cgi_binary: args
             .get::<String>("php-binary")
             .ok_or("invalid php binary path")?,
-    })
+        proxy_routes: parse_proxy_routes(args.get::<String>("proxy"))?,
+        forward_proxy: args
+            .get::<bool>("forward-proxy")
+            .ok_or("invalid value for forward proxy")?,
+        http3: args
+            .get::<bool>("http3")
+            .ok_or("invalid value for HTTP/3")?,
+        tls_reload_interval: parse_reload_interval(args.get::<u64>("tls-reload-interval"))?,
+        https_redirect: parse_https_redirect(
+            args.get::<String>("https-redirect"),
+            args.get::<String>("https-redirect-host"),
+        )?,
+        hsts: parse_hsts(args.get::<String>("hsts"))?,
+        trusted_proxies: parse_trusted_proxies(args.get::<String>("trusted-proxies"))?,
+        rate_limits: parse_rate_limits(args.get::<String>("rate-limit"))?,
+        auth_failure_rate: parse_auth_failure_rate(args.get::<String>("auth-failure-rate"))?,
+        acls: parse_acls(args.get::<String>("acl"))?,
+        access_logs: parse_access_logs(args.get::<String>("access-log"))?,
+        metrics: parse_metrics(args.get::<String>("metrics"))?,
+        server_status: parse_server_status(args.get::<String>("server-status"))?,
+    };
+    let dashboard = args
+        .get::<bool>("dashboard")
+        .ok_or("invalid value for dashboard")?;
+    Ok((settings, dashboard))
 }
 
 #[tokio::main]
@@ -126,13 +823,36 @@ async fn main() -> Result<(), String> {
     if env::var("RUST_LOG").is_err() {
         unsafe { env::set_var("RUST_LOG", "info") }
     }
-    env_logger::init();
+
+    let args = env::args().collect::<Vec<_>>();
+    // subcommands
+    match args.get(1).map(String::as_str) {
+        Some("hash-password") => return hash_password(&args[2..]),
+        Some("sign-url") => return sign_url(&args[2..]),
+        _ => (),
+    }
 
     // parse arguments
-    let server_settings = parse_args()?;
+    let (server_settings, dashboard) = parse_args()?;
+    // the log messages are shown by the dashboard, if any
+    let logs = match dashboard {
+        true => Some(dashboard::init_logger().map_err(|e| e.to_string())?),
+        false => {
+            env_logger::init();
+            None
+        }
+    };
     debug!("server settings: {:?}", server_settings);
 
-    info!("Starting server on {}", server_settings.address);
+    info!(
+        "Starting server on {}",
+        server_settings
+            .listeners
+            .iter()
+            .map(|listener| listener.address.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
 
     // create server
     let mut server = Server::new(server_settings)
@@ -140,26 +860,47 @@ async fn main() -> Result<(), String> {
         .map_err(|e| e.to_string())?;
     info!("Server listening");
 
-    // setup keypress handling
-    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
-    tokio::spawn(async {
-        info!("Press <q> then <Enter> to stop the server");
-        let stdin = io::stdin();
-        // detecting keydown events
-        for c in stdin.keys() {
-            if let termion::event::Key::Char('q') = c.unwrap() {
-                break;
-            }
+    // setup keypress handling, by the dashboard if any
+    let status = server.status();
+    let mut ui: pin::Pin<Box<dyn Future<Output = io::Result<()>>>> = match logs {
+        Some(logs) => Box::pin(dashboard::run(status.clone(), logs)),
+        None => {
+            let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+            tokio::spawn(async {
+                info!("Press <q> then <Enter> to stop the server");
+                let stdin = io::stdin();
+                // detecting keydown events
+                for c in stdin.keys() {
+                    if let termion::event::Key::Char('q') = c.unwrap() {
+                        break;
+                    }
+                }
+                // send quit signal to main task
+                tx.send(()).unwrap();
+            });
+            Box::pin(async {
+                let _ = rx.await;
+                Ok(())
+            })
         }
-        // send quit signal to main task
-        tx.send(()).unwrap();
-    });
+    };
 
     tokio::select! {
-        _ = rx => {},
+        result = &mut ui => return result.map_err(|e| format!("dashboard error: {}", e)),
         _ = server.listen() => {}
     }
 
+    // the server is drained: the open connections are given some time to finish
+    tokio::select! {
+        result = &mut ui => result.map_err(|e| format!("dashboard error: {}", e))?,
+        idle = status.wait_idle(status::DRAIN_TIMEOUT) => if !idle {
+            warn!(
+                "Stopping the server with {} connection(s) still open",
+                status.active_connections()
+            );
+        },
+    }
+
     Ok(())
 }
 
@@ -189,4 +930,388 @@ mod tests {
             ]))
         );
     }
+
+    #[test]
+    fn parse_hash_password_args_test() {
+        let args = |args: &[&str]| {
+            args.iter()
+                .map(|arg| String::from(*arg))
+                .collect::<Vec<_>>()
+        };
+        assert!(parse_hash_password_args(&args(&[])).is_err());
+        assert!(parse_hash_password_args(&args(&["foo:bar"])).is_err());
+        assert!(parse_hash_password_args(&args(&["--scheme", "md5", "foo"])).is_err());
+        assert!(parse_hash_password_args(&args(&["foo", "bar"])).is_err());
+        assert_eq!(
+            parse_hash_password_args(&args(&["foo"])),
+            Ok((HashScheme::Bcrypt, String::from("foo")))
+        );
+        assert_eq!(
+            parse_hash_password_args(&args(&["--scheme", "argon2", "foo"])),
+            Ok((HashScheme::Argon2, String::from("foo")))
+        );
+    }
+
+    #[test]
+    fn parse_sign_url_args_test() {
+        let args = |args: &[&str]| {
+            args.iter()
+                .map(|arg| String::from(*arg))
+                .collect::<Vec<_>>()
+        };
+        assert!(parse_sign_url_args(&args(&[])).is_err());
+        assert!(parse_sign_url_args(&args(&["/file.pdf"])).is_err());
+        assert!(parse_sign_url_args(&args(&["--url-secret", "secret", "file.pdf"])).is_err());
+        assert!(
+            parse_sign_url_args(&args(&[
+                "--url-secret",
+                "secret",
+                "--expires-in",
+                "1h",
+                "/file.pdf"
+            ]))
+            .is_err()
+        );
+        assert_eq!(
+            parse_sign_url_args(&args(&["--url-secret", "secret", "/file.pdf"])),
+            Ok((
+                path::PathBuf::from("secret"),
+                3600,
+                String::from("/file.pdf")
+            ))
+        );
+        assert_eq!(
+            parse_sign_url_args(&args(&[
+                "--url-secret",
+                "secret",
+                "--expires-in",
+                "600",
+                "/file.pdf"
+            ])),
+            Ok((
+                path::PathBuf::from("secret"),
+                600,
+                String::from("/file.pdf")
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_tls_hosts_test() {
+        assert_eq!(parse_tls_hosts(None), Ok(Vec::new()));
+        assert!(parse_tls_hosts(Some(String::new())).is_err());
+        assert_eq!(
+            parse_tls_hosts(Some(String::from(
+                "alt.localhost=ssl/alt.crt,ssl/alt.key;a.localhost,b.localhost=a.crt,a.key"
+            ))),
+            Ok(vec![
+                "alt.localhost=ssl/alt.crt,ssl/alt.key".parse().unwrap(),
+                "a.localhost,b.localhost=a.crt,a.key".parse().unwrap()
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_listeners_test() {
+        assert!(parse_listeners(None, false, None).is_err());
+        assert!(parse_listeners(Some(String::from("localhost:80")), false, None).is_err());
+        assert!(parse_listeners(None, false, Some(String::from("0.0.0.0:80;"))).is_err());
+        assert_eq!(
+            parse_listeners(
+                Some(String::from("0.0.0.0:443")),
+                true,
+                Some(String::from(
+                    "[::]:80|dual-stack;unix:/run/server.sock|mode=660"
+                ))
+            ),
+            Ok(vec![
+                Listener::tcp("0.0.0.0:443".parse().unwrap(), true),
+                "[::]:80|dual-stack".parse().unwrap(),
+                "unix:/run/server.sock|mode=660".parse().unwrap(),
+            ])
+        );
+    }
+
+    #[test]
+    fn read_key_password_test() {
+        assert_eq!(read_key_password(None), Ok(None));
+        assert!(read_key_password(Some(path::PathBuf::from("ssl/none.txt"))).is_err());
+
+        let password_file = env::temp_dir().join(format!("password-{}", std::process::id()));
+        fs::write(&password_file, "\n").unwrap();
+        assert!(read_key_password(Some(password_file.clone())).is_err());
+        fs::write(&password_file, "server-password\n").unwrap();
+        assert_eq!(
+            read_key_password(Some(password_file.clone())),
+            Ok(Some(KeyPassword(String::from("server-password"))))
+        );
+        fs::remove_file(&password_file).unwrap();
+    }
+
+    #[test]
+    fn parse_tls_policy_test() {
+        assert_eq!(parse_tls_policy(None, None, None), Ok(TlsPolicy::default()));
+        assert!(parse_tls_policy(Some(String::from("1.0")), None, None).is_err());
+        assert!(parse_tls_policy(None, Some(String::new()), None).is_err());
+        assert_eq!(
+            parse_tls_policy(
+                Some(String::from("1.3")),
+                Some(String::from(
+                    "TLS13_AES_256_GCM_SHA384,TLS13_CHACHA20_POLY1305_SHA256"
+                )),
+                Some(String::from("X25519"))
+            ),
+            Ok(TlsPolicy {
+                min_version: "1.3".parse().unwrap(),
+                cipher_suites: vec![
+                    String::from("TLS13_AES_256_GCM_SHA384"),
+                    String::from("TLS13_CHACHA20_POLY1305_SHA256")
+                ],
+                kx_groups: vec![String::from("X25519")],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_reload_interval_test() {
+        assert!(parse_reload_interval(None).is_err());
+        assert_eq!(parse_reload_interval(Some(0)), Ok(None));
+        assert_eq!(
+            parse_reload_interval(Some(10)),
+            Ok(Some(time::Duration::from_secs(10)))
+        );
+    }
+
+    #[test]
+    fn parse_https_redirect_test() {
+        assert_eq!(parse_https_redirect(None, None), Ok(None));
+        assert!(parse_https_redirect(None, Some(String::from("example.com"))).is_err());
+        assert!(parse_https_redirect(Some(String::from("localhost:80")), None).is_err());
+        assert_eq!(
+            parse_https_redirect(
+                Some(String::from("0.0.0.0:80")),
+                Some(String::from("example.com"))
+            ),
+            Ok(Some(HttpsRedirect {
+                address: "0.0.0.0:80".parse().unwrap(),
+                host: Some(String::from("example.com")),
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_rate_limits_test() {
+        assert_eq!(parse_rate_limits(None), Ok(Vec::new()));
+        assert!(parse_rate_limits(Some(String::from("ip=10/s;"))).is_err());
+        assert_eq!(
+            parse_rate_limits(Some(String::from("ip=10/s;/login=5/min|burst=1"))),
+            Ok(vec![
+                "ip=10/s".parse().unwrap(),
+                "/login=5/min|burst=1".parse().unwrap()
+            ])
+        );
+        assert_eq!(parse_auth_failure_rate(None), Ok(None));
+        assert!(parse_auth_failure_rate(Some(String::from("5"))).is_err());
+        assert_eq!(
+            parse_auth_failure_rate(Some(String::from("5/min"))),
+            Ok(Some(Rate {
+                requests: 5,
+                per: time::Duration::from_secs(60),
+                burst: 5,
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_acls_test() {
+        assert_eq!(parse_acls(None), Ok(Vec::new()));
+        assert!(parse_acls(Some(String::from("/admin=deny:all;"))).is_err());
+        assert_eq!(
+            parse_acls(Some(String::from(
+                "*=deny:192.0.2.0/24;/admin=allow:10.0.0.0/8,deny:all"
+            ))),
+            Ok(vec![
+                "*=deny:192.0.2.0/24".parse().unwrap(),
+                "/admin=allow:10.0.0.0/8,deny:all".parse().unwrap()
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_access_logs_test() {
+        assert_eq!(parse_access_logs(None), Ok(Vec::new()));
+        assert!(parse_access_logs(Some(String::from("stdout;"))).is_err());
+        assert_eq!(
+            parse_access_logs(Some(String::from(
+                "/var/log/example.log|format=json|host=example.org;stdout|format=common"
+            ))),
+            Ok(vec![
+                "/var/log/example.log|format=json|host=example.org"
+                    .parse()
+                    .unwrap(),
+                "stdout|format=common".parse().unwrap()
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_server_status_test() {
+        assert_eq!(parse_server_status(None), Ok(None));
+        assert!(parse_server_status(Some(String::from("server-status"))).is_err());
+        assert_eq!(
+            parse_server_status(Some(String::from("/server-status"))),
+            Ok(Some(String::from("/server-status")))
+        );
+    }
+
+    #[test]
+    fn parse_metrics_test() {
+        assert_eq!(parse_metrics(None), Ok(None));
+        assert!(parse_metrics(Some(String::from("metrics"))).is_err());
+        assert_eq!(
+            parse_metrics(Some(String::from("/metrics"))),
+            Ok(Some(MetricsEndpoint::Path(String::from("/metrics"))))
+        );
+        assert_eq!(
+            parse_metrics(Some(String::from("127.0.0.1:9100"))),
+            Ok(Some(MetricsEndpoint::Listener(
+                "127.0.0.1:9100".parse().unwrap()
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_auth_locations_test() {
+        assert_eq!(parse_auth_locations(None), Ok(Vec::new()));
+        assert!(parse_auth_locations(Some(String::from("/admin=Admin;"))).is_err());
+        assert_eq!(
+            parse_auth_locations(Some(String::from(
+                "/admin=Admin|require=group:admins;/health=public"
+            ))),
+            Ok(vec![
+                "/admin=Admin|require=group:admins".parse().unwrap(),
+                "/health=public".parse().unwrap()
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_jwt_keys_test() {
+        assert_eq!(parse_jwt_keys(None), Ok(Vec::new()));
+        assert!(parse_jwt_keys(Some(String::from("HS256=secret;"))).is_err());
+        assert_eq!(
+            parse_jwt_keys(Some(String::from("HS256=secret;ES256=ec.pem|kid=1"))),
+            Ok(vec![
+                "HS256=secret".parse().unwrap(),
+                "ES256=ec.pem|kid=1".parse().unwrap()
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_auth_schemes_test() {
+        assert_eq!(parse_auth_schemes(None), Ok(vec![AuthScheme::Basic]));
+        assert!(parse_auth_schemes(Some(String::new())).is_err());
+        assert!(parse_auth_schemes(Some(String::from("ntlm"))).is_err());
+        assert!(parse_auth_schemes(Some(String::from("digest,digest"))).is_err());
+        assert_eq!(
+            parse_auth_schemes(Some(String::from("digest, basic"))),
+            Ok(vec![AuthScheme::Digest, AuthScheme::Basic])
+        );
+    }
+
+    #[test]
+    fn parse_trusted_proxies_test() {
+        assert_eq!(parse_trusted_proxies(None), Ok(Vec::new()));
+        assert!(parse_trusted_proxies(Some(String::from("10.0.0.0/33"))).is_err());
+        assert!(parse_trusted_proxies(Some(String::from("10.0.0.0/8,"))).is_err());
+        assert_eq!(
+            parse_trusted_proxies(Some(String::from("10.0.0.0/8, ::1"))),
+            Ok(vec![
+                "10.0.0.0/8".parse().unwrap(),
+                "::1/128".parse().unwrap()
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_login_test() {
+        assert_eq!(parse_login(None), Ok(None));
+        assert!(parse_login(Some(String::from("login"))).is_err());
+        assert_eq!(
+            parse_login(Some(String::from("/login|idle=600"))),
+            Ok(Some(LoginSettings {
+                login_path: String::from("/login"),
+                logout_path: String::from("/logout"),
+                idle_timeout: time::Duration::from_secs(600),
+                max_age: time::Duration::from_secs(43200),
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_hsts_test() {
+        assert_eq!(parse_hsts(None), Ok(None));
+        assert!(parse_hsts(Some(String::from("includeSubDomains"))).is_err());
+        assert_eq!(
+            parse_hsts(Some(String::from("max-age=300; preload"))),
+            Ok(Some(Hsts {
+                max_age: 300,
+                include_subdomains: false,
+                preload: true,
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_client_auth_test() {
+        let ca_path = || Some(path::PathBuf::from("ssl/root.crt"));
+        assert_eq!(parse_client_auth(None, None, None, None), Ok(None));
+        assert!(parse_client_auth(None, ca_path(), None, None).is_err());
+        assert!(parse_client_auth(None, None, None, Some(String::from("client"))).is_err());
+        assert!(parse_client_auth(ca_path(), None, Some(String::from("maybe")), None).is_err());
+        assert!(parse_client_auth(ca_path(), None, None, Some(String::from("a,,b"))).is_err());
+        assert_eq!(
+            parse_client_auth(ca_path(), None, Some(String::from("required")), None),
+            Ok(Some(ClientAuth {
+                ca_path: path::PathBuf::from("ssl/root.crt"),
+                crl_path: None,
+                required: true,
+                allowed_names: Vec::new(),
+            }))
+        );
+        assert_eq!(
+            parse_client_auth(
+                ca_path(),
+                Some(path::PathBuf::from("ssl/root.crl")),
+                Some(String::from("optional")),
+                Some(String::from("client.localhost,admin@localhost"))
+            ),
+            Ok(Some(ClientAuth {
+                ca_path: path::PathBuf::from("ssl/root.crt"),
+                crl_path: Some(path::PathBuf::from("ssl/root.crl")),
+                required: false,
+                allowed_names: vec![
+                    String::from("client.localhost"),
+                    String::from("admin@localhost")
+                ],
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_proxy_routes_test() {
+        assert_eq!(parse_proxy_routes(None), Ok(Vec::new()));
+        assert!(parse_proxy_routes(Some(String::new())).is_err());
+        assert!(parse_proxy_routes(Some(String::from("/api"))).is_err());
+        assert_eq!(
+            parse_proxy_routes(Some(String::from(
+                "/api=127.0.0.1:9000;host:app.local=127.0.0.1:9001|least-conn"
+            ))),
+            Ok(vec![
+                "/api=127.0.0.1:9000".parse().unwrap(),
+                "host:app.local=127.0.0.1:9001|least-conn".parse().unwrap()
+            ])
+        );
+    }
 }
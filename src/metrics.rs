@@ -0,0 +1,483 @@
+//! Metrics of the server, exposed in the Prometheus text format: requests by status class, method
+//! and handler, latency and body size histograms, connections, TLS handshake failures, PHP scripts
+//! and compression ratios.
+//!
+//! They are only made of atomic counters, so that connections never wait for each other to record
+//! their requests.
+
+use crate::http_req::ReqVerb;
+use crate::req_parser::SupportedEncoding;
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{net, str::FromStr, sync, time};
+
+/// Content type of the Prometheus text format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// Path of the metrics on their own listener.
+pub const DEFAULT_PATH: &str = "/metrics";
+
+/// Bounds of the duration buckets, in microseconds (5 ms to 10 s).
+const DURATION_BUCKETS: &[u64] = &[
+    5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 2_500_000, 5_000_000,
+    10_000_000,
+];
+/// Bounds of the body size buckets, in bytes (100 B to 100 MB).
+const SIZE_BUCKETS: &[u64] = &[
+    100,
+    1_000,
+    10_000,
+    100_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+];
+/// Bounds of the compression ratio buckets (compressed size / original size), in millionths.
+const RATIO_BUCKETS: &[u64] = &[
+    100_000, 200_000, 300_000, 400_000, 500_000, 600_000, 700_000, 800_000, 900_000, 1_000_000,
+];
+const MICROS: f64 = 1_000_000.0;
+
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+/// Request methods, the last one standing for the requests which could not be parsed.
+const METHODS: [&str; 10] = [
+    "GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS", "TRACE", "CONNECT", "other",
+];
+const ENCODINGS: [&str; 4] = ["gzip", "deflate", "zstd", "br"];
+
+/// Where the metrics are served.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricsEndpoint {
+    /// On a path of the server, subject to its access control and authentication
+    Path(String),
+    /// On their own plain HTTP listener (e.g. only reachable by the monitoring system), at
+    /// `/metrics`
+    Listener(net::SocketAddr),
+}
+
+impl FromStr for MetricsEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('/') {
+            return Ok(Self::Path(String::from(s)));
+        }
+        s.parse().map(Self::Listener).map_err(|_| {
+            format!(
+                "Invalid metrics endpoint (expected a path or an address): {}",
+                s
+            )
+        })
+    }
+}
+
+/// What served a request, as labelled in the metrics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Handler {
+    Static,
+    DirListing,
+    Php,
+    Proxy,
+    WebSocket,
+    Tunnel,
+    Login,
+    Redirect,
+    Metrics,
+    Status,
+    /// Requests refused or answered by the server itself (errors, OPTIONS, TRACE, ...)
+    Other,
+}
+
+impl Handler {
+    const ALL: [Self; 11] = [
+        Self::Static,
+        Self::DirListing,
+        Self::Php,
+        Self::Proxy,
+        Self::WebSocket,
+        Self::Tunnel,
+        Self::Login,
+        Self::Redirect,
+        Self::Metrics,
+        Self::Status,
+        Self::Other,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Static => "static",
+            Self::DirListing => "directory",
+            Self::Php => "php",
+            Self::Proxy => "proxy",
+            Self::WebSocket => "websocket",
+            Self::Tunnel => "tunnel",
+            Self::Login => "login",
+            Self::Redirect => "redirect",
+            Self::Metrics => "metrics",
+            Self::Status => "status",
+            Self::Other => "other",
+        }
+    }
+}
+
+/// A histogram, whose observations are counted in buckets of upper bounds (in an integer unit,
+/// e.g. microseconds).
+struct Histogram {
+    bounds: &'static [u64],
+    /// Observations of each bucket (not cumulative), the last one being unbounded
+    buckets: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, value: u64) {
+        let bucket = self.bounds.partition_point(|bound| *bound < value);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    fn count(&self) -> u64 {
+        self.buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Write the series of the histogram, with some labels (e.g. `handler="php",`) and the
+    /// number of units per reported unit (e.g. microseconds per second).
+    fn write(&self, out: &mut String, name: &str, labels: &str, scale: f64) {
+        let mut count = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let bound = match self.bounds.get(i) {
+                Some(bound) => (*bound as f64 / scale).to_string(),
+                None => String::from("+Inf"),
+            };
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}le=\"{}\"}} {}",
+                name, labels, bound, count
+            );
+        }
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{}}}", labels),
+        };
+        let sum = self.sum.load(Ordering::Relaxed) as f64 / scale;
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, count);
+    }
+}
+
+/// Metrics of a server.
+pub(crate) struct Metrics {
+    /// Requests, by status class, method and handler
+    requests: Vec<AtomicU64>,
+    /// Time taken to serve the requests, by handler
+    request_durations: Vec<Histogram>,
+    request_sizes: Histogram,
+    response_sizes: Histogram,
+    active_connections: AtomicU64,
+    connections: AtomicU64,
+    tls_handshake_failures: AtomicU64,
+    php_durations: Histogram,
+    php_errors: AtomicU64,
+    /// Compression ratios of the files served, by encoding
+    compression_ratios: Vec<Histogram>,
+}
+
+/// A connection counted as active until it is dropped.
+pub(crate) struct ActiveConnection(sync::Arc<Metrics>);
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        let len = STATUS_CLASSES.len() * METHODS.len() * Handler::ALL.len();
+        Self {
+            requests: (0..len).map(|_| AtomicU64::new(0)).collect(),
+            request_durations: Handler::ALL
+                .iter()
+                .map(|_| Histogram::new(DURATION_BUCKETS))
+                .collect(),
+            request_sizes: Histogram::new(SIZE_BUCKETS),
+            response_sizes: Histogram::new(SIZE_BUCKETS),
+            active_connections: AtomicU64::new(0),
+            connections: AtomicU64::new(0),
+            tls_handshake_failures: AtomicU64::new(0),
+            php_durations: Histogram::new(DURATION_BUCKETS),
+            php_errors: AtomicU64::new(0),
+            compression_ratios: ENCODINGS
+                .iter()
+                .map(|_| Histogram::new(RATIO_BUCKETS))
+                .collect(),
+        }
+    }
+
+    /// Count a new connection, active until the returned guard is dropped.
+    pub(crate) fn open_connection(self: &sync::Arc<Self>) -> ActiveConnection {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ActiveConnection(self.clone())
+    }
+
+    pub(crate) fn tls_handshake_failed(&self) {
+        self.tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a request (whose method is unknown if it could not be parsed), once its response is
+    /// sent.
+    pub(crate) fn observe_request(
+        &self,
+        verb: Option<&ReqVerb>,
+        status: u16,
+        handler: Handler,
+        duration: time::Duration,
+        request_size: u64,
+        response_size: u64,
+    ) {
+        let class = (usize::from(status / 100)).clamp(1, STATUS_CLASSES.len()) - 1;
+        let method = match verb {
+            Some(ReqVerb::Get) => 0,
+            Some(ReqVerb::Head) => 1,
+            Some(ReqVerb::Post) => 2,
+            Some(ReqVerb::Put) => 3,
+            Some(ReqVerb::Patch) => 4,
+            Some(ReqVerb::Delete) => 5,
+            Some(ReqVerb::Options) => 6,
+            Some(ReqVerb::Trace) => 7,
+            Some(ReqVerb::Connect) => 8,
+            None => 9,
+        };
+        let index = (class * METHODS.len() + method) * Handler::ALL.len() + handler as usize;
+        self.requests[index].fetch_add(1, Ordering::Relaxed);
+        self.request_durations[handler as usize].observe(duration.as_micros() as u64);
+        self.request_sizes.observe(request_size);
+        self.response_sizes.observe(response_size);
+    }
+
+    /// Record the execution of a PHP script, failed or not.
+    pub(crate) fn observe_php(&self, duration: time::Duration, failed: bool) {
+        self.php_durations.observe(duration.as_micros() as u64);
+        if failed {
+            self.php_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Record the compression of a file served.
+    pub(crate) fn observe_compression(
+        &self,
+        encoding: &SupportedEncoding,
+        original_size: u64,
+        compressed_size: u64,
+    ) {
+        if original_size == 0 {
+            return;
+        }
+        let encoding = match encoding {
+            SupportedEncoding::Gzip => 0,
+            SupportedEncoding::Deflate => 1,
+            SupportedEncoding::Zstd => 2,
+            SupportedEncoding::Br => 3,
+        };
+        let ratio = compressed_size as f64 / original_size as f64;
+        self.compression_ratios[encoding].observe((ratio * MICROS) as u64);
+    }
+
+    /// Metrics in the Prometheus text format (the series without any observation being omitted).
+    pub(crate) fn render(&self) -> String {
+        let mut out = String::new();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        out.push_str("# HELP rhs_http_requests_total Requests served, by status class, method and handler.\n");
+        out.push_str("# TYPE rhs_http_requests_total counter\n");
+        for (class_index, class) in STATUS_CLASSES.iter().enumerate() {
+            for (method_index, method) in METHODS.iter().enumerate() {
+                for handler in Handler::ALL {
+                    let index = (class_index * METHODS.len() + method_index) * Handler::ALL.len()
+                        + handler as usize;
+                    let count = load(&self.requests[index]);
+                    if count > 0 {
+                        let _ = writeln!(
+                            out,
+                            "rhs_http_requests_total{{class=\"{}\",method=\"{}\",handler=\"{}\"}} {}",
+                            class,
+                            method,
+                            handler.name(),
+                            count
+                        );
+                    }
+                }
+            }
+        }
+
+        out.push_str("# HELP rhs_http_request_duration_seconds Time taken to serve the requests, by handler.\n");
+        out.push_str("# TYPE rhs_http_request_duration_seconds histogram\n");
+        for handler in Handler::ALL {
+            let histogram = &self.request_durations[handler as usize];
+            if histogram.count() > 0 {
+                histogram.write(
+                    &mut out,
+                    "rhs_http_request_duration_seconds",
+                    &format!("handler=\"{}\",", handler.name()),
+                    MICROS,
+                );
+            }
+        }
+
+        out.push_str("# HELP rhs_http_request_size_bytes Size of the request bodies.\n");
+        out.push_str("# TYPE rhs_http_request_size_bytes histogram\n");
+        self.request_sizes
+            .write(&mut out, "rhs_http_request_size_bytes", "", 1.0);
+        out.push_str("# HELP rhs_http_response_size_bytes Size of the response bodies sent.\n");
+        out.push_str("# TYPE rhs_http_response_size_bytes histogram\n");
+        self.response_sizes
+            .write(&mut out, "rhs_http_response_size_bytes", "", 1.0);
+
+        out.push_str("# HELP rhs_connections_active Connections currently open.\n");
+        out.push_str("# TYPE rhs_connections_active gauge\n");
+        let _ = writeln!(
+            out,
+            "rhs_connections_active {}",
+            load(&self.active_connections)
+        );
+        out.push_str("# HELP rhs_connections_total Connections accepted.\n");
+        out.push_str("# TYPE rhs_connections_total counter\n");
+        let _ = writeln!(out, "rhs_connections_total {}", load(&self.connections));
+        out.push_str("# HELP rhs_tls_handshake_failures_total TLS handshakes which failed.\n");
+        out.push_str("# TYPE rhs_tls_handshake_failures_total counter\n");
+        let _ = writeln!(
+            out,
+            "rhs_tls_handshake_failures_total {}",
+            load(&self.tls_handshake_failures)
+        );
+
+        out.push_str("# HELP rhs_php_duration_seconds Execution time of the PHP scripts.\n");
+        out.push_str("# TYPE rhs_php_duration_seconds histogram\n");
+        self.php_durations
+            .write(&mut out, "rhs_php_duration_seconds", "", MICROS);
+        out.push_str("# HELP rhs_php_errors_total PHP scripts which could not be run.\n");
+        out.push_str("# TYPE rhs_php_errors_total counter\n");
+        let _ = writeln!(out, "rhs_php_errors_total {}", load(&self.php_errors));
+
+        out.push_str("# HELP rhs_compression_ratio Compressed size of the files served over their original size, by encoding.\n");
+        out.push_str("# TYPE rhs_compression_ratio histogram\n");
+        for (encoding, histogram) in ENCODINGS.iter().zip(&self.compression_ratios) {
+            if histogram.count() > 0 {
+                histogram.write(
+                    &mut out,
+                    "rhs_compression_ratio",
+                    &format!("encoding=\"{}\",", encoding),
+                    MICROS,
+                );
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_endpoint_from_str_test() {
+        assert_eq!(
+            "/metrics".parse(),
+            Ok(MetricsEndpoint::Path(String::from("/metrics")))
+        );
+        assert_eq!(
+            "127.0.0.1:9100".parse(),
+            Ok(MetricsEndpoint::Listener("127.0.0.1:9100".parse().unwrap()))
+        );
+        assert!("metrics".parse::<MetricsEndpoint>().is_err());
+        assert!("localhost:9100".parse::<MetricsEndpoint>().is_err());
+    }
+
+    #[test]
+    fn histogram_test() {
+        let histogram = Histogram::new(&[10, 100]);
+        for value in [5, 10, 50, 1000] {
+            histogram.observe(value);
+        }
+        assert_eq!(histogram.count(), 4);
+        let mut out = String::new();
+        histogram.write(&mut out, "size", "kind=\"a\",", 10.0);
+        assert_eq!(
+            out,
+            "size_bucket{kind=\"a\",le=\"1\"} 2\n\
+             size_bucket{kind=\"a\",le=\"10\"} 3\n\
+             size_bucket{kind=\"a\",le=\"+Inf\"} 4\n\
+             size_sum{kind=\"a\"} 106.5\n\
+             size_count{kind=\"a\"} 4\n"
+        );
+    }
+
+    #[test]
+    fn metrics_test() {
+        let metrics = sync::Arc::new(Metrics::new());
+        let connection = metrics.open_connection();
+        let other = metrics.open_connection();
+        drop(other);
+        metrics.tls_handshake_failed();
+        metrics.observe_request(
+            Some(&ReqVerb::Get),
+            200,
+            Handler::Static,
+            time::Duration::from_millis(20),
+            0,
+            2048,
+        );
+        metrics.observe_request(
+            Some(&ReqVerb::Get),
+            404,
+            Handler::Static,
+            time::Duration::from_millis(2),
+            0,
+            100,
+        );
+        metrics.observe_request(None, 400, Handler::Other, time::Duration::ZERO, 0, 0);
+        metrics.observe_php(time::Duration::from_millis(300), false);
+        metrics.observe_php(time::Duration::from_millis(1), true);
+        metrics.observe_compression(&SupportedEncoding::Br, 1000, 250);
+        metrics.observe_compression(&SupportedEncoding::Gzip, 0, 20);
+
+        let out = metrics.render();
+        for line in [
+            "rhs_http_requests_total{class=\"2xx\",method=\"GET\",handler=\"static\"} 1",
+            "rhs_http_requests_total{class=\"4xx\",method=\"GET\",handler=\"static\"} 1",
+            "rhs_http_requests_total{class=\"4xx\",method=\"other\",handler=\"other\"} 1",
+            "rhs_http_request_duration_seconds_bucket{handler=\"static\",le=\"0.005\"} 1",
+            "rhs_http_request_duration_seconds_bucket{handler=\"static\",le=\"0.025\"} 2",
+            "rhs_http_request_duration_seconds_sum{handler=\"static\"} 0.022",
+            "rhs_http_response_size_bytes_bucket{le=\"100\"} 2",
+            "rhs_http_response_size_bytes_count 3",
+            "rhs_connections_active 1",
+            "rhs_connections_total 2",
+            "rhs_tls_handshake_failures_total 1",
+            "rhs_php_duration_seconds_count 2",
+            "rhs_php_errors_total 1",
+            "rhs_compression_ratio_bucket{encoding=\"br\",le=\"0.3\"} 1",
+            "rhs_compression_ratio_sum{encoding=\"br\"} 0.25",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {}", line);
+        }
+        assert!(!out.contains("handler=\"php\""));
+        assert!(!out.contains("encoding=\"gzip\""));
+
+        drop(connection);
+        assert!(metrics.render().contains("rhs_connections_active 0\n"));
+    }
+}
@@ -0,0 +1,957 @@
+//! Reverse proxy: forward requests to upstream HTTP servers.
+//!
+//! A proxy route matches requests by path prefix or by virtual host (Host header), and forwards
+//! them to a pool of upstream servers. For every request, an upstream server is selected from the
+//! pool (round-robin or least connections), skipping servers that recently failed.
+
+use crate::client::Client;
+use crate::http_header::{GeneralHeader, HeaderValue, ReqHeader, ResHeader, SimpleHeaderValue};
+use crate::http_req::{HttpReq, ReqPath, ReqTarget, ReqVerb};
+use crate::http_res::{HttpRes, ResBody};
+use crate::res_parser::{self, ResBodyLength, ResHeadParsingError};
+use crate::utils;
+use crate::{client, http_header};
+
+use log::{debug, warn};
+use std::{fmt, io, str::FromStr, sync, sync::atomic, time};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Value added to the Via header of forwarded requests and responses.
+const VIA: &str = "1.1 rust-http-server";
+/// Maximum time to wait for a connection with an upstream server.
+const CONNECT_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+/// Default maximum time to wait for the response of an upstream server.
+const DEFAULT_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+/// Number of consecutive failures after which an upstream server is considered down.
+const MAX_FAILURES: u32 = 3;
+/// Time during which an upstream server considered down is not selected.
+const FAILURE_COOLDOWN: time::Duration = time::Duration::from_secs(10);
+
+/// How a proxy route matches requests.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteMatch {
+    /// Requests whose (decoded) path starts with this prefix
+    PathPrefix(String),
+    /// Requests whose Host header is this host name (port excluded)
+    Host(String),
+}
+
+/// How an upstream server is selected among a pool.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Balancing {
+    RoundRobin,
+    LeastConnections,
+}
+
+/// A reverse proxy route, typically populated from CLI.
+///
+/// The textual format is `MATCH=UPSTREAM[,UPSTREAM...][|OPTION...]` where `MATCH` is either a path
+/// prefix (e.g. `/api`) or a virtual host (e.g. `host:app.example.org`), `UPSTREAM` is a socket
+/// address (e.g. `127.0.0.1:9000`) and `OPTION` is one of `round-robin` (default), `least-conn`,
+/// `host=NAME` (rewrite the Host header sent upstream) or `timeout=SECONDS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyRoute {
+    pub route_match: RouteMatch,
+    pub upstreams: Vec<String>,
+    pub balancing: Balancing,
+    /// Host header sent to the upstream servers. If None, the Host header of the client is kept
+    pub host: Option<String>,
+    /// Maximum time to wait for the response of an upstream server
+    pub timeout: time::Duration,
+}
+
+impl FromStr for ProxyRoute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('|');
+        let (route_match, upstreams) = parts
+            .next()
+            .and_then(|p| p.split_once('='))
+            .ok_or(format!("Invalid proxy route: {}", s))?;
+
+        let route_match = if let Some(host) = route_match.strip_prefix("host:") {
+            RouteMatch::Host(host.to_ascii_lowercase())
+        } else if route_match.starts_with('/') {
+            RouteMatch::PathPrefix(String::from(route_match))
+        } else {
+            return Err(format!("Invalid proxy route match: {}", route_match));
+        };
+
+        let upstreams = upstreams
+            .split(',')
+            .map(|u| u.trim())
+            .filter(|u| !u.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if upstreams.is_empty() {
+            return Err(format!("No upstream server for proxy route: {}", s));
+        }
+
+        let mut route = Self {
+            route_match,
+            upstreams,
+            balancing: Balancing::RoundRobin,
+            host: None,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        for option in parts {
+            match option.split_once('=') {
+                None if option == "round-robin" => route.balancing = Balancing::RoundRobin,
+                None if option == "least-conn" => route.balancing = Balancing::LeastConnections,
+                Some(("host", host)) if !host.is_empty() => route.host = Some(String::from(host)),
+                Some(("timeout", timeout)) => {
+                    route.timeout = time::Duration::from_secs(
+                        timeout
+                            .parse()
+                            .map_err(|_| format!("Invalid proxy timeout: {}", timeout))?,
+                    )
+                }
+                _ => return Err(format!("Invalid proxy route option: {}", option)),
+            }
+        }
+        Ok(route)
+    }
+}
+
+impl ProxyRoute {
+    fn matches(&self, host: Option<&str>, path: &str) -> bool {
+        match &self.route_match {
+            RouteMatch::PathPrefix(prefix) => utils::matches_path_prefix(path, prefix),
+            RouteMatch::Host(name) => host.is_some_and(|host| utils::matches_host(host, name)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProxyError {
+    /// No upstream server could be reached
+    NoUpstream,
+    /// The upstream server did not answer in time
+    Timeout,
+    /// The request body could not be forwarded
+    Body(io::Error),
+    /// The upstream server response is invalid
+    Client(client::Error),
+    /// The request target does not designate a server to forward the request to
+    InvalidTarget,
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoUpstream => write!(f, "no upstream server available"),
+            Self::Timeout => write!(f, "upstream server timed out"),
+            Self::Body(e) => write!(f, "cannot forward request body: {}", e),
+            Self::Client(e) => write!(f, "upstream error: {}", e),
+            Self::InvalidTarget => write!(f, "request target is not an absolute URI"),
+        }
+    }
+}
+
+/// Information about the client added to forwarded requests.
+pub struct Forwarded<'a> {
+    /// IP address of the client
+    pub client_ip: &'a str,
+    /// Protocol used by the client (http or https)
+    pub proto: &'a str,
+}
+
+/// An upstream server, with passive health tracking.
+struct Upstream {
+    addr: String,
+    active_connections: sync::Arc<atomic::AtomicUsize>,
+    failures: atomic::AtomicU32,
+    down_until: sync::Mutex<Option<time::Instant>>,
+}
+
+impl Upstream {
+    fn new(addr: &str) -> Self {
+        Self {
+            addr: String::from(addr),
+            active_connections: sync::Arc::new(atomic::AtomicUsize::new(0)),
+            failures: atomic::AtomicU32::new(0),
+            down_until: sync::Mutex::new(None),
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.down_until
+            .lock()
+            .unwrap()
+            .is_none_or(|until| time::Instant::now() >= until)
+    }
+
+    fn report_failure(&self) {
+        let failures = self.failures.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+        if failures >= MAX_FAILURES {
+            warn!(
+                "Upstream server {} failed {} times, marking it as down",
+                self.addr, failures
+            );
+            *self.down_until.lock().unwrap() = Some(time::Instant::now() + FAILURE_COOLDOWN);
+            self.failures.store(0, atomic::Ordering::Relaxed);
+        }
+    }
+
+    fn report_success(&self) {
+        self.failures.store(0, atomic::Ordering::Relaxed);
+        *self.down_until.lock().unwrap() = None;
+    }
+}
+
+/// Count an active connection with an upstream server, for the time it is alive.
+struct ActiveConnection(sync::Arc<atomic::AtomicUsize>);
+
+impl ActiveConnection {
+    fn new(counter: &sync::Arc<atomic::AtomicUsize>) -> Self {
+        counter.fetch_add(1, atomic::Ordering::Relaxed);
+        Self(counter.clone())
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, atomic::Ordering::Relaxed);
+    }
+}
+
+/// A response body that is still to be read from an upstream server.
+pub struct UpstreamBody {
+    client: Client,
+    body_length: ResBodyLength,
+    _active_connection: Option<ActiveConnection>,
+}
+
+impl UpstreamBody {
+    /// Length of the body, if known in advance (0 for chunked bodies).
+    pub fn len(&self) -> usize {
+        match self.body_length {
+            ResBodyLength::Fixed(len) => len,
+            _ => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(
+            self.body_length,
+            ResBodyLength::None | ResBodyLength::Fixed(0)
+        )
+    }
+
+    /// Stream the body from the upstream server to the client.
+    pub async fn copy_to<W: AsyncWrite + Unpin>(
+        &mut self,
+        writer: &mut W,
+    ) -> Result<u64, ResHeadParsingError> {
+        res_parser::copy_res_body(self.client.stream(), writer, &self.body_length).await
+    }
+}
+
+/// A pool of upstream servers serving a proxy route.
+pub struct UpstreamPool {
+    route: ProxyRoute,
+    upstreams: Vec<Upstream>,
+    next: atomic::AtomicUsize,
+}
+
+impl UpstreamPool {
+    fn new(route: &ProxyRoute) -> Self {
+        Self {
+            route: route.clone(),
+            upstreams: route.upstreams.iter().map(|u| Upstream::new(u)).collect(),
+            next: atomic::AtomicUsize::new(0),
+        }
+    }
+
+    /// Select an available upstream server, excluding the ones already tried.
+    fn select(&self, tried: &[usize]) -> Option<usize> {
+        let candidates = (0..self.upstreams.len())
+            .filter(|i| !tried.contains(i) && self.upstreams[*i].is_available())
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return None;
+        }
+        let start = self.next.fetch_add(1, atomic::Ordering::Relaxed);
+        match self.route.balancing {
+            Balancing::RoundRobin => Some(candidates[start % candidates.len()]),
+            Balancing::LeastConnections => {
+                // rotate the candidates so that ties are spread among servers
+                (0..candidates.len())
+                    .map(|i| candidates[(start + i) % candidates.len()])
+                    .min_by_key(|i| {
+                        self.upstreams[*i]
+                            .active_connections
+                            .load(atomic::Ordering::Relaxed)
+                    })
+            }
+        }
+    }
+
+    /// Forward a request to an upstream server, and return its response.
+    ///
+    /// The request body (of the given length) is streamed from the client reader, and the response
+    /// body is streamed from the upstream server when the response is sent.
+    pub async fn forward<R: AsyncBufRead + Unpin>(
+        &self,
+        req: &mut HttpReq,
+        client_reader: &mut R,
+        body_len: usize,
+        forwarded: &Forwarded<'_>,
+    ) -> Result<HttpRes, ProxyError> {
+        self.prepare_req(req, forwarded);
+
+        // connect to an upstream server, trying the next one on failure
+        let mut tried = Vec::new();
+        let (upstream, mut client) = loop {
+            let Some(i) = self.select(&tried) else {
+                utils::discard_body(client_reader, body_len).await;
+                return Err(ProxyError::NoUpstream);
+            };
+            tried.push(i);
+            let upstream = &self.upstreams[i];
+            match connect(&upstream.addr).await {
+                Ok(client) => break (upstream, client),
+                Err(err) => warn!("Cannot connect to upstream {}: {}", upstream.addr, err),
+            }
+            upstream.report_failure();
+        };
+        debug!("forwarding request to upstream {}", upstream.addr);
+        let active_connection = ActiveConnection::new(&upstream.active_connections);
+
+        let res = match exchange(
+            &mut client,
+            req,
+            client_reader,
+            body_len,
+            self.route.timeout,
+        )
+        .await
+        {
+            Ok(res) => res,
+            Err(err) => {
+                if matches!(err, ProxyError::Client(_) | ProxyError::Timeout) {
+                    upstream.report_failure();
+                }
+                return Err(err);
+            }
+        };
+        upstream.report_success();
+
+        proxied_response(client, res, req.verb(), Some(active_connection)).await
+    }
+
+    /// Rewrite the request headers before forwarding the request.
+    fn prepare_req(&self, req: &mut HttpReq, forwarded: &Forwarded<'_>) {
+        prepare_headers(req);
+        let headers = req.headers();
+
+        if let Some(host) = self.route.host.as_ref() {
+            headers.insert(
+                ReqHeader::ReqOnly(http_header::ReqOnlyHeader::Host),
+                HeaderValue::Simple(SimpleHeaderValue::String(host.clone())),
+            );
+        }
+
+        // previous proxies may have set X-Forwarded-For (its name was lowercased when parsed)
+        let forwarded_for = match headers.remove(&ReqHeader::Other(String::from("x-forwarded-for")))
+        {
+            Some(previous) => format!("{}, {}", previous, forwarded.client_ip),
+            None => String::from(forwarded.client_ip),
+        };
+        headers.insert(
+            ReqHeader::Other(String::from("X-Forwarded-For")),
+            HeaderValue::Simple(SimpleHeaderValue::String(forwarded_for)),
+        );
+        headers.remove(&ReqHeader::Other(String::from("x-forwarded-proto")));
+        headers.insert(
+            ReqHeader::Other(String::from("X-Forwarded-Proto")),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from(forwarded.proto))),
+        );
+    }
+}
+
+/// The reverse proxy, made of the upstream pools of all the configured routes.
+pub struct ReverseProxy {
+    pools: Vec<UpstreamPool>,
+}
+
+impl ReverseProxy {
+    pub fn new(routes: &[ProxyRoute]) -> Self {
+        Self {
+            pools: routes.iter().map(UpstreamPool::new).collect(),
+        }
+    }
+
+    /// Find the pool serving a request, i.e. the pool of the first route matching it (if any).
+    pub fn find_pool(&self, host: Option<&str>, target: &ReqTarget) -> Option<&UpstreamPool> {
+        match target {
+            ReqTarget::Path(ReqPath { decoded: path, .. }) => self
+                .pools
+                .iter()
+                .find(|pool| pool.route.matches(host, path)),
+            _ => None,
+        }
+    }
+}
+
+/// Forward a request made to a forward proxy (i.e. with an absolute URI) to the origin server.
+///
+/// As for the reverse proxy, the request body (of the given length) is streamed from the client
+/// reader, and the response body is streamed from the origin server when the response is sent.
+pub async fn forward_to_origin<R: AsyncBufRead + Unpin>(
+    req: &mut HttpReq,
+    client_reader: &mut R,
+    body_len: usize,
+) -> Result<HttpRes, ProxyError> {
+    let (authority, path) = match req.target() {
+        ReqTarget::Absolute(authority, path) => (authority.clone(), path.clone()),
+        _ => {
+            utils::discard_body(client_reader, body_len).await;
+            return Err(ProxyError::InvalidTarget);
+        }
+    };
+
+    // the origin server expects the path only, and the Host header must match the URI authority
+    req.set_target(ReqTarget::Path(path));
+    prepare_headers(req);
+    req.headers().insert(
+        ReqHeader::ReqOnly(http_header::ReqOnlyHeader::Host),
+        HeaderValue::Simple(SimpleHeaderValue::String(authority.clone())),
+    );
+
+    let mut client = match connect(&with_default_port(&authority, 80)).await {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("Cannot connect to origin server {}: {}", authority, err);
+            utils::discard_body(client_reader, body_len).await;
+            return Err(ProxyError::NoUpstream);
+        }
+    };
+    debug!("forwarding request to origin server {}", authority);
+    let res = exchange(&mut client, req, client_reader, body_len, DEFAULT_TIMEOUT).await?;
+    proxied_response(client, res, req.verb(), None).await
+}
+
+/// Open a TCP connection with the server targeted by a CONNECT request, for tunnelling.
+pub async fn open_tunnel(authority: &str) -> Result<tokio::net::TcpStream, ProxyError> {
+    match tokio::time::timeout(CONNECT_TIMEOUT, tokio::net::TcpStream::connect(authority)).await {
+        Ok(Ok(stream)) => Ok(stream),
+        Ok(Err(err)) => {
+            warn!("Cannot open tunnel to {}: {}", authority, err);
+            Err(ProxyError::NoUpstream)
+        }
+        Err(_) => {
+            warn!("Timeout opening tunnel to {}", authority);
+            Err(ProxyError::Timeout)
+        }
+    }
+}
+
+/// Open a connection with an upstream server.
+async fn connect(addr: &str) -> Result<Client, client::Error> {
+    match tokio::time::timeout(CONNECT_TIMEOUT, Client::connect(addr)).await {
+        Ok(result) => result,
+        Err(_) => Err(client::Error::Io(io::Error::from(io::ErrorKind::TimedOut))),
+    }
+}
+
+/// Send a request to an upstream server, and wait for the response head.
+///
+/// If the request cannot be sent, the rest of its body is discarded, so that it is not read from
+/// the client connection as the next request.
+async fn exchange<R: AsyncBufRead + Unpin>(
+    client: &mut Client,
+    req: &mut HttpReq,
+    client_reader: &mut R,
+    body_len: usize,
+    timeout: time::Duration,
+) -> Result<HttpRes, ProxyError> {
+    let mut remaining = body_len;
+    if let Err(err) = send_req(client, req, client_reader, &mut remaining).await {
+        utils::discard_body(client_reader, remaining).await;
+        return Err(err);
+    }
+
+    // wait for the response head
+    match tokio::time::timeout(timeout, client.read_res_head()).await {
+        Ok(result) => result.map_err(ProxyError::Client),
+        Err(_) => Err(ProxyError::Timeout),
+    }
+}
+
+/// Send the head of a request to an upstream server, then stream its body from the client.
+///
+/// The length of the body not read yet from the client is kept up to date, even on failure. Writing
+/// to the upstream server fails with a client error, and reading from the client with a body error.
+async fn send_req<R: AsyncBufRead + Unpin>(
+    client: &mut Client,
+    req: &mut HttpReq,
+    client_reader: &mut R,
+    remaining: &mut usize,
+) -> Result<(), ProxyError> {
+    client.write_req(req).await.map_err(ProxyError::Client)?;
+    let stream = client.stream().get_mut();
+    while *remaining != 0 {
+        let buf = client_reader.fill_buf().await.map_err(ProxyError::Body)?;
+        if buf.is_empty() {
+            return Err(ProxyError::Body(io::ErrorKind::UnexpectedEof.into()));
+        }
+        let len = buf.len().min(*remaining);
+        stream
+            .write_all(&buf[..len])
+            .await
+            .map_err(|err| ProxyError::Client(client::Error::Io(err)))?;
+        client_reader.consume(len);
+        *remaining -= len;
+    }
+    stream
+        .flush()
+        .await
+        .map_err(|err| ProxyError::Client(client::Error::Io(err)))
+}
+
+/// Turn the response of an upstream server into the response sent to the client.
+async fn proxied_response(
+    client: Client,
+    mut res: HttpRes,
+    verb: &ReqVerb,
+    active_connection: Option<ActiveConnection>,
+) -> Result<HttpRes, ProxyError> {
+    let body_length = ResBodyLength::of(&mut res, *verb == ReqVerb::Head);
+    remove_hop_by_hop_headers(res.headers(), &[ResHeader::General(GeneralHeader::Upgrade)]);
+    append_header(res.headers(), ResHeader::General(GeneralHeader::Via), VIA);
+
+    if body_length == ResBodyLength::None {
+        return Ok(res);
+    }
+    // without a length, the body is streamed as it comes (e.g. server-sent events), and ends with
+    // the client connection as well
+    if body_length == ResBodyLength::UntilClose {
+        res.set_header(
+            ResHeader::General(GeneralHeader::Connection),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from("close"))),
+        );
+    }
+    res.set_body(Some(ResBody::Upstream(Box::new(UpstreamBody {
+        client,
+        body_length,
+        _active_connection: active_connection,
+    }))));
+    Ok(res)
+}
+
+/// Rewrite the request headers that must be rewritten by any proxy (reverse or forward).
+fn prepare_headers(req: &mut HttpReq) {
+    let max_forwards = match req.verb() {
+        ReqVerb::Options | ReqVerb::Trace => req.max_forwards(),
+        _ => None,
+    };
+    let headers = req.headers();
+    remove_hop_by_hop_headers(
+        headers,
+        &[
+            ReqHeader::General(GeneralHeader::TransferEncoding),
+            ReqHeader::General(GeneralHeader::Upgrade),
+            ReqHeader::General(GeneralHeader::Trailer),
+            ReqHeader::ReqOnly(http_header::ReqOnlyHeader::TE),
+            ReqHeader::ReqOnly(http_header::ReqOnlyHeader::ProxyAuthorization),
+        ],
+    );
+
+    // Max-Forwards is decremented by each proxy (a value of 0 is answered by the proxy itself)
+    if let Some(max_forwards) = max_forwards {
+        headers.insert(
+            ReqHeader::ReqOnly(http_header::ReqOnlyHeader::MaxForwards),
+            HeaderValue::Simple(SimpleHeaderValue::String(
+                max_forwards.saturating_sub(1).to_string(),
+            )),
+        );
+    }
+    append_header(headers, ReqHeader::General(GeneralHeader::Via), VIA);
+
+    // upstream connections are not reused
+    headers.insert(
+        ReqHeader::General(GeneralHeader::Connection),
+        HeaderValue::Simple(SimpleHeaderValue::String(String::from("close"))),
+    );
+}
+
+/// Add a default port to an authority (e.g. example.org:80 from example.org), if it has none.
+fn with_default_port(authority: &str, port: u16) -> String {
+    match authority.rsplit_once(':') {
+        Some((_, p)) if !p.ends_with(']') => String::from(authority),
+        _ => format!("{}:{}", authority, port),
+    }
+}
+
+/// Remove the hop-by-hop headers, i.e. the ones meant for a single connection, which must not be
+/// forwarded: Connection, the headers it lists, Keep-Alive and some extra ones.
+fn remove_hop_by_hop_headers<H>(
+    headers: &mut std::collections::HashMap<H, HeaderValue>,
+    extra: &[H],
+) where
+    H: fmt::Display + Eq + std::hash::Hash,
+{
+    let mut names = vec![
+        String::from("connection"),
+        String::from("keep-alive"),
+        String::from("proxy-connection"),
+    ];
+    if let Some(connection) = headers
+        .iter()
+        .find(|(name, _)| name.to_string().eq_ignore_ascii_case("connection"))
+        .map(|(_, value)| value.to_string())
+    {
+        names.extend(connection.split(',').map(|n| n.trim().to_ascii_lowercase()));
+    }
+    headers.retain(|name, _| {
+        !extra.contains(name) && !names.contains(&name.to_string().to_ascii_lowercase())
+    });
+}
+
+/// Append a value to a comma-separated list header, creating it if needed.
+fn append_header<H: Eq + std::hash::Hash>(
+    headers: &mut std::collections::HashMap<H, HeaderValue>,
+    name: H,
+    value: &str,
+) {
+    let value = match headers.remove(&name) {
+        Some(previous) => format!("{}, {}", previous, value),
+        None => String::from(value),
+    };
+    headers.insert(name, HeaderValue::Simple(SimpleHeaderValue::String(value)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_header::ReqOnlyHeader;
+    use crate::http_req::ReqHead;
+    use std::collections;
+
+    fn path(p: &str) -> ReqTarget {
+        ReqTarget::Path(ReqPath {
+            original: String::from(p),
+            decoded: String::from(p),
+            query: String::new(),
+        })
+    }
+
+    #[test]
+    fn proxy_route_from_str_test() {
+        assert_eq!(
+            ProxyRoute::from_str("/api=127.0.0.1:9000"),
+            Ok(ProxyRoute {
+                route_match: RouteMatch::PathPrefix(String::from("/api")),
+                upstreams: vec![String::from("127.0.0.1:9000")],
+                balancing: Balancing::RoundRobin,
+                host: None,
+                timeout: DEFAULT_TIMEOUT,
+            })
+        );
+        assert_eq!(
+            ProxyRoute::from_str(
+                "host:App.Example.org=10.0.0.1:80,10.0.0.2:80|least-conn|host=internal|timeout=3"
+            ),
+            Ok(ProxyRoute {
+                route_match: RouteMatch::Host(String::from("app.example.org")),
+                upstreams: vec![String::from("10.0.0.1:80"), String::from("10.0.0.2:80")],
+                balancing: Balancing::LeastConnections,
+                host: Some(String::from("internal")),
+                timeout: time::Duration::from_secs(3),
+            })
+        );
+
+        assert!(ProxyRoute::from_str("").is_err());
+        assert!(ProxyRoute::from_str("/api").is_err());
+        assert!(ProxyRoute::from_str("/api=").is_err());
+        assert!(ProxyRoute::from_str("api=127.0.0.1:9000").is_err());
+        assert!(ProxyRoute::from_str("/api=127.0.0.1:9000|foo").is_err());
+        assert!(ProxyRoute::from_str("/api=127.0.0.1:9000|timeout=foo").is_err());
+    }
+
+    #[test]
+    fn find_pool_test() {
+        let proxy = ReverseProxy::new(&[
+            ProxyRoute::from_str("host:app.example.org=127.0.0.1:9000").unwrap(),
+            ProxyRoute::from_str("/api=127.0.0.1:9001").unwrap(),
+            ProxyRoute::from_str("/static/=127.0.0.1:9002").unwrap(),
+        ]);
+        let upstream = |host, target| {
+            proxy
+                .find_pool(host, &target)
+                .map(|pool| pool.upstreams[0].addr.as_str())
+        };
+
+        assert_eq!(
+            upstream(Some("app.example.org:8080"), path("/api")),
+            Some("127.0.0.1:9000")
+        );
+        assert_eq!(
+            upstream(Some("APP.example.org"), path("/")),
+            Some("127.0.0.1:9000")
+        );
+        assert_eq!(
+            upstream(Some("example.org"), path("/api")),
+            Some("127.0.0.1:9001")
+        );
+        assert_eq!(upstream(None, path("/api/users")), Some("127.0.0.1:9001"));
+        assert_eq!(upstream(None, path("/apis")), None);
+        assert_eq!(
+            upstream(None, path("/static/app.js")),
+            Some("127.0.0.1:9002")
+        );
+        assert_eq!(upstream(None, path("/")), None);
+        assert_eq!(upstream(None, ReqTarget::All), None);
+    }
+
+    #[test]
+    fn upstream_selection_test() {
+        let pool = UpstreamPool::new(
+            &ProxyRoute::from_str("/=127.0.0.1:1,127.0.0.1:2,127.0.0.1:3").unwrap(),
+        );
+        // round-robin
+        assert_eq!(pool.select(&[]), Some(0));
+        assert_eq!(pool.select(&[]), Some(1));
+        assert_eq!(pool.select(&[]), Some(2));
+        assert_eq!(pool.select(&[]), Some(0));
+        assert_eq!(pool.select(&[0, 1, 2]), None);
+
+        // passive health check: an upstream that keeps failing is skipped for a while
+        for _ in 0..MAX_FAILURES {
+            assert!(pool.upstreams[1].is_available());
+            pool.upstreams[1].report_failure();
+        }
+        assert!(!pool.upstreams[1].is_available());
+        for _ in 0..4 {
+            assert_ne!(pool.select(&[]), Some(1));
+        }
+        pool.upstreams[1].report_success();
+        assert!(pool.upstreams[1].is_available());
+
+        // least connections
+        let pool = UpstreamPool::new(
+            &ProxyRoute::from_str("/=127.0.0.1:1,127.0.0.1:2|least-conn").unwrap(),
+        );
+        let _first = ActiveConnection::new(&pool.upstreams[0].active_connections);
+        for _ in 0..4 {
+            assert_eq!(pool.select(&[]), Some(1));
+        }
+        let second = ActiveConnection::new(&pool.upstreams[1].active_connections);
+        let _third = ActiveConnection::new(&pool.upstreams[1].active_connections);
+        assert_eq!(pool.select(&[]), Some(0));
+        drop(second);
+        assert_eq!(
+            pool.upstreams[1]
+                .active_connections
+                .load(atomic::Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[test]
+    fn prepare_req_test() {
+        let pool =
+            UpstreamPool::new(&ProxyRoute::from_str("/=127.0.0.1:1|host=upstream.local").unwrap());
+        let head = ReqHead::new(
+            ReqVerb::Get,
+            path("/"),
+            String::from("HTTP/1.1"),
+            collections::HashMap::from([
+                (
+                    ReqHeader::ReqOnly(ReqOnlyHeader::Host),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from("example.org"))),
+                ),
+                (
+                    ReqHeader::General(GeneralHeader::Connection),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from(
+                        "keep-alive, X-Secret",
+                    ))),
+                ),
+                (
+                    ReqHeader::Other(String::from("x-secret")),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from("foo"))),
+                ),
+                (
+                    ReqHeader::Other(String::from("keep-alive")),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from("timeout=5"))),
+                ),
+                (
+                    ReqHeader::Other(String::from("x-forwarded-for")),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from("10.0.0.1"))),
+                ),
+                (
+                    ReqHeader::ReqOnly(ReqOnlyHeader::ProxyAuthorization),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from("Basic foo"))),
+                ),
+                (
+                    ReqHeader::ReqOnly(ReqOnlyHeader::UserAgent),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from("test"))),
+                ),
+            ]),
+            None,
+            None,
+        );
+        let mut req = HttpReq::new(chrono::Utc::now(), head, None);
+        pool.prepare_req(
+            &mut req,
+            &Forwarded {
+                client_ip: "192.168.0.1",
+                proto: "https",
+            },
+        );
+
+        let value = |s: &str| HeaderValue::Simple(SimpleHeaderValue::String(String::from(s)));
+        assert_eq!(
+            *req.headers(),
+            collections::HashMap::from([
+                (
+                    ReqHeader::ReqOnly(ReqOnlyHeader::Host),
+                    value("upstream.local")
+                ),
+                (
+                    ReqHeader::General(GeneralHeader::Connection),
+                    value("close")
+                ),
+                (ReqHeader::General(GeneralHeader::Via), value(VIA)),
+                (
+                    ReqHeader::Other(String::from("X-Forwarded-For")),
+                    value("10.0.0.1, 192.168.0.1")
+                ),
+                (
+                    ReqHeader::Other(String::from("X-Forwarded-Proto")),
+                    value("https")
+                ),
+                (ReqHeader::ReqOnly(ReqOnlyHeader::UserAgent), value("test")),
+            ])
+        );
+    }
+
+    #[test]
+    fn prepare_headers_test() {
+        let build_req = |verb, max_forwards: &str| {
+            let head = ReqHead::new(
+                verb,
+                path("/"),
+                String::from("HTTP/1.1"),
+                collections::HashMap::from([(
+                    ReqHeader::ReqOnly(ReqOnlyHeader::MaxForwards),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from(max_forwards))),
+                )]),
+                None,
+                None,
+            );
+            HttpReq::new(chrono::Utc::now(), head, None)
+        };
+
+        // Max-Forwards is only decremented for TRACE and OPTIONS requests
+        let mut req = build_req(ReqVerb::Trace, "5");
+        prepare_headers(&mut req);
+        assert_eq!(req.max_forwards(), Some(4));
+        let mut req = build_req(ReqVerb::Options, "1");
+        prepare_headers(&mut req);
+        assert_eq!(req.max_forwards(), Some(0));
+        let mut req = build_req(ReqVerb::Get, "5");
+        prepare_headers(&mut req);
+        assert_eq!(req.max_forwards(), Some(5));
+    }
+
+    #[test]
+    fn with_default_port_test() {
+        assert_eq!(with_default_port("example.org", 80), "example.org:80");
+        assert_eq!(
+            with_default_port("example.org:8080", 80),
+            "example.org:8080"
+        );
+        assert_eq!(with_default_port("[::1]", 80), "[::1]:80");
+        assert_eq!(with_default_port("[::1]:8080", 80), "[::1]:8080");
+    }
+
+    #[tokio::test]
+    async fn proxied_response_test() {
+        // the upstream server delimits the response body by closing the connection
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream
+                .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: text/event-stream\r\n\r\n")
+                .await
+                .unwrap();
+            for i in 0..3 {
+                let event = format!("data: {}\n\n", i);
+                stream.write_all(event.as_bytes()).await.unwrap();
+            }
+        });
+        let mut client = connect(&addr.to_string()).await.unwrap();
+        let res = client.read_res_head().await.unwrap();
+
+        let mut res = proxied_response(client, res, &ReqVerb::Get, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            res.headers()
+                .get(&ResHeader::General(GeneralHeader::Connection))
+                .map(|value| value.to_string()),
+            Some(String::from("close"))
+        );
+        let Some(ResBody::Upstream(body)) = res.body_mut() else {
+            panic!("the body is not streamed");
+        };
+        let mut forwarded = Vec::new();
+        body.copy_to(&mut forwarded).await.unwrap();
+        assert_eq!(forwarded, b"data: 0\n\ndata: 1\n\ndata: 2\n\n");
+    }
+
+    #[tokio::test]
+    async fn forward_failure_test() {
+        // the upstream server closes the connection without reading the request
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                drop(stream);
+            }
+        });
+        let pool = UpstreamPool::new(&ProxyRoute::from_str(&format!("/={}", addr)).unwrap());
+        let head = ReqHead::new(
+            ReqVerb::Post,
+            path("/"),
+            String::from("HTTP/1.1"),
+            collections::HashMap::new(),
+            None,
+            None,
+        );
+        let mut req = HttpReq::new(chrono::Utc::now(), head, None);
+        let body_len = 8 * 1024 * 1024;
+        let mut stream = vec![b'a'; body_len];
+        stream.extend_from_slice(b"next");
+        let mut client_reader = stream.as_slice();
+
+        let forwarded = Forwarded {
+            client_ip: "192.168.0.1",
+            proto: "http",
+        };
+        assert!(matches!(
+            pool.forward(&mut req, &mut client_reader, body_len, &forwarded)
+                .await,
+            Err(ProxyError::Client(_))
+        ));
+        // the rest of the body is not taken for the next request
+        assert_eq!(client_reader, b"next");
+        assert_eq!(
+            pool.upstreams[0].failures.load(atomic::Ordering::Relaxed),
+            1
+        );
+
+        // only absolute URIs designate an origin server
+        let mut client_reader: &[u8] = b"bodynext";
+        assert!(matches!(
+            forward_to_origin(&mut req, &mut client_reader, 4).await,
+            Err(ProxyError::InvalidTarget)
+        ));
+        assert_eq!(client_reader, b"next");
+    }
+}
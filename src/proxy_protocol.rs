@@ -0,0 +1,410 @@
+//! Original addresses of the clients behind load balancers and proxies: HAProxy PROXY protocol (v1
+//! and v2), sent at the start of the connections, and Forwarded (or X-Forwarded-For) headers of the
+//! requests made by trusted proxies.
+//!
+//! See https://www.haproxy.org/download/3.0/doc/proxy-protocol.txt
+
+use crate::http_header::ReqHeader;
+use crate::http_req::ReqHead;
+use crate::utils::IpNetwork;
+
+use std::{fmt, io, net};
+
+use tokio::io::AsyncReadExt;
+
+/// Start of the (text) v1 header.
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// Maximum length of the v1 header, including the final CRLF.
+const V1_MAX_LEN: usize = 107;
+/// Start of the (binary) v2 header.
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The connection does not start with a PROXY protocol header
+    MissingHeader,
+    InvalidHeader(String),
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::MissingHeader => write!(f, "missing PROXY protocol header"),
+            Self::InvalidHeader(e) => write!(f, "invalid PROXY protocol header: {}", e),
+        }
+    }
+}
+
+/// Original addresses of a proxied connection.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ProxiedAddrs {
+    /// Address of the client
+    pub(crate) source: net::SocketAddr,
+    /// Address the client connected to (on the proxy)
+    pub(crate) destination: net::SocketAddr,
+}
+
+/// Read the PROXY protocol header at the start of a connection, and nothing more.
+///
+/// `None` is returned when the proxy does not pass the addresses of the connection (e.g. for its
+/// own health checks), which are then the ones of the proxy.
+pub(crate) async fn read_header<S: tokio::io::AsyncRead + Unpin>(
+    stream: &mut S,
+) -> Result<Option<ProxiedAddrs>, Error> {
+    // both versions start with 6 distinct bytes
+    let mut header = vec![0; V1_PREFIX.len()];
+    stream.read_exact(&mut header).await.map_err(Error::Io)?;
+    if header == V1_PREFIX {
+        // the header is read byte by byte, to leave the request bytes in the stream
+        while !header.ends_with(b"\r\n") {
+            if header.len() == V1_MAX_LEN {
+                return Err(Error::InvalidHeader(String::from("v1 header too long")));
+            }
+            header.push(stream.read_u8().await.map_err(Error::Io)?);
+        }
+        let line = String::from_utf8(header[V1_PREFIX.len()..header.len() - 2].to_vec())
+            .map_err(|_| Error::InvalidHeader(String::from("non UTF-8 v1 header")))?;
+        parse_v1(&line)
+    } else if V2_SIGNATURE.starts_with(&header) {
+        header.resize(V2_SIGNATURE.len() + 4, 0);
+        stream
+            .read_exact(&mut header[V1_PREFIX.len()..])
+            .await
+            .map_err(Error::Io)?;
+        if !header.starts_with(V2_SIGNATURE) {
+            return Err(Error::MissingHeader);
+        }
+        let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+        let mut addresses = vec![0; len];
+        stream.read_exact(&mut addresses).await.map_err(Error::Io)?;
+        parse_v2(header[12], header[13], &addresses)
+    } else {
+        Err(Error::MissingHeader)
+    }
+}
+
+/// Parse a v1 header line, without its `PROXY ` prefix and final CRLF (e.g. `TCP4 192.0.2.1
+/// 198.51.100.1 56324 443`).
+fn parse_v1(line: &str) -> Result<Option<ProxiedAddrs>, Error> {
+    let invalid = || Error::InvalidHeader(format!("invalid v1 header: {}", line));
+    let parts = line.split(' ').collect::<Vec<_>>();
+    let is_ipv4 = match parts.first() {
+        Some(&"UNKNOWN") => return Ok(None),
+        Some(&"TCP4") => true,
+        Some(&"TCP6") => false,
+        _ => return Err(invalid()),
+    };
+    let [_, source_ip, destination_ip, source_port, destination_port] = parts[..] else {
+        return Err(invalid());
+    };
+    let parse_addr = |ip: &str, port: &str| {
+        let ip = ip
+            .parse::<net::IpAddr>()
+            .ok()
+            .filter(|ip| ip.is_ipv4() == is_ipv4);
+        match (ip, port.parse()) {
+            (Some(ip), Ok(port)) => Ok(net::SocketAddr::new(ip, port)),
+            _ => Err(invalid()),
+        }
+    };
+    Ok(Some(ProxiedAddrs {
+        source: parse_addr(source_ip, source_port)?,
+        destination: parse_addr(destination_ip, destination_port)?,
+    }))
+}
+
+/// Parse the end of a v2 header, from its version and command, its address family and protocol,
+/// and its addresses (followed by optional TLVs, ignored).
+fn parse_v2(
+    version_command: u8,
+    family_protocol: u8,
+    addresses: &[u8],
+) -> Result<Option<ProxiedAddrs>, Error> {
+    match (version_command >> 4, version_command & 0x0f) {
+        (2, 0) => return Ok(None),
+        (2, 1) => (),
+        (2, command) => {
+            return Err(Error::InvalidHeader(format!(
+                "unknown v2 command: {}",
+                command
+            )));
+        }
+        (version, _) => {
+            return Err(Error::InvalidHeader(format!(
+                "unsupported version: {}",
+                version
+            )));
+        }
+    }
+    let too_short = || Error::InvalidHeader(String::from("v2 addresses too short"));
+    let port = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+    match family_protocol >> 4 {
+        // IPv4
+        1 => {
+            let bytes = addresses.get(..12).ok_or_else(too_short)?;
+            let ip = |bytes: &[u8]| net::Ipv4Addr::from([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            Ok(Some(ProxiedAddrs {
+                source: net::SocketAddr::from((ip(&bytes[0..4]), port(&bytes[8..10]))),
+                destination: net::SocketAddr::from((ip(&bytes[4..8]), port(&bytes[10..12]))),
+            }))
+        }
+        // IPv6
+        2 => {
+            let bytes = addresses.get(..36).ok_or_else(too_short)?;
+            let ip = |bytes: &[u8]| net::Ipv6Addr::from(<[u8; 16]>::try_from(bytes).unwrap());
+            Ok(Some(ProxiedAddrs {
+                source: net::SocketAddr::from((ip(&bytes[0..16]), port(&bytes[32..34]))),
+                destination: net::SocketAddr::from((ip(&bytes[16..32]), port(&bytes[34..36]))),
+            }))
+        }
+        // unspecified or Unix domain socket addresses, not usable as client addresses
+        _ => Ok(None),
+    }
+}
+
+/// IP address of the client of a request made by a trusted proxy, from its Forwarded (preferred) or
+/// X-Forwarded-For header.
+///
+/// The addresses added by the proxies are read from the last one, skipping the trusted proxies: the
+/// first untrusted address is the one of the client, as the previous ones may be forged by it.
+pub(crate) fn forwarded_client_ip(
+    req: &ReqHead,
+    peer_ip: net::IpAddr,
+    trusted_proxies: &[IpNetwork],
+) -> Option<net::IpAddr> {
+    let is_trusted = |ip: net::IpAddr| trusted_proxies.iter().any(|network| network.contains(ip));
+    if !is_trusted(peer_ip) {
+        return None;
+    }
+    // header names were lowercased when parsed
+    let addresses = match req.header_str(&ReqHeader::Other(String::from("forwarded"))) {
+        Some(forwarded) => forwarded
+            .split(',')
+            .map(|element| {
+                element
+                    .split(';')
+                    .find_map(|pair| match pair.trim().split_once('=') {
+                        Some((name, node)) if name.eq_ignore_ascii_case("for") => {
+                            parse_forwarded_node(node)
+                        }
+                        _ => None,
+                    })
+            })
+            .collect::<Vec<_>>(),
+        None => req
+            .header_str(&ReqHeader::Other(String::from("x-forwarded-for")))?
+            .split(',')
+            .map(|ip| ip.trim().parse().ok())
+            .collect(),
+    };
+    let mut client_ip = None;
+    for address in addresses.into_iter().rev() {
+        // unknown (or obfuscated) addresses cannot be trusted
+        let Some(ip) = address else { break };
+        client_ip = Some(ip);
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    client_ip
+}
+
+/// Parse the IP address of a node of the Forwarded header (e.g. `192.0.2.43:4711` or
+/// `"[2001:db8:cafe::17]"`).
+fn parse_forwarded_node(node: &str) -> Option<net::IpAddr> {
+    let node = node.trim().trim_matches('"');
+    match node.strip_prefix('[') {
+        Some(node) => node.split_once(']')?.0.parse().ok(),
+        None => node.split(':').next()?.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_header::{HeaderValue, SimpleHeaderValue};
+    use crate::http_req::{ReqPath, ReqTarget, ReqVerb};
+
+    use std::collections;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn parse_v1_test() {
+        assert_eq!(
+            parse_v1("TCP4 192.0.2.1 198.51.100.1 56324 443").unwrap(),
+            Some(ProxiedAddrs {
+                source: "192.0.2.1:56324".parse().unwrap(),
+                destination: "198.51.100.1:443".parse().unwrap(),
+            })
+        );
+        assert_eq!(
+            parse_v1("TCP6 2001:db8::1 2001:db8::2 56324 443").unwrap(),
+            Some(ProxiedAddrs {
+                source: "[2001:db8::1]:56324".parse().unwrap(),
+                destination: "[2001:db8::2]:443".parse().unwrap(),
+            })
+        );
+        assert_eq!(parse_v1("UNKNOWN").unwrap(), None);
+        assert_eq!(
+            parse_v1("UNKNOWN 192.0.2.1 198.51.100.1 1 2").unwrap(),
+            None
+        );
+
+        assert!(parse_v1("TCP4 2001:db8::1 2001:db8::2 56324 443").is_err());
+        assert!(parse_v1("TCP4 192.0.2.1 198.51.100.1 56324").is_err());
+        assert!(parse_v1("TCP4 192.0.2.1 198.51.100.1 56324 65536").is_err());
+        assert!(parse_v1("UDP4 192.0.2.1 198.51.100.1 56324 443").is_err());
+    }
+
+    #[test]
+    fn parse_v2_test() {
+        let ipv4 = [192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb];
+        assert_eq!(
+            parse_v2(0x21, 0x11, &ipv4).unwrap(),
+            Some(ProxiedAddrs {
+                source: "192.0.2.1:56324".parse().unwrap(),
+                destination: "198.51.100.1:443".parse().unwrap(),
+            })
+        );
+        let mut ipv6 = vec![0x20, 0x01, 0x0d, 0xb8];
+        ipv6.extend([0; 11]);
+        ipv6.extend([1, 0x20, 0x01, 0x0d, 0xb8]);
+        ipv6.extend([0; 11]);
+        ipv6.extend([2, 0xdc, 0x04, 0x01, 0xbb]);
+        // TLVs are ignored
+        ipv6.extend([0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            parse_v2(0x21, 0x21, &ipv6).unwrap(),
+            Some(ProxiedAddrs {
+                source: "[2001:db8::1]:56324".parse().unwrap(),
+                destination: "[2001:db8::2]:443".parse().unwrap(),
+            })
+        );
+        // LOCAL command, and Unix domain socket addresses
+        assert_eq!(parse_v2(0x20, 0x11, &ipv4).unwrap(), None);
+        assert_eq!(parse_v2(0x21, 0x31, &[0; 216]).unwrap(), None);
+
+        assert!(parse_v2(0x11, 0x11, &ipv4).is_err());
+        assert!(parse_v2(0x22, 0x11, &ipv4).is_err());
+        assert!(parse_v2(0x21, 0x11, &ipv4[..8]).is_err());
+    }
+
+    #[tokio::test]
+    async fn read_header_test() {
+        // the request following the header is left in the stream
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client
+            .write_all(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            read_header(&mut server).await.unwrap(),
+            Some(ProxiedAddrs {
+                source: "192.0.2.1:56324".parse().unwrap(),
+                destination: "198.51.100.1:443".parse().unwrap(),
+            })
+        );
+        let mut buf = vec![0; 16];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, b"GET / HTTP/1.1\r\n");
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(V2_SIGNATURE).await.unwrap();
+        client
+            .write_all(&[
+                0x21, 0x11, 0x00, 0x0c, 192, 0, 2, 1, 198, 51, 100, 1, 0, 1, 0, 2,
+            ])
+            .await
+            .unwrap();
+        client.write_all(b"GET").await.unwrap();
+        assert_eq!(
+            read_header(&mut server).await.unwrap(),
+            Some(ProxiedAddrs {
+                source: "192.0.2.1:1".parse().unwrap(),
+                destination: "198.51.100.1:2".parse().unwrap(),
+            })
+        );
+        let mut buf = vec![0; 3];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, b"GET");
+
+        // clients must not be trusted without the header
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        assert!(matches!(
+            read_header(&mut server).await,
+            Err(Error::MissingHeader)
+        ));
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&[b'A'; 200]).await.unwrap();
+        client.write_all(b"PROXY ").await.unwrap();
+        assert!(read_header(&mut server).await.is_err());
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(b"PROXY ").await.unwrap();
+        client.write_all(&[b'A'; 200]).await.unwrap();
+        assert!(matches!(
+            read_header(&mut server).await,
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn forwarded_client_ip_test() {
+        let build_req = |header: &str, value: &str| {
+            ReqHead::new(
+                ReqVerb::Get,
+                ReqTarget::Path(ReqPath {
+                    original: String::from("/"),
+                    decoded: String::from("/"),
+                    query: String::new(),
+                }),
+                String::from("HTTP/1.1"),
+                collections::HashMap::from([(
+                    ReqHeader::Other(String::from(header)),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from(value))),
+                )]),
+                None,
+                None,
+            )
+        };
+        let trusted = [
+            "10.0.0.0/8".parse().unwrap(),
+            "2001:db8::/32".parse().unwrap(),
+        ];
+        let ip = |ip: &str| ip.parse::<net::IpAddr>().unwrap();
+
+        let req = build_req("x-forwarded-for", "198.51.100.1, 203.0.113.7, 10.0.0.2");
+        assert_eq!(
+            forwarded_client_ip(&req, ip("10.0.0.1"), &trusted),
+            Some(ip("203.0.113.7"))
+        );
+        // the headers of untrusted peers are ignored
+        assert_eq!(forwarded_client_ip(&req, ip("192.0.2.1"), &trusted), None);
+        let req = build_req("x-forwarded-for", "10.0.0.3, 10.0.0.2");
+        assert_eq!(
+            forwarded_client_ip(&req, ip("10.0.0.1"), &trusted),
+            Some(ip("10.0.0.3"))
+        );
+        let req = build_req("x-forwarded-for", "203.0.113.7, unknown, 10.0.0.2");
+        assert_eq!(
+            forwarded_client_ip(&req, ip("10.0.0.1"), &trusted),
+            Some(ip("10.0.0.2"))
+        );
+
+        let req = build_req(
+            "forwarded",
+            r#"for=198.51.100.1, for="[2001:db8:cafe::17]:4711";proto=https, For=10.0.0.2:8080"#,
+        );
+        assert_eq!(
+            forwarded_client_ip(&req, ip("::ffff:10.0.0.1"), &trusted),
+            Some(ip("198.51.100.1"))
+        );
+        let req = build_req("forwarded", "for=_hidden;proto=https");
+        assert_eq!(forwarded_client_ip(&req, ip("10.0.0.1"), &trusted), None);
+        let req = build_req("x-real-ip", "198.51.100.1");
+        assert_eq!(forwarded_client_ip(&req, ip("10.0.0.1"), &trusted), None);
+    }
+}
@@ -0,0 +1,424 @@
+//! Rate limiting of the requests, with token buckets keyed by client IP address, authenticated
+//! user or path prefix. Exceeding clients are answered with `429 Too Many Requests`.
+
+use crate::utils;
+
+use std::{cmp, collections, str::FromStr, sync, time};
+
+/// Maximum number of buckets, so that clients (e.g. using many addresses) cannot exhaust the memory
+/// of the server.
+const MAX_BUCKETS: usize = 10_000;
+/// Number of least recently used buckets dropped at once when there are too many, so that going
+/// through all of them does not happen on each new bucket.
+const DROPPED_BUCKETS: usize = MAX_BUCKETS / 10;
+
+/// A rate of requests, allowing bursts of `burst` requests.
+///
+/// Its string format is `REQUESTS/UNIT[|burst=N]`, where `UNIT` is `s`, `min` or `h` (e.g.
+/// `100/min|burst=20`). Without burst, the requests of a whole period can be made at once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rate {
+    pub requests: u32,
+    pub per: time::Duration,
+    pub burst: u32,
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('|');
+        let (requests, unit) = parts
+            .next()
+            .and_then(|rate| rate.split_once('/'))
+            .ok_or(format!("Invalid rate: {}", s))?;
+        let requests = match requests.parse() {
+            Ok(0) | Err(_) => return Err(format!("Invalid number of requests: {}", requests)),
+            Ok(requests) => requests,
+        };
+        let per = match unit {
+            "s" => time::Duration::from_secs(1),
+            "min" => time::Duration::from_secs(60),
+            "h" => time::Duration::from_secs(3600),
+            _ => return Err(format!("Invalid rate unit: {}", unit)),
+        };
+        let mut burst = requests;
+        for option in parts {
+            match option.split_once('=') {
+                Some(("burst", value)) => {
+                    burst = match value.parse() {
+                        Ok(0) | Err(_) => return Err(format!("Invalid burst: {}", value)),
+                        Ok(burst) => burst,
+                    }
+                }
+                _ => return Err(format!("Invalid rate option: {}", option)),
+            }
+        }
+        Ok(Self {
+            requests,
+            per,
+            burst,
+        })
+    }
+}
+
+/// What the requests sharing a rate limit have in common.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateLimitKey {
+    /// IP address of the client (or the one reported by a trusted proxy)
+    ClientIp,
+    /// Name of the authenticated user (requests without valid credentials are not limited)
+    User,
+    /// Path prefix of the requests, all clients sharing the same limit
+    PathPrefix(String),
+}
+
+/// A rate limit applied to the requests made to the server.
+///
+/// Its string format is `KEY=RATE`, where `KEY` is `ip`, `user` or a path prefix (e.g.
+/// `ip=100/min|burst=20` or `/api=10/s`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub key: RateLimitKey,
+    pub rate: Rate,
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, rate) = s
+            .split_once('=')
+            .ok_or(format!("Invalid rate limit: {}", s))?;
+        let key = match key {
+            "ip" => RateLimitKey::ClientIp,
+            "user" => RateLimitKey::User,
+            _ if key.starts_with('/') => RateLimitKey::PathPrefix(String::from(key)),
+            _ => return Err(format!("Invalid rate limit key: {}", key)),
+        };
+        Ok(Self {
+            key,
+            rate: rate.parse()?,
+        })
+    }
+}
+
+/// Requests allowed by a rate, refilled over time.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: time::Instant,
+}
+
+impl Bucket {
+    fn new(rate: &Rate, now: time::Instant) -> Self {
+        Self {
+            tokens: rate.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: &Rate, now: time::Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        let refilled = elapsed * rate.requests as f64 / rate.per.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(rate.burst as f64);
+        self.updated = now;
+    }
+
+    /// Time until a request is allowed, if none is right now.
+    fn wait_time(&self, rate: &Rate) -> Option<time::Duration> {
+        match self.tokens >= 1.0 {
+            true => None,
+            false => Some(rate.per.mul_f64((1.0 - self.tokens) / rate.requests as f64)),
+        }
+    }
+}
+
+/// Identifies a bucket: the index of its rate limit (none for failed authentications), and the key
+/// of the requests (client IP address, user name or path prefix).
+type BucketId = (Option<usize>, String);
+
+/// Rate limits of the server, with the buckets of the clients.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limits: Vec<RateLimit>,
+    /// Stricter rate of the failed authentications of a client IP address, once exceeded any
+    /// authentication attempt is refused
+    auth_failure_rate: Option<Rate>,
+    buckets: sync::Mutex<collections::HashMap<BucketId, Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: Vec<RateLimit>, auth_failure_rate: Option<Rate>) -> Self {
+        Self {
+            limits,
+            auth_failure_rate,
+            buckets: sync::Mutex::new(collections::HashMap::new()),
+        }
+    }
+
+    /// Count a request against the rate limits it is subject to.
+    ///
+    /// When one of them is exceeded, the request is refused and the time until the client may
+    /// retry is returned (the other limits are then not counted).
+    pub(crate) fn check(
+        &self,
+        client_ip: &str,
+        user: Option<&str>,
+        path: &str,
+    ) -> Result<(), time::Duration> {
+        self.check_at(client_ip, user, path, time::Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        client_ip: &str,
+        user: Option<&str>,
+        path: &str,
+        now: time::Instant,
+    ) -> Result<(), time::Duration> {
+        if self.limits.is_empty() {
+            return Ok(());
+        }
+        let ids = self
+            .limits
+            .iter()
+            .enumerate()
+            .filter_map(|(index, limit)| {
+                let key = match &limit.key {
+                    RateLimitKey::ClientIp => client_ip,
+                    RateLimitKey::User => user?,
+                    RateLimitKey::PathPrefix(prefix)
+                        if utils::matches_path_prefix(path, prefix) =>
+                    {
+                        prefix
+                    }
+                    RateLimitKey::PathPrefix(_) => return None,
+                };
+                Some(((Some(index), String::from(key)), &limit.rate))
+            })
+            .collect::<Vec<_>>();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        drop_least_recent_buckets(&mut buckets, ids.len());
+        // all the limits must allow the request before it is counted
+        let mut retry_after = None;
+        for (id, rate) in &ids {
+            let bucket = buckets
+                .entry(id.clone())
+                .or_insert_with(|| Bucket::new(rate, now));
+            bucket.refill(rate, now);
+            retry_after = retry_after.max(bucket.wait_time(rate));
+        }
+        if let Some(retry_after) = retry_after {
+            return Err(retry_after);
+        }
+        for (id, _) in ids {
+            if let Some(bucket) = buckets.get_mut(&id) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether a client may attempt to authenticate, i.e. has not failed too often.
+    ///
+    /// Otherwise, the time until it may retry is returned.
+    pub(crate) fn check_auth(&self, client_ip: &str) -> Result<(), time::Duration> {
+        self.check_auth_at(client_ip, time::Instant::now())
+    }
+
+    fn check_auth_at(&self, client_ip: &str, now: time::Instant) -> Result<(), time::Duration> {
+        let Some(rate) = self.auth_failure_rate.as_ref() else {
+            return Ok(());
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        match buckets.get_mut(&(None, String::from(client_ip))) {
+            Some(bucket) => {
+                bucket.refill(rate, now);
+                bucket.wait_time(rate).map_or(Ok(()), Err)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Count a failed authentication of a client.
+    pub(crate) fn record_auth_failure(&self, client_ip: &str) {
+        self.record_auth_failure_at(client_ip, time::Instant::now())
+    }
+
+    fn record_auth_failure_at(&self, client_ip: &str, now: time::Instant) {
+        let Some(rate) = self.auth_failure_rate.as_ref() else {
+            return;
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        drop_least_recent_buckets(&mut buckets, 1);
+        let bucket = buckets
+            .entry((None, String::from(client_ip)))
+            .or_insert_with(|| Bucket::new(rate, now));
+        bucket.refill(rate, now);
+        bucket.tokens = (bucket.tokens - 1.0).max(0.0);
+    }
+}
+
+/// Forget the buckets of the clients which have not made requests for the longest time, when
+/// `added` new buckets would exceed `MAX_BUCKETS`.
+fn drop_least_recent_buckets(buckets: &mut collections::HashMap<BucketId, Bucket>, added: usize) {
+    if buckets.len() + added <= MAX_BUCKETS {
+        return;
+    }
+    let mut by_use = buckets
+        .iter()
+        .map(|(id, bucket)| (bucket.updated, id.clone()))
+        .collect::<Vec<_>>();
+    let dropped = cmp::min(buckets.len(), DROPPED_BUCKETS.max(added));
+    if dropped < by_use.len() {
+        by_use.select_nth_unstable_by_key(dropped, |(updated, _)| *updated);
+    }
+    for (_, id) in &by_use[..dropped] {
+        buckets.remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_from_str_test() {
+        assert_eq!(
+            "ip=100/min|burst=20".parse::<RateLimit>(),
+            Ok(RateLimit {
+                key: RateLimitKey::ClientIp,
+                rate: Rate {
+                    requests: 100,
+                    per: time::Duration::from_secs(60),
+                    burst: 20,
+                },
+            })
+        );
+        assert_eq!(
+            "/api=10/s".parse::<RateLimit>(),
+            Ok(RateLimit {
+                key: RateLimitKey::PathPrefix(String::from("/api")),
+                rate: Rate {
+                    requests: 10,
+                    per: time::Duration::from_secs(1),
+                    burst: 10,
+                },
+            })
+        );
+        assert_eq!(
+            "user=1000/h".parse::<RateLimit>().unwrap().key,
+            RateLimitKey::User
+        );
+        assert!("ip".parse::<RateLimit>().is_err());
+        assert!("host=10/s".parse::<RateLimit>().is_err());
+        assert!("ip=10".parse::<RateLimit>().is_err());
+        assert!("ip=0/s".parse::<RateLimit>().is_err());
+        assert!("ip=10/day".parse::<RateLimit>().is_err());
+        assert!("ip=10/s|burst=0".parse::<RateLimit>().is_err());
+        assert!("ip=10/s|delay=1".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn check_test() {
+        let limiter = RateLimiter::new(
+            vec![
+                "ip=2/s".parse().unwrap(),
+                "user=1/min".parse().unwrap(),
+                "/api=10/s|burst=3".parse().unwrap(),
+            ],
+            None,
+        );
+        let now = time::Instant::now();
+        assert!(limiter.check_at("192.0.2.1", None, "/", now).is_ok());
+        assert!(limiter.check_at("192.0.2.1", None, "/", now).is_ok());
+        assert_eq!(
+            limiter.check_at("192.0.2.1", None, "/", now),
+            Err(time::Duration::from_millis(500))
+        );
+        // clients have their own buckets, refilled over time
+        assert!(limiter.check_at("192.0.2.2", None, "/", now).is_ok());
+        let later = now + time::Duration::from_millis(500);
+        assert!(limiter.check_at("192.0.2.1", None, "/", later).is_ok());
+        assert!(limiter.check_at("192.0.2.1", None, "/", later).is_err());
+
+        // users are limited whatever their address
+        assert!(
+            limiter
+                .check_at("192.0.2.3", Some("user"), "/", now)
+                .is_ok()
+        );
+        assert_eq!(
+            limiter.check_at("192.0.2.4", Some("user"), "/", now),
+            Err(time::Duration::from_secs(60))
+        );
+        // refused requests are not counted by the other limits
+        assert!(limiter.check_at("192.0.2.4", None, "/", now).is_ok());
+        assert!(limiter.check_at("192.0.2.4", None, "/", now).is_ok());
+
+        // path prefixes are shared by all the clients
+        assert!(limiter.check_at("192.0.2.5", None, "/api", now).is_ok());
+        assert!(limiter.check_at("192.0.2.6", None, "/api/v1", now).is_ok());
+        assert!(limiter.check_at("192.0.2.7", None, "/apis", now).is_ok());
+        assert!(limiter.check_at("192.0.2.8", None, "/api/", now).is_ok());
+        assert_eq!(
+            limiter.check_at("192.0.2.9", None, "/api/v2", now),
+            Err(time::Duration::from_millis(100))
+        );
+
+        // without limits, nothing is tracked
+        let limiter = RateLimiter::new(Vec::new(), None);
+        assert!(limiter.check_at("192.0.2.1", None, "/", now).is_ok());
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn auth_failures_test() {
+        let limiter = RateLimiter::new(
+            vec!["ip=100/s".parse().unwrap()],
+            Some("2/min".parse().unwrap()),
+        );
+        let now = time::Instant::now();
+        assert!(limiter.check_auth_at("192.0.2.1", now).is_ok());
+        limiter.record_auth_failure_at("192.0.2.1", now);
+        assert!(limiter.check_auth_at("192.0.2.1", now).is_ok());
+        limiter.record_auth_failure_at("192.0.2.1", now);
+        assert_eq!(
+            limiter.check_auth_at("192.0.2.1", now),
+            Err(time::Duration::from_secs(30))
+        );
+        assert!(limiter.check_auth_at("192.0.2.2", now).is_ok());
+        // the other requests of the client are still allowed
+        assert!(limiter.check_at("192.0.2.1", None, "/", now).is_ok());
+        let later = now + time::Duration::from_secs(30);
+        assert!(limiter.check_auth_at("192.0.2.1", later).is_ok());
+
+        let limiter = RateLimiter::new(Vec::new(), None);
+        limiter.record_auth_failure_at("192.0.2.1", now);
+        assert!(limiter.check_auth_at("192.0.2.1", now).is_ok());
+    }
+
+    #[test]
+    fn max_buckets_test() {
+        let limiter = RateLimiter::new(vec!["ip=1/min".parse().unwrap()], None);
+        let now = time::Instant::now();
+        assert!(limiter.check_at("limited", None, "/", now).is_ok());
+        // a client using many addresses (still limited) cannot make the buckets grow without bound
+        for i in 0..MAX_BUCKETS * 3 {
+            let at = now + time::Duration::from_millis(i as u64);
+            assert!(limiter.check_at(&i.to_string(), None, "/", at).is_ok());
+            assert!(limiter.buckets.lock().unwrap().len() <= MAX_BUCKETS);
+            // the clients making requests keep their bucket
+            if i % 1000 == 0 {
+                assert!(limiter.check_at("limited", None, "/", at).is_err());
+            }
+        }
+        // the least recently used buckets are dropped first
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.contains_key(&(Some(0), String::from("limited"))));
+        assert!(buckets.contains_key(&(Some(0), (MAX_BUCKETS * 3 - 1).to_string())));
+        assert!(!buckets.contains_key(&(Some(0), String::from("0"))));
+    }
+}
@@ -42,6 +42,7 @@ pub enum FirstLineParsingError {
     InvalidVerb,
     InvalidTargetQuery,
     InvalidTargetEncoding,
+    InvalidTargetAuthority,
 }
 
 #[derive(Debug, PartialEq)]
@@ -52,6 +53,8 @@ pub enum HeaderParsingError {
     InvalidMime,
     InvalidFloat,
     InvalidBasicCredentials,
+    InvalidDigestCredentials,
+    InvalidBearerToken,
     NumberParsing,
 }
 
@@ -206,14 +209,23 @@ impl ReqHeadParser {
             None
         };
 
-        Ok(ReqHead::new(
+        let mut head = ReqHead::new(
             verb,
             target,
             version,
             headers,
             authentication_credentials,
             encoding,
-        ))
+        );
+        if let Some(raw_target) = self
+            .raw_req_head
+            .request_line
+            .split(ascii::AsciiChar::Space)
+            .nth(1)
+        {
+            head.set_raw_target(raw_target.to_string());
+        }
+        Ok(head)
     }
 
     /// Reset the parser to parse a new request head
@@ -281,6 +293,118 @@ mod tests {
         );
     }
 
+    #[test]
+    fn normalize_path_test() {
+        for (path, normalized) in [
+            ("/", "/"),
+            ("", "/"),
+            ("/dir/page.html", "/dir/page.html"),
+            ("/dir/", "/dir/"),
+            ("//admin/x", "/admin/x"),
+            ("/health/../admin/secret", "/admin/secret"),
+            ("/a/./b/../c/", "/a/c/"),
+            ("/a/b/..", "/a/"),
+            ("/a/../../etc/passwd", "/../etc/passwd"),
+            ("/../../a", "/../../a"),
+            ("/..", "/../"),
+            ("/a/.../b", "/a/.../b"),
+        ] {
+            assert_eq!(utils::normalize_path(path), normalized, "{}", path);
+        }
+    }
+
+    #[test]
+    fn parse_first_line_normalized_test() {
+        assert_eq!(
+            utils::parse_first_line(ascii("GET /public/%2E%2E//admin/my%20file?a=b HTTP/1.1")),
+            Ok((
+                ReqVerb::Get,
+                ReqTarget::Path(ReqPath {
+                    decoded: String::from("/admin/my file"),
+                    original: String::from("/admin/my%20file?a=b"),
+                    query: String::from("a=b")
+                }),
+                String::from("HTTP/1.1")
+            ))
+        );
+
+        // the target as sent is kept (e.g. for digest credentials)
+        let mut parser = ReqHeadParser::new();
+        parser
+            .process_bytes(Vec::from("GET /public/%2E%2E//admin?a=b HTTP/1.1\r\n\r\n"))
+            .unwrap();
+        let head = parser.do_parse().unwrap();
+        assert_eq!(head.target().to_string(), "/admin?a=b");
+        assert_eq!(head.raw_target(), "/public/%2E%2E//admin?a=b");
+    }
+
+    #[test]
+    fn parse_first_line_proxy_test() {
+        assert_eq!(
+            utils::parse_first_line(ascii(
+                "GET http://Example.org:8080/dir/page.html?q=1 HTTP/1.1"
+            )),
+            Ok((
+                ReqVerb::Get,
+                ReqTarget::Absolute(
+                    String::from("example.org:8080"),
+                    ReqPath {
+                        decoded: String::from("/dir/page.html"),
+                        original: String::from("/dir/page.html?q=1"),
+                        query: String::from("q=1")
+                    }
+                ),
+                String::from("HTTP/1.1")
+            ))
+        );
+        assert_eq!(
+            utils::parse_first_line(ascii("OPTIONS http://example.org HTTP/1.1")),
+            Ok((
+                ReqVerb::Options,
+                ReqTarget::Absolute(
+                    String::from("example.org"),
+                    ReqPath {
+                        decoded: String::from("/"),
+                        original: String::from("/"),
+                        query: String::new()
+                    }
+                ),
+                String::from("HTTP/1.1")
+            ))
+        );
+        assert_eq!(
+            utils::parse_first_line(ascii("CONNECT example.org:443 HTTP/1.1")),
+            Ok((
+                ReqVerb::Connect,
+                ReqTarget::Authority(String::from("example.org:443")),
+                String::from("HTTP/1.1")
+            ))
+        );
+        assert_eq!(
+            utils::parse_first_line(ascii("CONNECT [::1]:443 HTTP/1.1")),
+            Ok((
+                ReqVerb::Connect,
+                ReqTarget::Authority(String::from("[::1]:443")),
+                String::from("HTTP/1.1")
+            ))
+        );
+
+        for line in [
+            "CONNECT example.org HTTP/1.1",
+            "CONNECT example.org:https HTTP/1.1",
+            "CONNECT /index.html HTTP/1.1",
+            "GET http://:80/ HTTP/1.1",
+            "GET http://user@example.org/ HTTP/1.1",
+        ] {
+            assert_eq!(
+                utils::parse_first_line(ascii(line)),
+                Err(ReqHeadParsingError::FirstLine(
+                    FirstLineParsingError::InvalidTargetAuthority
+                ))
+            );
+        }
+    }
+
     #[test]
     fn parse_header_test() {}
 
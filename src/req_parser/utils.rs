@@ -18,11 +18,16 @@ pub fn parse_first_line(
         .collect::<Vec<_>>()
         .as_slice()
     {
-        [verb, target, version] => Ok((
-            parse_http_verb(verb)?,
-            parse_http_target(target)?,
-            version.to_string(),
-        )),
+        [verb, target, version] => {
+            let verb = parse_http_verb(verb)?;
+            // CONNECT requests target an authority (host and port) instead of a path
+            let target = if verb == ReqVerb::Connect {
+                ReqTarget::Authority(parse_http_authority(target.as_str(), true)?)
+            } else {
+                parse_http_target(target)?
+            };
+            Ok((verb, target, version.to_string()))
+        }
         _ => Err(ReqHeadParsingError::FirstLine(
             FirstLineParsingError::InvalidFieldCount,
         )),
@@ -32,10 +37,14 @@ pub fn parse_first_line(
 pub fn parse_http_verb(verb: &ascii::AsciiStr) -> Result<ReqVerb, ReqHeadParsingError> {
     match verb.as_bytes() {
         b"GET" => Ok(ReqVerb::Get),
+        b"HEAD" => Ok(ReqVerb::Head),
         b"POST" => Ok(ReqVerb::Post),
         b"PUT" => Ok(ReqVerb::Put),
         b"PATCH" => Ok(ReqVerb::Patch),
         b"DELETE" => Ok(ReqVerb::Delete),
+        b"OPTIONS" => Ok(ReqVerb::Options),
+        b"TRACE" => Ok(ReqVerb::Trace),
+        b"CONNECT" => Ok(ReqVerb::Connect),
         _ => Err(ReqHeadParsingError::FirstLine(
             FirstLineParsingError::InvalidVerb,
         )),
@@ -45,6 +54,25 @@ pub fn parse_http_verb(verb: &ascii::AsciiStr) -> Result<ReqVerb, ReqHeadParsing
 pub fn parse_http_target(target: &ascii::AsciiStr) -> Result<ReqTarget, ReqHeadParsingError> {
     match target.as_bytes() {
         b"*" => Ok(ReqTarget::All),
+        // absolute URI, as sent to a forward proxy
+        t if t.len() > 7 && t[..7].eq_ignore_ascii_case(b"http://") => {
+            let uri = &target[7..];
+            let path_start = uri
+                .chars()
+                .position(|ch| ch == ascii::AsciiChar::Slash || ch == ascii::AsciiChar::Question)
+                .unwrap_or(uri.len());
+            let authority = parse_http_authority(uri[..path_start].as_str(), false)?;
+            let path = match &uri[path_start..] {
+                path if path.is_empty() => ascii::AsciiStr::from_ascii("/").unwrap(),
+                path => path,
+            };
+            match parse_http_target(path)? {
+                ReqTarget::Path(path) => Ok(ReqTarget::Absolute(authority, path)),
+                _ => Err(ReqHeadParsingError::FirstLine(
+                    FirstLineParsingError::InvalidTargetAuthority,
+                )),
+            }
+        }
         _ => {
             let (encoded_path, query) = match *target
                 .split(ascii::AsciiChar::Question)
@@ -70,19 +98,83 @@ pub fn parse_http_target(target: &ascii::AsciiStr) -> Result<ReqTarget, ReqHeadP
                 }
             };
 
+            let decoded = urlencoding::decode(encoded_path.as_str()).map_err(|_| {
+                ReqHeadParsingError::FirstLine(FirstLineParsingError::InvalidTargetEncoding)
+            })?;
+            // the path is normalized once, so that the realms, access control lists, rate limits
+            // and files all see the same path (e.g. /public/../admin is /admin)
+            let normalized = normalize_path(&decoded);
+            let original = match normalized == decoded {
+                true => target.to_string(),
+                // the normalized path is forwarded to upstream servers as well
+                false => {
+                    let path = normalized
+                        .split('/')
+                        .map(|segment| urlencoding::encode(segment).into_owned())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    match query.is_empty() {
+                        true => path,
+                        false => format!("{}?{}", path, query),
+                    }
+                }
+            };
             Ok(ReqTarget::Path(ReqPath {
-                decoded: urlencoding::decode(encoded_path.as_str())
-                    .map_err(|_| {
-                        ReqHeadParsingError::FirstLine(FirstLineParsingError::InvalidTargetEncoding)
-                    })?
-                    .into_owned(),
-                original: target.to_string(),
+                decoded: normalized,
+                original,
                 query,
             }))
         }
     }
 }
 
+/// Normalize a decoded request path: remove its dot segments and collapse its repeated slashes,
+/// e.g. //a/./b/../c/ is /a/c/.
+///
+/// The `..` segments going above the root are kept, so that such paths are still refused when
+/// resolving files (and never match a path prefix but the root).
+pub fn normalize_path(path: &str) -> String {
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." if segments.last().is_some_and(|last| *last != "..") => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    // a trailing slash (or dot segment) still designates a directory
+    let is_dir = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+    let mut normalized = format!("/{}", segments.join("/"));
+    if is_dir && !segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// Parse the authority of a request target (host, and port if required), e.g. example.org:443
+pub fn parse_http_authority(
+    authority: &str,
+    port_required: bool,
+) -> Result<String, ReqHeadParsingError> {
+    let (host, port) = match authority.rsplit_once(':') {
+        // the colon may belong to an IPv6 address (e.g. [::1])
+        Some((host, port)) if !port.ends_with(']') => (host, Some(port)),
+        _ => (authority, None),
+    };
+    let valid_port = match port {
+        Some(port) => !port.is_empty() && port.parse::<u16>().is_ok(),
+        None => !port_required,
+    };
+    if host.is_empty() || !valid_port || host.contains(['/', '@', ' ']) {
+        return Err(ReqHeadParsingError::FirstLine(
+            FirstLineParsingError::InvalidTargetAuthority,
+        ));
+    }
+    Ok(authority.to_ascii_lowercase())
+}
+
 // because many headers are not used, exclude this function from coverage
 #[cfg_attr(coverage, coverage(off))]
 pub fn parse_header(
@@ -175,7 +267,8 @@ pub fn parse_header(
         (b"if-range", v) => req_only_simple_plain!(ReqOnlyHeader::IfRange, v),
         (b"if-unmodified-since", v) => req_only_simple_plain!(ReqOnlyHeader::IfUnmodifiedSince, v),
         (b"max-forwards", v) => req_only_simple_plain!(ReqOnlyHeader::MaxForwards, v),
-        (b"proxy-authorization", v) => req_only_simple_plain!(ReqOnlyHeader::ProxyAuthorization, v),
+        (b"proxy-authorization", v) => parse_authorization_header(v)
+            .map(|v| (ReqHeader::ReqOnly(ReqOnlyHeader::ProxyAuthorization), v)),
         (b"range", v) => req_only_simple_plain!(ReqOnlyHeader::Range, v),
         (b"referer", v) => req_only_simple_plain!(ReqOnlyHeader::Referer, v),
         (b"te", v) => req_only_simple_plain!(ReqOnlyHeader::TE, v),
@@ -332,6 +425,27 @@ pub fn parse_authorization_header(
                 HeaderParsingError::InvalidBasicCredentials,
             )),
         }
+    } else if value_str.len() > 7 && value_str[0..=6] == *"Digest " {
+        value_str[7..]
+            .as_str()
+            .parse()
+            .map(HeaderValue::Digest)
+            .map_err(|_e| ReqHeadParsingError::Header(HeaderParsingError::InvalidDigestCredentials))
+    } else if value_str.len() > 7 && value_str[0..=6] == *"Bearer " {
+        // token68 syntax (RFC 6750, section 2.1)
+        let token = value_str[7..].trim().as_str();
+        let (chars, padding) = token.split_at(token.trim_end_matches('=').len());
+        match !chars.is_empty()
+            && chars
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-._~+/".contains(c))
+            && padding.chars().all(|c| c == '=')
+        {
+            true => Ok(HeaderValue::Bearer(String::from(token))),
+            false => Err(ReqHeadParsingError::Header(
+                HeaderParsingError::InvalidBearerToken,
+            )),
+        }
     } else {
         Err(ReqHeadParsingError::Header(
             HeaderParsingError::InvalidBasicCredentials,
@@ -1,5 +1,7 @@
 //! Utility to build an HTTP response.
 
+use crate::auth::Identity;
+use crate::bearer::Claims;
 use crate::http_header::{
     EntityHeader, GeneralHeader, HeaderValue, ResHeader, ResOnlyHeader, SimpleHeaderValue,
 };
@@ -7,6 +9,8 @@ use crate::http_req::{ReqBody, ReqVerb};
 use crate::http_res::{self, HttpRes, ResBody};
 use crate::req_parser::SupportedEncoding;
 use crate::res_builder::ResBuildingError::PhpError;
+use crate::tls::ClientCert;
+use crate::{metrics, utils};
 
 use log::debug;
 use std::io::Write;
@@ -34,10 +38,13 @@ pub struct PhpScriptParams<'a> {
     pub interpreter_path: &'a str,
     pub script_path: &'a str,
     pub script_query: &'a str,
-    pub used_credentials: Option<(String, String)>,
+    /// User the client authenticated as, if any
+    pub identity: Option<&'a Identity>,
+    pub client_cert: Option<&'a ClientCert>,
     pub client_ip: &'a str,
     pub verb: &'a ReqVerb,
-    pub address: &'a net::SocketAddr,
+    /// Socket address of the server (none for Unix domain sockets)
+    pub address: Option<&'a net::SocketAddr>,
     pub version: &'a str,
     pub body: Option<&'a ReqBody>,
 }
@@ -222,11 +229,9 @@ impl ResBuilder {
             .env_clear()
             .env(
                 "AUTH_TYPE",
-                if params.used_credentials.is_some() {
-                    "Basic"
-                } else {
-                    ""
-                },
+                params
+                    .identity
+                    .map_or(String::new(), |identity| identity.scheme.to_string()),
             )
             .env("CONTENT_LENGTH", content_length)
             .env("CONTENT_TYPE", content_type)
@@ -239,11 +244,9 @@ impl ResBuilder {
             .env("REMOTE_IDENT", "")
             .env(
                 "REMOTE_USER",
-                if let Some((username, _)) = params.used_credentials {
-                    username
-                } else {
-                    String::new()
-                },
+                params
+                    .identity
+                    .map_or("", |identity| identity.username.as_str()),
             )
             .env("REQUEST_METHOD", params.verb.to_string())
             .env("SCRIPT_NAME", "")
@@ -254,11 +257,42 @@ impl ResBuilder {
                     .ok_or(PhpError(String::from("Invalid script path")))?,
             )
             .env("SCRIPT_FILENAME", params.script_path)
-            .env("SERVER_NAME", params.address.ip().to_string())
-            .env("SERVER_PORT", params.address.port().to_string())
+            .env(
+                "SERVER_NAME",
+                params.address.map_or(String::from("localhost"), |address| {
+                    address.ip().to_string()
+                }),
+            )
+            .env(
+                "SERVER_PORT",
+                params
+                    .address
+                    .map_or(String::from("0"), |address| address.port().to_string()),
+            )
             .env("SERVER_PROTOCOL", params.version)
             .env("SERVER_SOFTWARE", "rust-http-server")
             .env("REDIRECT_STATUS", "200")
+            .env(
+                "SSL_CLIENT_VERIFY",
+                if params.client_cert.is_some() {
+                    "SUCCESS"
+                } else {
+                    "NONE"
+                },
+            )
+            .envs(
+                params
+                    .client_cert
+                    .map(ClientCert::cgi_variables)
+                    .unwrap_or_default(),
+            )
+            .envs(
+                params
+                    .identity
+                    .and_then(|identity| identity.claims.as_ref())
+                    .map(Claims::cgi_variables)
+                    .unwrap_or_default(),
+            )
             .spawn()
             .map_err(|e| PhpError(e.to_string()))?;
 
@@ -304,16 +338,6 @@ impl ResBuilder {
     pub fn build_error(&mut self, status_code: u16, with_body: bool) -> &mut HttpRes {
         self.res.set_status(status_code);
 
-        // 401 unauthorized error: add authentication request header
-        if status_code == 401 {
-            self.res.set_header(
-                ResHeader::ResOnly(ResOnlyHeader::WWWAuthenticate),
-                HeaderValue::Simple(SimpleHeaderValue::String(String::from(
-                    "Basic realm=\"simple\"",
-                ))),
-            )
-        }
-
         // set content type
         if with_body {
             self.set_default_content_type();
@@ -348,6 +372,162 @@ impl ResBuilder {
         self.do_build()
     }
 
+    /// Generate an error page asking the client to authenticate, with some challenges (one per
+    /// scheme): a 401 error, or a 407 error for a proxy.
+    pub fn build_auth_required(&mut self, status_code: u16, challenges: &[String]) -> &mut HttpRes {
+        let header = match status_code {
+            407 => ResOnlyHeader::ProxyAuthenticate,
+            _ => ResOnlyHeader::WWWAuthenticate,
+        };
+        // one header per challenge
+        self.res.set_raw_headers(
+            challenges
+                .iter()
+                .map(|challenge| format!("{}: {}\r\n", header, challenge))
+                .collect(),
+        );
+        self.build_error(status_code, true)
+    }
+
+    /// Generate the login form, posted to its own path, which redirects the user to a page once
+    /// logged in (after a failed attempt, an error message is shown).
+    pub fn build_login_form(&mut self, action: &str, redirect: &str, failed: bool) -> &mut HttpRes {
+        self.res.set_status(200);
+        self.set_default_content_type();
+        // the form must not be cached, as it may be served to another user
+        self.res.set_header(
+            ResHeader::General(GeneralHeader::CacheControl),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from("no-store"))),
+        );
+        let message = format!(
+            "<!DOCTYPE html> \
+             <html lang=\"en\"> \
+                <head> \
+                    <meta charset=\"utf-8\"/> \
+                    <title>Log in</title> \
+                </head> \
+                <body> \
+                    <h1>Log in</h1> \
+                    {} \
+                    <form method=\"post\" action=\"{}\"> \
+                        <input type=\"hidden\" name=\"redirect\" value=\"{}\"/> \
+                        <p><label>Username <input name=\"username\" autocomplete=\"username\" required/></label></p> \
+                        <p><label>Password <input type=\"password\" name=\"password\" autocomplete=\"current-password\" required/></label></p> \
+                        <p><button type=\"submit\">Log in</button></p> \
+                    </form> \
+                </body> \
+             </html> \
+             \r\n",
+            if failed {
+                "<p>Invalid username or password.</p>"
+            } else {
+                ""
+            },
+            utils::escape_html(action),
+            utils::escape_html(redirect)
+        );
+        self.res
+            .set_body(Some(ResBody::Bytes(message.into_bytes())));
+        self.do_build()
+    }
+
+    /// Generate the response exposing the metrics of the server, in the Prometheus text format.
+    pub fn build_metrics(&mut self, metrics: String) -> &mut HttpRes {
+        self.res.set_status(200);
+        self.res.set_header(
+            ResHeader::Entity(EntityHeader::ContentType),
+            // as a string, the version parameter being required by Prometheus
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from(
+                metrics::CONTENT_TYPE,
+            ))),
+        );
+        self.res
+            .set_body(Some(ResBody::Bytes(metrics.into_bytes())));
+        self.do_build()
+    }
+
+    /// Generate a response with the status of the server, as an HTML page or in JSON.
+    pub fn build_server_status(&mut self, status: String, json: bool) -> &mut HttpRes {
+        self.res.set_status(200);
+        match json {
+            true => self.res.set_header(
+                ResHeader::Entity(EntityHeader::ContentType),
+                HeaderValue::Simple(SimpleHeaderValue::Mime(mime_guess::mime::APPLICATION_JSON)),
+            ),
+            false => self.set_default_content_type(),
+        }
+        // the status is always up to date
+        self.res.set_header(
+            ResHeader::General(GeneralHeader::CacheControl),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from("no-store"))),
+        );
+        self.res.set_body(Some(ResBody::Bytes(status.into_bytes())));
+        self.do_build()
+    }
+
+    /// Generate the response to an OPTIONS request, listing the allowed request methods.
+    pub fn build_options(&mut self, allowed_verbs: &[ReqVerb]) -> &mut HttpRes {
+        self.res.set_header(
+            ResHeader::Entity(EntityHeader::Allow),
+            HeaderValue::Simple(SimpleHeaderValue::String(
+                allowed_verbs
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            )),
+        );
+        self.res.set_header(
+            ResHeader::Entity(EntityHeader::ContentLength),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from("0"))),
+        );
+        self.do_build()
+    }
+
+    /// Generate the response to a TRACE request, i.e. echo the request head back to the client.
+    pub fn build_trace(&mut self, req_head: Vec<u8>) -> &mut HttpRes {
+        self.res.set_header(
+            ResHeader::Entity(EntityHeader::ContentType),
+            HeaderValue::Simple(SimpleHeaderValue::Mime(
+                mime_guess::Mime::from_str("message/http").unwrap(),
+            )),
+        );
+        self.res.set_body(Some(ResBody::Bytes(req_head)));
+        self.do_build()
+    }
+
+    /// Generate a redirect response, to another location.
+    pub fn build_redirect(&mut self, status_code: u16, location: &str) -> &mut HttpRes {
+        self.res.set_status(status_code);
+        self.res.set_header(
+            ResHeader::ResOnly(ResOnlyHeader::Location),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from(location))),
+        );
+        self.res.set_header(
+            ResHeader::Entity(EntityHeader::ContentLength),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from("0"))),
+        );
+        self.do_build()
+    }
+
+    /// Generate the response accepting a WebSocket opening handshake.
+    pub fn build_switching_protocols(&mut self, accept_key: &str) -> &mut HttpRes {
+        self.res.set_status(101);
+        self.res.set_header(
+            ResHeader::General(GeneralHeader::Upgrade),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from("websocket"))),
+        );
+        self.res.set_header(
+            ResHeader::General(GeneralHeader::Connection),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from("Upgrade"))),
+        );
+        self.res.set_header(
+            ResHeader::Other(String::from("Sec-WebSocket-Accept")),
+            HeaderValue::Simple(SimpleHeaderValue::String(String::from(accept_key))),
+        );
+        self.do_build()
+    }
+
     /// Build the HTTP response to be sent back to the client.
     pub fn do_build(&mut self) -> &mut HttpRes {
         // set date if not already present
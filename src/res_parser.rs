@@ -0,0 +1,490 @@
+//! HTTP response parsing.
+//!
+//! Parsing is done in two steps, like for requests: first parse the response head (status line and
+//! headers) to determine how the body is delimited, and then read the body from the stream.
+
+mod utils;
+pub use utils::{copy_res_body, decode_res_body, read_res_body};
+
+use crate::http_header::{EntityHeader, GeneralHeader, HeaderValue, ResHeader, SimpleHeaderValue};
+use crate::http_res::HttpRes;
+use crate::req_parser::HeaderParsingError;
+
+use log::debug;
+use std::{collections, io};
+
+/// Maximum size of a response body read in memory (e.g. bodies delimited by the connection close).
+pub const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+struct RawResHead {
+    status_line: ascii::AsciiString,
+    headers: collections::HashMap<ascii::AsciiString, ascii::AsciiString>,
+    // headers that cannot be merged into a single comma-separated value (e.g. Set-Cookie)
+    raw_headers: String,
+    last_header_name: Option<ascii::AsciiString>,
+}
+
+impl RawResHead {
+    fn new() -> Self {
+        Self {
+            status_line: ascii::AsciiString::new(),
+            headers: collections::HashMap::new(),
+            raw_headers: String::new(),
+            last_header_name: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ResHeadParserState {
+    StatusLine,
+    Headers,
+    Done,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StatusLineParsingError {
+    EmptyLine,
+    InvalidFieldCount,
+    InvalidVersion,
+    InvalidStatusCode,
+}
+
+#[derive(Debug)]
+pub enum ResHeadParsingError {
+    Ascii(ascii::AsAsciiStrError),
+    StatusLine(StatusLineParsingError),
+    Header(HeaderParsingError),
+    InvalidChunk,
+    BodyTooLarge,
+    Io(io::Error),
+}
+
+/// How the body of a response is delimited on the stream.
+#[derive(Debug, PartialEq)]
+pub enum ResBodyLength {
+    /// The response has no body (e.g. 204, 304 or response to a HEAD request)
+    None,
+    /// The body length is given by the Content-Length header
+    Fixed(usize),
+    /// The body is sent with chunked transfer-encoding
+    Chunked,
+    /// The body extends until the server closes the connection
+    UntilClose,
+}
+
+impl ResBodyLength {
+    /// Determine how the body of a parsed response is delimited (RFC 9112 section 6.3).
+    pub fn of(res: &mut HttpRes, head_only: bool) -> Self {
+        let status_code = res.status_code();
+        if head_only || (100..200).contains(&status_code) || [204, 304].contains(&status_code) {
+            return Self::None;
+        }
+        // the body is chunked only if chunked is the last coding applied, otherwise it can only be
+        // delimited by the end of the connection
+        if let Some(HeaderValue::Simple(SimpleHeaderValue::String(v))) = res
+            .headers()
+            .get(&ResHeader::General(GeneralHeader::TransferEncoding))
+        {
+            return match v.rsplit(',').next().map(str::trim) {
+                Some(coding) if coding.eq_ignore_ascii_case("chunked") => Self::Chunked,
+                _ => Self::UntilClose,
+            };
+        }
+        match res
+            .headers()
+            .get(&ResHeader::Entity(EntityHeader::ContentLength))
+        {
+            Some(HeaderValue::Simple(SimpleHeaderValue::Number(n))) => Self::Fixed(*n as usize),
+            _ => Self::UntilClose,
+        }
+    }
+}
+
+pub struct ResHeadParser {
+    state: ResHeadParserState,
+    raw_res_head: RawResHead,
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl Default for ResHeadParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ResHeadParser {
+    pub fn new() -> Self {
+        Self {
+            state: ResHeadParserState::StatusLine,
+            raw_res_head: RawResHead::new(),
+        }
+    }
+
+    /// Tell whether the parsing of the head is done or not. Call do_parse() when this returns true.
+    pub fn is_complete(&self) -> bool {
+        self.state == ResHeadParserState::Done
+    }
+
+    /// Process a line of HTTP response head
+    pub fn process_bytes(&mut self, bytes: Vec<u8>) -> Result<(), ResHeadParsingError> {
+        let line = ascii::AsciiString::from_ascii(bytes)
+            .map_err(|e| ResHeadParsingError::Ascii(e.ascii_error()))?;
+        debug!("Received line: {:?}", line);
+        let line = line.trim();
+        match self.state {
+            ResHeadParserState::StatusLine => {
+                if line.is_empty() {
+                    Err(ResHeadParsingError::StatusLine(
+                        StatusLineParsingError::EmptyLine,
+                    ))
+                } else {
+                    self.raw_res_head.status_line = ascii::AsciiString::from(line);
+                    self.state = ResHeadParserState::Headers;
+                    Ok(())
+                }
+            }
+            ResHeadParserState::Headers => {
+                if line.is_empty() {
+                    self.state = ResHeadParserState::Done;
+                    return Ok(());
+                }
+                match line.chars().position(|c_| c_ == ':') {
+                    // typical name: value header line
+                    Some(colon_idx) => {
+                        let (name, value) = (&line[..colon_idx], &line[colon_idx + 1..]);
+
+                        if let Some(ascii::AsciiChar::Space) = name.last() {
+                            return Err(ResHeadParsingError::Header(
+                                HeaderParsingError::SpaceBeforeColon,
+                            ));
+                        }
+
+                        // header names should be treated case-insensitive
+                        let name = name.trim().to_ascii_lowercase();
+                        if name == "set-cookie" {
+                            // cookies cannot be folded into a list, so keep each line as is
+                            self.raw_res_head
+                                .raw_headers
+                                .push_str(&format!("Set-Cookie: {}\r\n", value.trim_start()));
+                            self.raw_res_head.last_header_name = None;
+                            return Ok(());
+                        }
+                        let entry = self.raw_res_head.headers.entry(name.clone()).or_default();
+                        if !entry.is_empty() {
+                            // repeated header: merge values as a comma-separated list
+                            entry.push_str(ascii::AsciiStr::from_ascii(", ").unwrap());
+                        }
+                        entry.push_str(value.trim_start());
+                        self.raw_res_head.last_header_name = Some(name);
+                        Ok(())
+                    }
+                    // if the line has no ':', then it may be the previous header line continued
+                    None => {
+                        if let Some(name) = self.raw_res_head.last_header_name.as_ref() {
+                            self.raw_res_head
+                                .headers
+                                .entry(name.clone())
+                                .or_default()
+                                .push_str(line);
+                            Ok(())
+                        } else {
+                            // error if there is no previous header
+                            Err(ResHeadParsingError::Header(HeaderParsingError::NoColon))
+                        }
+                    }
+                }
+            }
+            ResHeadParserState::Done => {
+                panic!("Head parser called when already done")
+            }
+        }
+    }
+
+    /// Return the parsed response (without body) once the line processing is complete.
+    pub fn do_parse(&mut self) -> Result<HttpRes, ResHeadParsingError> {
+        let (version, status_code) = utils::parse_status_line(&self.raw_res_head.status_line)?;
+
+        let mut res = HttpRes::new(&version);
+        res.set_status(status_code);
+        for (name, value) in &self.raw_res_head.headers {
+            let (name, value) = utils::parse_header(name, value)?;
+            res.set_header(name, value);
+        }
+        if !self.raw_res_head.raw_headers.is_empty() {
+            res.set_raw_headers(self.raw_res_head.raw_headers.clone());
+        }
+
+        Ok(res)
+    }
+
+    /// Reset the parser to parse a new response head
+    pub fn reset(&mut self) {
+        self.state = ResHeadParserState::StatusLine;
+        self.raw_res_head = RawResHead::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_header::ResOnlyHeader;
+
+    fn parse_head(lines: &[&str]) -> Result<HttpRes, ResHeadParsingError> {
+        let mut parser = ResHeadParser::new();
+        for line in lines {
+            parser.process_bytes(Vec::from(line.as_bytes()))?;
+        }
+        assert!(parser.is_complete());
+        parser.do_parse()
+    }
+
+    fn ascii(s: &str) -> &ascii::AsciiStr {
+        ascii::AsciiStr::from_ascii(s).unwrap()
+    }
+
+    #[test]
+    fn parse_status_line_test() {
+        assert_eq!(
+            utils::parse_status_line(ascii("HTTP/1.1 200 OK")).unwrap(),
+            (String::from("HTTP/1.1"), 200)
+        );
+        assert_eq!(
+            utils::parse_status_line(ascii("HTTP/1.0 404 Not Found")).unwrap(),
+            (String::from("HTTP/1.0"), 404)
+        );
+        // the reason phrase is optional
+        assert_eq!(
+            utils::parse_status_line(ascii("HTTP/1.1 204")).unwrap(),
+            (String::from("HTTP/1.1"), 204)
+        );
+
+        assert!(matches!(
+            utils::parse_status_line(ascii("HTTP/1.1")),
+            Err(ResHeadParsingError::StatusLine(
+                StatusLineParsingError::InvalidFieldCount
+            ))
+        ));
+        assert!(matches!(
+            utils::parse_status_line(ascii("FOO/1.1 200 OK")),
+            Err(ResHeadParsingError::StatusLine(
+                StatusLineParsingError::InvalidVersion
+            ))
+        ));
+        assert!(matches!(
+            utils::parse_status_line(ascii("HTTP/1.1 2000 OK")),
+            Err(ResHeadParsingError::StatusLine(
+                StatusLineParsingError::InvalidStatusCode
+            ))
+        ));
+    }
+
+    #[test]
+    fn parse_res_head_test() {
+        let mut res = parse_head(&[
+            "HTTP/1.1 301 Moved Permanently\r\n",
+            "Location: /foo\r\n",
+            "Content-Length: 0\r\n",
+            "Vary: Accept\r\n",
+            "vary: Accept-Encoding\r\n",
+            "Set-Cookie: a=b\r\n",
+            "Set-Cookie: c=d\r\n",
+            "X-Folded: foo\r\n",
+            " bar\r\n",
+            "\r\n",
+        ])
+        .unwrap();
+
+        assert_eq!(res.status_code(), 301);
+        assert_eq!(res.version(), "HTTP/1.1");
+        assert_eq!(
+            res.headers()
+                .get(&ResHeader::ResOnly(ResOnlyHeader::Location)),
+            Some(&HeaderValue::Simple(SimpleHeaderValue::String(
+                String::from("/foo")
+            )))
+        );
+        assert_eq!(
+            res.headers()
+                .get(&ResHeader::Entity(EntityHeader::ContentLength)),
+            Some(&HeaderValue::Simple(SimpleHeaderValue::Number(0)))
+        );
+        assert_eq!(
+            res.headers().get(&ResHeader::ResOnly(ResOnlyHeader::Vary)),
+            Some(&HeaderValue::Simple(SimpleHeaderValue::String(
+                String::from("Accept, Accept-Encoding")
+            )))
+        );
+        assert_eq!(
+            res.headers()
+                .get(&ResHeader::Other(String::from("x-folded"))),
+            Some(&HeaderValue::Simple(SimpleHeaderValue::String(
+                String::from("foobar")
+            )))
+        );
+        let bytes = String::from_utf8(res.head_bytes()).unwrap();
+        assert!(bytes.contains("Set-Cookie: a=b\r\nSet-Cookie: c=d\r\n"));
+        assert_eq!(ResBodyLength::of(&mut res, false), ResBodyLength::Fixed(0));
+
+        assert!(matches!(
+            parse_head(&["HTTP/1.1 200 OK\r\n", "Content-Length: foo\r\n", "\r\n"]),
+            Err(ResHeadParsingError::Header(
+                HeaderParsingError::NumberParsing
+            ))
+        ));
+
+        let mut parser = ResHeadParser::new();
+        assert!(matches!(
+            parser.process_bytes(Vec::from(b"\r\n")),
+            Err(ResHeadParsingError::StatusLine(
+                StatusLineParsingError::EmptyLine
+            ))
+        ));
+        parser
+            .process_bytes(Vec::from(b"HTTP/1.1 200 OK\r\n"))
+            .unwrap();
+        assert!(matches!(
+            parser.process_bytes(Vec::from(b"Server : foo\r\n")),
+            Err(ResHeadParsingError::Header(
+                HeaderParsingError::SpaceBeforeColon
+            ))
+        ));
+    }
+
+    #[test]
+    fn res_body_length_test() {
+        let mut res = parse_head(&["HTTP/1.1 200 OK\r\n", "\r\n"]).unwrap();
+        assert_eq!(
+            ResBodyLength::of(&mut res, false),
+            ResBodyLength::UntilClose
+        );
+        assert_eq!(ResBodyLength::of(&mut res, true), ResBodyLength::None);
+
+        let mut res = parse_head(&["HTTP/1.1 304 Not Modified\r\n", "\r\n"]).unwrap();
+        assert_eq!(ResBodyLength::of(&mut res, false), ResBodyLength::None);
+
+        let mut res = parse_head(&[
+            "HTTP/1.1 200 OK\r\n",
+            "Transfer-Encoding: chunked\r\n",
+            "Content-Length: 12\r\n",
+            "\r\n",
+        ])
+        .unwrap();
+        assert_eq!(ResBodyLength::of(&mut res, false), ResBodyLength::Chunked);
+        let mut res = parse_head(&[
+            "HTTP/1.1 200 OK\r\n",
+            "Transfer-Encoding: gzip, Chunked \r\n",
+            "\r\n",
+        ])
+        .unwrap();
+        assert_eq!(ResBodyLength::of(&mut res, false), ResBodyLength::Chunked);
+        for coding in ["notchunked", "xchunked", "chunked, gzip"] {
+            let mut res = parse_head(&[
+                "HTTP/1.1 200 OK\r\n",
+                &format!("Transfer-Encoding: {}\r\n", coding),
+                "Content-Length: 12\r\n",
+                "\r\n",
+            ])
+            .unwrap();
+            assert_eq!(
+                ResBodyLength::of(&mut res, false),
+                ResBodyLength::UntilClose
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn read_res_body_test() {
+        let mut stream: &[u8] = b"hello world";
+        assert_eq!(
+            read_res_body(&mut stream, &ResBodyLength::Fixed(5))
+                .await
+                .unwrap(),
+            b"hello"
+        );
+        assert_eq!(
+            read_res_body(&mut stream, &ResBodyLength::UntilClose)
+                .await
+                .unwrap(),
+            b" world"
+        );
+
+        let mut stream: &[u8] = b"4\r\nWiki\r\n6;ext=1\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\nnext";
+        assert_eq!(
+            read_res_body(&mut stream, &ResBodyLength::Chunked)
+                .await
+                .unwrap(),
+            b"Wikipedia in \r\n\r\nchunks."
+        );
+        // the stream must be left at the end of the chunked body
+        assert_eq!(stream, b"next");
+
+        // chunked bodies are copied with their framing
+        let mut stream: &[u8] = b"4\r\nWiki\r\n0\r\n\r\nnext";
+        let mut copied = Vec::new();
+        assert_eq!(
+            copy_res_body(&mut stream, &mut copied, &ResBodyLength::Chunked)
+                .await
+                .unwrap(),
+            14
+        );
+        assert_eq!(copied, b"4\r\nWiki\r\n0\r\n\r\n");
+        assert_eq!(stream, b"next");
+        // truncated bodies are not copied as complete ones
+        for body_length in [ResBodyLength::Fixed(10), ResBodyLength::Chunked] {
+            let mut stream: &[u8] = b"a\r\nhello";
+            assert!(matches!(
+                copy_res_body(&mut stream, &mut Vec::new(), &body_length).await,
+                Err(ResHeadParsingError::Io(_))
+            ));
+        }
+
+        // or decoded, for protocols delimiting bodies on their own
+        let mut stream: &[u8] =
+            b"4\r\nWiki\r\n6;ext=1\r\npedia \r\n0\r\nExpires: never\r\n\r\nnext";
+        let mut decoded = Vec::new();
+        assert_eq!(
+            decode_res_body(&mut stream, &mut decoded, &ResBodyLength::Chunked)
+                .await
+                .unwrap(),
+            10
+        );
+        assert_eq!(decoded, b"Wikipedia ");
+        assert_eq!(stream, b"next");
+        let mut stream: &[u8] = b"4\r\nWi";
+        assert!(
+            decode_res_body(&mut stream, &mut Vec::new(), &ResBodyLength::Chunked)
+                .await
+                .is_err()
+        );
+
+        let mut stream: &[u8] = b"zz\r\nfoo\r\n0\r\n\r\n";
+        assert!(matches!(
+            read_res_body(&mut stream, &ResBodyLength::Chunked).await,
+            Err(ResHeadParsingError::InvalidChunk)
+        ));
+
+        // announced lengths are not trusted
+        let mut stream: &[u8] = b"hello";
+        assert!(matches!(
+            read_res_body(&mut stream, &ResBodyLength::Fixed(10)).await,
+            Err(ResHeadParsingError::Io(_))
+        ));
+        let mut stream: &[u8] = b"hello";
+        assert!(matches!(
+            read_res_body(&mut stream, &ResBodyLength::Fixed(MAX_BODY_SIZE + 1)).await,
+            Err(ResHeadParsingError::BodyTooLarge)
+        ));
+        for body in [
+            &b"ffffffffffffffff\r\nfoo\r\n0\r\n\r\n"[..],
+            b"3\r\nfoo\r\nfffffffffffffffe\r\nfoo\r\n0\r\n\r\n",
+            b"4000001\r\nfoo\r\n0\r\n\r\n",
+        ] {
+            let mut stream = body;
+            assert!(matches!(
+                read_res_body(&mut stream, &ResBodyLength::Chunked).await,
+                Err(ResHeadParsingError::BodyTooLarge)
+            ));
+        }
+    }
+}
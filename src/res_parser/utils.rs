@@ -0,0 +1,320 @@
+use crate::http_header::{
+    EntityHeader, GeneralHeader, HeaderValue, ResHeader, ResOnlyHeader, SimpleHeaderValue,
+};
+use crate::req_parser::HeaderParsingError;
+use crate::res_parser::{
+    MAX_BODY_SIZE, ResBodyLength, ResHeadParsingError, StatusLineParsingError,
+};
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Parse the status line of an HTTP response (e.g. HTTP/1.1 200 OK).
+///
+/// The reason phrase is ignored, as it is regenerated from the status code when needed.
+pub fn parse_status_line(line: &ascii::AsciiStr) -> Result<(String, u16), ResHeadParsingError> {
+    let mut fields = line.split(ascii::AsciiChar::Space);
+    match (fields.next(), fields.next()) {
+        (Some(version), Some(status_code)) if !status_code.is_empty() => {
+            if !version.as_str().starts_with("HTTP/") {
+                return Err(ResHeadParsingError::StatusLine(
+                    StatusLineParsingError::InvalidVersion,
+                ));
+            }
+            match status_code.as_str().parse::<u16>() {
+                Ok(code) if status_code.len() == 3 && code >= 100 => {
+                    Ok((version.to_string(), code))
+                }
+                _ => Err(ResHeadParsingError::StatusLine(
+                    StatusLineParsingError::InvalidStatusCode,
+                )),
+            }
+        }
+        _ => Err(ResHeadParsingError::StatusLine(
+            StatusLineParsingError::InvalidFieldCount,
+        )),
+    }
+}
+
+// because many headers are not used, exclude this function from coverage
+#[cfg_attr(coverage, coverage(off))]
+pub fn parse_header(
+    name: &ascii::AsciiStr,
+    value: &ascii::AsciiStr,
+) -> Result<(ResHeader, HeaderValue), ResHeadParsingError> {
+    // response header values are kept as plain strings, so that they can be forwarded as is
+    let name = match name.as_bytes() {
+        // general headers
+        b"cache-control" => ResHeader::General(GeneralHeader::CacheControl),
+        b"connection" => ResHeader::General(GeneralHeader::Connection),
+        b"date" => ResHeader::General(GeneralHeader::Date),
+        b"pragma" => ResHeader::General(GeneralHeader::Pragma),
+        b"trailer" => ResHeader::General(GeneralHeader::Trailer),
+        b"transfer-encoding" => ResHeader::General(GeneralHeader::TransferEncoding),
+        b"upgrade" => ResHeader::General(GeneralHeader::Upgrade),
+        b"via" => ResHeader::General(GeneralHeader::Via),
+        b"warning" => ResHeader::General(GeneralHeader::Warning),
+        // res only headers
+        b"accept-ranges" => ResHeader::ResOnly(ResOnlyHeader::AcceptRanges),
+        b"age" => ResHeader::ResOnly(ResOnlyHeader::Age),
+        b"etag" => ResHeader::ResOnly(ResOnlyHeader::ETag),
+        b"location" => ResHeader::ResOnly(ResOnlyHeader::Location),
+        b"proxy-authenticate" => ResHeader::ResOnly(ResOnlyHeader::ProxyAuthenticate),
+        b"retry-after" => ResHeader::ResOnly(ResOnlyHeader::RetryAfter),
+        b"server" => ResHeader::ResOnly(ResOnlyHeader::Server),
+        b"vary" => ResHeader::ResOnly(ResOnlyHeader::Vary),
+        b"www-authenticate" => ResHeader::ResOnly(ResOnlyHeader::WWWAuthenticate),
+        // entity headers
+        b"allow" => ResHeader::Entity(EntityHeader::Allow),
+        b"content-encoding" => ResHeader::Entity(EntityHeader::ContentEncoding),
+        b"content-language" => ResHeader::Entity(EntityHeader::ContentLanguage),
+        b"content-length" => {
+            return Ok((
+                ResHeader::Entity(EntityHeader::ContentLength),
+                HeaderValue::Simple(SimpleHeaderValue::Number(value.as_str().parse().map_err(
+                    |_| ResHeadParsingError::Header(HeaderParsingError::NumberParsing),
+                )?)),
+            ));
+        }
+        b"content-location" => ResHeader::Entity(EntityHeader::ContentLocation),
+        b"content-md5" => ResHeader::Entity(EntityHeader::ContentMD5),
+        b"content-range" => ResHeader::Entity(EntityHeader::ContentRange),
+        b"content-type" => ResHeader::Entity(EntityHeader::ContentType),
+        b"expires" => ResHeader::Entity(EntityHeader::Expires),
+        b"last-modified" => ResHeader::Entity(EntityHeader::LastModified),
+        // other
+        name => ResHeader::Other(
+            ascii::AsciiString::from_ascii(name)
+                .map_err(|e| ResHeadParsingError::Ascii(e.ascii_error()))?
+                .to_string(),
+        ),
+    };
+    Ok((
+        name,
+        HeaderValue::Simple(SimpleHeaderValue::String(value.to_string())),
+    ))
+}
+
+/// Read a response body from a stream, given the way it is delimited.
+///
+/// For chunked bodies, the chunks are concatenated and the trailers are discarded. The body is read
+/// as it comes, without trusting the announced lengths, and cannot exceed `MAX_BODY_SIZE`.
+pub async fn read_res_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    body_length: &ResBodyLength,
+) -> Result<Vec<u8>, ResHeadParsingError> {
+    let mut body = Vec::new();
+    match body_length {
+        ResBodyLength::None => (),
+        ResBodyLength::Fixed(len) => {
+            if *len > MAX_BODY_SIZE {
+                return Err(ResHeadParsingError::BodyTooLarge);
+            }
+            read_exactly(reader, *len, &mut body).await?;
+        }
+        ResBodyLength::UntilClose => {
+            (&mut *reader)
+                .take(MAX_BODY_SIZE as u64 + 1)
+                .read_to_end(&mut body)
+                .await
+                .map_err(ResHeadParsingError::Io)?;
+            if body.len() > MAX_BODY_SIZE {
+                return Err(ResHeadParsingError::BodyTooLarge);
+            }
+        }
+        ResBodyLength::Chunked => loop {
+            let (chunk_len, _) = read_chunk_size(reader).await?;
+            if chunk_len == 0 {
+                skip_trailers(reader).await?;
+                break;
+            }
+            if body
+                .len()
+                .checked_add(chunk_len)
+                .is_none_or(|len| len > MAX_BODY_SIZE)
+            {
+                return Err(ResHeadParsingError::BodyTooLarge);
+            }
+            read_exactly(reader, chunk_len, &mut body).await?;
+            // each chunk is followed by a CRLF
+            let mut crlf = [0; 2];
+            reader
+                .read_exact(&mut crlf)
+                .await
+                .map_err(ResHeadParsingError::Io)?;
+            if &crlf != b"\r\n" {
+                return Err(ResHeadParsingError::InvalidChunk);
+            }
+        },
+    }
+    Ok(body)
+}
+
+/// Append exactly `len` bytes read from a stream to a buffer, which grows as they are received.
+async fn read_exactly<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    len: usize,
+    buf: &mut Vec<u8>,
+) -> Result<(), ResHeadParsingError> {
+    let read = (&mut *reader)
+        .take(len as u64)
+        .read_to_end(buf)
+        .await
+        .map_err(ResHeadParsingError::Io)?;
+    if read != len {
+        return Err(ResHeadParsingError::Io(
+            std::io::ErrorKind::UnexpectedEof.into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Skip the trailers of a chunked body, until the final empty line.
+async fn skip_trailers<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<(), ResHeadParsingError> {
+    loop {
+        let mut line = Vec::new();
+        let n = (&mut *reader)
+            .take(8000)
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(ResHeadParsingError::Io)?;
+        if n == 0 || line.trim_ascii().is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+/// Copy a response body from a stream to another, removing its chunked framing if any.
+///
+/// This is used when the body is forwarded over a protocol which delimits it on its own (HTTP/2
+/// and HTTP/3 frames), the trailers being discarded like in `read_res_body`.
+pub async fn decode_res_body<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    body_length: &ResBodyLength,
+) -> Result<u64, ResHeadParsingError>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    if *body_length != ResBodyLength::Chunked {
+        return copy_res_body(reader, writer, body_length).await;
+    }
+    let mut copied = 0;
+    loop {
+        let (chunk_len, _) = read_chunk_size(reader).await?;
+        if chunk_len == 0 {
+            skip_trailers(reader).await?;
+            return Ok(copied);
+        }
+        let n = tokio::io::copy(&mut (&mut *reader).take(chunk_len as u64), writer)
+            .await
+            .map_err(ResHeadParsingError::Io)?;
+        if n != chunk_len as u64 {
+            return Err(ResHeadParsingError::Io(
+                std::io::ErrorKind::UnexpectedEof.into(),
+            ));
+        }
+        copied += n;
+        // each chunk is followed by a CRLF
+        let mut crlf = [0; 2];
+        reader
+            .read_exact(&mut crlf)
+            .await
+            .map_err(ResHeadParsingError::Io)?;
+        if &crlf != b"\r\n" {
+            return Err(ResHeadParsingError::InvalidChunk);
+        }
+    }
+}
+
+/// Copy a response body from a stream to another, without altering the way it is delimited.
+///
+/// This is used to forward a response body as it is received (e.g. from an upstream server).
+pub async fn copy_res_body<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    body_length: &ResBodyLength,
+) -> Result<u64, ResHeadParsingError>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    match body_length {
+        ResBodyLength::None => Ok(0),
+        ResBodyLength::Fixed(len) => {
+            let n = tokio::io::copy(&mut (&mut *reader).take(*len as u64), writer)
+                .await
+                .map_err(ResHeadParsingError::Io)?;
+            // a truncated body must not pass for a complete one
+            if n != *len as u64 {
+                return Err(ResHeadParsingError::Io(
+                    std::io::ErrorKind::UnexpectedEof.into(),
+                ));
+            }
+            Ok(n)
+        }
+        ResBodyLength::UntilClose => tokio::io::copy(reader, writer)
+            .await
+            .map_err(ResHeadParsingError::Io),
+        ResBodyLength::Chunked => {
+            let mut copied = 0;
+            loop {
+                let (chunk_len, line) = read_chunk_size(reader).await?;
+                writer
+                    .write_all(line.as_bytes())
+                    .await
+                    .map_err(ResHeadParsingError::Io)?;
+                copied += line.len() as u64;
+                if chunk_len == 0 {
+                    // copy trailers and the final empty line
+                    loop {
+                        let mut line = Vec::new();
+                        let n = (&mut *reader)
+                            .take(8000)
+                            .read_until(b'\n', &mut line)
+                            .await
+                            .map_err(ResHeadParsingError::Io)?;
+                        writer
+                            .write_all(&line)
+                            .await
+                            .map_err(ResHeadParsingError::Io)?;
+                        copied += n as u64;
+                        if n == 0 || line.trim_ascii().is_empty() {
+                            return Ok(copied);
+                        }
+                    }
+                }
+                // copy the chunk data and the CRLF that follows it
+                let len = (chunk_len as u64)
+                    .checked_add(2)
+                    .ok_or(ResHeadParsingError::InvalidChunk)?;
+                let n = tokio::io::copy(&mut (&mut *reader).take(len), writer)
+                    .await
+                    .map_err(ResHeadParsingError::Io)?;
+                if n != len {
+                    return Err(ResHeadParsingError::Io(
+                        std::io::ErrorKind::UnexpectedEof.into(),
+                    ));
+                }
+                copied += n;
+            }
+        }
+    }
+}
+
+/// Read a chunk-size line (hexadecimal size, optionally followed by extensions).
+///
+/// Both the chunk size and the raw line are returned.
+pub async fn read_chunk_size<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<(usize, String), ResHeadParsingError> {
+    let mut line = String::new();
+    let mut handle = (&mut *reader).take(1024);
+    handle
+        .read_line(&mut line)
+        .await
+        .map_err(ResHeadParsingError::Io)?;
+    let size = line.split(';').next().unwrap_or_default().trim();
+    let size = usize::from_str_radix(size, 16).map_err(|_| ResHeadParsingError::InvalidChunk)?;
+    Ok((size, line))
+}
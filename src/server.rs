@@ -2,50 +2,175 @@
 //!
 //! Set up a TCP socket and serve incoming requests.
 
+use crate::access::{self, Acl};
+use crate::access_log::{AccessLog, AccessLogger, LogEntry};
+use crate::auth::{Access, AuthScheme, Authenticator, Credentials, Identity, Location, Realm};
+use crate::bearer::{BearerVerifier, JwtKey};
+use crate::credentials;
+use crate::daemon::{self, PidFile};
 use crate::http_header::{
-    EntityHeader, HeaderValue, ReqHeader, ReqOnlyHeader, ResHeader, SimpleHeaderValue,
+    EntityHeader, GeneralHeader, HeaderValue, ReqHeader, ReqOnlyHeader, ResHeader, ResOnlyHeader,
+    SimpleHeaderValue,
 };
-use crate::http_req::{HttpReq, ReqBody, ReqPath, ReqTarget, ReqVerb};
+use crate::http_req::{HttpReq, ReqBody, ReqHead, ReqPath, ReqTarget, ReqVerb};
 use crate::http_res::{HttpRes, ResBody};
+use crate::https::{Hsts, HttpsRedirect};
+use crate::listener::{self, BoundListener, Connection, Listener};
+use crate::metrics::{self, Handler, Metrics, MetricsEndpoint};
+use crate::proxy::{Forwarded, ProxyError, ProxyRoute, ReverseProxy};
+use crate::rate_limit::{Rate, RateLimit, RateLimiter};
 use crate::req_parser::{ReqHeadParser, ReqHeadParsingError};
 use crate::res_builder::{PhpScriptParams, ResBuilder};
-use crate::{req_parser, utils};
+use crate::session::{self, LoginSettings};
+use crate::signed_url::UrlSigner;
+use crate::status::{ConnectionSlot, ServerStatus};
+use crate::tls::{ClientAuth, ClientCert, KeyPassword, TlsHost, TlsPolicy, TlsSession};
+use crate::utils::IpNetwork;
+use crate::websocket::{self, HandshakeError, WebSocket};
+use crate::{http2, http3, https, proxy, proxy_protocol, req_parser, tls, utils};
 
-use std::{collections, fmt, io, net, path, sync, vec};
+use std::{collections, fmt, io, net, path, sync, time, vec};
 
 use log::{debug, error, info, warn};
-use rustls::pki_types::pem::PemObject;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 
 /// Server settings, typically populated from CLI or configuration file.
 #[derive(Debug, Clone)]
 pub struct Settings {
-    /// Socket address to bind the server to
-    pub address: net::SocketAddr,
+    /// Listeners to bind the server to (TCP or Unix domain sockets, possibly passed by systemd),
+    /// each serving either plain HTTP or HTTPS
+    pub listeners: Vec<Listener>,
+    /// Run as this user (name or ID) once the listeners are bound, e.g. on privileged ports
+    pub user: Option<String>,
+    /// Run as this group (name or ID) once the listeners are bound, instead of the primary group
+    /// of the user
+    pub group: Option<String>,
+    /// File to write the process ID to, removed when the server is dropped
+    pub pid_file: Option<path::PathBuf>,
     /// Directory served as the root of the server (i.e. / in HTTP requests will be this dir, and so on)
     pub document_root: path::PathBuf,
     /// Allow clients to request for directory, and to be presented a list of elements in that directory
     pub allow_dir_listing: bool,
-    /// TLS certificate and key for HTTPS. If one of them is missing, the server will fall back to HTTP instead
+    /// TLS certificate and key for HTTPS. The server refuses to start if only one of them is provided, or if they
+    /// cannot be loaded (unless `allow_plain_http_fallback` is set)
     pub ssl_cert_path: Option<path::PathBuf>,
     pub ssl_key_path: Option<path::PathBuf>,
+    /// Password of the private keys, when they are encrypted (PKCS#8)
+    pub ssl_key_password: Option<KeyPassword>,
+    /// Serve plain HTTP when the TLS configuration is incomplete or invalid, instead of failing
+    pub allow_plain_http_fallback: bool,
+    /// Protocol versions, cipher suites and key exchange groups accepted for HTTPS
+    pub tls_policy: TlsPolicy,
+    /// Also listen for plain HTTP requests on another address, redirecting them to HTTPS (i.e. to
+    /// the first TCP listener serving HTTPS)
+    pub https_redirect: Option<HttpsRedirect>,
+    /// Strict-Transport-Security header sent with HTTPS responses
+    pub hsts: Option<Hsts>,
+    /// Proxies whose Forwarded / X-Forwarded-For headers are trusted to get the address of the
+    /// clients (e.g. for logging and PHP scripts)
+    pub trusted_proxies: Vec<IpNetwork>,
+    /// Rate limits of the requests, keyed by client IP address, authenticated user or path prefix
+    pub rate_limits: Vec<RateLimit>,
+    /// Rate of the failed authentications of a client IP address, above which its authentication
+    /// attempts are refused
+    pub auth_failure_rate: Option<Rate>,
+    /// Access control lists, denying access to some clients (by IP address) to the whole server, a
+    /// virtual host or a path prefix
+    pub acls: Vec<Acl>,
+    /// Certificates presented to clients requesting some host names (SNI), instead of the default one above
+    pub tls_hosts: Vec<TlsHost>,
+    /// Authenticate clients with certificates (mutual TLS), which requires HTTPS
+    pub client_auth: Option<ClientAuth>,
     /// Authentication credentials to use for basic HTTP authentication.
     /// When some credentials are provided, the server will require client to be authenticated before accessing any resource.
     /// This parameter can be a list of (username, password) credentials, any of which granting access to the server.
     pub authentication_credentials: Option<Vec<(String, String)>>,
+    /// htpasswd file whose users (with hashed passwords) are granted access as well, reloaded when
+    /// it changes
+    pub authentication_file: Option<path::PathBuf>,
+    /// Locations (path prefixes) protected by their own realm, or left public
+    pub authentication_locations: Vec<Location>,
+    /// htgroup file of the groups the authorization rules of the realms refer to, reloaded when it
+    /// changes
+    pub authentication_groups_file: Option<path::PathBuf>,
+    /// Schemes offered to clients to authenticate, in order of preference (the forward proxy only
+    /// offers the Basic scheme)
+    pub authentication_schemes: Vec<AuthScheme>,
+    /// File of the static tokens accepted with the Bearer scheme (with the name of the client they
+    /// identify), reloaded when it changes
+    pub bearer_tokens_file: Option<path::PathBuf>,
+    /// Keys verifying the JSON Web Tokens accepted with the Bearer scheme
+    pub jwt_keys: Vec<JwtKey>,
+    /// Issuer (`iss` claim) required from the JSON Web Tokens, if any
+    pub jwt_issuer: Option<String>,
+    /// Audience (`aud` claim) required from the JSON Web Tokens, if any
+    pub jwt_audience: Option<String>,
+    /// Login form opening sessions (kept in a cookie) for the users of the server credentials, if
+    /// any
+    pub login: Option<LoginSettings>,
+    /// File of the secret signing the URLs granting access to a path without credentials, if any
+    pub url_secret: Option<path::PathBuf>,
     pub php_cgi_binary: String,
+    /// Reverse proxy routes, forwarding matching requests to upstream servers
+    pub proxy_routes: Vec<ProxyRoute>,
+    /// Act as a forward proxy, i.e. accept requests with an absolute URI and CONNECT requests.
+    /// Authentication credentials (if any) are then required from proxy clients as well.
+    pub forward_proxy: bool,
+    /// Also listen for HTTP/3 requests (QUIC), on the UDP port matching the first TCP listener
+    /// serving HTTPS. The same certificate and key are used.
+    pub http3: bool,
+    /// Check the TLS certificate files for changes at this interval, and reload them when they
+    /// change (reloading also happens on SIGHUP)
+    pub tls_reload_interval: Option<time::Duration>,
+    /// Access logs of the requests served, possibly per virtual host (without any, the requests
+    /// are logged in the Common Log Format with the other messages)
+    pub access_logs: Vec<AccessLog>,
+    /// Expose the metrics of the server in the Prometheus text format, on a path or on their own
+    /// listener
+    pub metrics: Option<MetricsEndpoint>,
+    /// Path of the status page of the server (active connections and their current request), in
+    /// HTML or in JSON, subject to its access control and authentication
+    pub server_status: Option<String>,
 }
 
 pub struct Server {
-    listener: tokio::net::TcpListener,
+    listeners: Vec<BoundListener>,
+    /// Plain HTTP listener redirecting requests to HTTPS
+    redirect_listener: Option<tokio::net::TcpListener>,
+    /// Plain HTTP listener only serving the metrics
+    metrics_listener: Option<tokio::net::TcpListener>,
     settings: Settings,
-    tls_acceptor: Option<tokio_rustls::TlsAcceptor>,
+    state: sync::Arc<ServerState>,
+    quic_endpoint: Option<quinn::Endpoint>,
+    _pid_file: Option<PidFile>,
+}
+
+/// Runtime state shared by all the connections of a server.
+struct ServerState {
+    reverse_proxy: ReverseProxy,
+    /// WebSocket handlers, by request path
+    websocket_handlers:
+        sync::RwLock<collections::HashMap<String, sync::Arc<dyn websocket::Handler>>>,
+    /// Alt-Svc header value advertising the HTTP/3 listener, if any
+    alt_svc: Option<String>,
+    /// Acceptor of the TLS connections (with HTTPS), replaced when the certificates are reloaded
+    tls_acceptor: Option<sync::RwLock<tokio_rustls::TlsAcceptor>>,
+    rate_limiter: RateLimiter,
+    /// Realms and credentials granting access to the server
+    auth: Authenticator,
+    /// Verifier of the signed URLs, if any
+    url_signer: Option<UrlSigner>,
+    access_logger: AccessLogger,
+    metrics: sync::Arc<Metrics>,
+    status: sync::Arc<ServerStatus>,
 }
 
 pub enum Error {
     Io(io::Error),
-    Tls(rustls::Error),
-    TlsPem(rustls::pki_types::pem::Error),
+    Tls(tls::Error),
+    Credentials(credentials::Error),
+    /// No listener is configured
+    NoListener,
 }
 
 /// This error is meant to be displayed to the user in case of server failure, hence the Display trait.
@@ -55,104 +180,646 @@ impl fmt::Display for Error {
         match self {
             Self::Io(e) => write!(f, "I/O error: {}", e),
             Self::Tls(e) => write!(f, "TLS error: {}", e),
-            Self::TlsPem(e) => write!(f, "TLS Pem error: {}", e),
+            Self::Credentials(e) => write!(f, "credentials error: {}", e),
+            Self::NoListener => write!(f, "no address to listen on"),
         }
     }
 }
 
+impl Settings {
+    /// Socket address of the first TCP listener serving HTTPS, if any.
+    pub(crate) fn https_address(&self) -> Option<net::SocketAddr> {
+        self.listeners
+            .iter()
+            .filter(|listener| listener.tls)
+            .find_map(Listener::socket_address)
+    }
+}
+
 impl Server {
     /// Create a new server instance, based on the provided settings.
     ///
-    /// This function will attempt to bind a socket to the provided socket address, and may fail if
-    /// the socket cannot be bound.
+    /// This function will attempt to bind the sockets of all the listeners, and may fail if one of
+    /// them cannot be bound.
     pub async fn new(settings: Settings) -> Result<Self, Error> {
-        let listener = tokio::net::TcpListener::bind(settings.address)
-            .await
-            .map_err(Error::Io)?;
-        if let Some(cert_path) = settings.ssl_cert_path.as_ref()
-            && let Some(key_path) = settings.ssl_key_path.as_ref()
-        {
-            let certs = rustls::pki_types::CertificateDer::pem_file_iter(cert_path)
-                .map_err(Error::TlsPem)?
-                .collect::<Result<Vec<_>, _>>()
-                .map_err(Error::TlsPem)?;
-            let key =
-                rustls::pki_types::PrivateKeyDer::from_pem_file(key_path).map_err(Error::TlsPem)?;
-            let config = rustls::ServerConfig::builder()
-                .with_no_client_auth()
-                .with_single_cert(certs, key)
-                .map_err(Error::Tls)?;
-            Ok(Self {
-                listener,
-                settings,
-                tls_acceptor: Some(tokio_rustls::TlsAcceptor::from(sync::Arc::new(config))),
-            })
-        } else {
-            Ok(Self {
-                listener,
-                settings,
-                tls_acceptor: None,
-            })
+        if settings.listeners.is_empty() {
+            return Err(Error::NoListener);
         }
+        // sockets may be bound by systemd (socket activation), instead of the server
+        let mut inherited = listener::inherited_sockets().map_err(Error::Io)?;
+        let mut listeners = Vec::new();
+        for listener in &settings.listeners {
+            listeners.extend(listener.bind(&mut inherited).map_err(Error::Io)?);
+        }
+        if !inherited.is_empty() {
+            warn!("{} socket(s) passed by systemd not used", inherited.len());
+        }
+        let serves_https = settings.listeners.iter().any(|listener| listener.tls);
+        let tls_config = match build_tls_config(&settings) {
+            Ok(None) if serves_https => Err(Error::Tls(tls::Error::TlsListenerWithoutCertificate)),
+            result => result,
+        };
+        let tls_config = match tls_config {
+            Err(err) if settings.allow_plain_http_fallback => {
+                warn!(
+                    "Invalid TLS configuration, falling back to plain HTTP: {}",
+                    err
+                );
+                None
+            }
+            result => result?,
+        };
+        let https_address = settings.https_address().filter(|_| tls_config.is_some());
+        let (quic_endpoint, alt_svc) = match (tls_config.as_ref(), https_address, settings.http3) {
+            (Some(tls_config), Some(address), true) => (
+                Some(http3::bind(address, tls_config.clone()).map_err(Error::Io)?),
+                Some(http3::alt_svc(address.port())),
+            ),
+            (_, _, true) => {
+                warn!("HTTP/3 requires HTTPS, only listening for HTTP/1.1 and HTTP/2 requests");
+                (None, None)
+            }
+            (_, _, false) => (None, None),
+        };
+        let redirect_listener = match (https_address, settings.https_redirect.as_ref()) {
+            (Some(_), Some(redirect)) => Some(
+                tokio::net::TcpListener::bind(redirect.address)
+                    .await
+                    .map_err(Error::Io)?,
+            ),
+            (None, Some(_)) => {
+                warn!("Redirecting to HTTPS requires HTTPS, not listening for plain HTTP requests");
+                None
+            }
+            (_, None) => None,
+        };
+        let metrics_listener = match settings.metrics.as_ref() {
+            Some(MetricsEndpoint::Listener(address)) => Some(
+                tokio::net::TcpListener::bind(address)
+                    .await
+                    .map_err(Error::Io)?,
+            ),
+            _ => None,
+        };
+        let state = ServerState {
+            reverse_proxy: ReverseProxy::new(&settings.proxy_routes),
+            websocket_handlers: sync::RwLock::new(collections::HashMap::new()),
+            alt_svc,
+            tls_acceptor: tls_config.map(|tls_config| sync::RwLock::new(tls_acceptor(tls_config))),
+            rate_limiter: RateLimiter::new(
+                settings.rate_limits.clone(),
+                settings.auth_failure_rate.clone(),
+            ),
+            auth: Authenticator::new(
+                settings.authentication_credentials.as_deref(),
+                settings.authentication_file.as_deref(),
+                &settings.authentication_locations,
+                settings.authentication_groups_file.as_deref(),
+                &settings.authentication_schemes,
+                BearerVerifier::new(
+                    settings.bearer_tokens_file.as_deref(),
+                    &settings.jwt_keys,
+                    settings.jwt_issuer.as_deref(),
+                    settings.jwt_audience.as_deref(),
+                )
+                .map_err(Error::Credentials)?,
+                settings.login.as_ref(),
+            )
+            .map_err(Error::Credentials)?,
+            url_signer: settings
+                .url_secret
+                .as_deref()
+                .map(UrlSigner::load)
+                .transpose()
+                .map_err(Error::Credentials)?,
+            access_logger: AccessLogger::new(&settings.access_logs).map_err(Error::Io)?,
+            metrics: sync::Arc::new(Metrics::new()),
+            status: sync::Arc::new(ServerStatus::new()),
+        };
+        let pid_file = settings
+            .pid_file
+            .as_deref()
+            .map(PidFile::create)
+            .transpose()
+            .map_err(Error::Io)?;
+        // privileges are only needed to bind the sockets, and to read the TLS files
+        daemon::drop_privileges(settings.user.as_deref(), settings.group.as_deref())
+            .map_err(Error::Io)?;
+        Ok(Self {
+            listeners,
+            redirect_listener,
+            metrics_listener,
+            settings,
+            state: sync::Arc::new(state),
+            quic_endpoint,
+            _pid_file: pid_file,
+        })
+    }
+
+    /// Register a handler for the WebSocket connections opened on a path.
+    ///
+    /// Upgrade requests made on that path are answered with 101 Switching Protocols (if the
+    /// handshake is valid), and the connection is then handed over to the handler.
+    pub fn add_websocket_handler(&self, path: &str, handler: sync::Arc<dyn websocket::Handler>) {
+        self.state
+            .websocket_handlers
+            .write()
+            .unwrap()
+            .insert(String::from(path), handler);
+    }
+
+    /// Status of the server, also used to drain it.
+    pub fn status(&self) -> sync::Arc<ServerStatus> {
+        self.state.status.clone()
     }
 
-    /// Start the server, i.e. make it listening for requests on the socket.
+    /// Start the server, i.e. make it listening for requests on the socket(s), until it is drained
+    /// (the connections still open being served in the background).
     pub async fn listen(&mut self) {
+        let settings = self.settings.clone();
+        let state = self.state.clone();
+        let quic_endpoint = self.quic_endpoint.clone();
+        let listen_quic = async {
+            if let Some(endpoint) = quic_endpoint.clone() {
+                listen_quic(endpoint, settings.clone(), state.clone()).await;
+            }
+        };
+        let listen_redirect = async {
+            if let Some(listener) = self.redirect_listener.as_ref() {
+                listen_redirect(listener, &settings, &state).await;
+            }
+        };
+        let listen_metrics = async {
+            if let Some(listener) = self.metrics_listener.as_ref() {
+                listen_metrics(listener, &settings, &state).await;
+            }
+        };
+        let mut draining = state.status.draining();
+        tokio::select! {
+            _ = async {
+                tokio::join!(
+                    self.listen_streams(),
+                    listen_redirect,
+                    listen_metrics,
+                    listen_quic,
+                    reload_tls(&settings, &state, quic_endpoint.as_ref())
+                )
+            } => {}
+            _ = draining.wait_for(|draining| *draining) => {
+                info!("Draining the server, not accepting connections anymore");
+            }
+        }
+    }
+
+    async fn listen_streams(&self) {
         // accept connections and process them concurrently
         loop {
-            match self.listener.accept().await {
-                Ok((stream, peer_addr)) => {
-                    let settings = self.settings.clone();
-
-                    if let Some(acceptor) = self.tls_acceptor.as_ref() {
-                        match acceptor.accept(stream).await {
-                            Ok(stream) => {
-                                tokio::spawn(async move {
-                                    let mut handler =
-                                        ClientHandler::new(settings, peer_addr.to_string(), stream);
-                                    handler.handle().await;
-                                });
-                            }
-                            Err(err) => {
-                                warn!("Cannot accept TLS connection: {}", err);
-                                continue;
-                            }
+            let (listener, result) = listener::accept(&self.listeners).await;
+            let settings = self.settings.clone();
+            let state = self.state.clone();
+            let (tls, proxy_protocol) = (listener.tls, listener.proxy_protocol);
+            match result {
+                Ok(Connection::Tcp(stream, peer_addr, local_addr)) => {
+                    tokio::spawn(serve_stream(
+                        settings,
+                        state,
+                        peer_addr.to_string(),
+                        Some(local_addr),
+                        tls,
+                        proxy_protocol,
+                        stream,
+                    ));
+                }
+                Ok(Connection::Unix(stream, peer_addr)) => {
+                    tokio::spawn(serve_stream(
+                        settings,
+                        state,
+                        peer_addr,
+                        None,
+                        tls,
+                        proxy_protocol,
+                        stream,
+                    ));
+                }
+                Err(err) => error!("Cannot accept connection, {:?}", err),
+            }
+        }
+    }
+}
+
+/// Serve a connection accepted by a listener, over TLS if the listener serves HTTPS.
+///
+/// Behind a proxy using the PROXY protocol, the addresses of the connection are replaced by the
+/// original ones sent by the proxy.
+async fn serve_stream<S: AsyncStream + 'static>(
+    settings: Settings,
+    state: sync::Arc<ServerState>,
+    mut peer_addr: String,
+    mut local_addr: Option<net::SocketAddr>,
+    tls: bool,
+    proxy_protocol: bool,
+    mut stream: S,
+) {
+    let _connection = state.metrics.open_connection();
+    if proxy_protocol {
+        match proxy_protocol::read_header(&mut stream).await {
+            Ok(Some(addrs)) => {
+                debug!(
+                    "proxied connection from {} to {}",
+                    addrs.source, addrs.destination
+                );
+                peer_addr = addrs.source.to_string();
+                local_addr = Some(addrs.destination);
+            }
+            Ok(None) => (),
+            Err(err) => {
+                warn!(
+                    "Cannot read PROXY protocol header from {}: {}",
+                    peer_addr, err
+                );
+                return;
+            }
+        }
+    }
+
+    // the acceptor may be replaced while the connection is established
+    let tls_acceptor = state
+        .tls_acceptor
+        .as_ref()
+        .filter(|_| tls)
+        .map(|acceptor| acceptor.read().unwrap().clone());
+
+    if let Some(acceptor) = tls_acceptor {
+        match acceptor.accept(stream).await {
+            Ok(stream) => {
+                let client_cert = ClientCert::from_chain(stream.get_ref().1.peer_certificates());
+                let tls_session = TlsSession::of(stream.get_ref().1);
+                if stream.get_ref().1.alpn_protocol() == Some(http2::ALPN_H2) {
+                    serve_http2(
+                        settings,
+                        state,
+                        peer_addr,
+                        local_addr,
+                        client_cert,
+                        tls_session,
+                        stream,
+                    )
+                    .await;
+                } else {
+                    let mut handler = ClientHandler::new(
+                        settings,
+                        state,
+                        peer_addr,
+                        local_addr,
+                        client_cert,
+                        stream,
+                    );
+                    handler.tls_session = tls_session;
+                    handler.handle().await;
+                }
+            }
+            Err(err) => {
+                warn!("Cannot accept TLS connection: {}", err);
+                state.metrics.tls_handshake_failed();
+            }
+        }
+    } else {
+        // clients may start with HTTP/2 directly (h2c with prior knowledge)
+        let (is_h2, stream) = http2::detect_preface(stream).await;
+        if is_h2 {
+            serve_http2(settings, state, peer_addr, local_addr, None, None, stream).await;
+        } else {
+            let mut handler =
+                ClientHandler::new(settings, state, peer_addr, local_addr, None, stream);
+            handler.handle().await;
+        }
+    }
+}
+
+/// Accept plain HTTP connections, and answer all their requests with a redirect to HTTPS.
+async fn listen_redirect(
+    listener: &tokio::net::TcpListener,
+    settings: &Settings,
+    state: &sync::Arc<ServerState>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer_addr)) => {
+                let mut handler = ClientHandler::new(
+                    settings.clone(),
+                    state.clone(),
+                    peer_addr.to_string(),
+                    stream.local_addr().ok(),
+                    None,
+                    stream,
+                );
+                handler.redirect_to_https = true;
+                let connection = state.metrics.open_connection();
+                tokio::spawn(async move {
+                    handler.handle().await;
+                    drop(connection);
+                });
+            }
+            Err(err) => error!("Cannot accept TCP connection, {:?}", err),
+        }
+    }
+}
+
+/// Accept plain HTTP connections, and only serve the metrics to them.
+async fn listen_metrics(
+    listener: &tokio::net::TcpListener,
+    settings: &Settings,
+    state: &sync::Arc<ServerState>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer_addr)) => {
+                let mut handler = ClientHandler::new(
+                    settings.clone(),
+                    state.clone(),
+                    peer_addr.to_string(),
+                    stream.local_addr().ok(),
+                    None,
+                    stream,
+                );
+                handler.metrics_only = true;
+                tokio::spawn(async move { handler.handle().await });
+            }
+            Err(err) => error!("Cannot accept TCP connection, {:?}", err),
+        }
+    }
+}
+
+/// Build the TLS configuration of the server, from the certificate files (if HTTPS is enabled).
+///
+/// The ALPN protocols are left empty, as they depend on the listener (TCP or QUIC).
+fn build_tls_config(settings: &Settings) -> Result<Option<rustls::ServerConfig>, Error> {
+    let (cert_path, key_path) = match (
+        settings.ssl_cert_path.as_ref(),
+        settings.ssl_key_path.as_ref(),
+    ) {
+        (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+        (Some(_), None) | (None, Some(_)) => {
+            return Err(Error::Tls(tls::Error::IncompleteConfig));
+        }
+        (None, None) if !settings.tls_hosts.is_empty() => {
+            return Err(Error::Tls(tls::Error::NoDefaultCertificate));
+        }
+        (None, None) if settings.client_auth.is_some() => {
+            return Err(Error::Tls(tls::Error::ClientAuthWithoutTls));
+        }
+        (None, None) => return Ok(None),
+    };
+    let builder = tls::server_config_builder(&settings.tls_policy).map_err(Error::Tls)?;
+    let provider = builder.crypto_provider().clone();
+    let key_password = settings.ssl_key_password.as_ref();
+    // the certificate is selected with the server name requested by the client (SNI)
+    let default_key = tls::load_certified_key(cert_path, key_path, key_password, &provider)
+        .map_err(Error::Tls)?;
+    let resolver = tls::SniResolver::new(default_key, &settings.tls_hosts, key_password, &provider)
+        .map_err(Error::Tls)?;
+    let verifier = match settings.client_auth.as_ref() {
+        Some(client_auth) => tls::client_verifier(client_auth, &provider).map_err(Error::Tls)?,
+        None => rustls::server::WebPkiClientVerifier::no_client_auth(),
+    };
+    Ok(Some(
+        builder
+            .with_client_cert_verifier(verifier)
+            .with_cert_resolver(sync::Arc::new(resolver)),
+    ))
+}
+
+/// Create the acceptor of the TLS connections made over TCP.
+fn tls_acceptor(mut tls_config: rustls::ServerConfig) -> tokio_rustls::TlsAcceptor {
+    // prefer HTTP/2 when the client supports it
+    tls_config.alpn_protocols = vec![Vec::from(http2::ALPN_H2), Vec::from(http2::ALPN_HTTP1)];
+    tokio_rustls::TlsAcceptor::from(sync::Arc::new(tls_config))
+}
+
+/// Reload the TLS configuration when the certificate files change, or when SIGHUP is received.
+///
+/// New connections use the reloaded configuration, while established ones are left untouched. An
+/// invalid configuration is rejected, the previous one being kept.
+async fn reload_tls(
+    settings: &Settings,
+    state: &ServerState,
+    quic_endpoint: Option<&quinn::Endpoint>,
+) {
+    let Some(acceptor) = state.tls_acceptor.as_ref() else {
+        return;
+    };
+    let mut watcher = utils::FileWatcher::new(tls_files(settings));
+    let mut interval = settings.tls_reload_interval.map(tokio::time::interval);
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .inspect_err(|err| warn!("Cannot handle SIGHUP, {:?}", err))
+        .ok();
+    loop {
+        tokio::select! {
+            Some(()) = async { sighup.as_mut()?.recv().await } => {
+                info!("SIGHUP received, reloading the TLS configuration");
+                watcher.has_changed();
+            }
+            Some(()) = async { interval.as_mut()?.tick().await; Some(()) } => {
+                if !watcher.has_changed() {
+                    continue;
+                }
+                info!("TLS certificate files changed, reloading the TLS configuration");
+            }
+            else => break,
+        }
+
+        match build_tls_config(settings) {
+            Ok(Some(tls_config)) => {
+                if let Some(endpoint) = quic_endpoint {
+                    match http3::server_config(tls_config.clone()) {
+                        Ok(quic_config) => endpoint.set_server_config(Some(quic_config)),
+                        Err(err) => {
+                            error!("Cannot reload the QUIC configuration: {:?}", err);
+                            continue;
                         }
-                    } else {
-                        tokio::spawn(async move {
-                            let mut handler =
-                                ClientHandler::new(settings, peer_addr.to_string(), stream);
-                            handler.handle().await;
-                        });
                     }
                 }
-                Err(err) => error!("Cannot accept TCP connection, {:?}", err),
+                *acceptor.write().unwrap() = tls_acceptor(tls_config);
+                info!("TLS configuration reloaded");
+            }
+            Ok(None) => (),
+            Err(err) => {
+                error!(
+                    "Cannot reload the TLS configuration, keeping the current one: {}",
+                    err
+                )
             }
         }
     }
 }
 
-/// Define a wrapper trait for a TCP stream
-trait AsyncStream: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin {}
+/// Files the TLS configuration is loaded from.
+fn tls_files(settings: &Settings) -> Vec<path::PathBuf> {
+    let mut files = Vec::new();
+    files.extend(settings.ssl_cert_path.iter().cloned());
+    files.extend(settings.ssl_key_path.iter().cloned());
+    for tls_host in &settings.tls_hosts {
+        files.push(tls_host.cert_path.clone());
+        files.push(tls_host.key_path.clone());
+    }
+    if let Some(client_auth) = settings.client_auth.as_ref() {
+        files.push(client_auth.ca_path.clone());
+        files.extend(client_auth.crl_path.iter().cloned());
+    }
+    files
+}
+
+/// Accept QUIC connections and serve their HTTP/3 requests concurrently.
+async fn listen_quic(endpoint: quinn::Endpoint, settings: Settings, state: sync::Arc<ServerState>) {
+    let local_addr = endpoint.local_addr().ok();
+    while let Some(incoming) = endpoint.accept().await {
+        let settings = settings.clone();
+        let state = state.clone();
+        tokio::spawn(async move {
+            let _connection = state.metrics.open_connection();
+            let peer_addr = incoming.remote_address().to_string();
+            info!("HTTP/3 connection received from: {}", peer_addr);
+            let result = http3::serve_connection(incoming, |local, client_cert| {
+                let mut handler = ClientHandler::new(
+                    settings.clone(),
+                    state.clone(),
+                    peer_addr.clone(),
+                    local_addr,
+                    client_cert,
+                    local,
+                );
+                handler.tls_session = Some(TlsSession::QUIC);
+                async move { handler.handle().await }
+            })
+            .await;
+            if let Err(err) = result {
+                warn!("HTTP/3 connection error: {}", err);
+            }
+            info!("HTTP/3 connection closed: {}", peer_addr);
+        });
+    }
+}
+
+/// Define a wrapper trait for a TCP (or Unix domain socket) stream
+trait AsyncStream: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send {
+    /// Whether the stream is encrypted (i.e. the client uses HTTPS)
+    fn is_secure(&self) -> bool {
+        false
+    }
+}
 impl AsyncStream for tokio::net::TcpStream {}
-impl AsyncStream for tokio_rustls::server::TlsStream<tokio::net::TcpStream> {}
+impl AsyncStream for tokio::net::UnixStream {}
+impl<S: AsyncStream> AsyncStream for tokio_rustls::server::TlsStream<S> {
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+impl<S: AsyncStream> AsyncStream for http2::Prefixed<S> {
+    fn is_secure(&self) -> bool {
+        self.get_ref().is_secure()
+    }
+}
+impl AsyncStream for http2::LocalStream {
+    fn is_secure(&self) -> bool {
+        self.secure
+    }
+}
+
+/// Serve an HTTP/2 connection, each stream being handled like an HTTP/1.1 request.
+async fn serve_http2<S: AsyncStream>(
+    settings: Settings,
+    state: sync::Arc<ServerState>,
+    peer_addr: String,
+    local_addr: Option<net::SocketAddr>,
+    client_cert: Option<sync::Arc<ClientCert>>,
+    tls_session: Option<TlsSession>,
+    stream: S,
+) {
+    info!("HTTP/2 connection received from: {}", peer_addr);
+    let secure = stream.is_secure();
+    let result = http2::serve_connection(stream, secure, |local| {
+        let mut handler = ClientHandler::new(
+            settings.clone(),
+            state.clone(),
+            peer_addr.clone(),
+            local_addr,
+            client_cert.clone(),
+            local,
+        );
+        handler.tls_session = tls_session;
+        async move { handler.handle().await }
+    })
+    .await;
+    if let Err(err) = result {
+        warn!("HTTP/2 connection error: {}", err);
+    }
+    info!("HTTP/2 connection closed: {}", peer_addr);
+}
 
 /// A client handler is responsible for handling an HTTP connection, once received by the server.
 struct ClientHandler<S: AsyncStream> {
     settings: Settings,
+    state: sync::Arc<ServerState>,
     stream: S,
     peer_addr: String,
+    /// Socket address of the server the client is connected to (none for Unix domain sockets)
+    local_addr: Option<net::SocketAddr>,
+    /// Verified certificate of the client (mutual TLS)
+    client_cert: Option<sync::Arc<ClientCert>>,
+    /// Answer every request with a redirect to HTTPS
+    redirect_to_https: bool,
+    /// Only serve the metrics (on their own listener)
+    metrics_only: bool,
+    /// TLS session of the connection, if any (for the access logs)
+    tls_session: Option<TlsSession>,
     current_req: Option<HttpReq>,
+    /// What served the current request (for the metrics)
+    handler: Handler,
+    /// Slot of the connection in the status of the server
+    slot: ConnectionSlot,
 }
 
 impl<S: AsyncStream> ClientHandler<S> {
     /// Create a new client handler, from an established stream of communication.
-    fn new(settings: Settings, peer_addr: String, stream: S) -> Self {
+    fn new(
+        settings: Settings,
+        state: sync::Arc<ServerState>,
+        peer_addr: String,
+        local_addr: Option<net::SocketAddr>,
+        client_cert: Option<sync::Arc<ClientCert>>,
+        stream: S,
+    ) -> Self {
+        let slot = state.status.open(&peer_addr);
         Self {
             settings,
+            state,
             stream,
             peer_addr,
+            local_addr,
+            client_cert,
+            redirect_to_https: false,
+            metrics_only: false,
+            tls_session: None,
             current_req: None,
+            handler: Handler::Other,
+            slot,
+        }
+    }
+
+    /// Check whether the client certificate (if any) grants access to the server.
+    fn is_cert_authorized(&self) -> bool {
+        self.settings
+            .client_auth
+            .as_ref()
+            .is_none_or(|client_auth| client_auth.is_authorized(self.client_cert.as_deref()))
+    }
+
+    /// Address of the client of the current request: the one reported by a trusted proxy if any,
+    /// otherwise the IP address of the peer.
+    fn remote_addr(&self) -> String {
+        match self.current_req.as_ref().and_then(HttpReq::client_ip) {
+            Some(ip) => ip.to_string(),
+            None => String::from(client_ip(&self.peer_addr)),
         }
     }
 
@@ -161,26 +828,54 @@ impl<S: AsyncStream> ClientHandler<S> {
         info!("Connection received from: {}", self.peer_addr);
 
         let mut req_head_parser = ReqHeadParser::new();
+        let client_ip = String::from(client_ip(&self.peer_addr));
+        let proto = if self.stream.is_secure() {
+            "https"
+        } else {
+            "http"
+        };
+
+        // the client certificate is the same for all the requests of the connection
+        let is_cert_authorized = self.is_cert_authorized();
+        let redirect_to_https = self.redirect_to_https;
+        let metrics_only = self.metrics_only;
+        let client_cert = self.client_cert.clone();
+        let trusted_proxies = self.settings.trusted_proxies.clone();
+        let peer_ip = client_ip.parse().ok();
 
         let mut connection_closed = false;
         let mut req_parsing_error = Ok(());
+        // idle connections are closed when the server is drained
+        let mut draining = self.state.status.draining();
 
         while !connection_closed {
             req_head_parser.reset();
+            self.current_req = None;
+            self.handler = Handler::Other;
 
             // use a buffered reader to read the stream one line at a time
             let mut buf_reader = tokio::io::BufReader::new(&mut self.stream);
 
             debug!("waiting for request head");
+            let mut is_idle = true;
             while !req_head_parser.is_complete() {
                 // read one line from the stream
                 // with a maximum limit on bytes read (8000)
                 let mut line: Vec<u8> = Vec::new();
                 let mut handle = buf_reader.take(8000);
-                let result = handle.read_until(b'\n', &mut line).await;
+                let result = tokio::select! {
+                    result = handle.read_until(b'\n', &mut line) => Some(result),
+                    _ = draining.wait_for(|draining| *draining), if is_idle => None,
+                };
                 buf_reader = handle.into_inner();
+                is_idle = false;
 
                 // handle connection closing
+                let Some(result) = result else {
+                    info!("Closing idle connection, the server is drained");
+                    connection_closed = true;
+                    break;
+                };
                 if let Err(err) = result {
                     warn!("Cannot read line from buffered stream: {:?}", err);
                     connection_closed = true;
@@ -206,42 +901,260 @@ impl<S: AsyncStream> ClientHandler<S> {
 
             debug!("done reading request head");
             debug!("parsing request head");
+            let received = chrono::Utc::now();
             match req_head_parser.do_parse() {
                 Ok(parsed_head) => {
                     debug!("request head parsing done");
+                    self.slot.start_request(&parsed_head);
+                    let mut should_close = parsed_head.should_close();
 
-                    // read body if needed
-                    let mut body = None;
-                    let body_len = parsed_head.body_len();
-                    if body_len != 0 {
-                        debug!("reading request body");
-                        let mut buf = vec![0; body_len];
-                        if let Err(err) = buf_reader.read_exact(&mut buf).await {
-                            warn!("Unable to read request body: {:?}", err);
-                            continue;
-                        }
+                    // requests made by trusted proxies tell the address of their client
+                    let forwarded_ip = peer_ip.and_then(|ip| {
+                        proxy_protocol::forwarded_client_ip(&parsed_head, ip, &trusted_proxies)
+                    });
+                    let remote_addr =
+                        forwarded_ip.map_or_else(|| client_ip.clone(), |ip| ip.to_string());
+
+                    // TRACE and OPTIONS requests are not forwarded anymore once Max-Forwards is 0
+                    let is_options = *parsed_head.verb() == ReqVerb::Options;
+                    let is_final_recipient = (is_options || *parsed_head.verb() == ReqVerb::Trace)
+                        && parsed_head.max_forwards() == Some(0);
 
-                        // decode the request body
-                        match req_parser::decode_req_body(&parsed_head, buf) {
-                            Ok(req_body) => {
-                                body = Some(ReqBody::new(
-                                    req_body,
-                                    String::from(parsed_head.body_type().unwrap_or_default()),
-                                ))
+                    let state = self.state.clone();
+
+                    // only allowed clients count against the rate limits of their address, user
+                    // and path
+                    let path = match parsed_head.target() {
+                        ReqTarget::Path(ReqPath { decoded, .. })
+                        | ReqTarget::Absolute(_, ReqPath { decoded, .. }) => decoded.as_str(),
+                        ReqTarget::All | ReqTarget::Authority(_) => "",
+                    };
+                    let is_allowed = access::is_allowed(
+                        &self.settings.acls,
+                        forwarded_ip.or(peer_ip),
+                        parsed_head.host(),
+                        path,
+                    );
+                    // the credentials are checked once (a digest nonce count cannot be reused),
+                    // in the realm protecting the path: requests made to the forward proxy
+                    // authenticate to the proxy instead
+                    let is_forward_proxy_req = self.settings.forward_proxy
+                        && matches!(
+                            parsed_head.target(),
+                            ReqTarget::Absolute(..) | ReqTarget::Authority(_)
+                        );
+                    // the login form is public, and always served by the server itself
+                    let is_login_req = state
+                        .auth
+                        .login_settings()
+                        .is_some_and(|login| path == login.login_path || path == login.logout_path);
+                    // signed URLs grant access to their path without credentials
+                    let signature = match parsed_head.target() {
+                        ReqTarget::Path(ReqPath { decoded, query, .. }) => state
+                            .url_signer
+                            .as_ref()
+                            .and_then(|signer| signer.verify(decoded, query)),
+                        _ => None,
+                    };
+                    let creds = Credentials::of(&parsed_head);
+                    let access = match is_forward_proxy_req
+                        || redirect_to_https
+                        || metrics_only
+                        || is_login_req
+                    {
+                        true => Ok(Access::Public),
+                        false => match signature {
+                            Some(Ok(())) => Ok(Access::Public),
+                            Some(Err(err)) => {
+                                info!("Rejected signed URL: {}", err);
+                                Ok(Access::Forbidden)
                             }
-                            Err(err) => {
-                                self.handle_req_parsing_error(&err).await;
+                            None => check_credentials(
+                                &state,
+                                &remote_addr,
+                                state.auth.realm(path),
+                                creds,
+                            ),
+                        },
+                    };
+                    let is_granted = access.as_ref().is_ok_and(|access| access.is_granted());
+                    let user = match &access {
+                        Ok(Access::Granted(identity)) => Some(identity.username.clone()),
+                        _ => None,
+                    };
+                    let rate_limited = match is_allowed {
+                        true => state
+                            .rate_limiter
+                            .check(&remote_addr, user.as_deref(), path)
+                            .err(),
+                        false => None,
+                    };
+                    let new_req = |head: ReqHead, body: Option<ReqBody>| {
+                        let mut req = HttpReq::new(received, head, body);
+                        req.set_client_cert(client_cert.clone());
+                        req.set_client_ip(forwarded_ip);
+                        req.set_user(user.clone());
+                        req
+                    };
+
+                    // forward the request to an upstream server if it matches a proxy route
+                    let pool = match is_final_recipient {
+                        true => None,
+                        false => state
+                            .reverse_proxy
+                            .find_pool(parsed_head.host(), parsed_head.target()),
+                    };
+                    // hand WebSocket upgrade requests over to the handler registered on their path
+                    let websocket_handler = match parsed_head.target() {
+                        ReqTarget::Path(ReqPath { decoded: path, .. })
+                            if websocket::is_upgrade_req(&parsed_head) && is_granted =>
+                        {
+                            state.websocket_handlers.read().unwrap().get(path).cloned()
+                        }
+                        _ => None,
+                    };
+                    match (pool, websocket_handler) {
+                        // requests made on the plain HTTP listener redirecting to HTTPS
+                        _ if redirect_to_https => {
+                            utils::discard_body(&mut buf_reader, parsed_head.body_len()).await;
+                            self.current_req = Some(new_req(parsed_head, None));
+                            self.serve_https_redirect().await;
+                        }
+                        // clients denied access by an access control list
+                        _ if !is_allowed => {
+                            utils::discard_body(&mut buf_reader, parsed_head.body_len()).await;
+                            self.current_req = Some(new_req(parsed_head, None));
+                            self.serve_error(403, true).await;
+                        }
+                        // requests made on the listener of the metrics
+                        _ if metrics_only => {
+                            utils::discard_body(&mut buf_reader, parsed_head.body_len()).await;
+                            let is_metrics_path = path == metrics::DEFAULT_PATH;
+                            self.current_req = Some(new_req(parsed_head, None));
+                            match is_metrics_path {
+                                true => self.serve_metrics().await,
+                                false => self.serve_error(404, true).await,
+                            }
+                        }
+                        // clients exceeding a rate limit
+                        _ if let Some(retry_after) = rate_limited => {
+                            utils::discard_body(&mut buf_reader, parsed_head.body_len()).await;
+                            self.current_req = Some(new_req(parsed_head, None));
+                            self.serve_rate_limited(retry_after).await;
+                        }
+                        // clients whose certificate does not grant access
+                        _ if !is_cert_authorized => {
+                            utils::discard_body(&mut buf_reader, parsed_head.body_len()).await;
+                            self.current_req = Some(new_req(parsed_head, None));
+                            self.serve_error(403, true).await;
+                        }
+                        // requests made to the forward proxy (absolute URI or CONNECT)
+                        _ if is_forward_proxy_req => {
+                            let body_len = parsed_head.body_len();
+                            let mut req = new_req(parsed_head, None);
+                            let realm = state.auth.proxy_realm();
+                            let access = check_credentials(
+                                &state,
+                                &remote_addr,
+                                realm,
+                                req.proxy_auth_creds()
+                                    .map(|(u, p)| Credentials::Basic(u, p)),
+                            );
+                            if let Ok(Access::Granted(identity)) = &access {
+                                req.set_user(Some(identity.username.clone()));
+                            }
+                            if !access.as_ref().is_ok_and(|access| access.is_granted())
+                                || is_final_recipient
+                            {
+                                utils::discard_body(&mut buf_reader, body_len).await;
+                                self.current_req = Some(req);
+                                if let Err(retry_after) = access {
+                                    self.serve_rate_limited(retry_after).await;
+                                } else if let Some(realm) = realm
+                                    && !matches!(access, Ok(Access::Granted(_)))
+                                {
+                                    self.serve_auth_required(407, realm, false).await;
+                                } else if is_options {
+                                    self.serve_options().await;
+                                } else {
+                                    self.serve_trace().await;
+                                }
+                            } else if *req.verb() == ReqVerb::Connect {
+                                // bytes sent by the client right after the request head belong
+                                // to the tunnel
+                                let buffered = Vec::from(buf_reader.buffer());
+                                self.current_req = Some(req);
+                                self.serve_tunnel(buffered).await;
+                                connection_closed = true;
                                 continue;
+                            } else {
+                                debug!("forwarding request to origin server");
+                                let result =
+                                    proxy::forward_to_origin(&mut req, &mut buf_reader, body_len)
+                                        .await;
+                                self.current_req = Some(req);
+                                should_close |= self.serve_proxied(result).await;
                             }
                         }
-                    }
+                        (_, Some(handler)) => {
+                            self.current_req = Some(new_req(parsed_head, None));
+                            // once upgraded, the connection is not used for HTTP anymore
+                            if self.serve_websocket(handler).await {
+                                connection_closed = true;
+                                continue;
+                            }
+                        }
+                        (Some(pool), None) if is_granted && !is_login_req => {
+                            debug!("forwarding request to upstream server");
+                            let body_len = parsed_head.body_len();
+                            let mut req = new_req(parsed_head, None);
+                            let forwarded = Forwarded {
+                                client_ip: &client_ip,
+                                proto,
+                            };
+                            let result = pool
+                                .forward(&mut req, &mut buf_reader, body_len, &forwarded)
+                                .await;
+                            self.current_req = Some(req);
+                            should_close |= self.serve_proxied(result).await;
+                        }
+                        _ => {
+                            // read body if needed
+                            let mut body = None;
+                            let body_len = parsed_head.body_len();
+                            if body_len != 0 {
+                                debug!("reading request body");
+                                let mut buf = vec![0; body_len];
+                                if let Err(err) = buf_reader.read_exact(&mut buf).await {
+                                    warn!("Unable to read request body: {:?}", err);
+                                    continue;
+                                }
+
+                                // decode the request body
+                                match req_parser::decode_req_body(&parsed_head, buf) {
+                                    Ok(req_body) => {
+                                        body = Some(ReqBody::new(
+                                            req_body,
+                                            String::from(
+                                                parsed_head.body_type().unwrap_or_default(),
+                                            ),
+                                        ))
+                                    }
+                                    Err(err) => {
+                                        self.handle_req_parsing_error(&err).await;
+                                        continue;
+                                    }
+                                }
+                            }
 
-                    // serve the request
-                    self.current_req = Some(HttpReq::new(chrono::Utc::now(), parsed_head, body));
-                    self.serve_req().await;
+                            // serve the request
+                            self.current_req = Some(new_req(parsed_head, body));
+                            self.serve_req(access).await;
+                        }
+                    }
 
                     // handle connection closing if needed
-                    if self.current_req.as_ref().unwrap().should_close() {
+                    if should_close {
                         if let Err(err) = self.stream.shutdown().await {
                             warn!("Cannot close connection, {:?}", err);
                         };
@@ -287,6 +1200,29 @@ impl<S: AsyncStream> ClientHandler<S> {
         Box::pin(self.send_response(res)).await;
     }
 
+    /// Ask the client to authenticate in a realm: to the server (401) or to the proxy (407). A
+    /// stale nonce is reported, for the client to retry with a new one.
+    async fn serve_auth_required(&mut self, status_code: u16, realm: &Realm, stale: bool) {
+        let challenges = self.state.auth.challenges(realm, stale);
+        let mut res_builder = ResBuilder::new("HTTP/1.1");
+        let res = res_builder.build_auth_required(status_code, &challenges);
+        Box::pin(self.send_response(res)).await;
+    }
+
+    /// Refuse a request exceeding a rate limit, telling the client when to retry.
+    async fn serve_rate_limited(&mut self, retry_after: time::Duration) {
+        warn!("Rate limit exceeded by {}", self.remote_addr());
+        let mut res_builder = ResBuilder::new("HTTP/1.1");
+        let res = res_builder.build_error(429, true);
+        // in whole seconds, rounded up
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        res.set_header(
+            ResHeader::ResOnly(ResOnlyHeader::RetryAfter),
+            HeaderValue::Simple(SimpleHeaderValue::Number(seconds)),
+        );
+        Box::pin(self.send_response(res)).await;
+    }
+
     async fn serve_io_error(&mut self, err: &io::Error) {
         match err.kind() {
             io::ErrorKind::NotFound => self.serve_error(404, true).await,
@@ -295,39 +1231,87 @@ impl<S: AsyncStream> ClientHandler<S> {
         }
     }
 
-    async fn serve_req(&mut self) {
+    /// Serve a request, once its credentials (if any) were checked: the user is then the one
+    /// granted access, if any.
+    async fn serve_req(&mut self, access: Result<Access, time::Duration>) {
         debug!("serving request");
 
         let req = self.current_req.as_ref().unwrap();
 
-        // handle authentication
-        let mut used_credentials = None;
-        if let Some(creds_list) = self.settings.authentication_credentials.as_ref() {
-            // if the client provided some credentials, check them
-            if let Some((client_username, client_password)) = req.auth_creds()
-                && creds_list.iter().any(|(username, password)| {
-                    client_username == username && client_password == password
-                })
-            {
+        // handle authentication, in the realm protecting the path (if any)
+        let path = match req.target() {
+            ReqTarget::Path(ReqPath { decoded, .. }) => decoded.as_str(),
+            _ => "",
+        };
+        let state = self.state.clone();
+        let identity = match access {
+            Ok(Access::Public) => None,
+            // if the client provided some valid credentials, grant access
+            Ok(Access::Granted(identity)) => {
                 info!("User has valid credentials, granting access");
-                used_credentials = Some((client_username.clone(), client_password.clone()));
+                Some(identity)
+            }
+            // browsers are sent to the login form instead, if the realm accepts its sessions
+            Ok(Access::Unauthenticated)
+                if let Some(login) = state.auth.login_settings()
+                    && state.auth.realm(path).unwrap().accepts_sessions()
+                    && matches!(req.verb(), ReqVerb::Get | ReqVerb::Head)
+                    && req.accepts_html() =>
+            {
+                let location = format!(
+                    "{}?redirect={}",
+                    login.login_path,
+                    urlencoding::encode(&req.target().to_string())
+                );
+                let mut res_builder = ResBuilder::new(req.version());
+                let res = res_builder.build_redirect(303, &location);
+                self.send_response(res).await;
+                return;
             }
             // otherwise, send error
-            else {
-                self.serve_error(401, true).await;
+            Ok(access @ (Access::Unauthenticated | Access::Stale)) => {
+                let realm = state.auth.realm(path).unwrap();
+                self.serve_auth_required(401, realm, access == Access::Stale)
+                    .await;
                 return;
             }
-        }
+            Ok(Access::Forbidden) => {
+                info!("User is not granted access to {}", path);
+                self.serve_error(403, true).await;
+                return;
+            }
+            Err(retry_after) => {
+                self.serve_rate_limited(retry_after).await;
+                return;
+            }
+        };
 
-        if let ReqTarget::Path(ReqPath { decoded: path, .. }) = req.target()
+        if let Some(login) = state.auth.login_settings()
+            && path == login.login_path
+        {
+            self.serve_login(login).await;
+        } else if let Some(login) = state.auth.login_settings()
+            && path == login.logout_path
+        {
+            self.serve_logout(login).await;
+        } else if let Some(MetricsEndpoint::Path(metrics_path)) = self.settings.metrics.as_ref()
+            && path == metrics_path
+        {
+            self.serve_metrics().await;
+        } else if self.settings.server_status.as_deref() == Some(path) {
+            self.serve_server_status().await;
+        } else if *req.verb() == ReqVerb::Trace {
+            // echoing requests would let scripts read cookies and credentials (cross-site tracing)
+            self.serve_error(405, true).await;
+        } else if let ReqTarget::Path(ReqPath { decoded: path, .. }) = req.target()
             && path.ends_with(".php")
         {
             debug!("serving PHP content");
-            self.serve_php(used_credentials).await;
+            self.serve_php(identity).await;
         } else {
             debug!("serving static content");
             match req.verb() {
-                ReqVerb::Get => self.serve_static_resource().await,
+                ReqVerb::Get | ReqVerb::Head => self.serve_static_resource().await,
                 _ => self.serve_error(405, true).await,
             };
         }
@@ -335,7 +1319,284 @@ impl<S: AsyncStream> ClientHandler<S> {
         debug!("request served");
     }
 
-    async fn serve_php(&mut self, used_credentials: Option<(String, String)>) {
+    /// Serve the login form, or log the user in with the submitted form: a session is then opened,
+    /// and the user sent back to the page it was redirected from.
+    async fn serve_login(&mut self, login: &LoginSettings) {
+        self.handler = Handler::Login;
+        let req = self.current_req.as_ref().unwrap();
+        let mut res_builder = ResBuilder::new(req.version());
+        let res = match req.verb() {
+            ReqVerb::Get | ReqVerb::Head => {
+                let query = match req.target() {
+                    ReqTarget::Path(ReqPath { query, .. }) => query.as_str(),
+                    _ => "",
+                };
+                let redirect = utils::form_value(query, "redirect").unwrap_or_default();
+                res_builder.build_login_form(&login.login_path, &redirect, false)
+            }
+            ReqVerb::Post => {
+                let form = req
+                    .body()
+                    .map(|body| String::from_utf8_lossy(body.bytes()).into_owned())
+                    .unwrap_or_default();
+                let field = |name| utils::form_value(&form, name).unwrap_or_default();
+                let (username, password) = (field("username"), field("password"));
+                let remote_addr = self.remote_addr();
+                if let Err(retry_after) = self.state.rate_limiter.check_auth(&remote_addr) {
+                    self.serve_rate_limited(retry_after).await;
+                    return;
+                }
+                match self.state.auth.login(&username, &password) {
+                    Some(set_cookie) => {
+                        info!("User {} logged in", username);
+                        let res = res_builder
+                            .build_redirect(303, session::safe_redirect(&field("redirect")));
+                        res.set_header(
+                            ResHeader::Other(String::from("Set-Cookie")),
+                            HeaderValue::Simple(SimpleHeaderValue::String(set_cookie)),
+                        );
+                        res
+                    }
+                    None => {
+                        info!("Invalid login of user {}", username);
+                        self.state.rate_limiter.record_auth_failure(&remote_addr);
+                        res_builder.build_login_form(&login.login_path, &field("redirect"), true)
+                    }
+                }
+            }
+            _ => res_builder.build_error(405, true),
+        };
+        self.send_response(res).await;
+    }
+
+    /// Log the user out: its session is closed, its cookie removed, and it is sent to the login
+    /// form.
+    ///
+    /// Only POST requests are accepted, so that other sites cannot log the user out with a mere link
+    /// or image.
+    async fn serve_logout(&mut self, login: &LoginSettings) {
+        self.handler = Handler::Login;
+        let req = self.current_req.as_ref().unwrap();
+        if *req.verb() != ReqVerb::Post {
+            self.serve_error(405, true).await;
+            return;
+        }
+        let cookie = req
+            .header_str(&ReqHeader::Other(String::from("cookie")))
+            .and_then(session::cookie_value);
+        let mut res_builder = ResBuilder::new(req.version());
+        let res = res_builder.build_redirect(303, &login.login_path);
+        if let Some(set_cookie) = self.state.auth.logout(cookie) {
+            res.set_header(
+                ResHeader::Other(String::from("Set-Cookie")),
+                HeaderValue::Simple(SimpleHeaderValue::String(set_cookie)),
+            );
+        }
+        self.send_response(res).await;
+    }
+
+    /// Answer an OPTIONS request made to the forward proxy once Max-Forwards is 0: requests of any
+    /// method are forwarded, TRACE and OPTIONS being answered here, and CONNECT opens a tunnel.
+    async fn serve_options(&mut self) {
+        let req = self.current_req.as_ref().unwrap();
+        let allowed_verbs = [
+            ReqVerb::Options,
+            ReqVerb::Get,
+            ReqVerb::Head,
+            ReqVerb::Post,
+            ReqVerb::Put,
+            ReqVerb::Patch,
+            ReqVerb::Delete,
+            ReqVerb::Trace,
+            ReqVerb::Connect,
+        ];
+        let mut res_builder = ResBuilder::new(req.version());
+        let res = res_builder.build_options(&allowed_verbs);
+        self.send_response(res).await;
+    }
+
+    /// Serve the metrics of the server, in the Prometheus text format.
+    async fn serve_metrics(&mut self) {
+        self.handler = Handler::Metrics;
+        let req = self.current_req.as_ref().unwrap();
+        let mut res_builder = ResBuilder::new(req.version());
+        let res = match req.verb() {
+            ReqVerb::Get | ReqVerb::Head => res_builder.build_metrics(self.state.metrics.render()),
+            _ => res_builder.build_error(405, true),
+        };
+        self.send_response(res).await;
+    }
+
+    /// Serve the status of the server, in JSON if the `json` query parameter is given (e.g.
+    /// /server-status?json), or as an HTML page.
+    async fn serve_server_status(&mut self) {
+        self.handler = Handler::Status;
+        let req = self.current_req.as_ref().unwrap();
+        let json = match req.target() {
+            ReqTarget::Path(ReqPath { query, .. }) => query.split('&').any(|param| param == "json"),
+            _ => false,
+        };
+        let snapshot = self.state.status.snapshot();
+        let status = match json {
+            true => snapshot.to_json(),
+            false => snapshot.to_html(),
+        };
+        let mut res_builder = ResBuilder::new(req.version());
+        let res = match req.verb() {
+            ReqVerb::Get | ReqVerb::Head => res_builder.build_server_status(status, json),
+            _ => res_builder.build_error(405, true),
+        };
+        self.send_response(res).await;
+    }
+
+    /// Answer a TRACE request made to the forward proxy once Max-Forwards is 0, by echoing it.
+    async fn serve_trace(&mut self) {
+        let req = self.current_req.as_mut().unwrap();
+        // credentials are not echoed back
+        for header in [
+            ReqHeader::ReqOnly(ReqOnlyHeader::Authorization),
+            ReqHeader::ReqOnly(ReqOnlyHeader::ProxyAuthorization),
+            ReqHeader::Other(String::from("cookie")),
+        ] {
+            req.headers().remove(&header);
+        }
+        let mut res_builder = ResBuilder::new(req.version());
+        let res = res_builder.build_trace(req.head_bytes());
+        self.send_response(res).await;
+    }
+
+    /// Open a tunnel between the client and the server targeted by a CONNECT request.
+    async fn serve_tunnel(&mut self, buffered: Vec<u8>) {
+        self.handler = Handler::Tunnel;
+        let req = self.current_req.as_ref().unwrap();
+        let authority = match req.target() {
+            ReqTarget::Authority(authority) => authority.clone(),
+            _ => {
+                self.serve_error(400, true).await;
+                return;
+            }
+        };
+        let mut upstream = match proxy::open_tunnel(&authority).await {
+            Ok(upstream) => upstream,
+            Err(err) => {
+                self.serve_proxied(Err(err)).await;
+                return;
+            }
+        };
+
+        // the response establishing the tunnel has no header
+        let mut res = HttpRes::new(req.version());
+        self.send_response(&mut res).await;
+
+        if !buffered.is_empty()
+            && let Err(err) = upstream.write_all(&buffered).await
+        {
+            warn!("Cannot write to tunnel: {:?}", err);
+            return;
+        }
+        match tokio::io::copy_bidirectional(&mut self.stream, &mut upstream).await {
+            Ok((sent, received)) => debug!(
+                "tunnel to {} closed ({} bytes sent, {} bytes received)",
+                authority, sent, received
+            ),
+            Err(err) => warn!("Tunnel to {} closed: {:?}", authority, err),
+        }
+    }
+
+    /// Complete the WebSocket opening handshake, and run the handler until the connection ends.
+    ///
+    /// Return whether the connection was upgraded (i.e. the handshake was valid).
+    async fn serve_websocket(&mut self, handler: sync::Arc<dyn websocket::Handler>) -> bool {
+        self.handler = Handler::WebSocket;
+        let req = self.current_req.as_ref().unwrap();
+        let accept_key = match websocket::validate_handshake(req) {
+            Ok(accept_key) => accept_key,
+            Err(HandshakeError::UnsupportedVersion) => {
+                warn!("Unsupported WebSocket version");
+                let mut res_builder = ResBuilder::new(req.version());
+                let res = res_builder.build_error(426, true);
+                res.set_header(
+                    ResHeader::Other(String::from("Sec-WebSocket-Version")),
+                    HeaderValue::Simple(SimpleHeaderValue::String(String::from(
+                        websocket::WEBSOCKET_VERSION,
+                    ))),
+                );
+                self.send_response(res).await;
+                return false;
+            }
+            Err(err) => {
+                warn!("Invalid WebSocket handshake: {:?}", err);
+                self.serve_error(400, true).await;
+                return false;
+            }
+        };
+        let mut res_builder = ResBuilder::new(req.version());
+        let res = res_builder.build_switching_protocols(&accept_key);
+        self.send_response(res).await;
+
+        let req = self.current_req.take().unwrap();
+        let websocket = WebSocket::new(
+            &mut self.stream as &mut dyn websocket::Stream,
+            websocket::Role::Server,
+        );
+        handler.handle(websocket, &req).await;
+        debug!("WebSocket connection closed");
+        if let Err(err) = self.stream.shutdown().await {
+            debug!("Cannot close connection, {:?}", err);
+        }
+        true
+    }
+
+    async fn serve_https_redirect(&mut self) {
+        self.handler = Handler::Redirect;
+        let req = self.current_req.as_ref().unwrap();
+        let configured_host = self
+            .settings
+            .https_redirect
+            .as_ref()
+            .and_then(|redirect| redirect.host.as_deref());
+        let location = self
+            .settings
+            .https_address()
+            .and_then(|address| https::redirect_location(req, configured_host, address.port()));
+        match location {
+            Some(location) => {
+                let mut res_builder = ResBuilder::new(req.version());
+                let res = res_builder.build_redirect(https::redirect_status(req.verb()), &location);
+                self.send_response(res).await;
+            }
+            None => self.serve_error(400, true).await,
+        }
+    }
+
+    /// Send the response of an upstream server (or an error if there is none): whether the client
+    /// connection must be closed once it is sent is returned.
+    async fn serve_proxied(&mut self, result: Result<HttpRes, ProxyError>) -> bool {
+        self.handler = Handler::Proxy;
+        match result {
+            Ok(mut res) => {
+                // only responses whose body ends with the connection keep this header
+                let closes = res
+                    .headers()
+                    .get(&ResHeader::General(GeneralHeader::Connection))
+                    .is_some_and(|value| value.to_string().eq_ignore_ascii_case("close"));
+                self.send_response(&mut res).await;
+                closes
+            }
+            Err(err) => {
+                warn!("Cannot forward request: {}", err);
+                match err {
+                    ProxyError::Timeout => self.serve_error(504, true).await,
+                    ProxyError::InvalidTarget => self.serve_error(400, true).await,
+                    _ => self.serve_error(502, true).await,
+                }
+                false
+            }
+        }
+    }
+
+    async fn serve_php(&mut self, identity: Option<Identity>) {
+        self.handler = Handler::Php;
         let req = self.current_req.as_ref().unwrap();
 
         let (path, query) = match req.target() {
@@ -361,21 +1622,27 @@ impl<S: AsyncStream> ClientHandler<S> {
             }
         };
 
+        let remote_addr = self.remote_addr();
         let mut res_builder = ResBuilder::new(req.version());
-        if let Err(err) = res_builder
+        let started = time::Instant::now();
+        let result = res_builder
             .run_php_script(PhpScriptParams {
                 interpreter_path: self.settings.php_cgi_binary.as_ref(),
                 script_path: script_path.as_str(),
                 script_query: query,
-                used_credentials,
-                client_ip: self.peer_addr.split(':').next().unwrap(),
+                identity: identity.as_ref(),
+                client_cert: req.client_cert(),
+                client_ip: &remote_addr,
                 verb: req.verb(),
-                address: &self.settings.address,
+                address: self.local_addr.as_ref(),
                 version: req.version(),
                 body: req.body(),
             })
-            .await
-        {
+            .await;
+        self.state
+            .metrics
+            .observe_php(started.elapsed(), result.is_err());
+        if let Err(err) = result {
             warn!("Cannot serve PHP script: {}", err);
             self.serve_error(500, true).await;
         }
@@ -384,11 +1651,14 @@ impl<S: AsyncStream> ClientHandler<S> {
     }
 
     async fn serve_static_resource(&mut self) {
+        self.handler = Handler::Static;
         let req = self.current_req.as_ref().unwrap();
         let mut res_builder = ResBuilder::new(req.version());
         match req.target() {
-            // target '*' not supported for get resource
-            ReqTarget::All => self.serve_error(400, true).await,
+            // target '*' not supported for get resource, nor proxy targets (outside forward proxy mode)
+            ReqTarget::All | ReqTarget::Absolute(..) | ReqTarget::Authority(_) => {
+                self.serve_error(400, true).await
+            }
             // serve target from path
             ReqTarget::Path(ReqPath { decoded: path, .. }) => {
                 let full_path = match self.resolve_path(path).await {
@@ -402,6 +1672,7 @@ impl<S: AsyncStream> ClientHandler<S> {
                 // prevent directory listing by default
                 if full_path.is_dir() {
                     if self.settings.allow_dir_listing {
+                        self.handler = Handler::DirListing;
                         match res_builder.list_directory(full_path.as_path(), path) {
                             Ok(()) => {
                                 let res = res_builder.do_build();
@@ -424,6 +1695,16 @@ impl<S: AsyncStream> ClientHandler<S> {
                 {
                     Ok(()) => {
                         let res = res_builder.do_build();
+                        if let Some(encoding) = req.accepted_encoding()
+                            && res.has_header(ResHeader::Entity(EntityHeader::ContentEncoding))
+                            && let Ok(metadata) = full_path.metadata()
+                        {
+                            self.state.metrics.observe_compression(
+                                encoding,
+                                metadata.len(),
+                                res.body_len() as u64,
+                            );
+                        }
                         self.send_response(res).await
                     }
                     Err(err) => {
@@ -466,41 +1747,44 @@ impl<S: AsyncStream> ClientHandler<S> {
             && let HeaderValue::Simple(SimpleHeaderValue::Mime(actual)) = actual.get()
         {
             match accepted.get() {
-                HeaderValue::Simple(SimpleHeaderValue::Mime(accepted)) => {
-                    if !utils::are_mime_compatible(accepted, actual) {
-                        self.serve_error(415, false).await;
-                        return;
-                    }
+                HeaderValue::Simple(SimpleHeaderValue::Mime(accepted))
+                    if !utils::are_mime_compatible(accepted, actual) =>
+                {
+                    self.serve_error(415, false).await;
+                    return;
                 }
-                HeaderValue::Parsed(v) => {
+                HeaderValue::Parsed(v)
                     if v.0.iter().all(|(v, _)| match v {
                         SimpleHeaderValue::Mime(accepted) => {
                             !utils::are_mime_compatible(accepted, actual)
                         }
                         _ => true,
-                    }) {
-                        self.serve_error(415, false).await;
-                        return;
-                    }
+                    }) =>
+                {
+                    self.serve_error(415, false).await;
+                    return;
                 }
                 _ => (),
             }
         }
 
-        // log request and response
-        info!(
-            "{} - - {} {} {} {}",
-            self.peer_addr,
-            self.current_req.as_ref().map_or(String::from("-"), |r| r
-                .date()
-                .format("[%d/%b/%Y:%H:%M:%S %z]")
-                .to_string()),
-            self.current_req
-                .as_ref()
-                .map_or(String::from("-"), |r| format!(r#""{}""#, r.first_line())),
-            res.status_code(),
-            res.body_len()
-        );
+        // advertise the HTTP/3 listener
+        if let Some(alt_svc) = self.state.alt_svc.as_ref() {
+            res.set_header(
+                ResHeader::Other(String::from("Alt-Svc")),
+                HeaderValue::Simple(SimpleHeaderValue::String(alt_svc.clone())),
+            );
+        }
+
+        // require clients to use HTTPS from now on
+        if self.stream.is_secure()
+            && let Some(hsts) = self.settings.hsts.as_ref()
+        {
+            res.set_header(
+                ResHeader::Other(String::from("Strict-Transport-Security")),
+                HeaderValue::Simple(SimpleHeaderValue::String(hsts.to_string())),
+            );
+        }
 
         // write response head to socket
         let res_head = res.head_bytes();
@@ -511,24 +1795,108 @@ impl<S: AsyncStream> ClientHandler<S> {
             warn!("Cannot flush response head: {:?}", err)
         }
 
-        // write response body (if any) to socket
+        // write response body (if any) to socket, responses to HEAD requests having none
+        let is_head = self
+            .current_req
+            .as_ref()
+            .is_some_and(|req| *req.verb() == ReqVerb::Head);
+        let mut sent = 0;
         match res.body_mut() {
+            _ if is_head => (),
             Some(ResBody::Bytes(bytes)) => {
                 debug!("sending {} bytes", bytes.len());
-                if let Err(err) = self.stream.write_all(bytes).await {
-                    warn!("Cannot write response body bytes: {:?}", err);
+                match self.stream.write_all(bytes).await {
+                    Ok(()) => sent = bytes.len() as u64,
+                    Err(err) => warn!("Cannot write response body bytes: {:?}", err),
                 }
             }
             Some(ResBody::Stream(file, _)) => match tokio::io::copy(file, &mut self.stream).await {
-                Ok(n) => debug!("sent {} bytes", n),
+                Ok(n) => {
+                    debug!("sent {} bytes", n);
+                    sent = n;
+                }
                 Err(err) => {
                     warn!("Cannot write response body stream: {:?}", err);
                 }
             },
+            Some(ResBody::Upstream(body)) => match body.copy_to(&mut self.stream).await {
+                Ok(n) => {
+                    debug!("forwarded {} bytes", n);
+                    sent = n;
+                }
+                Err(err) => {
+                    warn!("Cannot forward response body: {:?}", err);
+                }
+            },
             None => (),
         }
-        if let Err(err) = self.stream.flush().await {
+        if !is_head && let Err(err) = self.stream.flush().await {
             warn!("Cannot flush response body: {:?}", err)
         }
+
+        // log request and response, once sent
+        self.record_request(res.status_code(), sent);
+    }
+
+    /// Record the current request (if any) in the access logs, in the metrics and in the status of
+    /// the server.
+    fn record_request(&self, status: u16, bytes: u64) {
+        let req = self.current_req.as_ref();
+        let now = chrono::Utc::now();
+        let latency = req
+            .and_then(|req| (now - *req.date()).to_std().ok())
+            .unwrap_or_default();
+        self.state.access_logger.log(&LogEntry {
+            remote_addr: &self.remote_addr(),
+            req,
+            date: req.map_or(now, |req| *req.date()),
+            status,
+            bytes,
+            latency,
+            tls: self.tls_session.as_ref(),
+        });
+        self.state.metrics.observe_request(
+            req.map(HttpReq::verb),
+            status,
+            self.handler,
+            latency,
+            req.map_or(0, |req| req.body_len() as u64),
+            bytes,
+        );
+        self.slot.finish_request(req, status, bytes);
+    }
+}
+
+/// Check the credentials given by a client (if any) in a realm, unless it failed to authenticate
+/// too often: the time until it may retry is then returned.
+fn check_credentials(
+    state: &ServerState,
+    remote_addr: &str,
+    realm: Option<&Realm>,
+    creds: Option<Credentials>,
+) -> Result<Access, time::Duration> {
+    // session cookies are signed, so an expired one is not a failed attempt
+    if creds.is_none() || realm.is_none() || matches!(creds, Some(Credentials::Session(_))) {
+        return Ok(state.auth.check(realm, creds));
+    }
+    state.rate_limiter.check_auth(remote_addr)?;
+    let access = state.auth.check(realm, creds);
+    if access == Access::Unauthenticated {
+        state.rate_limiter.record_auth_failure(remote_addr);
+    }
+    Ok(access)
+}
+
+/// Extract the IP address from a peer socket address (e.g. "::1" from "[::1]:8080").
+///
+/// Peers without an IP address (connected to a Unix domain socket) are identified by their whole
+/// address (e.g. "unix:").
+fn client_ip(peer_addr: &str) -> &str {
+    if peer_addr.starts_with(listener::UNIX_PREFIX) {
+        return peer_addr;
+    }
+    match peer_addr.rsplit_once(':') {
+        Some((ip, _)) => ip.trim_start_matches('[').trim_end_matches(']'),
+        None => peer_addr,
     }
 }
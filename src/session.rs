@@ -0,0 +1,308 @@
+//! Sessions of the users logged in with the login form, kept in memory and identified by a signed
+//! cookie.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use aws_lc_rs::hmac;
+use base64::Engine;
+use std::{collections, str::FromStr, sync, time};
+
+/// Name of the session cookie.
+pub const COOKIE_NAME: &str = "rhs_session";
+/// Maximum number of open sessions, the oldest ones being closed first.
+pub const MAX_SESSIONS: usize = 10000;
+
+/// Settings of the login form, and of the sessions it opens.
+///
+/// Its string format is `LOGIN-PATH[|logout=PATH][|idle=SECONDS][|max-age=SECONDS]` (e.g.
+/// `/login|logout=/logout|idle=1800|max-age=43200`, the defaults).
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginSettings {
+    /// Path of the login form
+    pub login_path: String,
+    /// Path closing the session
+    pub logout_path: String,
+    /// Time after which an unused session expires
+    pub idle_timeout: time::Duration,
+    /// Time after which a session expires, even if used
+    pub max_age: time::Duration,
+}
+
+impl FromStr for LoginSettings {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('|');
+        let login_path = match parts.next() {
+            Some(path) if path.starts_with('/') => String::from(path),
+            _ => return Err(format!("Invalid login path: {}", s)),
+        };
+        let mut settings = Self {
+            login_path,
+            logout_path: String::from("/logout"),
+            idle_timeout: time::Duration::from_secs(1800),
+            max_age: time::Duration::from_secs(43200),
+        };
+        for option in parts {
+            let seconds = |value: &str| match value.parse() {
+                Ok(seconds) if seconds > 0 => Ok(time::Duration::from_secs(seconds)),
+                _ => Err(format!("Invalid session duration: {}", option)),
+            };
+            match option.split_once('=') {
+                Some(("logout", path)) if path.starts_with('/') => {
+                    settings.logout_path = String::from(path)
+                }
+                Some(("idle", value)) => settings.idle_timeout = seconds(value)?,
+                Some(("max-age", value)) => settings.max_age = seconds(value)?,
+                _ => return Err(format!("Invalid login option: {}", option)),
+            }
+        }
+        if settings.logout_path == settings.login_path {
+            return Err(String::from("The login and logout paths must differ"));
+        }
+        Ok(settings)
+    }
+}
+
+/// Value of the session cookie among the ones of a Cookie header, if any.
+pub fn cookie_value(cookies: &str) -> Option<&str> {
+    cookies
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .map(|(_, value)| value)
+}
+
+/// Page to redirect a user to once logged in: only local paths are allowed, for the login form
+/// not to send users to another site, nor to inject headers (the root path being used otherwise).
+pub fn safe_redirect(target: &str) -> &str {
+    match target.starts_with('/')
+        && !target.starts_with("//")
+        && !target.contains('\\')
+        && !target.chars().any(char::is_control)
+    {
+        true => target,
+        false => "/",
+    }
+}
+
+struct Session {
+    username: String,
+    opened: time::Instant,
+    last_used: time::Instant,
+}
+
+/// Open sessions, by ID.
+pub(crate) struct Sessions {
+    settings: LoginSettings,
+    /// Key signing the session IDs, generated when the server starts (as the sessions do not
+    /// outlive it)
+    key: hmac::Key,
+    sessions: sync::Mutex<collections::HashMap<String, Session>>,
+}
+
+impl Sessions {
+    pub(crate) fn new(settings: LoginSettings) -> Self {
+        let mut key = [0; 32];
+        OsRng.fill_bytes(&mut key);
+        Self {
+            settings,
+            key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+            sessions: sync::Mutex::new(collections::HashMap::new()),
+        }
+    }
+
+    pub(crate) fn settings(&self) -> &LoginSettings {
+        &self.settings
+    }
+
+    /// Open a session for a user: the value of its cookie is returned.
+    pub(crate) fn open(&self, username: &str) -> String {
+        let mut bytes = [0; 16];
+        OsRng.fill_bytes(&mut bytes);
+        let id: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let now = time::Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| !self.is_expired(session, now));
+        if sessions.len() >= MAX_SESSIONS
+            && let Some(oldest) = sessions
+                .iter()
+                .min_by_key(|(_, session)| session.opened)
+                .map(|(id, _)| id.clone())
+        {
+            sessions.remove(&oldest);
+        }
+        sessions.insert(
+            id.clone(),
+            Session {
+                username: String::from(username),
+                opened: now,
+                last_used: now,
+            },
+        );
+        let sig = hmac::sign(&self.key, id.as_bytes());
+        format!(
+            "{}.{}",
+            id,
+            base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(sig.as_ref())
+        )
+    }
+
+    /// User of the session of a cookie, if it is validly signed and not expired. The session is
+    /// then marked as used.
+    pub(crate) fn user(&self, cookie: &str) -> Option<String> {
+        let id = self.verify(cookie)?;
+        let now = time::Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get_mut(id) {
+            Some(session) if self.is_expired(session, now) => {
+                sessions.remove(id);
+                None
+            }
+            Some(session) => {
+                session.last_used = now;
+                Some(session.username.clone())
+            }
+            None => None,
+        }
+    }
+
+    /// Close the session of a cookie, if any.
+    pub(crate) fn close(&self, cookie: &str) {
+        if let Some(id) = self.verify(cookie) {
+            self.sessions.lock().unwrap().remove(id);
+        }
+    }
+
+    /// Set-Cookie header value of a session cookie, expiring with the session (or right away, to
+    /// remove it from the browser).
+    pub(crate) fn set_cookie(&self, cookie: Option<&str>) -> String {
+        format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+            COOKIE_NAME,
+            cookie.unwrap_or_default(),
+            cookie.map_or(0, |_| self.settings.max_age.as_secs())
+        )
+    }
+
+    /// Session ID of a cookie, if its signature is valid (checked in constant time).
+    fn verify<'a>(&self, cookie: &'a str) -> Option<&'a str> {
+        let (id, sig) = cookie.split_once('.')?;
+        let sig = base64::prelude::BASE64_URL_SAFE_NO_PAD.decode(sig).ok()?;
+        hmac::verify(&self.key, id.as_bytes(), &sig).ok()?;
+        Some(id)
+    }
+
+    fn is_expired(&self, session: &Session, now: time::Instant) -> bool {
+        now >= session.last_used + self.settings.idle_timeout
+            || now >= session.opened + self.settings.max_age
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_settings_from_str_test() {
+        assert_eq!(
+            "/login".parse(),
+            Ok(LoginSettings {
+                login_path: String::from("/login"),
+                logout_path: String::from("/logout"),
+                idle_timeout: time::Duration::from_secs(1800),
+                max_age: time::Duration::from_secs(43200),
+            })
+        );
+        assert_eq!(
+            "/auth/login|logout=/auth/logout|idle=60|max-age=3600".parse(),
+            Ok(LoginSettings {
+                login_path: String::from("/auth/login"),
+                logout_path: String::from("/auth/logout"),
+                idle_timeout: time::Duration::from_secs(60),
+                max_age: time::Duration::from_secs(3600),
+            })
+        );
+        assert!("login".parse::<LoginSettings>().is_err());
+        assert!("/login|logout=logout".parse::<LoginSettings>().is_err());
+        assert!("/logout".parse::<LoginSettings>().is_err());
+        assert!("/login|idle=0".parse::<LoginSettings>().is_err());
+        assert!("/login|max-age=1h".parse::<LoginSettings>().is_err());
+        assert!("/login|secure=false".parse::<LoginSettings>().is_err());
+    }
+
+    #[test]
+    fn cookie_value_test() {
+        assert_eq!(cookie_value("rhs_session=abc.def"), Some("abc.def"));
+        assert_eq!(cookie_value("a=b; rhs_session=abc; c=d"), Some("abc"));
+        assert_eq!(cookie_value("a=b;rhs_session="), Some(""));
+        assert_eq!(cookie_value("a=b; rhs_sessions=abc"), None);
+        assert_eq!(cookie_value(""), None);
+    }
+
+    #[test]
+    fn safe_redirect_test() {
+        assert_eq!(
+            safe_redirect("/private/index.php?a=b"),
+            "/private/index.php?a=b"
+        );
+        assert_eq!(safe_redirect(""), "/");
+        assert_eq!(safe_redirect("https://example.org/"), "/");
+        assert_eq!(safe_redirect("//example.org/"), "/");
+        assert_eq!(safe_redirect("/\\example.org/"), "/");
+        assert_eq!(safe_redirect("/\r\nSet-Cookie: a=b"), "/");
+    }
+
+    #[test]
+    fn sessions_test() {
+        let mut settings: LoginSettings = "/login".parse().unwrap();
+        let sessions = Sessions::new(settings.clone());
+        let cookie = sessions.open("alice");
+        assert_eq!(sessions.user(&cookie), Some(String::from("alice")));
+        assert_eq!(
+            sessions.set_cookie(Some(&cookie)),
+            format!(
+                "rhs_session={}; Path=/; Max-Age=43200; HttpOnly; Secure; SameSite=Lax",
+                cookie
+            )
+        );
+        assert_eq!(
+            sessions.set_cookie(None),
+            "rhs_session=; Path=/; Max-Age=0; HttpOnly; Secure; SameSite=Lax"
+        );
+
+        // the session ID is signed
+        let (id, sig) = cookie.split_once('.').unwrap();
+        assert_eq!(sessions.user(id), None);
+        assert_eq!(sessions.user(&format!("{}.{}", id, &sig[1..])), None);
+        let other = sessions.open("bob");
+        let (_, other_sig) = other.split_once('.').unwrap();
+        assert_eq!(sessions.user(&format!("{}.{}", id, other_sig)), None);
+        // by a key of its own
+        assert_eq!(Sessions::new(settings.clone()).user(&cookie), None);
+
+        sessions.close(&cookie);
+        assert_eq!(sessions.user(&cookie), None);
+        assert_eq!(sessions.user(&other), Some(String::from("bob")));
+
+        // idle and absolute expiry
+        settings.idle_timeout = time::Duration::from_millis(100);
+        let sessions = Sessions::new(settings.clone());
+        let cookie = sessions.open("alice");
+        std::thread::sleep(time::Duration::from_millis(60));
+        assert!(sessions.user(&cookie).is_some());
+        std::thread::sleep(time::Duration::from_millis(60));
+        assert!(sessions.user(&cookie).is_some());
+        std::thread::sleep(time::Duration::from_millis(120));
+        assert_eq!(sessions.user(&cookie), None);
+
+        settings.idle_timeout = time::Duration::from_secs(60);
+        settings.max_age = time::Duration::from_millis(100);
+        let sessions = Sessions::new(settings);
+        let cookie = sessions.open("alice");
+        std::thread::sleep(time::Duration::from_millis(60));
+        assert!(sessions.user(&cookie).is_some());
+        std::thread::sleep(time::Duration::from_millis(60));
+        assert_eq!(sessions.user(&cookie), None);
+    }
+}
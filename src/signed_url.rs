@@ -0,0 +1,207 @@
+//! Signed URLs, granting access to a single path until they expire, without credentials (e.g. to
+//! share a private file).
+//!
+//! A URL is signed with two query parameters: `expires`, its expiry date (as a UNIX timestamp),
+//! and `signature`, the HMAC-SHA256 of its decoded path and of this date (base64url-encoded),
+//! computed with a secret of the server.
+
+use crate::{credentials, utils};
+use aws_lc_rs::hmac;
+use base64::Engine;
+use std::{fmt, fs, path, time};
+
+/// Query parameter holding the expiry date of a signed URL.
+pub const EXPIRES_PARAM: &str = "expires";
+/// Query parameter holding the signature of a signed URL.
+pub const SIGNATURE_PARAM: &str = "signature";
+
+/// Reason why a signed URL is rejected.
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    /// The expiry date is missing or invalid
+    Malformed,
+    /// The signature does not match the path and the expiry date
+    Invalid,
+    Expired,
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "missing or invalid expiry date"),
+            Self::Invalid => write!(f, "invalid signature"),
+            Self::Expired => write!(f, "expired"),
+        }
+    }
+}
+
+/// Signer (and verifier) of URLs, with the secret of the server.
+pub struct UrlSigner {
+    key: hmac::Key,
+}
+
+impl UrlSigner {
+    /// Load the secret from a file (its trailing whitespace being ignored): it must be at least 32
+    /// bytes long.
+    pub fn load(path: &path::Path) -> Result<Self, credentials::Error> {
+        let secret =
+            fs::read(path).map_err(|e| credentials::Error::Io(path::PathBuf::from(path), e))?;
+        let secret = secret.trim_ascii_end();
+        if secret.len() < 32 {
+            return Err(credentials::Error::Parse(
+                path::PathBuf::from(path),
+                String::from("URL signing secrets must be at least 32 bytes long"),
+            ));
+        }
+        Ok(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+        })
+    }
+
+    /// Sign the URL of a (decoded) path, until an expiry date (a UNIX timestamp): the path is
+    /// returned URL-encoded, with its query string.
+    pub fn sign(&self, path: &str, expires: u64) -> String {
+        let encoded_path = path
+            .split('/')
+            .map(|segment| urlencoding::encode(segment))
+            .collect::<Vec<_>>()
+            .join("/");
+        format!(
+            "{}?{}={}&{}={}",
+            encoded_path,
+            EXPIRES_PARAM,
+            expires,
+            SIGNATURE_PARAM,
+            base64::prelude::BASE64_URL_SAFE_NO_PAD
+                .encode(hmac::sign(&self.key, &message(path, expires)))
+        )
+    }
+
+    /// Verify the signature of the URL of a (decoded) path, from its query string: none is
+    /// returned if the URL is not signed.
+    pub fn verify(&self, path: &str, query: &str) -> Option<Result<(), SignatureError>> {
+        let signature = utils::form_value(query, SIGNATURE_PARAM)?;
+        let expires = match utils::form_value(query, EXPIRES_PARAM).map(|e| e.parse()) {
+            Some(Ok(expires)) => expires,
+            _ => return Some(Err(SignatureError::Malformed)),
+        };
+        // the signature is checked in constant time, before the expiry date it covers
+        let is_valid = base64::prelude::BASE64_URL_SAFE_NO_PAD
+            .decode(signature)
+            .is_ok_and(|signature| {
+                hmac::verify(&self.key, &message(path, expires), &signature).is_ok()
+            });
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Some(match (is_valid, now < expires) {
+            (false, _) => Err(SignatureError::Invalid),
+            (true, false) => Err(SignatureError::Expired),
+            (true, true) => Ok(()),
+        })
+    }
+}
+
+/// Message signed for a URL: its path and its expiry date, on separate lines (the date being on
+/// the last one, a path containing a line feed cannot be mistaken for another).
+fn message(path: &str, expires: u64) -> Vec<u8> {
+    format!("{}\n{}", path, expires).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_signer(secret: &str) -> UrlSigner {
+        let path = std::env::temp_dir().join(format!(
+            "signed-url-test-{}-{}",
+            std::process::id(),
+            secret.len()
+        ));
+        fs::write(&path, secret).unwrap();
+        let signer = UrlSigner::load(&path);
+        fs::remove_file(&path).unwrap();
+        signer.unwrap()
+    }
+
+    #[test]
+    fn url_signer_load_test() {
+        let path =
+            std::env::temp_dir().join(format!("signed-url-load-test-{}", std::process::id()));
+        fs::write(&path, "too short\n").unwrap();
+        assert!(matches!(
+            UrlSigner::load(&path),
+            Err(credentials::Error::Parse(..))
+        ));
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            UrlSigner::load(&path),
+            Err(credentials::Error::Io(..))
+        ));
+    }
+
+    #[test]
+    fn url_signer_test() {
+        let signer = load_signer("a secret only known to the server\n");
+        let future = 4102444800;
+        let url = signer.sign("/private/annual report.pdf", future);
+        let (path, query) = url.split_once('?').unwrap();
+        assert_eq!(path, "/private/annual%20report.pdf");
+        assert!(query.starts_with("expires=4102444800&signature="));
+        assert_eq!(
+            signer.verify("/private/annual report.pdf", query),
+            Some(Ok(()))
+        );
+        // other parameters are ignored
+        assert_eq!(
+            signer.verify(
+                "/private/annual report.pdf",
+                &format!("download=1&{}", query)
+            ),
+            Some(Ok(()))
+        );
+
+        // the signature only covers its path, until its expiry date
+        assert_eq!(
+            signer.verify("/private/other.pdf", query),
+            Some(Err(SignatureError::Invalid))
+        );
+        assert_eq!(
+            signer.verify(
+                "/private/annual report.pdf",
+                &query.replace("4102444800", "4102444801")
+            ),
+            Some(Err(SignatureError::Invalid))
+        );
+        assert_eq!(
+            signer.verify("/private/annual report.pdf", &format!("{}x", query)),
+            Some(Err(SignatureError::Invalid))
+        );
+        let url = signer.sign("/private/annual report.pdf", 1700000000);
+        assert_eq!(
+            signer.verify("/private/annual report.pdf", url.split_once('?').unwrap().1),
+            Some(Err(SignatureError::Expired))
+        );
+        // with the secret of the server
+        assert_eq!(
+            load_signer("another secret, of another server")
+                .verify("/private/annual report.pdf", query),
+            Some(Err(SignatureError::Invalid))
+        );
+
+        assert_eq!(signer.verify("/private/annual report.pdf", ""), None);
+        assert_eq!(
+            signer.verify("/private/annual report.pdf", "signature=abc"),
+            Some(Err(SignatureError::Malformed))
+        );
+        assert_eq!(
+            signer.verify(
+                "/private/annual report.pdf",
+                "expires=tomorrow&signature=abc"
+            ),
+            Some(Err(SignatureError::Malformed))
+        );
+    }
+}
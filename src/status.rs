@@ -0,0 +1,599 @@
+//! Status of the server, in the manner of the Apache `server-status` page: the connections being
+//! served (with their current request, the bytes sent and how long it has been running), and the
+//! totals of the requests served since the server started.
+//!
+//! Each connection only updates its own slot, so that connections never wait for each other: the
+//! requests of each path are counted in the slot, and only added to the shared counts (of the
+//! busiest paths) every few requests and when the connection is closed.
+
+use crate::http_req::{HttpReq, ReqHead, ReqPath, ReqTarget};
+use crate::utils;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{cmp, sync, time};
+
+/// Time given to the connections to finish their requests once the server is drained.
+pub const DRAIN_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+/// Number of distinct paths whose requests are counted (so that clients cannot exhaust the memory
+/// of the server), the least requested one being replaced by a new path once it is reached.
+const MAX_PATHS: usize = 100;
+/// Number of requests of a connection after which its counts of paths are added to the shared ones.
+const PATH_BATCH: u64 = 64;
+/// Number of busiest paths listed in the status.
+pub(crate) const TOP_PATHS: usize = 10;
+
+/// Status shared by all the connections of a server.
+pub struct ServerStatus {
+    started: time::Instant,
+    start_date: chrono::DateTime<chrono::Utc>,
+    next_id: AtomicU64,
+    /// Connections being served, by ID (i.e. in the order they were opened)
+    connections: sync::Mutex<BTreeMap<u64, sync::Arc<Slot>>>,
+    requests: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+    bytes_sent: AtomicU64,
+    /// Requests served, by path
+    paths: sync::Mutex<TopPaths>,
+    /// Whether the server is drained, i.e. stops accepting connections and closes the idle ones
+    draining: tokio::sync::watch::Sender<bool>,
+}
+
+/// State of a connection (or of an HTTP/2 or HTTP/3 stream).
+struct Slot {
+    client: String,
+    opened: time::Instant,
+    requests: AtomicU64,
+    bytes_sent: AtomicU64,
+    request: sync::Mutex<Option<Request>>,
+    /// Requests served, by path, not added to the counts of the server yet
+    paths: sync::Mutex<HashMap<String, u64>>,
+}
+
+/// Approximate counts of the requests of the busiest paths, with the Space-Saving algorithm: once
+/// `MAX_PATHS` paths are counted, a new path replaces the least requested one and inherits its
+/// count. A count may thus be overestimated (by at most the count it inherited), but a busy path is
+/// never replaced by paths requested only a few times (e.g. by a scanner).
+#[derive(Default)]
+struct TopPaths {
+    counts: HashMap<String, u64>,
+}
+
+/// Current (or last) request of a connection.
+struct Request {
+    line: String,
+    host: Option<String>,
+    started: time::Instant,
+    /// When the response was sent, if it was
+    finished: Option<time::Instant>,
+}
+
+/// Slot of a connection in the status of the server, freed when dropped.
+pub(crate) struct ConnectionSlot {
+    status: sync::Arc<ServerStatus>,
+    id: u64,
+    slot: sync::Arc<Slot>,
+}
+
+/// Copy of the status of the server at some point in time.
+#[derive(Debug)]
+pub(crate) struct Snapshot {
+    pub(crate) start_date: chrono::DateTime<chrono::Utc>,
+    pub(crate) uptime: time::Duration,
+    pub(crate) draining: bool,
+    pub(crate) requests: u64,
+    pub(crate) client_errors: u64,
+    pub(crate) server_errors: u64,
+    pub(crate) bytes_sent: u64,
+    pub(crate) connections: Vec<ConnectionSnapshot>,
+    /// Busiest paths, with their number of requests
+    pub(crate) top_paths: Vec<(String, u64)>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ConnectionSnapshot {
+    pub(crate) client: String,
+    pub(crate) age: time::Duration,
+    pub(crate) requests: u64,
+    pub(crate) bytes_sent: u64,
+    /// Whether a request is being served
+    pub(crate) busy: bool,
+    /// Current (or last) request line, and its Host header
+    pub(crate) request: Option<(String, Option<String>)>,
+    /// Time taken by the current (or last) request so far
+    pub(crate) duration: time::Duration,
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl Default for ServerStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerStatus {
+    pub fn new() -> Self {
+        Self {
+            started: time::Instant::now(),
+            start_date: chrono::Utc::now(),
+            next_id: AtomicU64::new(0),
+            connections: sync::Mutex::new(BTreeMap::new()),
+            requests: AtomicU64::new(0),
+            client_errors: AtomicU64::new(0),
+            server_errors: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            paths: sync::Mutex::new(TopPaths::default()),
+            draining: tokio::sync::watch::Sender::new(false),
+        }
+    }
+
+    /// Register a connection from a client, listed in the status until the returned slot is
+    /// dropped.
+    pub(crate) fn open(self: &sync::Arc<Self>, client: &str) -> ConnectionSlot {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let slot = sync::Arc::new(Slot {
+            client: String::from(client),
+            opened: time::Instant::now(),
+            requests: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            request: sync::Mutex::new(None),
+            paths: sync::Mutex::new(HashMap::new()),
+        });
+        self.connections.lock().unwrap().insert(id, slot.clone());
+        ConnectionSlot {
+            status: self.clone(),
+            id,
+            slot,
+        }
+    }
+
+    /// Drain the server: it stops accepting connections, and the open ones are closed once their
+    /// current request is served.
+    pub fn drain(&self) {
+        self.draining.send_replace(true);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    /// Receiver notified when the server is drained.
+    pub(crate) fn draining(&self) -> tokio::sync::watch::Receiver<bool> {
+        self.draining.subscribe()
+    }
+
+    /// Number of connections being served.
+    pub fn active_connections(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    /// Wait until all the connections are closed, for at most some time: whether they all were is
+    /// returned.
+    pub async fn wait_idle(&self, timeout: time::Duration) -> bool {
+        let deadline = time::Instant::now() + timeout;
+        while self.active_connections() > 0 {
+            if time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(time::Duration::from_millis(100)).await;
+        }
+        true
+    }
+
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        let now = time::Instant::now();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let slots = self
+            .connections
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        let connections = slots
+            .iter()
+            .map(|slot| {
+                let request = slot.request.lock().unwrap();
+                ConnectionSnapshot {
+                    client: slot.client.clone(),
+                    age: now - slot.opened,
+                    requests: load(&slot.requests),
+                    bytes_sent: load(&slot.bytes_sent),
+                    busy: request.as_ref().is_some_and(|req| req.finished.is_none()),
+                    request: request
+                        .as_ref()
+                        .map(|req| (req.line.clone(), req.host.clone())),
+                    duration: request.as_ref().map_or(time::Duration::ZERO, |req| {
+                        req.finished.unwrap_or(now) - req.started
+                    }),
+                }
+            })
+            .collect();
+        // add the requests not counted by the server yet
+        let mut paths = self.paths.lock().unwrap().counts.clone();
+        for slot in &slots {
+            for (path, count) in slot.paths.lock().unwrap().iter() {
+                *paths.entry(path.clone()).or_default() += count;
+            }
+        }
+        let mut top_paths = paths.into_iter().collect::<Vec<_>>();
+        top_paths.sort_by(|(a_path, a_count), (b_path, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_path.cmp(b_path))
+        });
+        top_paths.truncate(TOP_PATHS);
+        Snapshot {
+            start_date: self.start_date,
+            uptime: now - self.started,
+            draining: self.is_draining(),
+            requests: load(&self.requests),
+            client_errors: load(&self.client_errors),
+            server_errors: load(&self.server_errors),
+            bytes_sent: load(&self.bytes_sent),
+            connections,
+            top_paths,
+        }
+    }
+}
+
+impl ConnectionSlot {
+    /// Record the start of a request, once its head is parsed.
+    pub(crate) fn start_request(&self, head: &ReqHead) {
+        // the query is left out, as it may hold secrets (e.g. tokens or signatures)
+        let target = head.target().to_string();
+        let target = target
+            .split_once('?')
+            .map_or(target.as_str(), |(path, _)| path);
+        *self.slot.request.lock().unwrap() = Some(Request {
+            line: format!("{} {} {}", head.verb(), target, head.version()),
+            host: head.host().map(String::from),
+            started: time::Instant::now(),
+            finished: None,
+        });
+    }
+
+    /// Record a request (which could not be parsed if missing) once its response is sent.
+    pub(crate) fn finish_request(&self, req: Option<&HttpReq>, status: u16, bytes: u64) {
+        let requests = self.slot.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        self.slot.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(request) = self.slot.request.lock().unwrap().as_mut() {
+            request.finished = Some(time::Instant::now());
+        }
+        self.status.requests.fetch_add(1, Ordering::Relaxed);
+        self.status.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        match status {
+            400..500 => self.status.client_errors.fetch_add(1, Ordering::Relaxed),
+            500.. => self.status.server_errors.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+        if let Some(req) = req
+            && let ReqTarget::Path(ReqPath { decoded, .. }) = req.target()
+        {
+            let mut paths = self.slot.paths.lock().unwrap();
+            *paths.entry(decoded.clone()).or_default() += 1;
+        }
+        if requests.is_multiple_of(PATH_BATCH) {
+            self.flush_paths();
+        }
+    }
+
+    /// Add the requests of each path counted by the connection to the counts of the server.
+    fn flush_paths(&self) {
+        let paths = std::mem::take(&mut *self.slot.paths.lock().unwrap());
+        if paths.is_empty() {
+            return;
+        }
+        let mut top_paths = self.status.paths.lock().unwrap();
+        for (path, count) in paths {
+            top_paths.add(path, count);
+        }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.flush_paths();
+        self.status.connections.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl TopPaths {
+    fn add(&mut self, path: String, count: u64) {
+        if let Some(current) = self.counts.get_mut(&path) {
+            *current += count;
+            return;
+        }
+        let mut inherited = 0;
+        if self.counts.len() >= MAX_PATHS
+            && let Some((least, least_count)) = self
+                .counts
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(path, count)| (path.clone(), *count))
+        {
+            self.counts.remove(&least);
+            inherited = least_count;
+        }
+        self.counts.insert(path, inherited + count);
+    }
+}
+
+impl Snapshot {
+    /// Average number of requests per second since the server started.
+    pub(crate) fn request_rate(&self) -> f64 {
+        self.requests as f64 / self.uptime.as_secs_f64().max(1.0)
+    }
+
+    pub(crate) fn to_html(&self) -> String {
+        let rows = self
+            .connections
+            .iter()
+            .map(|connection| {
+                let (line, host) = match connection.request.as_ref() {
+                    Some((line, host)) => (line.as_str(), host.as_deref().unwrap_or("")),
+                    None => ("", ""),
+                };
+                format!(
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.3}</td></tr>",
+                    utils::escape_html(&connection.client),
+                    format_duration(connection.age),
+                    connection.requests,
+                    connection.bytes_sent,
+                    if connection.busy { "serving" } else { "idle" },
+                    utils::escape_html(host),
+                    utils::escape_html(line),
+                    connection.duration.as_secs_f64(),
+                )
+            })
+            .collect::<String>();
+        let paths = self
+            .top_paths
+            .iter()
+            .map(|(path, count)| {
+                format!(
+                    "<tr><td>{}</td><td>{}</td></tr>",
+                    utils::escape_html(path),
+                    count
+                )
+            })
+            .collect::<String>();
+        format!(
+            "<!DOCTYPE html> \
+             <html lang=\"en\"> \
+                <head> \
+                    <meta charset=\"utf-8\"/> \
+                    <title>Server status</title> \
+                </head> \
+                <body> \
+                    <h1>Server status</h1> \
+                    <ul> \
+                        <li>Started: {}</li> \
+                        <li>Uptime: {}{}</li> \
+                        <li>Requests: {} ({:.2}/s), {} client errors, {} server errors</li> \
+                        <li>Bytes sent: {}</li> \
+                    </ul> \
+                    <h2>{} active connection(s)</h2> \
+                    <table> \
+                        <tr><th>Client</th><th>Connected for</th><th>Requests</th><th>Bytes sent</th><th>State</th><th>Host</th><th>Request</th><th>Duration (s)</th></tr> \
+                        {} \
+                    </table> \
+                    <h2>Top paths</h2> \
+                    <table> \
+                        <tr><th>Path</th><th>Requests</th></tr> \
+                        {} \
+                    </table> \
+                </body> \
+             </html> \
+             \r\n",
+            self.start_date.to_rfc2822(),
+            format_duration(self.uptime),
+            if self.draining { " (draining)" } else { "" },
+            self.requests,
+            self.request_rate(),
+            self.client_errors,
+            self.server_errors,
+            self.bytes_sent,
+            self.connections.len(),
+            rows,
+            paths,
+        )
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        serde_json::json!({
+            "start_date": self.start_date.to_rfc3339(),
+            "uptime_seconds": self.uptime.as_secs(),
+            "draining": self.draining,
+            "requests": self.requests,
+            "requests_per_second": self.request_rate(),
+            "client_errors": self.client_errors,
+            "server_errors": self.server_errors,
+            "bytes_sent": self.bytes_sent,
+            "connections": self
+                .connections
+                .iter()
+                .map(|connection| {
+                    let (line, host) = match connection.request.as_ref() {
+                        Some((line, host)) => (Some(line), host.as_ref()),
+                        None => (None, None),
+                    };
+                    serde_json::json!({
+                        "client": connection.client,
+                        "connected_seconds": connection.age.as_secs(),
+                        "requests": connection.requests,
+                        "bytes_sent": connection.bytes_sent,
+                        "state": if connection.busy { "serving" } else { "idle" },
+                        "host": host,
+                        "request": line,
+                        "duration_seconds": connection.duration.as_secs_f64(),
+                    })
+                })
+                .collect::<Vec<_>>(),
+            "top_paths": self
+                .top_paths
+                .iter()
+                .map(|(path, count)| serde_json::json!({"path": path, "requests": count}))
+                .collect::<Vec<_>>(),
+        })
+        .to_string()
+    }
+}
+
+/// Format a duration in days, hours, minutes and seconds (e.g. 1d 02:03:04).
+pub(crate) fn format_duration(duration: time::Duration) -> String {
+    let secs = duration.as_secs();
+    let time = format!(
+        "{:02}:{:02}:{:02}",
+        (secs / 3600) % 24,
+        (secs / 60) % 60,
+        secs % 60
+    );
+    match secs / 86400 {
+        0 => time,
+        days => format!("{}d {}", days, time),
+    }
+}
+
+/// Share of the requests which failed (with a client or server error), in percent.
+pub(crate) fn error_ratio(requests: u64, errors: u64) -> f64 {
+    errors as f64 * 100.0 / cmp::max(requests, 1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::req_parser::ReqHeadParser;
+
+    fn parse_head(lines: &[&str]) -> ReqHead {
+        let mut parser = ReqHeadParser::new();
+        for line in lines {
+            parser
+                .process_bytes(Vec::from(format!("{}\r\n", line)))
+                .unwrap();
+        }
+        parser.process_bytes(Vec::from("\r\n")).unwrap();
+        parser.do_parse().unwrap()
+    }
+
+    #[test]
+    fn format_duration_test() {
+        assert_eq!(format_duration(time::Duration::from_secs(0)), "00:00:00");
+        assert_eq!(format_duration(time::Duration::from_secs(3723)), "01:02:03");
+        assert_eq!(
+            format_duration(time::Duration::from_secs(2 * 86400 + 59)),
+            "2d 00:00:59"
+        );
+    }
+
+    #[test]
+    fn server_status_test() {
+        let status = sync::Arc::new(ServerStatus::new());
+        let first = status.open("127.0.0.1");
+        let second = status.open("[::1]");
+        assert_eq!(status.active_connections(), 2);
+
+        let head = parse_head(&["GET /index.html?token=secret HTTP/1.1", "Host: example.org"]);
+        first.start_request(&head);
+        let req = HttpReq::new(chrono::Utc::now(), head, None);
+        first.finish_request(Some(&req), 200, 100);
+        assert_eq!(
+            first.slot.request.lock().unwrap().as_ref().unwrap().line,
+            "GET /index.html HTTP/1.1"
+        );
+        first.start_request(&parse_head(&["GET /<script> HTTP/1.1"]));
+        second.finish_request(None, 400, 10);
+
+        let snapshot = status.snapshot();
+        assert!(!snapshot.draining);
+        assert_eq!(snapshot.requests, 2);
+        assert_eq!(snapshot.client_errors, 1);
+        assert_eq!(snapshot.server_errors, 0);
+        assert_eq!(snapshot.bytes_sent, 110);
+        assert_eq!(snapshot.top_paths, vec![(String::from("/index.html"), 1)]);
+        assert_eq!(snapshot.connections.len(), 2);
+        let connection = &snapshot.connections[0];
+        assert_eq!(connection.client, "127.0.0.1");
+        assert_eq!(connection.requests, 1);
+        assert_eq!(connection.bytes_sent, 100);
+        assert!(connection.busy);
+        assert_eq!(
+            connection.request,
+            Some((String::from("GET /<script> HTTP/1.1"), None))
+        );
+        assert!(!snapshot.connections[1].busy);
+        assert_eq!(snapshot.connections[1].request, None);
+
+        let html = snapshot.to_html();
+        assert!(html.contains("<td>GET /&lt;script&gt; HTTP/1.1</td>"));
+        assert!(html.contains("<tr><td>/index.html</td><td>1</td></tr>"));
+        let json: serde_json::Value = serde_json::from_str(&snapshot.to_json()).unwrap();
+        assert_eq!(json["requests"], 2);
+        assert_eq!(json["connections"][0]["state"], "serving");
+        assert_eq!(json["connections"][1]["request"], serde_json::Value::Null);
+        assert_eq!(json["top_paths"][0]["path"], "/index.html");
+
+        drop(first);
+        assert_eq!(status.active_connections(), 1);
+        status.drain();
+        assert!(status.is_draining());
+        assert!(*status.draining().borrow());
+        drop(second);
+        assert_eq!(status.active_connections(), 0);
+    }
+
+    #[test]
+    fn top_paths_test() {
+        let status = sync::Arc::new(ServerStatus::new());
+        let request = |path: &str| {
+            let head = parse_head(&[&format!("GET {} HTTP/1.1", path)]);
+            HttpReq::new(chrono::Utc::now(), head, None)
+        };
+        let busy = status.open("127.0.0.1");
+        for _ in 0..PATH_BATCH + 10 {
+            busy.finish_request(Some(&request("/index.html")), 200, 0);
+        }
+        // the requests of the last batch are counted by the connection only
+        assert_eq!(
+            status.paths.lock().unwrap().counts.get("/index.html"),
+            Some(&PATH_BATCH)
+        );
+        assert_eq!(
+            status.snapshot().top_paths,
+            vec![(String::from("/index.html"), PATH_BATCH + 10)]
+        );
+        drop(busy);
+        assert_eq!(
+            status.paths.lock().unwrap().counts.get("/index.html"),
+            Some(&(PATH_BATCH + 10))
+        );
+
+        // a scanner requesting many paths does not replace the busy one
+        let scanner = status.open("127.0.0.2");
+        for i in 0..MAX_PATHS * 5 {
+            scanner.finish_request(Some(&request(&format!("/scan{}", i))), 404, 0);
+        }
+        drop(scanner);
+        let paths = status.paths.lock().unwrap();
+        assert_eq!(paths.counts.len(), MAX_PATHS);
+        assert_eq!(paths.counts.get("/index.html"), Some(&(PATH_BATCH + 10)));
+        drop(paths);
+        assert_eq!(
+            status.snapshot().top_paths[0],
+            (String::from("/index.html"), PATH_BATCH + 10)
+        );
+    }
+
+    #[tokio::test]
+    async fn wait_idle_test() {
+        let status = sync::Arc::new(ServerStatus::new());
+        assert!(status.wait_idle(time::Duration::ZERO).await);
+        let slot = status.open("127.0.0.1");
+        assert!(!status.wait_idle(time::Duration::from_millis(150)).await);
+        let waiting = tokio::spawn({
+            let status = status.clone();
+            async move { status.wait_idle(time::Duration::from_secs(5)).await }
+        });
+        drop(slot);
+        assert!(waiting.await.unwrap());
+    }
+}
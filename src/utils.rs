@@ -1,5 +1,8 @@
 //! Utility functions used in various places around the project.
 
+use std::{fmt, fs, net, path, str::FromStr, time};
+use tokio::io::{AsyncBufRead, AsyncReadExt};
+
 /// Check whether a Mime type is compatible with another, i.e. if the first is a superset of the second.
 ///
 /// # Examples
@@ -16,6 +19,227 @@ pub fn are_mime_compatible(accepted: &mime_guess::Mime, actual: &mime_guess::Mim
             || (accepted.subtype() == actual.subtype()))
 }
 
+/// Check whether a path starts with a prefix, at a segment boundary (i.e. `/api` matches `/api` and
+/// `/api/users`, but not `/apis`).
+///
+/// # Examples
+///
+/// ```
+/// use rust_http_server::utils::matches_path_prefix;
+/// assert!(matches_path_prefix("/api/users", "/api"));
+/// assert!(matches_path_prefix("/static/app.js", "/static/"));
+/// assert!(!matches_path_prefix("/apis", "/api"));
+/// ```
+pub fn matches_path_prefix(path: &str, prefix: &str) -> bool {
+    path.starts_with(prefix)
+        && (prefix.ends_with('/')
+            || path.len() == prefix.len()
+            || path[prefix.len()..].starts_with('/'))
+}
+
+/// Check whether the value of a Host header (which may include a port) designates a host name.
+///
+/// # Examples
+///
+/// ```
+/// use rust_http_server::utils::matches_host;
+/// assert!(matches_host("Example.org:8080", "example.org"));
+/// assert!(!matches_host("www.example.org", "example.org"));
+/// ```
+pub fn matches_host(host: &str, name: &str) -> bool {
+    // strip the port from the Host header, if any
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => host,
+    };
+    host.eq_ignore_ascii_case(name)
+}
+
+/// Escape the characters of a text having a special meaning in HTML, to insert it in a page (in an
+/// element or an attribute value).
+///
+/// # Examples
+///
+/// ```
+/// use rust_http_server::utils::escape_html;
+/// assert_eq!(escape_html("<a href=\"/?a=1&b=2\">"), "&lt;a href=&quot;/?a=1&amp;b=2&quot;&gt;");
+/// ```
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape the quotes, backslashes and control characters of a value coming from the client, so
+/// that it cannot forge log entries nor drive a terminal (with escape sequences).
+///
+/// # Examples
+///
+/// ```
+/// use rust_http_server::utils::escape;
+/// assert_eq!(escape("a\"b\\c\r\nd\x1b[2J"), r#"a\"b\\c\x0d\x0ad\x1b[2J"#);
+/// ```
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Decoded value of a field of a URL-encoded form (or query string), if any.
+///
+/// # Examples
+///
+/// ```
+/// use rust_http_server::utils::form_value;
+/// assert_eq!(form_value("user=john+doe&redirect=%2Fa%3Fb", "redirect"), Some(String::from("/a?b")));
+/// assert_eq!(form_value("user=john+doe&redirect=%2Fa%3Fb", "user"), Some(String::from("john doe")));
+/// assert_eq!(form_value("user=john", "password"), None);
+/// ```
+pub fn form_value(form: &str, name: &str) -> Option<String> {
+    form.split('&')
+        .filter_map(|field| field.split_once('='))
+        .find(|(field_name, _)| *field_name == name)
+        .and_then(|(_, value)| {
+            urlencoding::decode(&value.replace('+', " "))
+                .ok()
+                .map(|value| value.into_owned())
+        })
+}
+
+/// An IP network in CIDR notation (e.g. `192.168.0.0/16`), a single IP address being a network of
+/// its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpNetwork {
+    address: net::IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s, None),
+        };
+        let address: net::IpAddr = address
+            .parse()
+            .map_err(|_| format!("Invalid IP network: {}", s))?;
+        let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .ok_or_else(|| format!("Invalid IP network prefix length: {}", s))?,
+            None => max_prefix_len,
+        };
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+impl IpNetwork {
+    /// Check whether an IP address belongs to the network (IPv4-mapped IPv6 addresses being
+    /// considered as IPv4 ones).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rust_http_server::utils::IpNetwork;
+    /// let network: IpNetwork = "10.0.0.0/8".parse().unwrap();
+    /// assert!(network.contains("10.1.2.3".parse().unwrap()));
+    /// assert!(network.contains("::ffff:10.1.2.3".parse().unwrap()));
+    /// assert!(!network.contains("192.168.1.1".parse().unwrap()));
+    /// ```
+    pub fn contains(&self, ip: net::IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (net::IpAddr::V4(network), net::IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (net::IpAddr::V6(network), net::IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Discard a request body, so that the client connection stays usable.
+pub(crate) async fn discard_body<R: AsyncBufRead + Unpin>(client_reader: &mut R, body_len: usize) {
+    let _ = tokio::io::copy(
+        &mut (&mut *client_reader).take(body_len as u64),
+        &mut tokio::io::sink(),
+    )
+    .await;
+}
+
+/// Detect changes of files (e.g. renewed certificates or credential files), from their modification time.
+pub(crate) struct FileWatcher {
+    files: Vec<(path::PathBuf, Option<time::SystemTime>)>,
+}
+
+impl FileWatcher {
+    pub(crate) fn new(paths: Vec<path::PathBuf>) -> Self {
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let modified = Self::modified(&path);
+                (path, modified)
+            })
+            .collect();
+        Self { files }
+    }
+
+    fn modified(path: &path::Path) -> Option<time::SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Check whether some files changed since the previous check.
+    pub(crate) fn has_changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, modified) in self.files.iter_mut() {
+            let current = Self::modified(path);
+            if current != *modified {
+                *modified = current;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,4 +281,49 @@ mod tests {
             &mime_guess::mime::IMAGE_PNG
         ));
     }
+
+    #[test]
+    fn ip_network_test() {
+        let network: IpNetwork = "192.168.0.0/16".parse().unwrap();
+        assert!(network.contains("192.168.10.1".parse().unwrap()));
+        assert!(!network.contains("192.169.0.1".parse().unwrap()));
+        assert!(!network.contains("::1".parse().unwrap()));
+
+        let network: IpNetwork = "2001:db8::/32".parse().unwrap();
+        assert!(network.contains("2001:db8:cafe::17".parse().unwrap()));
+        assert!(!network.contains("2001:db9::1".parse().unwrap()));
+
+        let network: IpNetwork = "127.0.0.1".parse().unwrap();
+        assert_eq!(network.to_string(), "127.0.0.1/32");
+        assert!(network.contains("127.0.0.1".parse().unwrap()));
+        assert!(!network.contains("127.0.0.2".parse().unwrap()));
+        let network: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(network.contains("203.0.113.7".parse().unwrap()));
+
+        assert!("192.168.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("192.168.0.0/".parse::<IpNetwork>().is_err());
+        assert!("localhost".parse::<IpNetwork>().is_err());
+    }
+
+    #[test]
+    fn file_watcher_test() {
+        let dir = std::env::temp_dir().join(format!("file-watcher-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("server.crt");
+        fs::write(&file, "foo").unwrap();
+
+        let mut watcher = FileWatcher::new(vec![file.clone(), dir.join("none.crt")]);
+        assert!(!watcher.has_changed());
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(time::SystemTime::now() + time::Duration::from_secs(1))
+            .unwrap();
+        assert!(watcher.has_changed());
+        assert!(!watcher.has_changed());
+        fs::remove_file(&file).unwrap();
+        assert!(watcher.has_changed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
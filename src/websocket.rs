@@ -0,0 +1,667 @@
+//! WebSocket protocol (RFC 6455): opening handshake and framed messages.
+//!
+//! Once the server answered a valid upgrade request with 101 Switching Protocols, the connection is
+//! handed to the WebSocket handler registered on the request path. The handler exchanges messages
+//! with the client through a [`WebSocket`], which takes care of framing, masking, fragmentation and
+//! control frames.
+
+use crate::http_header::{GeneralHeader, ReqHeader};
+use crate::http_req::{HttpReq, ReqHead, ReqVerb};
+
+use base64::Engine;
+use sha1::Digest;
+use std::hash::{BuildHasher, Hasher};
+use std::{fmt, future, io, pin};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// GUID appended to the client key to compute the accept key (see RFC 6455, section 1.3).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The only protocol version supported (RFC 6455).
+pub const WEBSOCKET_VERSION: &str = "13";
+/// Maximum size of a message (possibly made of several fragments).
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Close status codes used when the connection fails.
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Define a wrapper trait for the stream a WebSocket runs over (plain TCP or TLS)
+pub trait Stream: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send {}
+impl<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send> Stream for S {}
+
+/// A handler serving the WebSocket connections opened on a path.
+pub trait Handler: Send + Sync {
+    /// Handle a WebSocket connection, until it is closed.
+    ///
+    /// The upgrade request is provided, e.g. to read its query or headers.
+    fn handle<'a>(
+        &'a self,
+        websocket: WebSocket<&'a mut dyn Stream>,
+        req: &'a HttpReq,
+    ) -> pin::Pin<Box<dyn future::Future<Output = ()> + Send + 'a>>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HandshakeError {
+    InvalidVerb,
+    InvalidVersion,
+    MissingUpgrade,
+    MissingConnectionUpgrade,
+    InvalidKey,
+    /// The client requested a WebSocket version other than 13
+    UnsupportedVersion,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The peer does not follow the protocol (e.g. unmasked client frame)
+    Protocol(&'static str),
+    /// A text message (or a close reason) is not valid UTF-8
+    InvalidUtf8,
+    MessageTooBig,
+    /// The closing handshake is done, no message can be exchanged anymore
+    ConnectionClosed,
+}
+
+#[cfg_attr(coverage, coverage(off))]
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Protocol(e) => write!(f, "protocol error: {}", e),
+            Self::InvalidUtf8 => write!(f, "invalid UTF-8 data"),
+            Self::MessageTooBig => write!(f, "message too big"),
+            Self::ConnectionClosed => write!(f, "connection closed"),
+        }
+    }
+}
+
+/// A WebSocket message.
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// Close frame, with an optional status code and reason
+    Close(Option<(u16, String)>),
+}
+
+/// Which end of the connection a WebSocket is: frames sent by clients must be masked, frames sent
+/// by servers must not.
+#[derive(Debug, PartialEq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+/// Check whether a request asks for an upgrade to the WebSocket protocol.
+pub fn is_upgrade_req(req: &ReqHead) -> bool {
+    req.header_str(&ReqHeader::General(GeneralHeader::Upgrade))
+        .is_some_and(|v| has_token(v, "websocket"))
+}
+
+/// Validate the opening handshake of a client, and compute the Sec-WebSocket-Accept value.
+pub fn validate_handshake(req: &HttpReq) -> Result<String, HandshakeError> {
+    if *req.verb() != ReqVerb::Get {
+        return Err(HandshakeError::InvalidVerb);
+    }
+    if req.version() != "HTTP/1.1" {
+        return Err(HandshakeError::InvalidVersion);
+    }
+    if !req
+        .header_str(&ReqHeader::General(GeneralHeader::Upgrade))
+        .is_some_and(|v| has_token(v, "websocket"))
+    {
+        return Err(HandshakeError::MissingUpgrade);
+    }
+    if !req
+        .header_str(&ReqHeader::General(GeneralHeader::Connection))
+        .is_some_and(|v| has_token(v, "upgrade"))
+    {
+        return Err(HandshakeError::MissingConnectionUpgrade);
+    }
+    let key = req
+        .header_str(&ReqHeader::Other(String::from("sec-websocket-key")))
+        .map(str::trim)
+        .ok_or(HandshakeError::InvalidKey)?;
+    match base64::prelude::BASE64_STANDARD.decode(key) {
+        Ok(nonce) if nonce.len() == 16 => (),
+        _ => return Err(HandshakeError::InvalidKey),
+    }
+    if req
+        .header_str(&ReqHeader::Other(String::from("sec-websocket-version")))
+        .map(str::trim)
+        != Some(WEBSOCKET_VERSION)
+    {
+        return Err(HandshakeError::UnsupportedVersion);
+    }
+    Ok(accept_key(key))
+}
+
+/// Compute the Sec-WebSocket-Accept value matching a Sec-WebSocket-Key.
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = sha1::Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+    base64::prelude::BASE64_STANDARD.encode(hasher.finalize())
+}
+
+/// Generate a key for the opening handshake of a client.
+pub fn generate_key() -> String {
+    let mut nonce = random_bytes(16);
+    nonce.truncate(16);
+    base64::prelude::BASE64_STANDARD.encode(nonce)
+}
+
+/// Check whether a comma-separated header value contains a token (case-insensitive).
+fn has_token(value: &str, token: &str) -> bool {
+    value
+        .split(',')
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// Generate some unpredictable bytes (at least `len`), e.g. for masking keys.
+///
+/// The standard library hasher is randomly seeded, which is enough for masking purposes (masking
+/// is meant to prevent proxy cache poisoning, not to encrypt).
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(len + 8);
+    while bytes.len() < len {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_usize(bytes.len());
+        bytes.extend(hasher.finish().to_ne_bytes());
+    }
+    bytes
+}
+
+/// A frame, as read from the stream.
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// A WebSocket connection, once the opening handshake is done.
+pub struct WebSocket<S> {
+    stream: S,
+    role: Role,
+    /// Message being received in several fragments (opcode and data received so far)
+    fragmented: Option<(u8, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin> WebSocket<S> {
+    pub fn new(stream: S, role: Role) -> Self {
+        Self {
+            stream,
+            role,
+            fragmented: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Receive the next message.
+    ///
+    /// Fragmented messages are reassembled, pings are answered automatically (and still returned),
+    /// and a close frame is answered with a close frame if needed. Once the closing handshake is
+    /// done, [`Error::ConnectionClosed`] is returned.
+    pub async fn recv(&mut self) -> Result<Message, Error> {
+        loop {
+            if self.close_received {
+                return Err(Error::ConnectionClosed);
+            }
+            let frame = match self.read_frame().await {
+                Ok(frame) => frame,
+                Err(err) => return Err(self.fail(err).await),
+            };
+            match frame.opcode {
+                OPCODE_PING => {
+                    if !self.close_sent {
+                        self.write_frame(true, OPCODE_PONG, &frame.payload).await?;
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                }
+                OPCODE_PONG => return Ok(Message::Pong(frame.payload)),
+                OPCODE_CLOSE => {
+                    self.close_received = true;
+                    let close = match parse_close_payload(&frame.payload) {
+                        Ok(close) => close,
+                        Err(err) => return Err(self.fail(err).await),
+                    };
+                    // answer with the same status code
+                    if !self.close_sent {
+                        let code = close.as_ref().map_or(CLOSE_NORMAL, |(code, _)| *code);
+                        self.send_close(code, "").await?;
+                    }
+                    return Ok(Message::Close(close));
+                }
+                OPCODE_TEXT | OPCODE_BINARY if self.fragmented.is_some() => {
+                    return Err(self
+                        .fail(Error::Protocol("expected a continuation frame"))
+                        .await);
+                }
+                OPCODE_TEXT | OPCODE_BINARY if !frame.fin => {
+                    self.fragmented = Some((frame.opcode, frame.payload));
+                }
+                OPCODE_TEXT | OPCODE_BINARY => {
+                    return match build_message(frame.opcode, frame.payload) {
+                        Ok(message) => Ok(message),
+                        Err(err) => Err(self.fail(err).await),
+                    };
+                }
+                OPCODE_CONTINUATION => {
+                    let Some((opcode, mut data)) = self.fragmented.take() else {
+                        return Err(self
+                            .fail(Error::Protocol("unexpected continuation frame"))
+                            .await);
+                    };
+                    if data.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+                        return Err(self.fail(Error::MessageTooBig).await);
+                    }
+                    data.extend(frame.payload);
+                    if !frame.fin {
+                        self.fragmented = Some((opcode, data));
+                        continue;
+                    }
+                    return match build_message(opcode, data) {
+                        Ok(message) => Ok(message),
+                        Err(err) => Err(self.fail(err).await),
+                    };
+                }
+                _ => return Err(self.fail(Error::Protocol("unknown opcode")).await),
+            }
+        }
+    }
+
+    /// Send a message, in a single frame.
+    pub async fn send(&mut self, message: Message) -> Result<(), Error> {
+        match message {
+            Message::Text(text) => self.write_frame(true, OPCODE_TEXT, text.as_bytes()).await,
+            Message::Binary(data) => self.write_frame(true, OPCODE_BINARY, &data).await,
+            Message::Ping(data) => self.write_control_frame(OPCODE_PING, &data).await,
+            Message::Pong(data) => self.write_control_frame(OPCODE_PONG, &data).await,
+            Message::Close(None) => {
+                self.write_control_frame(OPCODE_CLOSE, &[]).await?;
+                self.close_sent = true;
+                Ok(())
+            }
+            Message::Close(Some((code, reason))) => self.send_close(code, &reason).await,
+        }
+    }
+
+    /// Send a text or binary message, split into fragments of (at most) the given length.
+    pub async fn send_fragmented(
+        &mut self,
+        message: Message,
+        fragment_len: usize,
+    ) -> Result<(), Error> {
+        let (opcode, data) = match &message {
+            Message::Text(text) => (OPCODE_TEXT, text.as_bytes()),
+            Message::Binary(data) => (OPCODE_BINARY, data.as_slice()),
+            // control frames cannot be fragmented
+            _ => return self.send(message).await,
+        };
+        let fragments = data.chunks(fragment_len.max(1)).collect::<Vec<_>>();
+        if fragments.is_empty() {
+            return self.write_frame(true, opcode, &[]).await;
+        }
+        for (i, fragment) in fragments.iter().enumerate() {
+            let opcode = if i == 0 { opcode } else { OPCODE_CONTINUATION };
+            self.write_frame(i == fragments.len() - 1, opcode, fragment)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Start the closing handshake; the close frame of the peer is then returned by [`Self::recv`].
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        self.send_close(code, reason).await
+    }
+
+    async fn send_close(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        let mut payload = Vec::from(code.to_be_bytes());
+        payload.extend(reason.as_bytes());
+        self.write_control_frame(OPCODE_CLOSE, &payload).await?;
+        self.close_sent = true;
+        Ok(())
+    }
+
+    /// Fail the connection: send a close frame matching the error (if possible), and return it.
+    async fn fail(&mut self, err: Error) -> Error {
+        let code = match err {
+            Error::Protocol(_) => CLOSE_PROTOCOL_ERROR,
+            Error::InvalidUtf8 => CLOSE_INVALID_DATA,
+            Error::MessageTooBig => CLOSE_MESSAGE_TOO_BIG,
+            Error::Io(_) | Error::ConnectionClosed => return err,
+        };
+        if !self.close_sent {
+            let _ = self.send_close(code, "").await;
+        }
+        self.close_received = true;
+        err
+    }
+
+    async fn read_frame(&mut self) -> Result<Frame, Error> {
+        let mut head = [0; 2];
+        self.stream.read_exact(&mut head).await.map_err(Error::Io)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        if head[0] & 0x70 != 0 {
+            return Err(Error::Protocol("reserved bits set"));
+        }
+        let masked = head[1] & 0x80 != 0;
+        match self.role {
+            Role::Server if !masked => return Err(Error::Protocol("unmasked client frame")),
+            Role::Client if masked => return Err(Error::Protocol("masked server frame")),
+            _ => (),
+        }
+
+        let len = match head[1] & 0x7F {
+            126 => self.stream.read_u16().await.map_err(Error::Io)? as u64,
+            127 => self.stream.read_u64().await.map_err(Error::Io)?,
+            len => len as u64,
+        };
+        if opcode >= OPCODE_CLOSE && (!fin || len > 125) {
+            return Err(Error::Protocol("invalid control frame"));
+        }
+        if len > MAX_MESSAGE_SIZE as u64 {
+            return Err(Error::MessageTooBig);
+        }
+
+        let mut mask = [0; 4];
+        if masked {
+            self.stream.read_exact(&mut mask).await.map_err(Error::Io)?;
+        }
+        let mut payload = vec![0; len as usize];
+        self.stream
+            .read_exact(&mut payload)
+            .await
+            .map_err(Error::Io)?;
+        if masked {
+            apply_mask(&mut payload, mask);
+        }
+        Ok(Frame {
+            fin,
+            opcode,
+            payload,
+        })
+    }
+
+    async fn write_control_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), Error> {
+        if payload.len() > 125 {
+            return Err(Error::Protocol("control frame payload too long"));
+        }
+        self.write_frame(true, opcode, payload).await
+    }
+
+    async fn write_frame(&mut self, fin: bool, opcode: u8, payload: &[u8]) -> Result<(), Error> {
+        if self.close_sent {
+            return Err(Error::ConnectionClosed);
+        }
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(if fin { 0x80 } else { 0 } | opcode);
+        let mask_bit = if self.role == Role::Client { 0x80 } else { 0 };
+        match payload.len() {
+            len if len < 126 => frame.push(mask_bit | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(mask_bit | 126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(mask_bit | 127);
+                frame.extend((len as u64).to_be_bytes());
+            }
+        }
+        if self.role == Role::Client {
+            let mask: [u8; 4] = random_bytes(4)[..4].try_into().unwrap();
+            frame.extend(mask);
+            let start = frame.len();
+            frame.extend(payload);
+            apply_mask(&mut frame[start..], mask);
+        } else {
+            frame.extend(payload);
+        }
+        self.stream.write_all(&frame).await.map_err(Error::Io)?;
+        self.stream.flush().await.map_err(Error::Io)
+    }
+}
+
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+fn build_message(opcode: u8, data: Vec<u8>) -> Result<Message, Error> {
+    match opcode {
+        OPCODE_TEXT => String::from_utf8(data)
+            .map(Message::Text)
+            .map_err(|_| Error::InvalidUtf8),
+        _ => Ok(Message::Binary(data)),
+    }
+}
+
+/// Parse the payload of a close frame: an optional status code, followed by a UTF-8 reason.
+fn parse_close_payload(payload: &[u8]) -> Result<Option<(u16, String)>, Error> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(Error::Protocol("invalid close frame")),
+        [high, low, reason @ ..] => {
+            let code = u16::from_be_bytes([*high, *low]);
+            if !matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999) {
+                return Err(Error::Protocol("invalid close code"));
+            }
+            let reason = String::from_utf8(Vec::from(reason)).map_err(|_| Error::InvalidUtf8)?;
+            Ok(Some((code, reason)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_header::{HeaderValue, SimpleHeaderValue};
+    use crate::http_req::{ReqPath, ReqTarget};
+    use std::collections;
+
+    fn upgrade_req(verb: ReqVerb, headers: &[(&str, &str)]) -> HttpReq {
+        let head = ReqHead::new(
+            verb,
+            ReqTarget::Path(ReqPath {
+                original: String::from("/ws"),
+                decoded: String::from("/ws"),
+                query: String::new(),
+            }),
+            String::from("HTTP/1.1"),
+            headers
+                .iter()
+                .map(|(name, value)| {
+                    let name = match *name {
+                        "upgrade" => ReqHeader::General(GeneralHeader::Upgrade),
+                        "connection" => ReqHeader::General(GeneralHeader::Connection),
+                        name => ReqHeader::Other(String::from(name)),
+                    };
+                    (
+                        name,
+                        HeaderValue::Simple(SimpleHeaderValue::String(String::from(*value))),
+                    )
+                })
+                .collect::<collections::HashMap<_, _>>(),
+            None,
+            None,
+        );
+        HttpReq::new(chrono::Utc::now(), head, None)
+    }
+
+    #[test]
+    fn validate_handshake_test() {
+        let headers = [
+            ("upgrade", "websocket"),
+            ("connection", "keep-alive, Upgrade"),
+            ("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ=="),
+            ("sec-websocket-version", "13"),
+        ];
+        // example from RFC 6455
+        assert_eq!(
+            validate_handshake(&upgrade_req(ReqVerb::Get, &headers)),
+            Ok(String::from("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="))
+        );
+        assert_eq!(
+            validate_handshake(&upgrade_req(ReqVerb::Post, &headers)),
+            Err(HandshakeError::InvalidVerb)
+        );
+        assert_eq!(
+            validate_handshake(&upgrade_req(ReqVerb::Get, &headers[1..])),
+            Err(HandshakeError::MissingUpgrade)
+        );
+        assert_eq!(
+            validate_handshake(&upgrade_req(
+                ReqVerb::Get,
+                &[
+                    headers[0],
+                    ("connection", "keep-alive"),
+                    headers[2],
+                    headers[3]
+                ]
+            )),
+            Err(HandshakeError::MissingConnectionUpgrade)
+        );
+        assert_eq!(
+            validate_handshake(&upgrade_req(
+                ReqVerb::Get,
+                &[
+                    headers[0],
+                    headers[1],
+                    ("sec-websocket-key", "Zm9v"),
+                    headers[3]
+                ]
+            )),
+            Err(HandshakeError::InvalidKey)
+        );
+        assert_eq!(
+            validate_handshake(&upgrade_req(
+                ReqVerb::Get,
+                &[
+                    headers[0],
+                    headers[1],
+                    headers[2],
+                    ("sec-websocket-version", "8")
+                ]
+            )),
+            Err(HandshakeError::UnsupportedVersion)
+        );
+
+        // generated keys are valid
+        let key = generate_key();
+        assert_eq!(
+            base64::prelude::BASE64_STANDARD.decode(&key).unwrap().len(),
+            16
+        );
+        assert_ne!(key, generate_key());
+    }
+
+    #[tokio::test]
+    async fn websocket_messages_test() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut client = WebSocket::new(client, Role::Client);
+        let mut server = WebSocket::new(server, Role::Server);
+
+        client
+            .send(Message::Text(String::from("hello")))
+            .await
+            .unwrap();
+        assert_eq!(
+            server.recv().await.unwrap(),
+            Message::Text(String::from("hello"))
+        );
+        server.send(Message::Binary(vec![0; 300])).await.unwrap();
+        assert_eq!(client.recv().await.unwrap(), Message::Binary(vec![0; 300]));
+
+        // fragmented messages are reassembled, even with control frames in between
+        client
+            .write_frame(false, OPCODE_TEXT, "frag".as_bytes())
+            .await
+            .unwrap();
+        client.send(Message::Ping(vec![1, 2])).await.unwrap();
+        client
+            .write_frame(true, OPCODE_CONTINUATION, "mented".as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(server.recv().await.unwrap(), Message::Ping(vec![1, 2]));
+        assert_eq!(
+            server.recv().await.unwrap(),
+            Message::Text(String::from("fragmented"))
+        );
+        // pings are answered automatically
+        assert_eq!(client.recv().await.unwrap(), Message::Pong(vec![1, 2]));
+
+        server
+            .send_fragmented(Message::Binary(vec![1; 10]), 3)
+            .await
+            .unwrap();
+        assert_eq!(client.recv().await.unwrap(), Message::Binary(vec![1; 10]));
+
+        // closing handshake
+        client.close(1000, "bye").await.unwrap();
+        assert!(matches!(
+            client.send(Message::Text(String::new())).await,
+            Err(Error::ConnectionClosed)
+        ));
+        assert_eq!(
+            server.recv().await.unwrap(),
+            Message::Close(Some((1000, String::from("bye"))))
+        );
+        assert_eq!(
+            client.recv().await.unwrap(),
+            Message::Close(Some((1000, String::new())))
+        );
+        assert!(matches!(server.recv().await, Err(Error::ConnectionClosed)));
+        assert!(matches!(client.recv().await, Err(Error::ConnectionClosed)));
+    }
+
+    #[tokio::test]
+    async fn websocket_protocol_error_test() {
+        // servers only accept masked frames
+        let (client, server) = tokio::io::duplex(1024);
+        let mut client = WebSocket::new(client, Role::Server);
+        let mut server = WebSocket::new(server, Role::Server);
+        client
+            .send(Message::Text(String::from("hello")))
+            .await
+            .unwrap();
+        assert!(matches!(server.recv().await, Err(Error::Protocol(_))));
+        // the connection is failed with a close frame
+        let mut client = WebSocket::new(client.stream, Role::Client);
+        assert_eq!(
+            client.recv().await.unwrap(),
+            Message::Close(Some((CLOSE_PROTOCOL_ERROR, String::new())))
+        );
+
+        // text messages must be valid UTF-8
+        let (client, server) = tokio::io::duplex(1024);
+        let mut client = WebSocket::new(client, Role::Client);
+        let mut server = WebSocket::new(server, Role::Server);
+        client
+            .write_frame(true, OPCODE_TEXT, &[0xC3, 0x28])
+            .await
+            .unwrap();
+        assert!(matches!(server.recv().await, Err(Error::InvalidUtf8)));
+        assert_eq!(
+            client.recv().await.unwrap(),
+            Message::Close(Some((CLOSE_INVALID_DATA, String::new())))
+        );
+    }
+}
@@ -3,7 +3,11 @@
 //! Ensure the server behaves correctly in terms of content serving and HTTP errors.
 
 use base64::Engine;
-use rust_http_server::server;
+use rust_http_server::auth::AuthScheme;
+use rust_http_server::listener::Listener;
+use rust_http_server::{
+    client, credentials, digest, http_header, http_req, https, server, signed_url, tls, websocket,
+};
 use rustls::pki_types::pem::PemObject;
 use std::{path, pin, sync};
 use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
@@ -30,6 +34,7 @@ async fn spawn_server(
         .await
         .map_err(|e| anyhow::anyhow!(e.to_string()))
         .unwrap();
+    server.add_websocket_handler("/ws", sync::Arc::new(EchoHandler));
     let handle = tokio::spawn(async move {
         tokio::select! {
             _ = rx => (),
@@ -39,6 +44,32 @@ async fn spawn_server(
     (tx, handle)
 }
 
+/// WebSocket handler echoing the text and binary messages back to the client.
+struct EchoHandler;
+
+impl websocket::Handler for EchoHandler {
+    fn handle<'a>(
+        &'a self,
+        mut websocket: websocket::WebSocket<&'a mut dyn websocket::Stream>,
+        _req: &'a http_req::HttpReq,
+    ) -> pin::Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async move {
+            while let Ok(message) = websocket.recv().await {
+                let result = match message {
+                    websocket::Message::Text(_) | websocket::Message::Binary(_) => {
+                        websocket.send(message).await
+                    }
+                    websocket::Message::Close(_) => break,
+                    _ => Ok(()),
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
 /// Create a TCP stream with the server, using TLS if needed (for HTTPS).
 ///
 /// Such a raw TCP stream is meant to be used to send arbitrary data to the server
@@ -382,6 +413,438 @@ async fn server_content_test(use_tls: bool, addr: &str) {
     assert_eq!(res.status(), reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE);
 }
 
+/// Build a request without headers, as expected by the crate HTTP client.
+fn build_req(verb: http_req::ReqVerb, route: &str) -> http_req::HttpReq {
+    let head = http_req::ReqHead::new(
+        verb,
+        http_req::ReqTarget::Path(http_req::ReqPath {
+            original: String::from(route),
+            decoded: String::from(route),
+            query: String::new(),
+        }),
+        String::from("HTTP/1.1"),
+        std::collections::HashMap::new(),
+        None,
+        None,
+    );
+    http_req::HttpReq::new(chrono::Utc::now(), head, None)
+}
+
+async fn server_client_test(use_tls: bool, addr: &str) {
+    let mut client = if use_tls {
+        let config = client::Client::tls_config(path::Path::new("./ssl/root.crt")).unwrap();
+        client::Client::connect_tls(addr, config).await.unwrap()
+    } else {
+        client::Client::connect(addr).await.unwrap()
+    };
+
+    // several requests can be sent over the same connection
+    let mut res = client
+        .send(&mut build_req(http_req::ReqVerb::Get, "/lipsum.html"))
+        .await
+        .unwrap();
+    assert_eq!(res.status_code(), 200);
+    match res.body_mut() {
+        Some(rust_http_server::http_res::ResBody::Bytes(bytes)) => assert_eq!(
+            *bytes,
+            tokio::fs::read("./test-root/lipsum.html").await.unwrap()
+        ),
+        _ => panic!("expected a body"),
+    }
+
+    let res = client
+        .send(&mut build_req(http_req::ReqVerb::Get, "/non-existent"))
+        .await
+        .unwrap();
+    assert_eq!(res.status_code(), 404);
+    assert!(res.body_len() > 0);
+
+    // the response to a HEAD request has no body, even though its Content-Length is set
+    let res = client
+        .send(&mut build_req(http_req::ReqVerb::Head, "/lipsum.html"))
+        .await
+        .unwrap();
+    assert_eq!(res.status_code(), 200);
+    assert!(res.body_ref().is_none());
+
+    let res = client
+        .send(&mut build_req(
+            http_req::ReqVerb::Get,
+            "/subdir/lipsum-alt.txt",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status_code(), 200);
+}
+
+/// Build a WebSocket upgrade request, with the given Sec-WebSocket-Version.
+fn build_websocket_req(route: &str, key: &str, version: &str) -> http_req::HttpReq {
+    let mut req = build_req(http_req::ReqVerb::Get, route);
+    for (name, value) in [
+        (
+            http_header::ReqHeader::General(http_header::GeneralHeader::Upgrade),
+            "websocket",
+        ),
+        (
+            http_header::ReqHeader::General(http_header::GeneralHeader::Connection),
+            "Upgrade",
+        ),
+        (
+            http_header::ReqHeader::Other(String::from("sec-websocket-key")),
+            key,
+        ),
+        (
+            http_header::ReqHeader::Other(String::from("sec-websocket-version")),
+            version,
+        ),
+    ] {
+        req.headers().insert(
+            name,
+            http_header::HeaderValue::Simple(http_header::SimpleHeaderValue::String(String::from(
+                value,
+            ))),
+        );
+    }
+    req
+}
+
+async fn server_websocket_test(use_tls: bool, addr: &str) {
+    let connect = async || {
+        if use_tls {
+            let config = client::Client::tls_config(path::Path::new("./ssl/root.crt")).unwrap();
+            client::Client::connect_tls(addr, config).await.unwrap()
+        } else {
+            client::Client::connect(addr).await.unwrap()
+        }
+    };
+
+    // invalid handshakes are rejected
+    let mut client = connect().await;
+    let res = client
+        .send(&mut build_websocket_req("/ws", "Zm9v", "13"))
+        .await
+        .unwrap();
+    assert_eq!(res.status_code(), 400);
+    let mut res = client
+        .send(&mut build_websocket_req(
+            "/ws",
+            &websocket::generate_key(),
+            "8",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status_code(), 426);
+    assert_eq!(
+        res.headers()
+            .get(&http_header::ResHeader::Other(String::from(
+                "sec-websocket-version"
+            ))),
+        Some(&http_header::HeaderValue::Simple(
+            http_header::SimpleHeaderValue::String(String::from("13"))
+        ))
+    );
+
+    // upgrade requests on paths without handler are served as usual
+    let res = client
+        .send(&mut build_websocket_req(
+            "/lipsum.html",
+            &websocket::generate_key(),
+            "13",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status_code(), 200);
+
+    // valid handshake
+    let key = websocket::generate_key();
+    client
+        .write_req(&mut build_websocket_req("/ws", &key, "13"))
+        .await
+        .unwrap();
+    let mut res = client.read_res_head().await.unwrap();
+    assert_eq!(res.status_code(), 101);
+    assert_eq!(
+        res.headers()
+            .get(&http_header::ResHeader::Other(String::from(
+                "sec-websocket-accept"
+            ))),
+        Some(&http_header::HeaderValue::Simple(
+            http_header::SimpleHeaderValue::String(websocket::accept_key(&key))
+        ))
+    );
+
+    // exchange messages with the echo handler
+    let mut ws = websocket::WebSocket::new(client.stream(), websocket::Role::Client);
+    ws.send(websocket::Message::Text(String::from("hello")))
+        .await
+        .unwrap();
+    assert_eq!(
+        ws.recv().await.unwrap(),
+        websocket::Message::Text(String::from("hello"))
+    );
+    ws.send(websocket::Message::Ping(vec![1, 2, 3]))
+        .await
+        .unwrap();
+    assert_eq!(
+        ws.recv().await.unwrap(),
+        websocket::Message::Pong(vec![1, 2, 3])
+    );
+    let data = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+    ws.send_fragmented(websocket::Message::Binary(data.clone()), 4096)
+        .await
+        .unwrap();
+    assert_eq!(ws.recv().await.unwrap(), websocket::Message::Binary(data));
+    ws.close(1000, "done").await.unwrap();
+    assert_eq!(
+        ws.recv().await.unwrap(),
+        websocket::Message::Close(Some((1000, String::new())))
+    );
+}
+
+/// Open an HTTP/2 connection with the server (negotiated with ALPN when using TLS, or with prior
+/// knowledge otherwise).
+async fn create_http2_client(use_tls: bool, addr: &str) -> h2::client::SendRequest<bytes::Bytes> {
+    let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    if use_tls {
+        let mut certs = rustls::RootCertStore::empty();
+        certs
+            .add(rustls::pki_types::CertificateDer::from_pem_file("./ssl/root.crt").unwrap())
+            .unwrap();
+        let mut config = rustls::ClientConfig::builder()
+            .with_root_certificates(certs)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let domain = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+        let stream = tokio_rustls::TlsConnector::from(sync::Arc::new(config))
+            .connect(domain, stream)
+            .await
+            .unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        let (client, connection) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        client
+    } else {
+        let (client, connection) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        client
+    }
+}
+
+/// Read the whole body of an HTTP/2 response.
+async fn read_http2_body(res: http::Response<h2::RecvStream>) -> Vec<u8> {
+    let mut body = res.into_body();
+    let mut bytes = Vec::new();
+    while let Some(data) = body.data().await {
+        let data = data.unwrap();
+        body.flow_control().release_capacity(data.len()).unwrap();
+        bytes.extend_from_slice(&data);
+    }
+    bytes
+}
+
+async fn server_http2_test(use_tls: bool, addr: &str) {
+    let mut client = create_http2_client(use_tls, addr)
+        .await
+        .ready()
+        .await
+        .unwrap();
+    let url = build_url(use_tls, addr, "");
+
+    // several requests are sent at once on the same connection
+    let mut responses = Vec::new();
+    for (verb, route) in [
+        ("GET", "/lipsum.html"),
+        ("GET", "/non-existent"),
+        ("HEAD", "/lipsum.html"),
+        ("GET", "/subdir/lipsum-alt.txt"),
+    ] {
+        let req = http::Request::builder()
+            .method(verb)
+            .uri(format!("{}{}", url, route))
+            .body(())
+            .unwrap();
+        let (res, _) = client.send_request(req, true).unwrap();
+        responses.push(res);
+    }
+
+    let lipsum = tokio::fs::read("./test-root/lipsum.html").await.unwrap();
+    let res = responses.remove(0).await.unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert_eq!(res.headers()["content-length"], lipsum.len().to_string());
+    assert!(!res.headers().contains_key("connection"));
+    assert_eq!(read_http2_body(res).await, lipsum);
+    let res = responses.remove(0).await.unwrap();
+    assert_eq!(res.status(), http::StatusCode::NOT_FOUND);
+    assert!(!read_http2_body(res).await.is_empty());
+    let res = responses.remove(0).await.unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert!(read_http2_body(res).await.is_empty());
+    let res = responses.remove(0).await.unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert_eq!(
+        read_http2_body(res).await,
+        tokio::fs::read("./test-root/subdir/lipsum-alt.txt")
+            .await
+            .unwrap()
+    );
+
+    // request bodies are forwarded as well
+    let req = http::Request::builder()
+        .method("POST")
+        .uri(format!("{}/lipsum.html", url))
+        .body(())
+        .unwrap();
+    let (res, mut body) = client.send_request(req, false).unwrap();
+    body.send_data(bytes::Bytes::from_static(b"foo=bar"), true)
+        .unwrap();
+    assert_eq!(
+        res.await.unwrap().status(),
+        http::StatusCode::METHOD_NOT_ALLOWED
+    );
+
+    // common HTTP clients can use HTTP/2 with prior knowledge
+    if !use_tls {
+        let client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+        let res = client
+            .get(build_url(use_tls, addr, "/lipsum.html"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.version(), reqwest::Version::HTTP_2);
+        assert_eq!(res.bytes().await.unwrap(), lipsum);
+    }
+}
+
+/// Read the whole body of an HTTP/3 response.
+async fn read_http3_body(
+    stream: &mut h3::client::RequestStream<h3_quinn::BidiStream<bytes::Bytes>, bytes::Bytes>,
+) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    while let Some(mut data) = stream.recv_data().await.unwrap() {
+        while bytes::Buf::has_remaining(&data) {
+            let chunk = bytes::Buf::chunk(&data);
+            bytes.extend_from_slice(chunk);
+            let len = chunk.len();
+            bytes::Buf::advance(&mut data, len);
+        }
+    }
+    bytes
+}
+
+async fn server_http3_test(addr: &str, port: u16) {
+    let alt_svc = format!("h3=\":{}\"; ma=86400", port);
+
+    // the HTTP/3 listener is advertised over HTTP/1.1 and HTTP/2
+    let client = create_http_client().await;
+    let res = do_request(&client, true, addr, "/lipsum.html", reqwest::StatusCode::OK).await;
+    assert_eq!(res.headers()["alt-svc"], alt_svc);
+    let mut client = create_http2_client(true, addr).await.ready().await.unwrap();
+    let req = http::Request::builder()
+        .uri(build_url(true, addr, "/lipsum.html"))
+        .body(())
+        .unwrap();
+    let (res, _) = client.send_request(req, true).unwrap();
+    assert_eq!(res.await.unwrap().headers()["alt-svc"], alt_svc);
+
+    // connect with QUIC, on loopback
+    let mut certs = rustls::RootCertStore::empty();
+    certs
+        .add(rustls::pki_types::CertificateDer::from_pem_file("./ssl/root.crt").unwrap())
+        .unwrap();
+    let mut config = rustls::ClientConfig::builder()
+        .with_root_certificates(certs)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h3".to_vec()];
+    let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    endpoint.set_default_client_config(quinn::ClientConfig::new(sync::Arc::new(
+        quinn::crypto::rustls::QuicClientConfig::try_from(config).unwrap(),
+    )));
+    let connection = endpoint
+        .connect(format!("127.0.0.1:{}", port).parse().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .unwrap();
+    let (mut driver, mut send_request) = h3::client::new(h3_quinn::Connection::new(connection))
+        .await
+        .unwrap();
+    let driver = tokio::spawn(async move {
+        std::future::poll_fn(|cx| driver.poll_close(cx)).await;
+    });
+
+    // several requests are sent at once on the same connection
+    let mut streams = Vec::new();
+    for (verb, route) in [
+        ("GET", "/lipsum.html"),
+        ("GET", "/non-existent"),
+        ("HEAD", "/lipsum.html"),
+    ] {
+        let req = http::Request::builder()
+            .method(verb)
+            .uri(build_url(true, addr, route))
+            .body(())
+            .unwrap();
+        let mut stream = send_request.send_request(req).await.unwrap();
+        stream.finish().await.unwrap();
+        streams.push(stream);
+    }
+
+    let lipsum = tokio::fs::read("./test-root/lipsum.html").await.unwrap();
+    let res = streams[0].recv_response().await.unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert_eq!(res.headers()["content-length"], lipsum.len().to_string());
+    assert_eq!(read_http3_body(&mut streams[0]).await, lipsum);
+    let res = streams[1].recv_response().await.unwrap();
+    assert_eq!(res.status(), http::StatusCode::NOT_FOUND);
+    assert!(!read_http3_body(&mut streams[1]).await.is_empty());
+    let res = streams[2].recv_response().await.unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert!(read_http3_body(&mut streams[2]).await.is_empty());
+
+    // request bodies are forwarded as well
+    let req = http::Request::builder()
+        .method("POST")
+        .uri(build_url(true, addr, "/lipsum.html"))
+        .body(())
+        .unwrap();
+    let mut stream = send_request.send_request(req).await.unwrap();
+    stream
+        .send_data(bytes::Bytes::from_static(b"foo=bar"))
+        .await
+        .unwrap();
+    stream.finish().await.unwrap();
+    assert_eq!(
+        stream.recv_response().await.unwrap().status(),
+        http::StatusCode::METHOD_NOT_ALLOWED
+    );
+
+    // chunked responses from upstream servers are decoded
+    let upstream = spawn_chunked_upstream("127.0.0.1:8090").await;
+    let req = http::Request::builder()
+        .uri(build_url(true, addr, "/chunked/"))
+        .body(())
+        .unwrap();
+    let mut stream = send_request.send_request(req).await.unwrap();
+    stream.finish().await.unwrap();
+    let res = stream.recv_response().await.unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert!(!res.headers().contains_key("transfer-encoding"));
+    assert_eq!(
+        read_http3_body(&mut stream).await,
+        b"Wikipedia in \r\n\r\nchunks."
+    );
+    upstream.abort();
+
+    drop(streams);
+    drop(stream);
+    drop(send_request);
+    driver.await.unwrap();
+    endpoint.close(0u32.into(), b"");
+    endpoint.wait_idle().await;
+}
+
 async fn server_encoding_test(use_tls: bool, addr: &str) {
     let client = create_http_client().await;
     let url = build_url(use_tls, addr, "/lipsum.html");
@@ -605,67 +1068,1948 @@ async fn server_php_test(use_tls: bool, addr: &str, auth_creds: &Option<Vec<(Str
     }
 }
 
-async fn server_test(
-    use_tls: bool,
-    addr: &str,
-    allow_dir_listing: bool,
-    auth_creds: &Option<Vec<(String, String)>>,
-) {
-    if auth_creds.is_none() {
-        server_http_error_test(use_tls, addr).await;
-        server_connection_test(use_tls, addr).await;
-        server_dir_listing_test(use_tls, addr, allow_dir_listing).await;
-        server_content_test(use_tls, addr).await;
-        server_encoding_test(use_tls, addr).await;
-    }
-    server_php_test(use_tls, addr, auth_creds).await;
-    server_authentication_test(use_tls, addr, auth_creds).await;
+/// Launch an upstream server answering every request with the same chunked response.
+async fn spawn_chunked_upstream(addr: &str) -> tokio::task::JoinHandle<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = tokio::io::BufReader::new(reader);
+            let mut line = String::new();
+            while reader.read_line(&mut line).await.unwrap_or(0) > 2 {
+                line.clear();
+            }
+            let _ = writer
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+                    4\r\nWiki\r\n6;ext=1\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\n",
+                )
+                .await;
+        }
+    })
 }
 
-#[tokio::test]
-async fn integration_test() {
-    env_logger::builder().is_test(true).try_init().unwrap();
+/// Test the reverse proxy, whose upstream server is another instance of the server.
+async fn server_proxy_test(proxy_addr: &str) {
+    let client = create_http_client().await;
 
-    let addr = "localhost:8080";
-    let socket_addr = "0.0.0.0:8080".parse().unwrap();
-    let document_root = path::PathBuf::from("./test-root").canonicalize().unwrap();
-    let server_cert = path::PathBuf::from("./ssl/server.crt");
-    let server_key = path::PathBuf::from("./ssl/server.key");
+    // the response body is streamed back from the upstream server
+    let res = do_request(
+        &client,
+        false,
+        proxy_addr,
+        "/subdir/lipsum-alt.txt",
+        reqwest::StatusCode::OK,
+    )
+    .await;
+    assert_eq!(
+        res.headers().get("Via"),
+        Some(&reqwest::header::HeaderValue::from_static(
+            "1.1 rust-http-server"
+        ))
+    );
+    assert_eq!(
+        res.text().await.unwrap(),
+        tokio::fs::read_to_string("./test-root/subdir/lipsum-alt.txt")
+            .await
+            .unwrap()
+    );
+    do_request(
+        &client,
+        false,
+        proxy_addr,
+        "/subdir/non-existent",
+        reqwest::StatusCode::NOT_FOUND,
+    )
+    .await;
 
-    let mut settings = server::Settings {
-        address: socket_addr,
-        document_root: document_root.clone(),
-        allow_dir_listing: false,
-        ssl_cert_path: None,
-        ssl_key_path: None,
-        authentication_credentials: None,
-        php_cgi_binary: String::from("php-cgi"),
-    };
+    // the request body is streamed to the upstream server
+    let res = client
+        .post(build_url(false, proxy_addr, "/php/post.php"))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("foo=bar")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "array(1) {\n  [\"foo\"]=>\n  string(3) \"bar\"\n}\n"
+    );
 
-    for (allow_dir_listing, auth_creds) in &[
-        (true, None),
-        (
-            false,
-            Some(vec![(String::from("foo-user"), String::from("bar-pass"))]),
-        ),
-    ] {
-        settings.allow_dir_listing = *allow_dir_listing;
-        settings.authentication_credentials = auth_creds.clone();
+    // requests not matching a proxy route are served locally
+    check_res_body(
+        &client,
+        false,
+        proxy_addr,
+        "/lipsum.html",
+        &tokio::fs::read_to_string("./test-root/lipsum.html")
+            .await
+            .unwrap(),
+    )
+    .await;
 
-        // test with HTTP
-        settings.ssl_cert_path = None;
-        settings.ssl_key_path = None;
-        let (tx, handle) = spawn_server(settings.clone()).await;
-        server_test(false, addr, *allow_dir_listing, auth_creds).await;
-        tx.send(()).unwrap();
-        handle.await.unwrap();
+    // an unreachable upstream server triggers a bad gateway error
+    do_request(
+        &client,
+        false,
+        proxy_addr,
+        "/down/index.html",
+        reqwest::StatusCode::BAD_GATEWAY,
+    )
+    .await;
 
-        // test with HTTPS
-        settings.ssl_cert_path = Some(server_cert.clone());
-        settings.ssl_key_path = Some(server_key.clone());
-        let (tx, handle) = spawn_server(settings.clone()).await;
-        server_test(true, addr, *allow_dir_listing, auth_creds).await;
-        tx.send(()).unwrap();
-        handle.await.unwrap();
-    }
+    // chunked responses are forwarded as such, and decoded over HTTP/2
+    let upstream = spawn_chunked_upstream("127.0.0.1:8090").await;
+    check_res_body(
+        &client,
+        false,
+        proxy_addr,
+        "/chunked/",
+        "Wikipedia in \r\n\r\nchunks.",
+    )
+    .await;
+    let mut client = create_http2_client(false, proxy_addr)
+        .await
+        .ready()
+        .await
+        .unwrap();
+    let req = http::Request::builder()
+        .uri(build_url(false, proxy_addr, "/chunked/"))
+        .body(())
+        .unwrap();
+    let (res, _) = client.send_request(req, true).unwrap();
+    let res = res.await.unwrap();
+    assert_eq!(res.status(), http::StatusCode::OK);
+    assert!(!res.headers().contains_key("transfer-encoding"));
+    assert_eq!(read_http2_body(res).await, b"Wikipedia in \r\n\r\nchunks.");
+    upstream.abort();
+}
+
+/// Test the forward proxy, requiring credentials, with the server as origin server.
+async fn server_forward_proxy_test(proxy_addr: &str, origin_addr: &str, creds: &(String, String)) {
+    let (username, password) = creds;
+    let lipsum = tokio::fs::read_to_string("./test-root/lipsum.html")
+        .await
+        .unwrap();
+
+    // without credentials, proxy authentication is required
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::http(format!("http://{}", proxy_addr)).unwrap())
+        .build()
+        .unwrap();
+    let res = do_request(
+        &client,
+        false,
+        origin_addr,
+        "/lipsum.html",
+        reqwest::StatusCode::PROXY_AUTHENTICATION_REQUIRED,
+    )
+    .await;
+    assert_eq!(
+        res.headers().get("Proxy-Authenticate"),
+        Some(&reqwest::header::HeaderValue::from_static(
+            "Basic realm=\"simple\""
+        ))
+    );
+
+    // requests with an absolute URI are forwarded to the origin server
+    let client = reqwest::Client::builder()
+        .proxy(
+            reqwest::Proxy::http(format!("http://{}", proxy_addr))
+                .unwrap()
+                .basic_auth(username, password),
+        )
+        .build()
+        .unwrap();
+    let res = do_request(
+        &client,
+        false,
+        origin_addr,
+        "/lipsum.html",
+        reqwest::StatusCode::OK,
+    )
+    .await;
+    assert_eq!(
+        res.headers().get("Via"),
+        Some(&reqwest::header::HeaderValue::from_static(
+            "1.1 rust-http-server"
+        ))
+    );
+    assert_eq!(res.text().await.unwrap(), lipsum);
+
+    // TRACE requests are answered by the proxy once Max-Forwards reaches 0, but never by the
+    // origin server
+    let authorization = format!(
+        "Proxy-Authorization: {}\r\n",
+        build_authorization(username, password)
+    );
+    let do_proxy_request = async |verb: &str, max_forwards: u32| {
+        let (mut reader, mut writer) = create_raw_stream(false, proxy_addr).await;
+        writer
+            .write_all(
+                format!(
+                    "{} http://{}/ HTTP/1.1\r\n{}Max-Forwards: {}\r\nConnection: close\r\n\r\n",
+                    verb, origin_addr, authorization, max_forwards
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        writer.flush().await.unwrap();
+        let mut res = Vec::new();
+        reader.read_to_end(&mut res).await.unwrap();
+        String::from_utf8(res).unwrap()
+    };
+    let res = do_proxy_request("TRACE", 0).await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.contains(&format!(
+        "\r\n\r\nTRACE http://{}/ HTTP/1.1\r\n",
+        origin_addr
+    )));
+    // credentials are never echoed
+    assert!(!res.contains("Proxy-Authorization"));
+    let res = do_proxy_request("TRACE", 1).await;
+    assert!(res.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    assert!(res.contains("Via: 1.1 rust-http-server\r\n"));
+    let res = do_proxy_request("OPTIONS", 0).await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(
+        res.contains("Allow: OPTIONS, GET, HEAD, POST, PUT, PATCH, DELETE, TRACE, CONNECT\r\n")
+    );
+    let (mut reader, mut writer) = create_raw_stream(false, origin_addr).await;
+    do_raw_request(
+        &mut reader,
+        &mut writer,
+        b"TRACE / HTTP/1.1\r\nCookie: secret=1\r\nConnection: close\r\n\r\n",
+        b"HTTP/1.1 405 Method Not Allowed\r\n",
+    )
+    .await;
+
+    // CONNECT requests open a tunnel to the target server
+    let (mut reader, mut writer) = create_raw_stream(false, proxy_addr).await;
+    writer
+        .write_all(format!("CONNECT {} HTTP/1.1\r\n{}\r\n", origin_addr, authorization).as_bytes())
+        .await
+        .unwrap();
+    writer.flush().await.unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "HTTP/1.1 200 OK\r\n");
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "\r\n");
+    writer
+        .write_all(b"GET /lipsum.html HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    writer.flush().await.unwrap();
+    let mut res = String::new();
+    reader.read_to_string(&mut res).await.unwrap();
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.ends_with(&lipsum));
+
+    // an unreachable target server triggers a bad gateway error
+    let (mut reader, mut writer) = create_raw_stream(false, proxy_addr).await;
+    do_raw_request(
+        &mut reader,
+        &mut writer,
+        format!("CONNECT localhost:8089 HTTP/1.1\r\n{}\r\n", authorization).as_bytes(),
+        b"HTTP/1.1 502 Bad Gateway\r\n",
+    )
+    .await;
+}
+
+/// Connect to the server with TLS, requesting a server name (or none), and return the certificate
+/// presented by the server (if valid for that name).
+async fn get_server_cert(
+    addr: &str,
+    server_name: &str,
+    enable_sni: bool,
+) -> std::io::Result<rustls::pki_types::CertificateDer<'static>> {
+    let mut certs = rustls::RootCertStore::empty();
+    certs
+        .add(rustls::pki_types::CertificateDer::from_pem_file("./ssl/root.crt").unwrap())
+        .unwrap();
+    let mut config = rustls::ClientConfig::builder()
+        .with_root_certificates(certs)
+        .with_no_client_auth();
+    config.enable_sni = enable_sni;
+    let domain = rustls::pki_types::ServerName::try_from(server_name)
+        .unwrap()
+        .to_owned();
+    let stream = tokio::net::TcpStream::connect(addr).await?;
+    let stream = tokio_rustls::TlsConnector::from(sync::Arc::new(config))
+        .connect(domain, stream)
+        .await?;
+    Ok(stream.get_ref().1.peer_certificates().unwrap()[0].clone())
+}
+
+async fn server_sni_test(addr: &str) {
+    let server_cert = rustls::pki_types::CertificateDer::from_pem_file("./ssl/server.crt").unwrap();
+    let alt_cert = rustls::pki_types::CertificateDer::from_pem_file("./ssl/alt.crt").unwrap();
+
+    assert_eq!(
+        get_server_cert(addr, "alt.localhost", true).await.unwrap(),
+        alt_cert
+    );
+    assert_eq!(
+        get_server_cert(addr, "localhost", true).await.unwrap(),
+        server_cert
+    );
+    // without SNI, the default certificate is presented
+    assert_eq!(
+        get_server_cert(addr, "localhost", false).await.unwrap(),
+        server_cert
+    );
+}
+
+/// Connect to the server with TLS, offering only some protocol versions.
+async fn connect_tls_versions(
+    addr: &str,
+    versions: &[&'static rustls::SupportedProtocolVersion],
+) -> std::io::Result<tokio_rustls::client::TlsStream<tokio::net::TcpStream>> {
+    let mut certs = rustls::RootCertStore::empty();
+    certs
+        .add(rustls::pki_types::CertificateDer::from_pem_file("./ssl/root.crt").unwrap())
+        .unwrap();
+    let config = rustls::ClientConfig::builder_with_protocol_versions(versions)
+        .with_root_certificates(certs)
+        .with_no_client_auth();
+    let domain = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+    let stream = tokio::net::TcpStream::connect(addr).await?;
+    tokio_rustls::TlsConnector::from(sync::Arc::new(config))
+        .connect(domain, stream)
+        .await
+}
+
+async fn server_tls_policy_test(addr: &str) {
+    // TLS 1.2 is not accepted anymore
+    assert!(
+        connect_tls_versions(addr, &[&rustls::version::TLS12])
+            .await
+            .is_err()
+    );
+    let stream = connect_tls_versions(addr, &[&rustls::version::TLS13])
+        .await
+        .unwrap();
+    let connection = stream.get_ref().1;
+    assert_eq!(
+        connection
+            .negotiated_cipher_suite()
+            .map(|suite| suite.suite()),
+        Some(rustls::CipherSuite::TLS13_AES_128_GCM_SHA256)
+    );
+    assert_eq!(
+        connection
+            .negotiated_key_exchange_group()
+            .map(|group| group.name()),
+        Some(rustls::NamedGroup::X25519)
+    );
+}
+
+async fn server_tls_reload_test(addr: &str, cert_path: &path::Path, key_path: &path::Path) {
+    let server_cert = rustls::pki_types::CertificateDer::from_pem_file("./ssl/server.crt").unwrap();
+    let alt_cert = rustls::pki_types::CertificateDer::from_pem_file("./ssl/alt.crt").unwrap();
+    assert_eq!(
+        get_server_cert(addr, "localhost", true).await.unwrap(),
+        server_cert
+    );
+    let (mut reader, mut writer) = create_raw_stream(true, addr).await;
+
+    // replace the certificate, which is then presented to new connections
+    std::fs::copy("./ssl/alt.crt", cert_path).unwrap();
+    std::fs::copy("./ssl/alt.key", key_path).unwrap();
+    let mut reloaded = false;
+    for _ in 0..50 {
+        if get_server_cert(addr, "alt.localhost", true)
+            .await
+            .is_ok_and(|cert| cert == alt_cert)
+        {
+            reloaded = true;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(reloaded);
+
+    // established connections are not affected
+    writer
+        .write_all(b"GET /lipsum.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "HTTP/1.1 200 OK\r\n");
+
+    // an invalid certificate is rejected, the previous one being kept
+    std::fs::write(cert_path, "invalid").unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!(
+        get_server_cert(addr, "alt.localhost", true).await.unwrap(),
+        alt_cert
+    );
+}
+
+/// Send a request to the server with TLS, authenticating with a client certificate (if any), and
+/// return the raw response.
+async fn do_client_cert_request(
+    addr: &str,
+    client_cert: Option<&str>,
+    route: &str,
+) -> std::io::Result<String> {
+    let mut certs = rustls::RootCertStore::empty();
+    certs
+        .add(rustls::pki_types::CertificateDer::from_pem_file("./ssl/root.crt").unwrap())
+        .unwrap();
+    let builder = rustls::ClientConfig::builder().with_root_certificates(certs);
+    let config = match client_cert {
+        Some(name) => builder
+            .with_client_auth_cert(
+                vec![
+                    rustls::pki_types::CertificateDer::from_pem_file(format!("./ssl/{}.crt", name))
+                        .unwrap(),
+                ],
+                rustls::pki_types::PrivateKeyDer::from_pem_file(format!("./ssl/{}.key", name))
+                    .unwrap(),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+    let domain = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+    let stream = tokio::net::TcpStream::connect(addr).await?;
+    let mut stream = tokio_rustls::TlsConnector::from(sync::Arc::new(config))
+        .connect(domain, stream)
+        .await?;
+    stream
+        .write_all(
+            format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                route
+            )
+            .as_bytes(),
+        )
+        .await?;
+    let mut res = String::new();
+    stream.read_to_string(&mut res).await?;
+    Ok(res)
+}
+
+async fn server_client_auth_test(addr: &str, required: bool) {
+    if required {
+        // the handshake fails without a valid certificate
+        assert!(
+            do_client_cert_request(addr, None, "/lipsum.html")
+                .await
+                .is_err()
+        );
+        assert!(
+            do_client_cert_request(addr, Some("revoked"), "/lipsum.html")
+                .await
+                .is_err()
+        );
+        // the certificate details are available to PHP scripts
+        let res = do_client_cert_request(addr, Some("client"), "/php/index.php")
+            .await
+            .unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        for variable in [
+            "SSL_CLIENT_VERIFY",
+            "SUCCESS",
+            "SSL_CLIENT_S_DN",
+            "O=rust-http-server, CN=client.localhost",
+            "SSL_CLIENT_SAN_Email_0",
+            "client@localhost",
+        ] {
+            assert!(res.contains(variable));
+        }
+    } else {
+        // clients without a certificate are not granted access
+        let res = do_client_cert_request(addr, None, "/lipsum.html")
+            .await
+            .unwrap();
+        assert!(res.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(
+            do_client_cert_request(addr, Some("revoked"), "/lipsum.html")
+                .await
+                .is_err()
+        );
+        let res = do_client_cert_request(addr, Some("client"), "/lipsum.html")
+            .await
+            .unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
+
+async fn server_https_redirect_test(addr: &str, redirect_addr: &str) {
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let https_port = addr.rsplit_once(':').unwrap().1;
+
+    // the path and query are kept, with the port of the HTTPS listener
+    let res = client
+        .get(build_url(
+            false,
+            redirect_addr,
+            "/subdir/lipsum-alt.txt?foo=bar",
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(
+        res.headers()["Location"],
+        format!(
+            "https://localhost:{}/subdir/lipsum-alt.txt?foo=bar",
+            https_port
+        )
+    );
+    assert!(!res.headers().contains_key("Strict-Transport-Security"));
+
+    // the method and body must be kept by the client
+    let res = client
+        .post(build_url(false, redirect_addr, "/index.php"))
+        .header("Host", "example.com:80")
+        .body("foo=bar")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        res.headers()["Location"],
+        format!("https://example.com:{}/index.php", https_port)
+    );
+
+    // the redirect is followed up to the content, sent with HSTS
+    let res = create_http_client()
+        .await
+        .get(build_url(false, redirect_addr, "/lipsum.html"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.url().scheme(), "https");
+    assert_eq!(
+        res.headers()["Strict-Transport-Security"],
+        "max-age=31536000; includeSubDomains"
+    );
+    assert_eq!(
+        res.text().await.unwrap(),
+        tokio::fs::read_to_string("./test-root/lipsum.html")
+            .await
+            .unwrap()
+    );
+}
+
+async fn server_listeners_test(socket_path: &path::Path) {
+    let client = create_http_client().await;
+    let expected = tokio::fs::read_to_string("./test-root/lipsum.html")
+        .await
+        .unwrap();
+
+    // plain HTTP over IPv4, and HTTPS over IPv6 on the same port
+    let res = client
+        .get("http://127.0.0.1:8087/lipsum.html")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.text().await.unwrap(), expected);
+    let res = client
+        .get("https://[::1]:8087/lipsum.html")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.text().await.unwrap(), expected);
+    assert!(
+        client
+            .get("https://127.0.0.1:8087/lipsum.html")
+            .send()
+            .await
+            .is_err()
+    );
+
+    // Unix domain socket, whose peers have no IP address
+    let mut stream = tokio::net::UnixStream::connect(socket_path).await.unwrap();
+    stream
+        .write_all(b"GET /php/index.php HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut res = String::new();
+    stream.read_to_string(&mut res).await.unwrap();
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.contains("REMOTE_ADDR") && res.contains("\"unix:\""));
+}
+
+/// Send a PROXY protocol header (if any) and a request, and return the response.
+async fn do_proxied_request(addr: &str, proxy_header: &[u8], route: &str, headers: &str) -> String {
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(proxy_header).await.unwrap();
+    stream
+        .write_all(
+            format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+                route, headers
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut res = String::new();
+    let _ = stream.read_to_string(&mut res).await;
+    res
+}
+
+async fn server_proxy_protocol_test(proxied_addr: &str, addr: &str) {
+    // v1 and v2 headers give the address of the client
+    let res = do_proxied_request(
+        proxied_addr,
+        b"PROXY TCP4 198.51.100.1 127.0.0.1 56324 8088\r\n",
+        "/php/index.php",
+        "",
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.contains("\"198.51.100.1\""));
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+    header.extend([198, 51, 100, 2, 127, 0, 0, 1, 0xdc, 0x04, 0x1f, 0x98]);
+    let res = do_proxied_request(proxied_addr, &header, "/php/index.php", "").await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.contains("\"198.51.100.2\""));
+
+    // the headers of trusted proxies give the address of their client
+    let res = do_proxied_request(
+        proxied_addr,
+        b"PROXY TCP4 192.0.2.1 127.0.0.1 56324 8088\r\n",
+        "/php/index.php",
+        "X-Forwarded-For: 198.51.100.3, 203.0.113.7, 192.0.2.2\r\n",
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.contains("\"203.0.113.7\""));
+    assert!(!res.contains("\"192.0.2.1\""));
+
+    // a connection without header is closed
+    let res = do_proxied_request(proxied_addr, b"", "/php/index.php", "").await;
+    assert!(res.is_empty());
+
+    // the headers of other clients are ignored
+    let res = do_proxied_request(
+        addr,
+        b"",
+        "/php/index.php",
+        "X-Forwarded-For: 203.0.113.7\r\n",
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.contains("\"127.0.0.1\""));
+    assert!(!res.contains("203.0.113.7"));
+}
+
+async fn server_rate_limit_test(addr: &str, creds: &(String, String)) {
+    let client = create_http_client().await;
+    let (username, password) = creds;
+
+    // limited path
+    for _ in 0..2 {
+        let res = client
+            .get(build_url(false, addr, "/lipsum.html"))
+            .basic_auth(username, Some(password))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+    }
+    let res = client
+        .get(build_url(false, addr, "/lipsum.html"))
+        .basic_auth(username, Some(password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        res.headers().get("Retry-After"),
+        Some(&reqwest::header::HeaderValue::from_static("30"))
+    );
+    // the limited path cannot be reached through another path (sent raw, as HTTP clients would
+    // normalize it)
+    let authorization =
+        base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+    for path in ["//lipsum.html", "/subdir/../lipsum.html"] {
+        let (mut reader, mut writer) = create_raw_stream(false, addr).await;
+        writer
+            .write_all(
+                format!(
+                    "GET {} HTTP/1.1\r\nAuthorization: Basic {}\r\nConnection: close\r\n\r\n",
+                    path, authorization
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "HTTP/1.1 429 Too Many Requests\r\n", "{}", path);
+    }
+    let res = client
+        .get(build_url(false, addr, "/subdir/"))
+        .basic_auth(username, Some(password))
+        .send()
+        .await
+        .unwrap();
+    assert_ne!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+    // failed authentications
+    let res = client
+        .get(build_url(false, addr, "/subdir/"))
+        .basic_auth(username, Some("wrong-password"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    // once exceeded, even valid credentials are refused
+    for password in ["wrong-password", password] {
+        let res = client
+            .get(build_url(false, addr, "/subdir/"))
+            .basic_auth(username, Some(password))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            res.headers().get("Retry-After"),
+            Some(&reqwest::header::HeaderValue::from_static("60"))
+        );
+    }
+    let res = client
+        .get(build_url(false, addr, "/subdir/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+async fn server_acl_test(proxied_addr: &str) {
+    let internal: &[u8] = b"PROXY TCP4 198.51.100.1 127.0.0.1 56324 8094\r\n";
+    let external: &[u8] = b"PROXY TCP4 203.0.113.1 127.0.0.1 56324 8094\r\n";
+    for (proxy_header, route, status) in [
+        (internal, "/subdir/lipsum-alt.txt", "200 OK"),
+        (external, "/subdir/lipsum-alt.txt", "403 Forbidden"),
+        (external, "/subdir", "403 Forbidden"),
+        (external, "/lipsum.html", "200 OK"),
+        // dot segments and repeated slashes do not escape the lists, the paths being normalized
+        (
+            external,
+            "/lipsum.html/../subdir/lipsum-alt.txt",
+            "403 Forbidden",
+        ),
+        (external, "/%2e/subdir/lipsum-alt.txt", "403 Forbidden"),
+        (external, "//subdir/lipsum-alt.txt", "403 Forbidden"),
+    ] {
+        let res = do_proxied_request(proxied_addr, proxy_header, route, "").await;
+        assert!(
+            res.starts_with(&format!("HTTP/1.1 {}\r\n", status)),
+            "{}",
+            route
+        );
+    }
+}
+
+async fn server_auth_file_test(addr: &str, file: &path::Path) {
+    let client = create_http_client().await;
+    let get = |username: &str, password: &str| {
+        client
+            .get(build_url(false, addr, "/lipsum.html"))
+            .basic_auth(username, Some(password))
+            .send()
+    };
+
+    for (username, password, status) in [
+        ("bcrypt-user", "password", reqwest::StatusCode::OK),
+        ("bcrypt-user", "Password", reqwest::StatusCode::UNAUTHORIZED),
+        ("sha-user", "Hello world!", reqwest::StatusCode::OK),
+        ("argon2-user", "argon2-pass", reqwest::StatusCode::OK),
+        (
+            "unknown-user",
+            "password",
+            reqwest::StatusCode::UNAUTHORIZED,
+        ),
+    ] {
+        assert_eq!(get(username, password).await.unwrap().status(), status);
+    }
+
+    // the file is reloaded when it changes
+    let hash = credentials::HashScheme::Sha512Crypt
+        .hash("new-pass")
+        .unwrap();
+    std::fs::write(file, format!("new-user:{}\n", hash)).unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(file)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(1))
+        .unwrap();
+    let res = get("new-user", "new-pass").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = get("bcrypt-user", "password").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+/// Test the locations protected by their own realm (or left public), with authorization rules.
+async fn server_auth_realms_test(addr: &str) {
+    let client = create_http_client().await;
+    let get = |path: &str, creds: Option<(&str, &str)>| {
+        let req = client.get(build_url(false, addr, path));
+        match creds {
+            Some((username, password)) => req.basic_auth(username, Some(password)),
+            None => req,
+        }
+        .send()
+    };
+    let user = Some(("new-user", "new-pass"));
+
+    // public locations
+    let res = get("/php/get.php", None).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // each realm asks for authentication with its own name
+    for (path, realm) in [
+        ("/", "simple"),
+        ("/subdir/lipsum-alt.txt", "Staff"),
+        ("/lipsum.html", "Lipsum"),
+    ] {
+        let res = get(path, None).await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            res.headers().get(reqwest::header::WWW_AUTHENTICATE),
+            Some(
+                &reqwest::header::HeaderValue::from_str(&format!("Basic realm=\"{}\"", realm))
+                    .unwrap()
+            )
+        );
+        let res = get(path, Some(("new-user", "wrong-pass"))).await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    }
+
+    // authenticated users are granted access by the rules of the realm (group membership here),
+    // otherwise access is forbidden
+    let res = get("/subdir/lipsum-alt.txt", user).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = get("/lipsum.html", user).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // dot segments and repeated slashes do not escape the realms, the paths being normalized
+    // (they are sent raw, as HTTP clients would normalize them)
+    for path in [
+        "/php/../lipsum.html",
+        "/php/%2e%2e/lipsum.html",
+        "//lipsum.html",
+        "/php//../subdir/lipsum-alt.txt",
+    ] {
+        let (mut reader, mut writer) = create_raw_stream(false, addr).await;
+        writer
+            .write_all(format!("GET {} HTTP/1.1\r\nConnection: close\r\n\r\n", path).as_bytes())
+            .await
+            .unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "HTTP/1.1 401 Unauthorized\r\n", "{}", path);
+    }
+}
+
+/// Test the Digest authentication scheme, offered before the Basic one.
+async fn server_digest_test(addr: &str) {
+    let client = create_http_client().await;
+    let get = |path: &str, authorization: Option<String>| {
+        let req = client.get(build_url(false, addr, path));
+        match authorization {
+            Some(authorization) => req.header("Authorization", authorization),
+            None => req,
+        }
+        .send()
+    };
+
+    // a nonce is issued, for each algorithm in order of preference
+    let res = get("/lipsum.html", None).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let challenges = res
+        .headers()
+        .get_all(reqwest::header::WWW_AUTHENTICATE)
+        .iter()
+        .map(|value| String::from(value.to_str().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(challenges.len(), 3);
+    assert!(
+        challenges[0]
+            .starts_with(r#"Digest realm="simple", qop="auth", algorithm=SHA-256, nonce=""#)
+    );
+    assert!(challenges[1].contains("algorithm=MD5"));
+    assert_eq!(challenges[2], r#"Basic realm="simple""#);
+    let nonce = challenges[0]
+        .rsplit_once("nonce=")
+        .unwrap()
+        .1
+        .trim_matches('"');
+
+    let mut creds = digest::DigestCredentials {
+        username: String::from("digest-user"),
+        realm: String::from("simple"),
+        nonce: String::from(nonce),
+        uri: String::from("/php/index.php?foo=bar"),
+        algorithm: digest::Algorithm::Sha256,
+        nc: 1,
+        cnonce: String::from("f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ"),
+        response: String::new(),
+    };
+    let authorization = |creds: &mut digest::DigestCredentials, password| {
+        creds.response = creds.expected_response(password, "GET");
+        Some(creds.to_string())
+    };
+
+    // the authenticated user is given to PHP scripts
+    let res = get(
+        "/php/index.php?foo=bar",
+        authorization(&mut creds, "digest-pass"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body = res.text().await.unwrap();
+    assert!(body.contains("digest-user"));
+    assert!(body.contains("Digest"));
+
+    // requests cannot be replayed, and digests are computed for a request target
+    let res = get("/php/index.php?foo=bar", Some(creds.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    creds.nc = 2;
+    let res = get("/php/index.php", authorization(&mut creds, "digest-pass"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    creds.uri = String::from("/lipsum.html");
+    creds.algorithm = digest::Algorithm::Md5;
+    creds.nc = 3;
+    let res = get("/lipsum.html", authorization(&mut creds, "wrong-pass"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    creds.nc = 4;
+    let res = get("/lipsum.html", authorization(&mut creds, "digest-pass"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    // the digest is computed for the target as sent, before its path is normalized
+    creds.uri = String::from("//lipsum.html");
+    creds.nc = 5;
+    let res = get("//lipsum.html", authorization(&mut creds, "digest-pass"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    creds.uri = String::from("/lipsum.html");
+
+    // unknown (or expired) nonces are stale, the client may retry with a new one
+    creds.nonce = String::from("0123456789abcdef");
+    let res = get("/lipsum.html", authorization(&mut creds, "digest-pass"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let challenge = res
+        .headers()
+        .get(reqwest::header::WWW_AUTHENTICATE)
+        .unwrap();
+    assert!(challenge.to_str().unwrap().ends_with("stale=true"));
+
+    // the Basic scheme is still accepted
+    let res = client
+        .get(build_url(false, addr, "/lipsum.html"))
+        .basic_auth("digest-user", Some("digest-pass"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+/// Test the bearer tokens of an API location: static tokens, and JSON Web Tokens (ES256).
+async fn server_bearer_test(addr: &str, creds: &(String, String)) {
+    let client = create_http_client().await;
+    let get = |path: &str, token: Option<&str>| {
+        let req = client.get(build_url(false, addr, path));
+        match token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        }
+        .send()
+    };
+
+    let key = aws_lc_rs::signature::EcdsaKeyPair::from_pkcs8(
+        &aws_lc_rs::signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+        rustls::pki_types::PrivatePkcs8KeyDer::from_pem_file("ssl/jwt-ec.key")
+            .unwrap()
+            .secret_pkcs8_der(),
+    )
+    .unwrap();
+    let exp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 300;
+    let sign = |claims: &str| {
+        let signed = format!(
+            "{}.{}",
+            base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"ES256","typ":"JWT"}"#),
+            base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(claims.replace("EXP", &exp.to_string()))
+        );
+        let sig = key
+            .sign(&aws_lc_rs::rand::SystemRandom::new(), signed.as_bytes())
+            .unwrap();
+        format!(
+            "{}.{}",
+            signed,
+            base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(sig.as_ref())
+        )
+    };
+
+    let res = get("/php/index.php", None).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        res.headers()
+            .get(reqwest::header::WWW_AUTHENTICATE)
+            .unwrap(),
+        r#"Bearer realm="API""#
+    );
+
+    // the claims of the token are given to PHP scripts
+    let writer = sign(
+        r#"{"sub": "bob", "iss": "https://issuer.localhost", "aud": "api", "exp": EXP, "scope": "read write"}"#,
+    );
+    let res = get("/php/index.php", Some(&writer)).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body = res.text().await.unwrap();
+    assert!(body.contains("bob"));
+    assert!(body.contains("Bearer"));
+    assert!(body.contains("JWT_SCOPE"));
+    assert!(body.contains("read write"));
+
+    // authorization rules on the claims
+    let reader = sign(
+        r#"{"sub": "carol", "iss": "https://issuer.localhost", "aud": "api", "exp": EXP, "scope": "read"}"#,
+    );
+    let res = get("/php/index.php", Some(&reader)).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let other_audience = sign(
+        r#"{"sub": "bob", "iss": "https://issuer.localhost", "aud": "web", "exp": EXP, "scope": "write"}"#,
+    );
+    let res = get("/php/index.php", Some(&other_audience)).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let tampered = format!("{}x", &writer[..writer.len() - 1]);
+    let res = get("/php/index.php", Some(&tampered)).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    // static tokens
+    let res = get("/php/index.php", Some("ci-token-0123456789"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = get("/php/index.php", Some("invalid token")).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    // the other paths are still protected with the Basic scheme
+    let res = get("/lipsum.html", Some(&writer)).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let res = client
+        .get(build_url(false, addr, "/lipsum.html"))
+        .basic_auth(&creds.0, Some(&creds.1))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+}
+
+async fn server_login_test(addr: &str, creds: &(String, String)) {
+    // the redirects are checked instead of being followed
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let login = |username: &str, password: &str| {
+        client
+            .post(build_url(false, addr, "/login"))
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/x-www-form-urlencoded",
+            )
+            .body(format!(
+                "username={}&password={}&redirect=%2Fphp%2Findex.php%3Fa%3Db",
+                username, password
+            ))
+            .send()
+    };
+
+    // browsers are sent to the login form, the other clients asked to authenticate
+    let res = client
+        .get(build_url(false, addr, "/php/index.php?a=b"))
+        .header(reqwest::header::ACCEPT, "text/html,*/*;q=0.8")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::SEE_OTHER);
+    assert_eq!(
+        res.headers().get(reqwest::header::LOCATION).unwrap(),
+        "/login?redirect=%2Fphp%2Findex.php%3Fa%3Db"
+    );
+    let res = client
+        .get(build_url(false, addr, "/php/index.php"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let res = client
+        .get(build_url(
+            false,
+            addr,
+            "/login?redirect=%2Fphp%2Findex.php%3Fa%3Db%22%3E",
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        res.headers().get(reqwest::header::CACHE_CONTROL).unwrap(),
+        "no-store"
+    );
+    let body = res.text().await.unwrap();
+    assert!(body.contains("<form method=\"post\" action=\"/login\">"));
+    assert!(body.contains("value=\"/php/index.php?a=b&quot;&gt;\""));
+
+    // a failed login shows the form again
+    let res = login(&creds.0, "wrong-pass").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(res.headers().get(reqwest::header::SET_COOKIE).is_none());
+    assert!(
+        res.text()
+            .await
+            .unwrap()
+            .contains("Invalid username or password")
+    );
+
+    // a successful one opens a session, and sends the user back to the page
+    let res = login(&creds.0, &creds.1).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::SEE_OTHER);
+    assert_eq!(
+        res.headers().get(reqwest::header::LOCATION).unwrap(),
+        "/php/index.php?a=b"
+    );
+    let set_cookie = res
+        .headers()
+        .get(reqwest::header::SET_COOKIE)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(set_cookie.starts_with("rhs_session="));
+    assert!(set_cookie.contains("; HttpOnly; Secure; SameSite=Lax"));
+    let cookie = String::from(set_cookie.split_once(';').unwrap().0);
+
+    let res = client
+        .get(build_url(false, addr, "/php/index.php"))
+        .header(reqwest::header::COOKIE, format!("theme=dark; {}", cookie))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let body = res.text().await.unwrap();
+    assert!(body.contains(&creds.0));
+    assert!(body.contains("Form"));
+
+    // logging out requires a POST request, and closes the session
+    let res = client
+        .get(build_url(false, addr, "/logout"))
+        .header(reqwest::header::COOKIE, &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::METHOD_NOT_ALLOWED);
+    assert!(res.headers().get(reqwest::header::SET_COOKIE).is_none());
+    let res = client
+        .get(build_url(false, addr, "/php/index.php"))
+        .header(reqwest::header::COOKIE, &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = client
+        .post(build_url(false, addr, "/logout"))
+        .header(reqwest::header::COOKIE, &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::SEE_OTHER);
+    assert_eq!(
+        res.headers().get(reqwest::header::LOCATION).unwrap(),
+        "/login"
+    );
+    assert!(
+        res.headers()
+            .get(reqwest::header::SET_COOKIE)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("rhs_session=; Path=/; Max-Age=0;")
+    );
+    let res = client
+        .get(build_url(false, addr, "/php/index.php"))
+        .header(reqwest::header::COOKIE, &cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    // only local pages can be redirected to
+    let res = client
+        .post(build_url(false, addr, "/login"))
+        .body(format!(
+            "username={}&password={}&redirect=https%3A%2F%2Fexample.org%2F",
+            creds.0, creds.1
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.headers().get(reqwest::header::LOCATION).unwrap(), "/");
+}
+
+async fn server_signed_url_test(addr: &str, secret_file: &path::Path) {
+    let client = create_http_client().await;
+    let get = |url: &str| client.get(build_url(false, addr, url)).send();
+    let signer = signed_url::UrlSigner::load(secret_file).unwrap();
+    let expires = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 300;
+
+    let res = get("/lipsum.html").await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    // a signed URL grants access to its path without credentials
+    let url = signer.sign("/lipsum.html", expires);
+    let res = get(&url).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(res.text().await.unwrap().contains("Lorem ipsum"));
+
+    // but not to the other paths
+    let (_, query) = url.split_once('?').unwrap();
+    let res = get(&format!("/php/index.php?{}", query)).await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // tampered or expired signatures are refused
+    let res = get(&url.replace(&expires.to_string(), &(expires + 3600).to_string()))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+    let res = get(&signer.sign("/lipsum.html", expires - 600))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::FORBIDDEN);
+}
+
+/// Lines of a log file, once it has (at least) a number of them, as they are written in the
+/// background.
+async fn read_log_lines(path: &path::Path, count: usize) -> Vec<String> {
+    for _ in 0..50 {
+        let lines: Vec<String> = std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect();
+        if lines.len() >= count {
+            return lines;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("missing access log lines in {}", path.display());
+}
+
+async fn server_access_log_test(addr: &str, creds: &(String, String), log_dir: &path::Path) {
+    let client = create_http_client().await;
+    let req = |verb: reqwest::Method, auth: bool| {
+        let req = client
+            .request(verb, build_url(false, addr, "/lipsum.html"))
+            .header("Referer", "http://example.org/")
+            .header("User-Agent", "access-log-test");
+        match auth {
+            true => req.basic_auth(&creds.0, Some(&creds.1)),
+            false => req,
+        }
+    };
+
+    let res = req(reqwest::Method::GET, true).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let len = res.bytes().await.unwrap().len();
+    let res = req(reqwest::Method::GET, false).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let res = req(reqwest::Method::HEAD, true).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+
+    // entries in the Combined Log Format, with the authenticated user and the bytes sent
+    let lines = read_log_lines(&log_dir.join("access.log"), 3).await;
+    assert!(lines[0].starts_with(&format!("127.0.0.1 - {} [", creds.0)));
+    assert!(lines[0].ends_with(&format!(
+        r#""GET /lipsum.html HTTP/1.1" 200 {} "http://example.org/" "access-log-test""#,
+        len
+    )));
+    assert!(lines[1].starts_with("127.0.0.1 - - ["));
+    assert!(lines[1].contains(r#""GET /lipsum.html HTTP/1.1" 401 "#));
+    assert!(lines[2].contains(r#""HEAD /lipsum.html HTTP/1.1" 200 - "#));
+
+    // a virtual host with a log of its own, in JSON lines
+    let res = req(reqwest::Method::GET, true)
+        .header("Host", "json.localhost")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let lines = read_log_lines(&log_dir.join("json.log"), 1).await;
+    let entry: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+    assert_eq!(entry["user"], creds.0.as_str());
+    assert_eq!(entry["host"], "json.localhost");
+    assert_eq!(entry["status"], 200);
+    assert_eq!(entry["bytes"], len);
+    assert_eq!(entry["referer"], "http://example.org/");
+    assert_eq!(entry["user_agent"], "access-log-test");
+    assert_eq!(entry["tls_protocol"], serde_json::Value::Null);
+    assert_eq!(
+        read_log_lines(&log_dir.join("access.log"), 3).await.len(),
+        3
+    );
+}
+
+/// Test the status page of the server (requiring credentials), then drain the server: its idle
+/// connections are closed, and it stops accepting new ones.
+async fn server_status_test(settings: server::Settings, addr: &str, creds: &(String, String)) {
+    let mut server = server::Server::new(settings)
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))
+        .unwrap();
+    let status = server.status();
+    let listen = tokio::spawn(async move { server.listen().await });
+
+    // a keep-alive connection, idle after its first request
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"HEAD /lipsum.html HTTP/1.1\r\nHost: status.localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut reader = tokio::io::BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    assert!(line.starts_with("HTTP/1.1 401"));
+    while line != "\r\n" {
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+    }
+
+    let client = create_http_client().await;
+    let url = build_url(false, addr, "/server-status");
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let res = client
+        .post(&url)
+        .basic_auth(&creds.0, Some(&creds.1))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::METHOD_NOT_ALLOWED);
+    let res = client
+        .get(&url)
+        .basic_auth(&creds.0, Some(&creds.1))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.headers()["Content-Type"], "text/html");
+    assert_eq!(res.headers()["Cache-Control"], "no-store");
+    let html = res.text().await.unwrap();
+    assert!(html.contains("<h1>Server status</h1>"));
+    assert!(html.contains("<td>HEAD /lipsum.html HTTP/1.1</td>"));
+
+    let res = client
+        .get(format!("{}?json", url))
+        .basic_auth(&creds.0, Some(&creds.1))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert_eq!(res.headers()["Content-Type"], "application/json");
+    let json: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(json["draining"], false);
+    assert_eq!(json["requests"], 4);
+    assert_eq!(json["client_errors"], 3);
+    assert_eq!(json["server_errors"], 0);
+    let connections = json["connections"].as_array().unwrap();
+    // the connections are listed in the order they were opened
+    assert_eq!(connections[0]["request"], "HEAD /lipsum.html HTTP/1.1");
+    assert_eq!(connections[0]["host"], "status.localhost");
+    assert_eq!(connections[0]["state"], "idle");
+    assert_eq!(connections[0]["requests"], 1);
+    assert!(connections.iter().any(|connection| {
+        connection["request"] == "GET /server-status HTTP/1.1" && connection["state"] == "serving"
+    }));
+    assert_eq!(json["top_paths"][0]["path"], "/server-status");
+    assert_eq!(json["top_paths"][0]["requests"], 3);
+    assert_eq!(json["top_paths"][1]["path"], "/lipsum.html");
+
+    // once drained, the idle connections are closed and no connection is accepted anymore
+    status.drain();
+    listen.await.unwrap();
+    let mut rest = Vec::new();
+    assert_eq!(reader.read_to_end(&mut rest).await.unwrap(), 0);
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    assert!(status.wait_idle(std::time::Duration::from_secs(5)).await);
+}
+
+async fn server_metrics_test(addr: &str, metrics_addr: &str, creds: &(String, String)) {
+    let client = create_http_client().await;
+    let get = |path: &str| {
+        client
+            .get(build_url(false, addr, path))
+            .basic_auth(&creds.0, Some(&creds.1))
+    };
+
+    let res = get("/lipsum.html").send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = get("/lipsum.html")
+        .header("Accept-Encoding", "gzip")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.headers()["Content-Encoding"], "gzip");
+    let res = get("/php/index.php").send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    let res = client
+        .get(build_url(false, addr, "/lipsum.html"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    // the metrics are served on their own listener, without credentials
+    let res = client
+        .get(build_url(false, metrics_addr, "/metrics"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(
+        res.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain; version=0.0.4")
+    );
+    let metrics = res.text().await.unwrap();
+    for line in [
+        r#"rhs_http_requests_total{class="2xx",method="GET",handler="static"} 2"#,
+        r#"rhs_http_requests_total{class="2xx",method="GET",handler="php"} 1"#,
+        r#"rhs_http_requests_total{class="4xx",method="GET",handler="other"} 1"#,
+        r#"rhs_http_request_duration_seconds_count{handler="static"} 2"#,
+        "rhs_http_response_size_bytes_count 4",
+        "rhs_php_duration_seconds_count 1",
+        "rhs_php_errors_total 0",
+        r#"rhs_compression_ratio_count{encoding="gzip"} 1"#,
+        "rhs_tls_handshake_failures_total 0",
+    ] {
+        assert!(metrics.lines().any(|l| l == line), "missing {}", line);
+    }
+    assert!(metrics.contains("\nrhs_connections_active "));
+    let res = client
+        .get(build_url(false, metrics_addr, "/lipsum.html"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+async fn server_test(
+    use_tls: bool,
+    addr: &str,
+    allow_dir_listing: bool,
+    auth_creds: &Option<Vec<(String, String)>>,
+) {
+    if auth_creds.is_none() {
+        server_http_error_test(use_tls, addr).await;
+        server_connection_test(use_tls, addr).await;
+        server_dir_listing_test(use_tls, addr, allow_dir_listing).await;
+        server_content_test(use_tls, addr).await;
+        server_client_test(use_tls, addr).await;
+        server_encoding_test(use_tls, addr).await;
+        server_websocket_test(use_tls, addr).await;
+        server_http2_test(use_tls, addr).await;
+    }
+    server_php_test(use_tls, addr, auth_creds).await;
+    server_authentication_test(use_tls, addr, auth_creds).await;
+}
+
+#[tokio::test]
+async fn integration_test() {
+    env_logger::builder().is_test(true).try_init().unwrap();
+
+    let addr = "localhost:8080";
+    let socket_addr = "0.0.0.0:8080".parse().unwrap();
+    let document_root = path::PathBuf::from("./test-root").canonicalize().unwrap();
+    let server_cert = path::PathBuf::from("./ssl/server.crt");
+    let server_key = path::PathBuf::from("./ssl/server.key");
+
+    let mut settings = server::Settings {
+        listeners: vec![Listener::tcp(socket_addr, false)],
+        user: None,
+        group: None,
+        pid_file: None,
+        document_root: document_root.clone(),
+        allow_dir_listing: false,
+        ssl_cert_path: None,
+        ssl_key_path: None,
+        ssl_key_password: None,
+        allow_plain_http_fallback: false,
+        tls_policy: Default::default(),
+        authentication_credentials: None,
+        authentication_file: None,
+        authentication_locations: Vec::new(),
+        authentication_groups_file: None,
+        authentication_schemes: vec![AuthScheme::Basic],
+        bearer_tokens_file: None,
+        jwt_keys: Vec::new(),
+        jwt_issuer: None,
+        jwt_audience: None,
+        login: None,
+        url_secret: None,
+        php_cgi_binary: String::from("php-cgi"),
+        proxy_routes: Vec::new(),
+        forward_proxy: false,
+        http3: false,
+        tls_hosts: Vec::new(),
+        client_auth: None,
+        tls_reload_interval: None,
+        https_redirect: None,
+        hsts: None,
+        trusted_proxies: Vec::new(),
+        rate_limits: Vec::new(),
+        auth_failure_rate: None,
+        acls: Vec::new(),
+        access_logs: Vec::new(),
+        metrics: None,
+        server_status: None,
+    };
+
+    for (allow_dir_listing, auth_creds) in &[
+        (true, None),
+        (
+            false,
+            Some(vec![(String::from("foo-user"), String::from("bar-pass"))]),
+        ),
+    ] {
+        settings.allow_dir_listing = *allow_dir_listing;
+        settings.authentication_credentials = auth_creds.clone();
+
+        // test with HTTP
+        settings.listeners = vec![Listener::tcp(socket_addr, false)];
+        settings.ssl_cert_path = None;
+        settings.ssl_key_path = None;
+        let (tx, handle) = spawn_server(settings.clone()).await;
+        server_test(false, addr, *allow_dir_listing, auth_creds).await;
+        tx.send(()).unwrap();
+        handle.await.unwrap();
+
+        // test with HTTPS
+        settings.listeners = vec![Listener::tcp(socket_addr, true)];
+        settings.ssl_cert_path = Some(server_cert.clone());
+        settings.ssl_key_path = Some(server_key.clone());
+        let (tx, handle) = spawn_server(settings.clone()).await;
+        server_test(true, addr, *allow_dir_listing, auth_creds).await;
+        tx.send(()).unwrap();
+        handle.await.unwrap();
+    }
+
+    // test the reverse proxy, forwarding requests to the server (HTTP only)
+    settings.listeners = vec![Listener::tcp(socket_addr, false)];
+    settings.ssl_cert_path = None;
+    settings.ssl_key_path = None;
+    settings.authentication_credentials = None;
+    let (tx, handle) = spawn_server(settings.clone()).await;
+    let mut proxy_settings = settings.clone();
+    proxy_settings.listeners = vec![Listener::tcp("0.0.0.0:8081".parse().unwrap(), false)];
+    proxy_settings.proxy_routes = vec![
+        "/subdir=localhost:8080".parse().unwrap(),
+        "/php=localhost:8080|least-conn".parse().unwrap(),
+        "/down=localhost:8089".parse().unwrap(),
+        "/chunked=127.0.0.1:8090".parse().unwrap(),
+    ];
+    let (proxy_tx, proxy_handle) = spawn_server(proxy_settings.clone()).await;
+    server_proxy_test("localhost:8081").await;
+    proxy_tx.send(()).unwrap();
+    proxy_handle.await.unwrap();
+
+    // test the forward proxy, with the server as origin server
+    let creds = (String::from("foo-user"), String::from("bar-pass"));
+    proxy_settings.forward_proxy = true;
+    proxy_settings.proxy_routes = Vec::new();
+    proxy_settings.authentication_credentials = Some(vec![creds.clone()]);
+    let (proxy_tx, proxy_handle) = spawn_server(proxy_settings).await;
+    server_forward_proxy_test("localhost:8081", "localhost:8080", &creds).await;
+    proxy_tx.send(()).unwrap();
+    proxy_handle.await.unwrap();
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // the server refuses to start with an incomplete TLS configuration, unless allowed to serve
+    // plain HTTP instead
+    let mut http3_settings = settings;
+    http3_settings.listeners = vec![Listener::tcp("0.0.0.0:8082".parse().unwrap(), true)];
+    http3_settings.ssl_cert_path = Some(server_cert);
+    assert!(matches!(
+        server::Server::new(http3_settings.clone()).await,
+        Err(server::Error::Tls(tls::Error::IncompleteConfig))
+    ));
+    http3_settings.allow_plain_http_fallback = true;
+    assert!(server::Server::new(http3_settings.clone()).await.is_ok());
+    http3_settings.allow_plain_http_fallback = false;
+    // an encrypted key cannot be used without its password
+    http3_settings.ssl_key_path = Some(path::PathBuf::from("./ssl/server-encrypted.key"));
+    assert!(matches!(
+        server::Server::new(http3_settings.clone()).await,
+        Err(server::Error::Tls(tls::Error::EncryptedKey(_)))
+    ));
+
+    // test HTTP/3, next to HTTPS (with an encrypted key and a TLS policy), and the certificate
+    // selection with SNI
+    http3_settings.ssl_key_password = Some(tls::KeyPassword(String::from("server-password")));
+    http3_settings.tls_policy = tls::TlsPolicy {
+        min_version: tls::TlsVersion::Tls13,
+        cipher_suites: vec![String::from("TLS13_AES_128_GCM_SHA256")],
+        kx_groups: vec![String::from("X25519")],
+    };
+    http3_settings.http3 = true;
+    http3_settings.tls_hosts = vec!["alt.localhost=ssl/alt.crt,ssl/alt.key".parse().unwrap()];
+    http3_settings.proxy_routes = vec!["/chunked=127.0.0.1:8090".parse().unwrap()];
+    let (tx, handle) = spawn_server(http3_settings.clone()).await;
+    server_http3_test("localhost:8082", 8082).await;
+    server_sni_test("localhost:8082").await;
+    server_tls_policy_test("localhost:8082").await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+    http3_settings.proxy_routes = Vec::new();
+
+    // the server cannot start with a certificate not valid for its host names
+    http3_settings.http3 = false;
+    http3_settings.tls_hosts = vec!["localhost=ssl/alt.crt,ssl/alt.key".parse().unwrap()];
+    assert!(matches!(
+        server::Server::new(http3_settings.clone()).await,
+        Err(server::Error::Tls(_))
+    ));
+
+    // test client certificate authentication (mutual TLS)
+    let mut client_auth_settings = http3_settings;
+    client_auth_settings.listeners = vec![Listener::tcp("0.0.0.0:8083".parse().unwrap(), true)];
+    client_auth_settings.tls_hosts = Vec::new();
+    for required in [true, false] {
+        client_auth_settings.client_auth = Some(tls::ClientAuth {
+            ca_path: path::PathBuf::from("./ssl/root.crt"),
+            crl_path: Some(path::PathBuf::from("./ssl/root.crl")),
+            required,
+            allowed_names: match required {
+                true => Vec::new(),
+                false => vec![String::from("client.localhost")],
+            },
+        });
+        let (tx, handle) = spawn_server(client_auth_settings.clone()).await;
+        server_client_auth_test("localhost:8083", required).await;
+        tx.send(()).unwrap();
+        handle.await.unwrap();
+    }
+
+    // test the certificate reloading, when its files change
+    let tls_dir = std::env::temp_dir().join(format!("rust-http-server-{}", std::process::id()));
+    std::fs::create_dir_all(&tls_dir).unwrap();
+    let (cert_path, key_path) = (tls_dir.join("server.crt"), tls_dir.join("server.key"));
+    std::fs::copy("./ssl/server.crt", &cert_path).unwrap();
+    std::fs::copy("./ssl/server.key", &key_path).unwrap();
+    let mut reload_settings = client_auth_settings;
+    reload_settings.listeners = vec![Listener::tcp("0.0.0.0:8084".parse().unwrap(), true)];
+    reload_settings.ssl_cert_path = Some(cert_path.clone());
+    reload_settings.ssl_key_path = Some(key_path.clone());
+    reload_settings.client_auth = None;
+    reload_settings.tls_reload_interval = Some(std::time::Duration::from_millis(100));
+    let (tx, handle) = spawn_server(reload_settings.clone()).await;
+    server_tls_reload_test("localhost:8084", &cert_path, &key_path).await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+    std::fs::remove_dir_all(&tls_dir).unwrap();
+
+    // test the redirect of plain HTTP requests to HTTPS
+    let mut redirect_settings = reload_settings;
+    redirect_settings.listeners = vec![Listener::tcp("0.0.0.0:8085".parse().unwrap(), true)];
+    redirect_settings.ssl_cert_path = Some(path::PathBuf::from("./ssl/server.crt"));
+    redirect_settings.ssl_key_path = Some(path::PathBuf::from("./ssl/server.key"));
+    redirect_settings.tls_reload_interval = None;
+    redirect_settings.authentication_credentials = None;
+    redirect_settings.https_redirect = Some(https::HttpsRedirect {
+        address: "0.0.0.0:8086".parse().unwrap(),
+        host: None,
+    });
+    redirect_settings.hsts = Some("max-age=31536000; includeSubDomains".parse().unwrap());
+    let (tx, handle) = spawn_server(redirect_settings.clone()).await;
+    server_https_redirect_test("localhost:8085", "localhost:8086").await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test several listeners at once, including a Unix domain socket
+    let socket_dir = std::env::temp_dir().join(format!("rust-http-server-{}", std::process::id()));
+    std::fs::create_dir_all(&socket_dir).unwrap();
+    let socket_path = socket_dir.join("server.sock");
+    let mut listeners_settings = redirect_settings;
+    listeners_settings.https_redirect = None;
+    listeners_settings.listeners = vec![
+        "127.0.0.1:8087".parse().unwrap(),
+        "[::1]:8087|tls".parse().unwrap(),
+        format!("unix:{}|mode=600", socket_path.display())
+            .parse()
+            .unwrap(),
+    ];
+    // HTTPS listeners require a certificate, unless allowed to serve plain HTTP instead
+    listeners_settings.ssl_cert_path = None;
+    listeners_settings.ssl_key_path = None;
+    assert!(matches!(
+        server::Server::new(listeners_settings.clone()).await,
+        Err(server::Error::Tls(
+            tls::Error::TlsListenerWithoutCertificate
+        ))
+    ));
+    listeners_settings.ssl_cert_path = Some(path::PathBuf::from("./ssl/server.crt"));
+    listeners_settings.ssl_key_path = Some(path::PathBuf::from("./ssl/server.key"));
+    let pid_path = socket_dir.join("server.pid");
+    listeners_settings.pid_file = Some(pid_path.clone());
+    let (tx, handle) = spawn_server(listeners_settings.clone()).await;
+    server_listeners_test(&socket_path).await;
+    assert_eq!(
+        std::fs::read_to_string(&pid_path).unwrap(),
+        format!("{}\n", std::process::id())
+    );
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+    // the socket and PID files are removed once the server is stopped
+    assert!(!socket_path.exists());
+    assert!(!pid_path.exists());
+    std::fs::remove_dir_all(&socket_dir).unwrap();
+
+    // test clients behind proxies, sending a PROXY protocol header or forwarding headers
+    let mut proxied_settings = listeners_settings;
+    proxied_settings.pid_file = None;
+    proxied_settings.listeners = vec![
+        "127.0.0.1:8088|proxy-protocol".parse().unwrap(),
+        "127.0.0.1:8092".parse().unwrap(),
+    ];
+    proxied_settings.trusted_proxies = vec!["192.0.2.0/24".parse().unwrap()];
+    let (tx, handle) = spawn_server(proxied_settings.clone()).await;
+    server_proxy_protocol_test("127.0.0.1:8088", "127.0.0.1:8092").await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test rate limits, stricter for failed authentications
+    let creds = (String::from("user"), String::from("pass"));
+    let mut rate_limit_settings = proxied_settings.clone();
+    rate_limit_settings.listeners = vec!["127.0.0.1:8093".parse().unwrap()];
+    rate_limit_settings.authentication_credentials = Some(vec![creds.clone()]);
+    rate_limit_settings.rate_limits = vec!["/lipsum.html=2/min".parse().unwrap()];
+    rate_limit_settings.auth_failure_rate = Some("1/min".parse().unwrap());
+    let (tx, handle) = spawn_server(rate_limit_settings).await;
+    server_rate_limit_test("127.0.0.1:8093", &creds).await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test access control lists, on the address of the clients behind a proxy
+    let mut acl_settings = proxied_settings;
+    acl_settings.listeners = vec!["127.0.0.1:8094|proxy-protocol".parse().unwrap()];
+    acl_settings.acls = vec!["/subdir=allow:198.51.100.0/24,deny:all".parse().unwrap()];
+    let (tx, handle) = spawn_server(acl_settings.clone()).await;
+    server_acl_test("127.0.0.1:8094").await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test the credentials of an htpasswd file, with hashed passwords
+    let auth_dir = std::env::temp_dir().join(format!("rust-http-server-{}", std::process::id()));
+    std::fs::create_dir_all(&auth_dir).unwrap();
+    let auth_file = auth_dir.join("users.htpasswd");
+    std::fs::write(
+        &auth_file,
+        format!(
+            "bcrypt-user:$2y$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe\n\
+            sha-user:$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5\n\
+            argon2-user:{}\n",
+            credentials::HashScheme::Argon2.hash("argon2-pass").unwrap()
+        ),
+    )
+    .unwrap();
+    let mut auth_file_settings = acl_settings;
+    auth_file_settings.listeners = vec!["127.0.0.1:8095".parse().unwrap()];
+    auth_file_settings.acls = Vec::new();
+    auth_file_settings.authentication_file = Some(path::PathBuf::from("./ssl/none.htpasswd"));
+    assert!(matches!(
+        server::Server::new(auth_file_settings.clone()).await,
+        Err(server::Error::Credentials(credentials::Error::Io(..)))
+    ));
+    auth_file_settings.authentication_file = Some(auth_file.clone());
+    let (tx, handle) = spawn_server(auth_file_settings.clone()).await;
+    server_auth_file_test("127.0.0.1:8095", &auth_file).await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test the realms protecting some locations, with authorization rules
+    let groups_file = auth_dir.join("groups");
+    std::fs::write(&groups_file, "staff: new-user\n").unwrap();
+    let mut realms_settings = auth_file_settings;
+    realms_settings.listeners = vec!["127.0.0.1:8096".parse().unwrap()];
+    realms_settings.authentication_locations = vec![
+        "/subdir/=Staff|require=group:staff".parse().unwrap(),
+        "/lipsum.html=Lipsum|require=user:admin".parse().unwrap(),
+        "/php=public".parse().unwrap(),
+    ];
+    assert!(matches!(
+        server::Server::new(realms_settings.clone()).await,
+        Err(server::Error::Credentials(credentials::Error::Realm(..)))
+    ));
+    realms_settings.authentication_groups_file = Some(groups_file);
+    let (tx, handle) = spawn_server(realms_settings.clone()).await;
+    server_auth_realms_test("127.0.0.1:8096").await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test the Digest authentication scheme
+    let mut digest_settings = realms_settings;
+    digest_settings.listeners = vec!["127.0.0.1:8097".parse().unwrap()];
+    digest_settings.authentication_file = None;
+    digest_settings.authentication_locations = Vec::new();
+    digest_settings.authentication_groups_file = None;
+    digest_settings.authentication_credentials = Some(vec![(
+        String::from("digest-user"),
+        String::from("digest-pass"),
+    )]);
+    digest_settings.authentication_schemes = vec![AuthScheme::Digest, AuthScheme::Basic];
+    let (tx, handle) = spawn_server(digest_settings.clone()).await;
+    server_digest_test("127.0.0.1:8097").await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test the bearer tokens (static ones and JSON Web Tokens) of an API location
+    let tokens_file = auth_dir.join("tokens");
+    std::fs::write(&tokens_file, "ci:ci-token-0123456789\n").unwrap();
+    let mut bearer_settings = digest_settings;
+    bearer_settings.listeners = vec!["127.0.0.1:8098".parse().unwrap()];
+    bearer_settings.authentication_credentials = Some(vec![creds.clone()]);
+    bearer_settings.authentication_schemes = vec![AuthScheme::Basic];
+    bearer_settings.authentication_locations = vec![
+        "/php=API|schemes=bearer|require=claim:scope=write,user:ci"
+            .parse()
+            .unwrap(),
+    ];
+    assert!(matches!(
+        server::Server::new(bearer_settings.clone()).await,
+        Err(server::Error::Credentials(credentials::Error::Realm(..)))
+    ));
+    bearer_settings.bearer_tokens_file = Some(tokens_file);
+    bearer_settings.jwt_keys = vec!["ES256=ssl/jwt-ec.pub".parse().unwrap()];
+    bearer_settings.jwt_issuer = Some(String::from("https://issuer.localhost"));
+    bearer_settings.jwt_audience = Some(String::from("api"));
+    let (tx, handle) = spawn_server(bearer_settings.clone()).await;
+    server_bearer_test("127.0.0.1:8098", &creds).await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test the login form, opening sessions for the users of the server credentials
+    let mut login_settings = bearer_settings;
+    login_settings.listeners = vec!["127.0.0.1:8099".parse().unwrap()];
+    login_settings.authentication_locations = Vec::new();
+    login_settings.login = Some("/login".parse().unwrap());
+    let (tx, handle) = spawn_server(login_settings.clone()).await;
+    server_login_test("127.0.0.1:8099", &creds).await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test the signed URLs, bypassing the authentication for their path
+    let secret_file = auth_dir.join("url-secret");
+    std::fs::write(&secret_file, "short secret\n").unwrap();
+    let mut signed_url_settings = login_settings;
+    signed_url_settings.listeners = vec!["127.0.0.1:8100".parse().unwrap()];
+    signed_url_settings.login = None;
+    signed_url_settings.url_secret = Some(secret_file.clone());
+    assert!(matches!(
+        server::Server::new(signed_url_settings.clone()).await,
+        Err(server::Error::Credentials(credentials::Error::Parse(..)))
+    ));
+    std::fs::write(&secret_file, "a secret only known to the server\n").unwrap();
+    let (tx, handle) = spawn_server(signed_url_settings.clone()).await;
+    server_signed_url_test("127.0.0.1:8100", &secret_file).await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test the access logs, the requests of a virtual host going to a log of its own
+    let mut access_log_settings = signed_url_settings;
+    access_log_settings.listeners = vec!["127.0.0.1:8101".parse().unwrap()];
+    access_log_settings.url_secret = None;
+    access_log_settings.access_logs = vec![
+        format!("{}/access.log", auth_dir.display())
+            .parse()
+            .unwrap(),
+        format!(
+            "{}/json.log|format=json|host=json.localhost",
+            auth_dir.display()
+        )
+        .parse()
+        .unwrap(),
+    ];
+    let (tx, handle) = spawn_server(access_log_settings.clone()).await;
+    server_access_log_test("127.0.0.1:8101", &creds, &auth_dir).await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test the metrics, on a path requiring credentials, then on their own listener
+    let mut metrics_settings = access_log_settings;
+    metrics_settings.listeners = vec!["127.0.0.1:8102".parse().unwrap()];
+    metrics_settings.access_logs = Vec::new();
+    metrics_settings.metrics = Some("/metrics".parse().unwrap());
+    let (tx, handle) = spawn_server(metrics_settings.clone()).await;
+    let client = create_http_client().await;
+    let url = build_url(false, "127.0.0.1:8102", "/metrics");
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::UNAUTHORIZED);
+    let res = client
+        .get(&url)
+        .basic_auth(&creds.0, Some(&creds.1))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), reqwest::StatusCode::OK);
+    assert!(
+        res.text()
+            .await
+            .unwrap()
+            .contains(r#"rhs_http_requests_total{class="4xx",method="GET",handler="other"} 1"#)
+    );
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+    metrics_settings.metrics = Some("127.0.0.1:8103".parse().unwrap());
+    let (tx, handle) = spawn_server(metrics_settings.clone()).await;
+    server_metrics_test("127.0.0.1:8102", "127.0.0.1:8103", &creds).await;
+    tx.send(()).unwrap();
+    handle.await.unwrap();
+
+    // test the status page of the server, and draining it
+    let mut status_settings = metrics_settings;
+    status_settings.listeners = vec!["127.0.0.1:8104".parse().unwrap()];
+    status_settings.metrics = None;
+    status_settings.server_status = Some(String::from("/server-status"));
+    server_status_test(status_settings, "127.0.0.1:8104", &creds).await;
+    std::fs::remove_dir_all(&auth_dir).unwrap();
 }